@@ -5,7 +5,8 @@ TARGET=x86_64-unknown-linux-musl
 PROJECT=rook
 TAG=localhost/$PROJECT:$TARGET
 
-docker build -t $TAG -f docker-build/Dockerfile.release --build-arg PROJECT=$PROJECT .
+docker build -t $TAG -f docker-build/Dockerfile.release --build-arg PROJECT=$PROJECT \
+    --build-arg COMMIT=$(git rev-parse --short=12 HEAD) .
 CID=$(docker container create $TAG)
 mkdir -p target/optimized/
 docker cp -q $CID:target/${TARGET}/release/${PROJECT} target/optimized/${PROJECT}.${TARGET}
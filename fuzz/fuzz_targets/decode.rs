@@ -0,0 +1,18 @@
+//! Every input either fails to decode or is the one encoding of what it decodes to.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rook::encoding::{decode_base64, decode_hex, hex};
+
+fuzz_target!(|s: &str| {
+    if let Some(bytes) = decode_hex(s) {
+        assert_eq!(hex(&bytes), s.to_ascii_lowercase());
+    }
+    if let Some(bytes) = decode_base64(s) {
+        let padding = s.bytes().rev().take_while(|&c| c == b'=').count();
+        assert_eq!(bytes.len(), s.len() / 4 * 3 - padding);
+        assert!(s
+            .trim_end_matches('=')
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/'));
+    }
+});
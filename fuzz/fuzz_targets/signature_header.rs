@@ -0,0 +1,35 @@
+//! Whatever a client puts in the signature headers, verifying it returns an error instead of
+//! panicking.
+#![no_main]
+use hyper::header::{HeaderMap, HeaderValue};
+use libfuzzer_sys::fuzz_target;
+use rook::verify::{
+    self, Algorithm, HmacSha256, Verifier, ROOK_ALG_HEADER, ROOK_ALG_SIGNATURE_HEADER,
+    ROOK_NONCE_HEADER, ROOK_SIGNATURE_HEADER, ROOK_TIMESTAMP_HEADER,
+};
+use std::time::Duration;
+
+fuzz_target!(|headers: Vec<(u8, &[u8])>| {
+    const NAMES: [&str; 5] = [
+        ROOK_SIGNATURE_HEADER,
+        ROOK_ALG_HEADER,
+        ROOK_ALG_SIGNATURE_HEADER,
+        ROOK_TIMESTAMP_HEADER,
+        ROOK_NONCE_HEADER,
+    ];
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_bytes(value) {
+            map.insert(NAMES[name as usize % NAMES.len()], value);
+        }
+    }
+    let _ = verify::signature(&map, ROOK_SIGNATURE_HEADER);
+    let hook = HmacSha256::rook(b"secret".to_vec())
+        .algorithms(vec![
+            Algorithm::Sha256,
+            Algorithm::Sha512,
+            Algorithm::Blake3,
+        ])
+        .replay_window(Duration::from_secs(300));
+    let _ = hook.verify(&map, b"body");
+});
@@ -0,0 +1,315 @@
+use crate::{
+    config::{self, RouteConfig},
+    dashboard,
+    encoding::decode_query,
+    exec, feed,
+    history::{self, History},
+    logging, maintenance,
+    metrics::METRICS,
+    router::{self, Peer, RequestId},
+    service::RookService,
+    version,
+};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::{convert::Infallible, sync::Arc, time::Instant};
+use time::format_description::well_known::Rfc3339;
+
+const DEFAULT_DELIVERIES: usize = 100;
+const MAX_DELIVERIES: usize = 1000;
+
+/// Operator endpoints.  These are served on their own listener (loopback by default) and are
+/// never reachable through the public webhook port.
+pub async fn handle(req: Request<Body>, rook: RookService) -> Result<Response<Body>, Infallible> {
+    debug!("admin {} {}", req.method(), req.uri().path());
+    let (cfg, history) = (rook.config(), rook.history());
+    if let Some(id) = replay_id(req.uri().path()) {
+        return Ok(match (req.method(), history) {
+            (&Method::POST, Some(history)) => replay(&history, &cfg, id.to_string()).await,
+            (&Method::POST, None) => {
+                text_response(StatusCode::NOT_FOUND, "replay needs [history]".into())
+            }
+            _ => text_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed".into()),
+        });
+    }
+    Ok(match (req.method(), req.uri().path()) {
+        (&Method::GET, "/admin" | "/admin/") => html_response(dashboard::render(&cfg)),
+        (&Method::GET, "/admin/routes") => json_response(config::routes(&cfg)),
+        (&Method::GET, "/admin/version") => json_response(version::json()),
+        (&Method::GET, "/admin/status") => json_response(status(&cfg)),
+        (&Method::GET, "/admin/jobs") => json_response(json!(jobs())),
+        (&Method::POST, "/admin/reload") => reload(&rook).await,
+        (&Method::POST, "/admin/pause") => pause(&cfg, req.uri().query().unwrap_or("")),
+        (&Method::POST, "/admin/resume") => resume(cfg, req.uri().query().unwrap_or("")),
+        (_, "/admin/metrics") if !cfg!(feature = "metrics") => text_response(
+            StatusCode::NOT_FOUND,
+            "metrics needs rook built with the `metrics` feature".into(),
+        ),
+        (&Method::GET, "/admin/metrics") => text_response(StatusCode::OK, METRICS.render()),
+        (&Method::GET, "/admin/events") => feed::response(),
+        (&Method::GET, "/admin/deliveries") => match history {
+            Some(history) => deliveries(&history, req.uri().query().unwrap_or("")).await,
+            None => json_response(json!(METRICS.history())),
+        },
+        (
+            _,
+            "/admin" | "/admin/" | "/admin/routes" | "/admin/metrics" | "/admin/deliveries"
+            | "/admin/version" | "/admin/status" | "/admin/jobs" | "/admin/reload"
+            | "/admin/events" | "/admin/pause" | "/admin/resume",
+        ) => text_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed".into()),
+        _ => text_response(StatusCode::NOT_FOUND, "not found".into()),
+    })
+}
+
+/// The hook commands still running, oldest first.
+fn jobs() -> Vec<Value> {
+    exec::running()
+        .into_iter()
+        .map(|job| {
+            json!({
+                "pid": job.pid,
+                "route": job.route,
+                "command": job.command,
+                "request_id": job.request_id,
+                "runtime_ms": job.started.elapsed().as_millis() as u64,
+            })
+        })
+        .collect()
+}
+
+/// What the server is doing right now, for `rook top`.
+fn status(cfg: &RouteConfig) -> Value {
+    let hooks: Vec<Value> = exec::outcomes()
+        .into_iter()
+        .map(|(route, o)| {
+            json!({
+                "route": route,
+                "succeeded": o.succeeded,
+                "failed": o.failed,
+                "last_spawn_error": o.last_spawn_error.map(|e| json!({
+                    "command": e.command,
+                    "error": e.error,
+                    "at": e.at.format(&Rfc3339).unwrap_or_default(),
+                })),
+            })
+        })
+        .collect();
+    let paused: Vec<Value> = maintenance::paused()
+        .into_iter()
+        .map(|(route, held)| json!({ "route": route, "held": held }))
+        .collect();
+    let waiting: Vec<Value> = maintenance::waiting()
+        .into_iter()
+        .map(|(route, held)| json!({ "route": route, "held": held }))
+        .collect();
+    json!({
+        "connections": METRICS.connections_open(),
+        "max_connections": cfg.max_connections,
+        "requests_in_flight": METRICS.requests_in_flight(),
+        "max_requests": cfg.max_requests,
+        "max_jobs": cfg.max_jobs,
+        "jobs": jobs(),
+        "hooks": hooks,
+        "paused": paused,
+        "outside_hours": waiting,
+    })
+}
+
+/// Load the config file again.  The new routes on success, or why the file didn't load; the
+/// server keeps its current config then.
+async fn reload(rook: &RookService) -> Response<Body> {
+    match rook.reload().await {
+        Ok(cfg) => {
+            logging::info!("reloaded config from admin endpoint");
+            json_response(json!({
+                "config": cfg.config_path,
+                "routes": config::routes(&cfg),
+            }))
+        }
+        Err(e) => {
+            log::warn!("config reload failed: {}", e);
+            text_response(StatusCode::UNPROCESSABLE_ENTITY, e)
+        }
+    }
+}
+
+/// `?route=<url>`: one of the config's hook urls
+fn hook_url(cfg: &RouteConfig, query: &str) -> Result<String, (StatusCode, &'static str)> {
+    let route = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .find(|(key, _)| *key == "route")
+        .and_then(|(_, value)| decode_query(value))
+        .ok_or((StatusCode::BAD_REQUEST, "missing route"))?;
+    if !cfg.gh_hooks.contains_key(&route) && !cfg.rook_hooks.contains_key(&route) {
+        return Err((StatusCode::NOT_FOUND, "no such route"));
+    }
+    Ok(route)
+}
+
+/// Pause a hook url; see [`maintenance`].
+fn pause(cfg: &RouteConfig, query: &str) -> Response<Body> {
+    let route = match hook_url(cfg, query) {
+        Ok(route) => route,
+        Err((status, e)) => return text_response(status, e.into()),
+    };
+    let paused = maintenance::pause(&route);
+    if paused {
+        logging::info!("'{}' paused from admin endpoint", route);
+    }
+    json_response(json!({ "route": route, "paused": paused }))
+}
+
+/// Resume a paused hook url, running the deliveries it held.
+fn resume(cfg: Arc<RouteConfig>, query: &str) -> Response<Body> {
+    let route = match hook_url(&cfg, query) {
+        Ok(route) => route,
+        Err((status, e)) => return text_response(status, e.into()),
+    };
+    match maintenance::resume(&route, cfg) {
+        Some(held) => {
+            logging::info!(
+                "'{}' resumed from admin endpoint, running {} held",
+                route,
+                held
+            );
+            json_response(json!({ "route": route, "resumed": true, "held": held }))
+        }
+        None => json_response(json!({ "route": route, "resumed": false, "held": 0 })),
+    }
+}
+
+/// `?limit=<n>&route=<url>` over the sqlite history
+async fn deliveries(history: &History, query: &str) -> Response<Body> {
+    let mut limit = DEFAULT_DELIVERIES;
+    let mut route = None;
+    for (key, value) in query.split('&').filter_map(|kv| kv.split_once('=')) {
+        match key {
+            "limit" => match value.parse::<usize>() {
+                Ok(n) => limit = n.min(MAX_DELIVERIES),
+                Err(_) => {
+                    return text_response(StatusCode::BAD_REQUEST, "bad limit".into());
+                }
+            },
+            "route" => route = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    match history.list(limit, route).await {
+        Ok(deliveries) => json_response(deliveries),
+        Err(e) => {
+            log::warn!("failed to read history: {}", e);
+            text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "history unavailable".into(),
+            )
+        }
+    }
+}
+
+/// `/admin/deliveries/<request id>/replay`
+fn replay_id(path: &str) -> Option<&str> {
+    path.strip_prefix("/admin/deliveries/")?
+        .strip_suffix("/replay")
+        .filter(|id| !id.is_empty())
+}
+
+/// Send a stored delivery through the router again, under a new request id.  The replay is
+/// recorded in the history like any other delivery.
+async fn replay(history: &History, cfg: &RouteConfig, id: String) -> Response<Body> {
+    let original = match history.get(id.clone()).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return text_response(StatusCode::NOT_FOUND, "no such delivery".into()),
+        Err(e) => {
+            log::warn!("failed to read history: {}", e);
+            return text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "history unavailable".into(),
+            );
+        }
+    };
+    let Some(body) = original.body else {
+        return text_response(
+            StatusCode::CONFLICT,
+            "no stored body: rejected before it was read, or history.store_body is off".into(),
+        );
+    };
+    let mut req = Request::builder()
+        .method(original.method.as_str())
+        .uri(original.path.as_str());
+    if let Some(headers) = original.headers.as_object() {
+        for (name, value) in headers {
+            if let (false, Some(value)) = (history::is_redacted(name), value.as_str()) {
+                req = req.header(name.as_str(), value);
+            }
+        }
+    }
+    let request_id = logging::new_request_id();
+    let mut req = match req.body(Body::from(body)) {
+        Ok(r) => r,
+        Err(e) => {
+            return text_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("stored delivery is not a valid request: {}", e),
+            )
+        }
+    };
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let peer = Peer {
+        ip: original.client,
+        client_cn: None,
+    };
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    let started = Instant::now();
+    let handled = logging::with_request_id(request_id.clone(), router::handle(req, cfg, &peer));
+    let mut res = match handled.await {
+        Ok(r) => r,
+        Err(e) => match e {},
+    };
+    let status = res.status();
+    logging::info!("replayed {} as {}: {}", id, request_id, status.as_u16());
+    METRICS.response(
+        &request_id,
+        peer.ip,
+        &method,
+        &path,
+        status,
+        started.elapsed(),
+    );
+    let record = history::Record::new(
+        &request_id,
+        peer.ip,
+        &method,
+        &original.path,
+        original.headers,
+        &mut res,
+    );
+    history.record(record).await;
+    json_response(json!({
+        "request_id": request_id,
+        "replay_of": id,
+        "status": status.as_u16(),
+    }))
+}
+
+fn json_response(value: Value) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(value.to_string().into())
+        .expect("error building body")
+}
+
+fn html_response(body: String) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(body.into())
+        .expect("error building body")
+}
+
+fn text_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body.into())
+        .expect("error building body")
+}
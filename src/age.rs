@@ -0,0 +1,45 @@
+//! Hook secrets encrypted with [age](https://age-encryption.org) and kept in the config itself:
+//! `secret_age` holds the armored ciphertext, and the `[age]` identity decrypts it when the
+//! config loads.
+use crate::config::AgeConfig;
+use ::age::{armor::ArmoredReader, Decryptor, Identity, IdentityFile};
+use std::{collections::HashMap, env, io::Read};
+
+const IDENTITY_ENV: &str = "ROOK_AGE_IDENTITY_FILE";
+
+/// Decrypt every armored ciphertext in `ciphertexts`, keyed by ciphertext.
+pub fn decrypt(
+    cfg: Option<&AgeConfig>,
+    ciphertexts: Vec<String>,
+) -> Result<HashMap<String, Vec<u8>>, String> {
+    let path = cfg
+        .and_then(|c| c.identity_file.clone())
+        .or_else(|| env::var(IDENTITY_ENV).ok().filter(|v| !v.is_empty()))
+        .ok_or_else(|| format!("secret_age needs age.identity_file or {}", IDENTITY_ENV))?;
+    let identities = IdentityFile::from_file(path.clone())
+        .map_err(|e| format!("failed to read age identity '{}': {}", path, e))?
+        .into_identities()
+        .map_err(|e| format!("bad age identity '{}': {}", path, e))?;
+    let mut secrets = HashMap::new();
+    for ciphertext in ciphertexts {
+        let plaintext = open(&identities, &ciphertext)
+            .map_err(|e| format!("failed to decrypt secret_age with '{}': {}", path, e))?;
+        // the same trimming as secret_file, so `echo secret | age ...` works
+        let plaintext = plaintext.trim_ascii().to_vec();
+        secrets.insert(ciphertext, plaintext);
+    }
+    Ok(secrets)
+}
+
+fn open(identities: &[Box<dyn Identity>], ciphertext: &str) -> Result<Vec<u8>, String> {
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext.trim().as_bytes()))
+        .map_err(|e| e.to_string())?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref()))
+        .map_err(|e| e.to_string())?;
+    let mut plaintext = vec![];
+    reader
+        .read_to_end(&mut plaintext)
+        .map_err(|e| e.to_string())?;
+    Ok(plaintext)
+}
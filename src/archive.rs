@@ -0,0 +1,180 @@
+//! Keep a copy of every verified delivery on disk, for audit trails and for reproducing a
+//! problem with the exact payload that caused it.
+//!
+//! Each delivery is two files named for when it arrived and its request id: `<name>.body`
+//! holds the body as received, and `<name>.json` the request id, client, hook url, and headers,
+//! with credentials redacted as the [delivery history](crate::history) does.  Names sort in
+//! arrival order.  Files are written off the request's thread, and the directory is pruned to
+//! `archive_keep` deliveries and `archive_retention_days` after each one.
+use crate::history;
+use hyper::HeaderMap;
+use serde_json::json;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use time::{
+    format_description::{well_known::Rfc3339, FormatItem},
+    macros::format_description,
+    OffsetDateTime,
+};
+
+/// `20261015T123834.123456789Z`, which sorts chronologically
+const STAMP: &[FormatItem] =
+    format_description!("[year][month][day]T[hour][minute][second].[subsecond digits:9]Z");
+const BODY: &str = "body";
+const SIDECAR: &str = "json";
+
+/// A hook's `archive_dir` and how long deliveries stay in it.
+pub struct Archive {
+    dir: PathBuf,
+    /// most deliveries to keep; None keeps any number
+    keep: Option<usize>,
+    max_age: Duration,
+}
+
+/// One verified request, as [`Archive::store`] writes it.
+pub struct Delivery<'a> {
+    pub request_id: Option<&'a str>,
+    pub client: IpAddr,
+    pub url: &'a str,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+}
+
+impl Archive {
+    /// Create `dir` if it's missing.
+    pub fn open(dir: &str, keep: Option<usize>, retention_days: u64) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create archive_dir '{}': {}", dir, e))?;
+        Ok(Archive {
+            dir: PathBuf::from(dir),
+            keep,
+            max_age: Duration::from_secs(retention_days * 24 * 60 * 60),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write `delivery` in the background, then prune the directory.
+    pub fn store(&self, delivery: &Delivery) {
+        let now = OffsetDateTime::now_utc();
+        let name = name(now, delivery.request_id);
+        let sidecar = json!({
+            "received": now.format(&Rfc3339).expect("formatting a utc timestamp"),
+            "request_id": delivery.request_id,
+            "client": delivery.client.to_string(),
+            "url": delivery.url,
+            "headers": history::headers_json(delivery.headers),
+            "size": delivery.body.len(),
+        });
+        let (dir, keep, max_age) = (self.dir.clone(), self.keep, self.max_age);
+        let body = delivery.body.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let written =
+                write_new(&dir.join(format!("{}.{}", name, BODY)), &body).and_then(|()| {
+                    let sidecar = serde_json::to_vec_pretty(&sidecar).unwrap_or_default();
+                    write_new(&dir.join(format!("{}.{}", name, SIDECAR)), &sidecar)
+                });
+            if let Err(e) = written {
+                log::warn!(
+                    "archive failed: dir={} name={} error={}",
+                    dir.display(),
+                    name,
+                    e
+                );
+            }
+            if let Err(e) = prune(&dir, keep, max_age) {
+                log::warn!("archive prune failed: dir={} error={}", dir.display(), e);
+            }
+        });
+    }
+}
+
+/// A file name for something received at `now`, which sorts in arrival order.
+pub(crate) fn name(now: OffsetDateTime, request_id: Option<&str>) -> String {
+    let stamp = now.format(STAMP).expect("formatting a utc timestamp");
+    match request_id {
+        Some(id) => format!("{}-{}", stamp, file_safe(id)),
+        None => stamp,
+    }
+}
+
+/// Write `data` to `path`, which must not exist yet.
+fn write_new(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(data)
+}
+
+/// Remove the oldest deliveries past `keep`, and any older than `max_age`.  Only files named
+/// like an archived delivery are touched.
+fn prune(dir: &Path, keep: Option<usize>, max_age: Duration) -> io::Result<()> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|n| n.strip_suffix(BODY)?.strip_suffix('.'))
+        else {
+            continue;
+        };
+        if is_stamped(name) {
+            names.push(name.to_string());
+        }
+    }
+    // newest first
+    names.sort_unstable_by(|a, b| b.cmp(a));
+    let cutoff = SystemTime::now().checked_sub(max_age);
+    for (i, name) in names.iter().enumerate() {
+        let body = dir.join(format!("{}.{}", name, BODY));
+        let expired = match keep {
+            Some(keep) if i >= keep => true,
+            _ => fs::metadata(&body)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| cutoff.is_some_and(|cutoff| modified < cutoff)),
+        };
+        if expired {
+            remove(&body)?;
+            remove(&dir.join(format!("{}.{}", name, SIDECAR)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Deliveries pruned by another write at the same time are already gone.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `name` starts with a [`STAMP`].
+fn is_stamped(name: &str) -> bool {
+    let b = name.as_bytes();
+    b.len() >= 26
+        && b[..8].iter().all(u8::is_ascii_digit)
+        && b[8] == b'T'
+        && b[9..15].iter().all(u8::is_ascii_digit)
+        && b[15] == b'.'
+        && b[16..25].iter().all(u8::is_ascii_digit)
+        && b[25] == b'Z'
+}
+
+/// Request ids are printable ascii chosen by the client; anything that could climb out of the
+/// directory or hide the file becomes `_`.
+fn file_safe(id: &str) -> String {
+    id.chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect()
+}
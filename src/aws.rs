@@ -0,0 +1,333 @@
+//! Hook secrets kept in AWS Secrets Manager (`secret_secretsmanager = "rook/deploy"`) or SSM
+//! Parameter Store (`secret_ssm = "/rook/deploy"`), read with the host's own credentials.
+//!
+//! Credentials come from the first of: `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` (with
+//! `AWS_SESSION_TOKEN`), the ECS task role, or the EC2 instance role through IMDSv2.  Like
+//! Vault secrets, they're read once while the config loads.
+use crate::config::AwsConfig;
+use hmac::{Hmac, Mac};
+use hyper::{body, header, Body, Client, Method, Request, StatusCode, Uri};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, env, time::Duration};
+use time::OffsetDateTime;
+
+type Connector = crate::tls::HttpsConnector;
+
+/// Secrets Manager secrets, then SSM parameters, keyed by reference.
+type Secrets = (HashMap<String, Vec<u8>>, HashMap<String, Vec<u8>>);
+
+/// per AWS API call
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// off EC2 the metadata address doesn't answer at all; don't wait long to find out
+const IMDS_TIMEOUT: Duration = Duration::from_secs(2);
+const IMDS_ENDPOINT: &str = "http://169.254.169.254";
+const ECS_ENDPOINT: &str = "http://169.254.170.2";
+
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+struct Aws {
+    client: Client<Connector>,
+    credentials: Credentials,
+    /// for names that aren't ARNs; looked up on first use
+    region: Option<String>,
+}
+
+/// Read every Secrets Manager secret (`name-or-arn`, or `name-or-arn#field` for a field of a
+/// json secret) and SSM parameter (name or ARN; SecureStrings are decrypted).
+pub async fn load(
+    cfg: Option<AwsConfig>,
+    secrets_manager: Vec<String>,
+    ssm: Vec<String>,
+) -> Result<Secrets, String> {
+    let ca_file = cfg
+        .as_ref()
+        .map_or_else(crate::config::default_ca_file, |c| c.ca_file.clone());
+    let client = Client::builder().build(crate::tls::https_connector(&ca_file)?);
+    let credentials = credentials(&client).await?;
+    let region = cfg
+        .and_then(|c| c.region)
+        .or_else(|| var("AWS_REGION"))
+        .or_else(|| var("AWS_DEFAULT_REGION"));
+    let mut aws = Aws {
+        client,
+        credentials,
+        region,
+    };
+    let mut secrets = HashMap::new();
+    for reference in secrets_manager {
+        let (id, field) = match reference.rsplit_once('#') {
+            Some((id, field)) => (id, Some(field)),
+            None => (reference.as_str(), None),
+        };
+        let body = json!({ "SecretId": id });
+        let res = aws
+            .call("secretsmanager", id, "secretsmanager.GetSecretValue", body)
+            .await?;
+        let value = res["SecretString"]
+            .as_str()
+            .ok_or_else(|| format!("secret '{}' has no SecretString", id))?;
+        let value = match field {
+            None => value.to_string(),
+            Some(field) => serde_json::from_str::<Value>(value)
+                .ok()
+                .and_then(|v| v[field].as_str().map(str::to_string))
+                .ok_or_else(|| format!("secret '{}' has no string field '{}'", id, field))?,
+        };
+        secrets.insert(reference.clone(), value.into_bytes());
+    }
+    let mut parameters = HashMap::new();
+    for name in ssm {
+        let body = json!({ "Name": name, "WithDecryption": true });
+        let res = aws
+            .call("ssm", &name, "AmazonSSM.GetParameter", body)
+            .await?;
+        let value = res["Parameter"]["Value"]
+            .as_str()
+            .ok_or_else(|| format!("parameter '{}' has no value", name))?;
+        parameters.insert(name.clone(), value.as_bytes().to_vec());
+    }
+    Ok((secrets, parameters))
+}
+
+impl Aws {
+    /// Call `target` on `service` in the region of `id` (when it's an ARN) or the default one.
+    async fn call(
+        &mut self,
+        service: &str,
+        id: &str,
+        target: &str,
+        body: Value,
+    ) -> Result<Value, String> {
+        let region = match arn_region(id) {
+            Some(region) => region.to_string(),
+            None => self.region().await?,
+        };
+        let endpoint = var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|| format!("https://{}.{}.amazonaws.com", service, region));
+        let uri: Uri = endpoint
+            .parse()
+            .map_err(|e| format!("bad aws endpoint '{}': {}", endpoint, e))?;
+        let body = body.to_string();
+        let host = uri.authority().map(|a| a.to_string()).unwrap_or_default();
+        let now = OffsetDateTime::now_utc();
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host),
+            ("x-amz-date", amz_date(now)),
+            ("x-amz-target", target.to_string()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.sort();
+        let authorization = sign(&self.credentials, &region, service, now, &headers, &body);
+        let mut req = Request::builder().method(Method::POST).uri(uri);
+        for (name, value) in &headers {
+            req = req.header(*name, value.as_str());
+        }
+        let req = req
+            .header(header::AUTHORIZATION, authorization)
+            .body(Body::from(body))
+            .map_err(|e| format!("bad aws request: {}", e))?;
+        let (status, res) = send(&self.client, req, TIMEOUT)
+            .await
+            .map_err(|e| format!("{} {} failed: {}", target, id, e))?;
+        let res: Value = serde_json::from_slice(&res).unwrap_or_default();
+        if status != StatusCode::OK {
+            // {"__type": "...#ResourceNotFoundException", "message": "..."}
+            let kind = res["__type"].as_str().unwrap_or_default();
+            let kind = kind.rsplit('#').next().unwrap_or_default();
+            let message = res["message"].as_str().or(res["Message"].as_str());
+            return Err(format!(
+                "{} {} answered {}: {} {}",
+                target,
+                id,
+                status,
+                kind,
+                message.unwrap_or_default()
+            ));
+        }
+        Ok(res)
+    }
+
+    async fn region(&mut self) -> Result<String, String> {
+        if let Some(region) = &self.region {
+            return Ok(region.clone());
+        }
+        let region = imds(&self.client, "/latest/meta-data/placement/region")
+            .await
+            .map_err(|e| format!("no aws region: set aws.region or AWS_REGION ({})", e))?;
+        self.region = Some(region.clone());
+        Ok(region)
+    }
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// `arn:aws:ssm:us-east-1:123456789012:parameter/rook/deploy` -> `us-east-1`
+fn arn_region(id: &str) -> Option<&str> {
+    id.strip_prefix("arn:")?
+        .split(':')
+        .nth(2)
+        .filter(|r| !r.is_empty())
+}
+
+async fn credentials(client: &Client<Connector>) -> Result<Credentials, String> {
+    if let Some(access_key_id) = var("AWS_ACCESS_KEY_ID") {
+        return Ok(Credentials {
+            access_key_id,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")
+                .ok_or("AWS_ACCESS_KEY_ID is set but AWS_SECRET_ACCESS_KEY isn't")?,
+            session_token: var("AWS_SESSION_TOKEN"),
+        });
+    }
+    let ecs = var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI")
+        .map(|path| format!("{}{}", ECS_ENDPOINT, path))
+        .or_else(|| var("AWS_CONTAINER_CREDENTIALS_FULL_URI"));
+    let res = match ecs {
+        Some(url) => {
+            let mut req = Request::get(&url);
+            if let Some(token) = var("AWS_CONTAINER_AUTHORIZATION_TOKEN") {
+                req = req.header(header::AUTHORIZATION, token);
+            }
+            let req = req.body(Body::empty()).map_err(|e| e.to_string())?;
+            match send(client, req, TIMEOUT).await {
+                Ok((StatusCode::OK, body)) => body.to_vec(),
+                Ok((status, _)) => return Err(format!("ecs credentials answered {}", status)),
+                Err(e) => return Err(format!("failed to get ecs credentials: {}", e)),
+            }
+        }
+        None => {
+            let path = "/latest/meta-data/iam/security-credentials/";
+            let no_role = |e| format!("no aws credentials in the environment or from ec2 ({})", e);
+            let role = imds(client, path).await.map_err(no_role)?;
+            let role = role.lines().next().unwrap_or_default();
+            imds(client, &format!("{}{}", path, role))
+                .await
+                .map_err(no_role)?
+                .into_bytes()
+        }
+    };
+    let res: Value = serde_json::from_slice(&res).unwrap_or_default();
+    let field = |name: &str| res[name].as_str().map(str::to_string);
+    Ok(Credentials {
+        access_key_id: field("AccessKeyId").ok_or("instance credentials have no AccessKeyId")?,
+        secret_access_key: field("SecretAccessKey")
+            .ok_or("instance credentials have no SecretAccessKey")?,
+        session_token: field("Token"),
+    })
+}
+
+/// GET `path` from the EC2 instance metadata service, with an IMDSv2 session token.
+async fn imds(client: &Client<Connector>, path: &str) -> Result<String, String> {
+    let endpoint = var("AWS_EC2_METADATA_SERVICE_ENDPOINT").unwrap_or(IMDS_ENDPOINT.to_string());
+    let endpoint = endpoint.trim_end_matches('/');
+    let req = Request::put(format!("{}/latest/api/token", endpoint))
+        .header("x-aws-ec2-metadata-token-ttl-seconds", "60")
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    let token = match send(client, req, IMDS_TIMEOUT).await? {
+        (StatusCode::OK, token) => String::from_utf8_lossy(&token).to_string(),
+        (status, _) => return Err(format!("instance metadata token answered {}", status)),
+    };
+    let req = Request::get(format!("{}{}", endpoint, path))
+        .header("x-aws-ec2-metadata-token", token)
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    match send(client, req, IMDS_TIMEOUT).await? {
+        (StatusCode::OK, body) => Ok(String::from_utf8_lossy(&body).trim().to_string()),
+        (status, _) => Err(format!("instance metadata {} answered {}", path, status)),
+    }
+}
+
+async fn send(
+    client: &Client<Connector>,
+    req: Request<Body>,
+    timeout: Duration,
+) -> Result<(StatusCode, body::Bytes), String> {
+    let exchange = async {
+        let res = client.request(req).await?;
+        let status = res.status();
+        body::to_bytes(res.into_body()).await.map(|b| (status, b))
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// `20150830T123600Z`
+fn amz_date(t: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+/// The SigV4 `Authorization` header for a POST to `/` with `headers` (sorted, lowercase) and
+/// `body`.
+fn sign(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    now: OffsetDateTime,
+    headers: &[(&str, String)],
+    body: &str,
+) -> String {
+    let date = &amz_date(now)[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{}\n{}",
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(body))
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date(now),
+        scope,
+        hex(&Sha256::digest(canonical_request))
+    );
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = [date, region, service, "aws4_request"]
+        .iter()
+        .fold(key.into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        signed_headers,
+        hex(&hmac(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("error initializing hmac");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
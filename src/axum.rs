@@ -0,0 +1,83 @@
+//! Mount rook's hooks in an existing axum app instead of running a second server.
+//!
+//! ```no_run
+//! # async fn run(cfg: rook::config::RouteConfig, app: axum::Router) -> Result<(), String> {
+//! use std::{net::SocketAddr, sync::Arc};
+//!
+//! let rook = rook::service::RookService::new(Arc::new(cfg))?;
+//! let app = app.merge(rook::axum::router(rook));
+//! axum::Server::bind(&"0.0.0.0:8080".parse().unwrap())
+//!     .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+//!     .await
+//!     .map_err(|e| e.to_string())
+//! # }
+//! ```
+//!
+//! The router answers each hook url at its full path, including any `path_prefix`, so merge it
+//! rather than nesting it.  Serve the app with connect info so rook sees the client's address.
+use crate::{
+    pattern,
+    service::{Connection, RookService},
+};
+use ::axum::{extract::ConnectInfo, Router};
+use hyper::{Body, Request};
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// A router answering every hook url in the service's config.
+pub fn router(rook: RookService) -> Router {
+    let cfg = rook.config();
+    let urls = cfg.gh_hooks.keys().chain(cfg.rook_hooks.keys());
+    let paths: BTreeSet<String> = urls
+        .filter(|url| url.starts_with('/'))
+        .map(|url| format!("{}{}", cfg.path_prefix, mount(url)))
+        .collect();
+    paths.into_iter().fold(Router::new(), |app, path| {
+        app.route_service(&path, WithConnectInfo(rook.clone()))
+    })
+}
+
+/// Where to answer `url`.  rook matches pattern urls itself, so they're mounted as a catch-all
+/// from their first pattern segment: `/hooks/:project/deploy` at `/hooks/*rook_path`.
+fn mount(url: &str) -> String {
+    if !pattern::is_pattern(url) {
+        return url.to_string();
+    }
+    let literal: Vec<&str> = url
+        .split('/')
+        .take_while(|s| !s.starts_with([':', '*']))
+        .collect();
+    format!("{}/*rook_path", literal.join("/"))
+}
+
+/// Turns axum's connect info into the [`Connection`] rook expects.
+#[derive(Clone)]
+struct WithConnectInfo(RookService);
+
+impl Service<Request<Body>> for WithConnectInfo {
+    type Response = <RookService as Service<Request<Body>>>::Response;
+    type Error = <RookService as Service<Request<Body>>>::Error;
+    type Future = <RookService as Service<Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let remote = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0);
+        if let (Some(remote), None) = (remote, req.extensions().get::<Connection>()) {
+            req.extensions_mut().insert(Connection {
+                remote,
+                client_cn: None,
+            });
+        }
+        self.0.call(req)
+    }
+}
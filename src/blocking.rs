@@ -0,0 +1,155 @@
+//! Blocking http and https requests for [`Notifier`](crate::exec::Notifier)s.
+//!
+//! Notifiers run in the forked process that waited on a hook's command, which can't start an
+//! async runtime: tokio's signal driver is shared by every runtime in the process, and its
+//! pipe was closed with the rest of the server's descriptors.  Requests here are one at a time
+//! over std sockets, with `Connection: close`.
+use hyper::Uri;
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// for connecting, and each read and write
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// more than any status or error message needs
+const MAX_RESPONSE: u64 = 64 * 1024;
+const USER_AGENT: &str = concat!("rook/", env!("CARGO_PKG_VERSION"));
+
+#[cfg(feature = "tls")]
+type TlsConfig = std::sync::Arc<tokio_rustls::rustls::ClientConfig>;
+#[cfg(not(feature = "tls"))]
+type TlsConfig = std::convert::Infallible;
+
+/// Sends requests to the urls of one scheme.
+#[derive(Clone)]
+pub struct Client {
+    tls: Option<TlsConfig>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Client {
+    /// A client for `url`'s scheme; https trusts the CAs in `ca_file`.
+    pub fn new(url: &Uri, ca_file: &str) -> Result<Self, String> {
+        match url.scheme_str() {
+            Some("http") => Ok(Client { tls: None }),
+            Some("https") => Ok(Client {
+                tls: Some(tls_config(ca_file)?),
+            }),
+            _ => Err(format!("url '{}' must be http or https", url)),
+        }
+    }
+
+    /// Send `body` to `url` and wait for the whole response.
+    pub fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, String> {
+        let uri: Uri = url.parse().map_err(|e| format!("bad url: {}", e))?;
+        let host = uri.host().ok_or("url has no host")?;
+        let port = uri.port_u16().unwrap_or(match self.tls {
+            Some(_) => 443,
+            None => 80,
+        });
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nhost: {}\r\nuser-agent: {}\r\ncontent-length: {}\r\n\
+             connection: close\r\n",
+            method,
+            uri.path_and_query().map_or("/", |p| p.as_str()),
+            uri.authority().map_or(host, |a| a.as_str()),
+            USER_AGENT,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+        let addr = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("no address")?;
+        let tcp = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+        tcp.set_read_timeout(Some(TIMEOUT))
+            .and_then(|()| tcp.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        let response = match &self.tls {
+            None => exchange(tcp, &request),
+            #[cfg(feature = "tls")]
+            Some(config) => exchange(crate::tls::connect_blocking(config, host, tcp)?, &request),
+            #[cfg(not(feature = "tls"))]
+            Some(never) => match *never {},
+        };
+        response.map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_config(ca_file: &str) -> Result<TlsConfig, String> {
+    crate::tls::client_config(ca_file)
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_config(_: &str) -> Result<TlsConfig, String> {
+    Err("https urls need rook built with the `tls` feature".to_string())
+}
+
+/// Write `request` and read the response until the server closes the connection.
+fn exchange(mut stream: impl Read + Write, request: &[u8]) -> io::Result<Response> {
+    stream.write_all(request)?;
+    stream.flush()?;
+    let mut raw = vec![];
+    match stream.take(MAX_RESPONSE).read_to_end(&mut raw) {
+        Ok(_) => {}
+        // servers that close without a TLS close_notify, once they've answered
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !raw.is_empty() => {}
+        Err(e) => return Err(e),
+    }
+    parse(&raw).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad http response"))
+}
+
+fn parse(raw: &[u8]) -> Option<Response> {
+    let end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    let body = &raw[end + 4..];
+    let body = match chunked {
+        true => dechunk(body),
+        false => body.to_vec(),
+    };
+    Some(Response { status, body })
+}
+
+/// The body of a `chunked` response, as far as it was read.
+fn dechunk(mut raw: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    while let Some(line) = raw.windows(2).position(|w| w == b"\r\n") {
+        let size = std::str::from_utf8(&raw[..line])
+            .ok()
+            .and_then(|s| usize::from_str_radix(s.split(';').next()?.trim(), 16).ok());
+        let Some(size @ 1..) = size else {
+            break;
+        };
+        let chunk = &raw[line + 2..];
+        body.extend_from_slice(&chunk[..size.min(chunk.len())]);
+        raw = chunk.get(size + 2..).unwrap_or_default();
+    }
+    body
+}
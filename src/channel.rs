@@ -0,0 +1,58 @@
+//! Hand verified deliveries to the embedding application instead of running commands.
+//!
+//! ```no_run
+//! # async fn run(mut cfg: rook::config::RouteConfig) {
+//! let (executor, mut deliveries) = rook::channel::channel(64);
+//! cfg.set_executors(executor);
+//! tokio::spawn(rook::serve(std::sync::Arc::new(cfg)));
+//! while let Some(delivery) = deliveries.recv().await {
+//!     println!("{} delivery to {}", delivery.payload.provider(), delivery.route);
+//! }
+//! # }
+//! ```
+use crate::exec::{Executor, Invocation, Payload};
+use tokio::sync::mpsc;
+
+/// A verified delivery for one hook.  A delivery to a route with several hooks is sent once
+/// per hook that verified it.
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    /// the hook url the delivery was sent to
+    pub route: String,
+    /// the hook's `command_path`, which isn't run
+    pub command: String,
+    pub request_id: Option<String>,
+    pub payload: Payload,
+    /// the body as received
+    pub body: Vec<u8>,
+}
+
+/// Sends each verified delivery to the receiver from [`channel`].
+#[derive(Clone)]
+pub struct Channel {
+    tx: mpsc::Sender<Delivery>,
+}
+
+/// An executor and the receiving end of its deliveries.  Up to `buffer` deliveries wait to be
+/// received; past that, hooks fail to start and rook answers `500` until the receiver catches
+/// up.
+pub fn channel(buffer: usize) -> (Channel, mpsc::Receiver<Delivery>) {
+    let (tx, rx) = mpsc::channel(buffer);
+    (Channel { tx }, rx)
+}
+
+impl Executor for Channel {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let delivery = Delivery {
+            route: invocation.route.to_string(),
+            command: invocation.command.to_string(),
+            request_id: invocation.request_id.map(str::to_string),
+            payload: invocation.payload.clone(),
+            body: invocation.body.to_vec(),
+        };
+        self.tx.try_send(delivery).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "delivery channel full".to_string(),
+            mpsc::error::TrySendError::Closed(_) => "delivery channel closed".to_string(),
+        })
+    }
+}
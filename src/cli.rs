@@ -0,0 +1,363 @@
+//! Subcommands of the `rook` binary besides running the server.
+use fork::Fork;
+use rook::{
+    client::{self, Style},
+    config::{self, RouteConfig},
+    remote::{self, Check},
+    upgrade,
+    verify::Algorithm,
+    version,
+};
+use serde_json::Value;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    process,
+    time::Duration,
+};
+
+pub const USAGE: &str = "\
+usage: rook [--dry-run] [--daemon] [--pidfile PATH] [--record DIR] CONFIG_FILE
+       rook [--dry-run] [--daemon] [--pidfile PATH] [--record DIR] CONFIG_URL
+            (--config-sha256 HEX | --config-secret SECRET_FILE [--config-refresh SECONDS])
+       rook [--dry-run] [--daemon] [--pidfile PATH] [--record DIR] (consul:KEY | etcd:KEY)
+       rook send [--github] [--event NAME] [--stamp] SECRET_FILE PAYLOAD_FILE URL
+       rook secret SECRET_FILE
+       rook routes [--json] CONFIG_FILE
+       rook sign [--alg sha256|sha512|blake3] SECRET_FILE PAYLOAD_FILE
+       rook selftest [--verbose] CONFIG_FILE
+       rook replay [--run] [--verbose] CONFIG_FILE DIR
+       rook top [--interval SECONDS] ADMIN_ADDRESS
+       rook --version";
+
+/// bytes of randomness in a generated secret
+const SECRET_LEN: usize = 32;
+
+/// Flags (with their values, if they take one) and positional arguments.
+pub struct Args {
+    flags: Vec<(String, Option<String>)>,
+    pub positional: Vec<String>,
+}
+
+impl Args {
+    /// `takes_value` lists the flags followed by a value; any other `--flag` is a switch.
+    pub fn parse(args: &[String], switches: &[&str], takes_value: &[&str]) -> Result<Args, String> {
+        let mut parsed = Args {
+            flags: vec![],
+            positional: vec![],
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.positional.push(arg.clone());
+            } else if takes_value.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                parsed.flags.push((arg.clone(), Some(value.clone())));
+            } else if switches.contains(&arg.as_str()) {
+                parsed.flags.push((arg.clone(), None));
+            } else {
+                return Err(format!("unknown flag {}\n{}", arg, USAGE));
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn has(&self, flag: &str) -> bool {
+        self.flags.iter().any(|(f, _)| f == flag)
+    }
+
+    pub fn value(&self, flag: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(f, _)| f == flag)
+            .and_then(|(_, v)| v.as_deref())
+    }
+}
+
+/// The secret in `path`, trimmed the same way hook `secret_file`s are.
+fn read_secret(path: &str) -> Result<Vec<u8>, String> {
+    fs::read_to_string(path)
+        .map(|s| s.trim().as_bytes().to_vec())
+        .map_err(|e| format!("failed to read secret at '{}': {}", path, e))
+}
+
+/// `rook send`: POST a payload file to a hook, signed as GitHub or a rook sender would.
+pub async fn send(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["--github", "--stamp"], &["--event"])?;
+    let [secret, payload, url] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let secret = read_secret(secret)?;
+    let body = fs::read(payload).map_err(|e| format!("failed to read '{}': {}", payload, e))?;
+    let style = if args.has("--github") || args.has("--event") {
+        Style::Github {
+            event: args.value("--event").unwrap_or("push").to_string(),
+        }
+    } else if args.has("--stamp") {
+        Style::RookStamped
+    } else {
+        Style::Rook
+    };
+    let res = client::send(url, &secret, body, &style).await?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| format!("failed to read response: {}", e))?;
+    println!("{}", status);
+    if !body.is_empty() {
+        println!("{}", String::from_utf8_lossy(&body));
+    }
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("{} answered {}", url, status))
+    }
+}
+
+/// `rook sign`: print the signature header value for a payload, to compare against what a
+/// sender computed.
+pub fn sign(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[], &["--alg"])?;
+    let [secret, payload] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let secret = read_secret(secret)?;
+    let body = fs::read(payload).map_err(|e| format!("failed to read '{}': {}", payload, e))?;
+    let algorithm: Algorithm = args.value("--alg").unwrap_or("sha256").parse()?;
+    println!("{}", algorithm.sign(&secret, &body));
+    Ok(())
+}
+
+/// Where to fetch the config at `url` from, and how to verify it, from the serve flags.
+pub fn config_source(url: &str, args: &Args) -> Result<remote::Source, String> {
+    let check = match (args.value("--config-sha256"), args.value("--config-secret")) {
+        (Some(_), None) if args.has("--config-refresh") => {
+            return Err("--config-refresh needs --config-secret".to_string())
+        }
+        (Some(pin), None) => Check::Sha256(pin.to_string()),
+        (None, Some(secret)) => Check::Signed(read_secret(secret)?),
+        (None, None) => {
+            return Err(format!(
+                "a config url needs --config-sha256 or --config-secret\n{}",
+                USAGE
+            ))
+        }
+        (Some(_), Some(_)) => {
+            return Err("use one of --config-sha256 and --config-secret".to_string())
+        }
+    };
+    let mut source = remote::Source::new(url, check);
+    if let Some(refresh) = args.value("--config-refresh") {
+        let secs: u64 = refresh
+            .parse()
+            .ok()
+            .filter(|s| *s > 0)
+            .ok_or_else(|| format!("bad --config-refresh '{}': expected seconds", refresh))?;
+        source = source.refresh(Duration::from_secs(secs));
+    }
+    Ok(source)
+}
+
+/// `rook secret`: write a new random secret, readable only by its owner, and print it once
+/// for the sender's settings.  Never replaces an existing file.
+pub fn secret(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[], &[])?;
+    let [path] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut bytes = [0u8; SECRET_LEN];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(|e| format!("failed to read /dev/urandom: {}", e))?;
+    let secret: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut f| writeln!(f, "{}", secret))
+        .map_err(|e| format!("failed to write secret to '{}': {}", path, e))?;
+    println!("{}", secret);
+    Ok(())
+}
+
+/// `rook routes`: every hook a config sets up, as a table or as the json `/admin/routes`
+/// serves.
+pub fn routes(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["--json"], &[])?;
+    let [cfg_path] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let cfg = config::from_file(cfg_path).map_err(|e| e.to_string())?;
+    let routes = config::routes(&cfg);
+    if args.has("--json") {
+        println!("{:#}", routes);
+        return Ok(());
+    }
+    let mut rows = vec![[
+        "URL",
+        "TYPE",
+        "ENABLED",
+        "REPO",
+        "HOST",
+        "METHODS",
+        "ACCESS",
+        "COMMAND",
+        "DESCRIPTION",
+    ]
+    .map(String::from)];
+    for route in routes.as_array().into_iter().flatten() {
+        rows.push([
+            text(&route["url"]),
+            text(&route["type"]),
+            text(&route["enabled"]),
+            text(&route["repo"]),
+            text(&route["host"]),
+            text(&route["methods"]),
+            access(route),
+            text(&route["command"]),
+            text(&route["description"]),
+        ]);
+    }
+    for line in table(&rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// `rows` (a header, usually) as lines with each column padded to its widest cell.
+pub fn table<const N: usize>(rows: &[[String; N]]) -> Vec<String> {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect()
+}
+
+/// A json field as a table cell: `-` when unset, lists joined with commas.
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.is_empty() => "-".to_string(),
+        Value::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+fn access(route: &Value) -> String {
+    let mut access = vec![];
+    if route["require_client_cert"] == Value::Bool(true) {
+        access.push("client cert".to_string());
+    }
+    for (field, label) in [("allow_cidrs", "allow"), ("deny_cidrs", "deny")] {
+        if route[field].as_array().is_some_and(|c| !c.is_empty()) {
+            access.push(format!("{} {}", label, text(&route[field])));
+        }
+    }
+    if access.is_empty() {
+        "any".to_string()
+    } else {
+        access.join("; ")
+    }
+}
+
+/// Detach from the terminal: fork twice with a new session in between, then point stdio at
+/// `/dev/null`.  The working directory is kept so relative paths in the config still work.
+pub fn daemonize(cfg: &RouteConfig) -> Result<(), String> {
+    if cfg.log_file.is_none() && cfg.syslog.is_none() && !cfg.journald {
+        eprintln!("warning: --daemon without log_file, syslog, or journald discards logs");
+    }
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| format!("failed to open /dev/null: {}", e))?;
+    match fork::daemon(true, true) {
+        Ok(Fork::Child) => {}
+        // the session leader between the two forks
+        Ok(Fork::Parent(_)) => process::exit(0),
+        Err(_) => return Err("failed to daemonize".to_string()),
+    }
+    for fd in 0..=2 {
+        // SAFETY: both descriptors are open; dup2 only replaces `fd`
+        unsafe { libc::dup2(null.as_raw_fd(), fd) };
+    }
+    Ok(())
+}
+
+/// A pidfile written once the server starts and removed when it stops.
+pub struct Pidfile {
+    path: String,
+    written: bool,
+}
+
+impl Pidfile {
+    /// Fails when the file names a process that's still running, other than the rook this one
+    /// is taking over from in an upgrade; stale files are replaced.
+    pub fn check(path: &str) -> Result<Pidfile, String> {
+        match fs::read_to_string(path) {
+            Ok(pid) => {
+                let pid: i32 = pid.trim().parse().unwrap_or(0);
+                let previous = upgrade::previous_pid() == Some(pid);
+                // SAFETY: signal 0 only checks whether the process exists
+                if pid > 0 && !previous && unsafe { libc::kill(pid, 0) } == 0 {
+                    return Err(format!(
+                        "rook is already running as pid {} ('{}')",
+                        pid, path
+                    ));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("failed to read pidfile '{}': {}", path, e)),
+        }
+        Ok(Pidfile {
+            path: path.to_string(),
+            written: false,
+        })
+    }
+
+    pub fn write(&mut self) -> Result<(), String> {
+        fs::write(&self.path, format!("{}\n", process::id()))
+            .map_err(|e| format!("failed to write pidfile '{}': {}", self.path, e))?;
+        self.written = true;
+        Ok(())
+    }
+}
+
+impl Drop for Pidfile {
+    /// Left alone once an upgraded rook has written its own pid.
+    fn drop(&mut self) {
+        let ours =
+            fs::read_to_string(&self.path).is_ok_and(|pid| pid.trim() == process::id().to_string());
+        if self.written && ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `rook --version`
+pub fn version() {
+    println!(
+        "rook {} ({} {})",
+        version::VERSION,
+        version::COMMIT,
+        version::BUILD_TIME
+    );
+    println!("features: {}", version::features().join(", "));
+}
@@ -0,0 +1,189 @@
+//! Send signed deliveries to a rook url, the way GitHub or another service would.
+//!
+//! Services that only trigger hooks can depend on rook with `default-features = false,
+//! features = ["client"]`, which leaves out the server.  A [`Sender`] signs and sends with one
+//! call, from async code or, with [`Sender::send_blocking`], from anywhere else:
+//!
+//! ```no_run
+//! # fn run() -> Result<(), String> {
+//! use rook::client::Sender;
+//!
+//! let build = Sender::new("http://127.0.0.1:9000/build", "hunter2").stamped();
+//! let res = build.send_blocking("build --release")?;
+//! assert!(res.status().is_success());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`send`] is the same for a single delivery:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use rook::client::{self, Style};
+//!
+//! let res = client::send(
+//!     "http://127.0.0.1:9000/build",
+//!     b"hunter2",
+//!     b"build --release".to_vec(),
+//!     &Style::Rook,
+//! )
+//! .await?;
+//! assert!(res.status().is_success());
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    github, id,
+    verify::{
+        self, GITHUB_SIGNATURE_HEADER, ROOK_NONCE_HEADER, ROOK_SIGNATURE_HEADER,
+        ROOK_TIMESTAMP_HEADER,
+    },
+};
+use hyper::{header, Body, Client, Method, Request, Response, Uri};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "tls")]
+type Connector = crate::tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = hyper::client::HttpConnector;
+
+/// CAs trusted for https urls
+#[cfg(feature = "tls")]
+const CA_FILE: &str = "/etc/ssl/certs/ca-certificates.crt";
+const USER_AGENT: &str = concat!("rook/", env!("CARGO_PKG_VERSION"));
+
+/// Which kind of hook the delivery is for.
+pub enum Style {
+    /// signed in `x-hub-signature-256`, with GitHub's event and delivery headers
+    Github { event: String },
+    /// signed in `x-rook-signature-256`
+    Rook,
+    /// signed in `x-rook-signature-256` with a fresh `x-rook-timestamp` and `x-rook-nonce`, as
+    /// hooks with a `replay_window` require
+    RookStamped,
+}
+
+/// Signs and sends deliveries to one hook url.
+pub struct Sender {
+    url: String,
+    secret: Vec<u8>,
+    style: Style,
+}
+
+impl Sender {
+    /// Deliveries to `url` signed with `secret`, for a `"rook"` hook.
+    pub fn new(url: &str, secret: impl Into<Vec<u8>>) -> Self {
+        Sender {
+            url: url.to_string(),
+            secret: secret.into(),
+            style: Style::Rook,
+        }
+    }
+
+    /// Stamp each delivery with a timestamp and nonce, as hooks with a `replay_window`
+    /// require.
+    pub fn stamped(mut self) -> Self {
+        self.style = Style::RookStamped;
+        self
+    }
+
+    /// Send deliveries as GitHub would for `event`, for a `"github"` hook.
+    pub fn github(mut self, event: &str) -> Self {
+        self.style = Style::Github {
+            event: event.to_string(),
+        };
+        self
+    }
+
+    /// POST `body`, signed.  Returns rook's response, whatever its status.
+    pub async fn send(&self, body: impl Into<Vec<u8>>) -> Result<Response<Body>, String> {
+        send(&self.url, &self.secret, body.into(), &self.style).await
+    }
+
+    /// [`send`](Sender::send) on a runtime of its own, for code that isn't async, with the
+    /// response body read.  Don't call it from inside an async runtime.
+    pub fn send_blocking(&self, body: impl Into<Vec<u8>>) -> Result<Response<Vec<u8>>, String> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("failed to start runtime: {}", e))?;
+        runtime.block_on(async {
+            let (parts, body) = self.send(body).await?.into_parts();
+            let body = hyper::body::to_bytes(body)
+                .await
+                .map_err(|e| format!("failed to read the response from '{}': {}", self.url, e))?;
+            Ok(Response::from_parts(parts, body.to_vec()))
+        })
+    }
+}
+
+/// POST `body` to `url`, signed with `secret`.  Returns rook's response, whatever its status.
+pub async fn send(
+    url: &str,
+    secret: &[u8],
+    body: Vec<u8>,
+    style: &Style,
+) -> Result<Response<Body>, String> {
+    let req = request(url, secret, body, style)?;
+    let client: Client<_, Body> = Client::builder().build(connector(req.uri())?);
+    client
+        .request(req)
+        .await
+        .map_err(|e| format!("failed to send to '{}': {}", url, e))
+}
+
+/// The signed POST [`send`] makes, without sending it.  `url` can be just a path, for a
+/// request handed straight to rook's `RookService`.
+pub fn request(
+    url: &str,
+    secret: &[u8],
+    body: Vec<u8>,
+    style: &Style,
+) -> Result<Request<Body>, String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| format!("bad url '{}': {}", url, e))?;
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::USER_AGENT, USER_AGENT)
+        // rook requires a length, which hyper's client would add but a service never sees
+        .header(header::CONTENT_LENGTH, body.len());
+    req = match style {
+        Style::Github { event } => req
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(GITHUB_SIGNATURE_HEADER, verify::sign(secret, &body))
+            .header(github::EVENT_HEADER, event.as_str())
+            .header("x-github-delivery", format!("{:016x}", id::random_u64())),
+        Style::Rook => req
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(ROOK_SIGNATURE_HEADER, verify::sign(secret, &body)),
+        Style::RookStamped => {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let nonce = format!("{:016x}", id::random_u64());
+            req.header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .header(
+                    ROOK_SIGNATURE_HEADER,
+                    verify::sign_stamped(secret, timestamp, &nonce, &body),
+                )
+                .header(ROOK_TIMESTAMP_HEADER, timestamp)
+                .header(ROOK_NONCE_HEADER, nonce)
+        }
+    };
+    req.body(Body::from(body)).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "tls")]
+fn connector(_: &Uri) -> Result<Connector, String> {
+    crate::tls::https_connector(CA_FILE)
+}
+
+#[cfg(not(feature = "tls"))]
+fn connector(uri: &Uri) -> Result<Connector, String> {
+    if uri.scheme_str() != Some("http") {
+        return Err("https urls need rook built with the `tls` feature".to_string());
+    }
+    Ok(Connector::new())
+}
@@ -0,0 +1,70 @@
+//! Checks that a hook's `command_path` can be run, so a typo'd path is found when the config
+//! loads rather than when the first delivery's command fails to start.
+//!
+//! A path without a `/` is looked up on `PATH`, as the command will be.  A relative path with
+//! one is checked against the working directory, which is where it's run from too; `strict`
+//! rejects those, since a service manager or a later `cd` can change what they point at.  A
+//! script's `#!` interpreter is checked too, when the script can be read and names one by
+//! absolute path.
+use std::{
+    env,
+    fs::{self, File},
+    io::Read,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// how much of a script is read for its `#!` line
+const SHEBANG_MAX: usize = 256;
+
+/// Why `command` can't be run, if it can't.
+pub(crate) fn check(command: &str, strict: bool) -> Result<(), String> {
+    if strict && command.contains('/') && !command.starts_with('/') {
+        return Err("is relative to the working directory".to_string());
+    }
+    let path = if command.contains('/') {
+        PathBuf::from(command)
+    } else {
+        on_path(command).ok_or_else(|| "isn't on PATH".to_string())?
+    };
+    executable(&path)?;
+    if let Some(interpreter) = interpreter(&path) {
+        executable(Path::new(&interpreter))
+            .map_err(|e| format!("has interpreter '{}', which {}", interpreter, e))?;
+    }
+    Ok(())
+}
+
+fn executable(path: &Path) -> Result<(), String> {
+    let meta = fs::metadata(path).map_err(|_| "doesn't exist".to_string())?;
+    if meta.is_dir() {
+        return Err("is a directory".to_string());
+    }
+    if meta.permissions().mode() & 0o111 == 0 {
+        return Err("isn't executable".to_string());
+    }
+    Ok(())
+}
+
+/// The first executable file named `command` in a `PATH` directory.
+fn on_path(command: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|path| executable(path).is_ok())
+}
+
+/// The absolute interpreter path on `path`'s `#!` line, if it has one and can be read.
+fn interpreter(path: &Path) -> Option<String> {
+    let mut head = Vec::with_capacity(SHEBANG_MAX);
+    File::open(path)
+        .ok()?
+        .take(SHEBANG_MAX as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+    let line = head.strip_prefix(b"#!")?.split(|b| *b == b'\n').next()?;
+    let interpreter = String::from_utf8_lossy(line)
+        .split_whitespace()
+        .next()?
+        .to_string();
+    interpreter.starts_with('/').then_some(interpreter)
+}
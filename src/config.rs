@@ -2,117 +2,1755 @@ use serde::{
     de::{self, Deserializer},
     Deserialize,
 };
-use std::{collections::HashMap, fmt::Display, fs, net::{SocketAddr, IpAddr}};
-use toml;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    archive::Archive,
+    cidr::{AccessList, Cidr},
+    command,
+    exec::{DryRun, Executor, Fanout, Lock, Notifier, Spawn, SpawnLocked, SpawnNotify},
+    filter::Filter,
+    forward::Forward,
+    glob, history,
+    hours::{Hours, OutsideHours},
+    lock,
+    logging::{self, AccessTemplate, Alert, LogFilter, LogFormat},
+    maintenance::{self, Maintenance},
+    pattern,
+    ratelimit::RateLimit,
+    remote,
+    render::{Renderer, ResponseFormat, Success},
+    router::{self, MAX_BODY_LENGTH},
+    spill::Spill,
+    topic,
+    verify::{Algorithm, Bearer, HmacSha256, Verifier, VerifyError},
+};
+use hyper::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode, Uri,
+};
+use serde_json::{json, Value};
+
+pub use crate::events::EventsConfig;
 
 pub struct RouteConfig {
     pub socket: SocketAddr,
+    pub log_format: LogFormat,
+    /// custom access line; replaces the `log_format` access lines
+    pub log_template: Option<AccessTemplate>,
+    pub log_level: Option<LogFilter>,
+    /// write logs here instead of stdout
+    pub log_file: Option<LogFileConfig>,
+    pub syslog: Option<SyslogConfig>,
+    pub journald: bool,
+    /// signature failures go here instead of the main log when set
+    pub audit_log: Option<LogFileConfig>,
+    /// export request traces with OTLP when set
+    pub otlp: Option<OtlpConfig>,
+    /// emit statsd metrics when set
+    pub statsd: Option<StatsdConfig>,
+    /// write hook start/finish events when set
+    pub events: Option<EventsConfig>,
+    /// report panics and hook failures to Sentry when set
+    pub sentry: Option<SentryConfig>,
+    pub access: AccessList,
+    pub trusted_proxies: Vec<Cidr>,
+    pub proxy_protocol: bool,
+    pub header_timeout: Duration,
+    pub body_timeout: Duration,
+    /// open connections, including those still in the PROXY/TLS preamble
+    pub max_connections: usize,
+    /// requests being routed, read, or dispatched at once
+    pub max_requests: usize,
+    /// hook commands running at once before new requests are shed; None never sheds
+    pub max_jobs: Option<usize>,
+    /// `Retry-After` seconds on shed requests
+    pub shed_retry_after: u64,
+    /// added to every response that doesn't already have them, including 404s
+    pub response_headers: HeaderMap,
+    /// stripped from request paths before route lookup; never ends with `/`
+    pub path_prefix: String,
+    pub tls: Option<TlsConfig>,
+    /// separate listener for the admin endpoints; disabled unless `[admin]` is configured
+    pub admin: Option<SocketAddr>,
+    /// persist deliveries in sqlite; disabled unless `[history]` is configured
+    pub history: Option<HistoryConfig>,
+    /// insert deliveries and job outcomes into postgres; disabled unless `[postgres]` is
+    /// configured
+    pub postgres: Option<PostgresConfig>,
+    /// log what each hook would run instead of running it
+    pub dry_run: bool,
+    /// every request and its status is saved in this directory as a [`crate::fixture`]; only
+    /// set by `--record`
+    pub record: Option<String>,
+    /// answers requests that match no hook url; 404 unless `[fallback]` is configured
+    pub fallback: Option<Fallback>,
     pub gh_hooks: HashMap<String, Vec<GithubHook>>,
     pub rook_hooks: HashMap<String, Vec<RookHook>>,
+    /// the broker `"mqtt"` hooks subscribe through; required by them
+    pub mqtt: Option<MqttConfig>,
+    pub mqtt_hooks: Vec<MqttHook>,
+    pub watch_hooks: Vec<WatchHook>,
+    /// the file this config was loaded from, which reloads read again; None for configs built
+    /// in code or from a string
+    pub config_path: Option<String>,
+    /// where a config fetched with [`from_url`] came from; reloads fetch it again
+    pub remote: Option<Arc<remote::Source>>,
+    /// the config's version; a signed remote config needs one, and only loads over a higher one
+    pub config_serial: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub struct TlsConfig {
+    pub cert_file: String,
+    pub key_file: String,
+    /// when set, clients may present a certificate signed by this CA
+    pub client_ca_file: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub struct HistoryConfig {
+    /// sqlite database file, created if missing
+    pub path: String,
+    /// deliveries older than this are deleted
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u64,
+    /// keep the full request body instead of only its sha256
+    #[serde(default)]
+    pub store_body: bool,
+}
+
+/// `[postgres]`: the database verified deliveries and job outcomes are inserted into.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub struct PostgresConfig {
+    /// `postgres://user@host:port/database`
+    pub url: String,
+    /// the password is read from this file
+    pub password_file: Option<String>,
+    #[serde(default = "default_postgres_deliveries_table")]
+    pub deliveries_table: String,
+    #[serde(default = "default_postgres_jobs_table")]
+    pub jobs_table: String,
+    /// keep the full request body instead of only its sha256
+    #[serde(default)]
+    pub store_body: bool,
+    #[serde(default)]
+    pub tls: bool,
+    /// trusted CAs with `tls`
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// Answers requests that match no hook url.  The command runs for every such request, without
+/// any signature check.
+pub struct Fallback {
+    pub status: StatusCode,
+    pub content_type: HeaderValue,
+    pub body: Vec<u8>,
+    pub command: Option<String>,
+    pub executor: Box<dyn Executor>,
+}
+
+#[derive(Deserialize)]
+pub struct LogFileConfig {
+    pub path: String,
+    /// rotate once the file reaches this size
+    pub max_size_mb: Option<u64>,
+    /// rotate at the start of every day or hour (UTC)
+    pub rotate: Option<Rotation>,
+    /// rotated files to keep, `<path>.1` being the newest
+    #[serde(default = "default_log_keep")]
+    pub keep: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Daily,
+    Hourly,
+}
+
+#[derive(Deserialize)]
+pub struct SyslogConfig {
+    /// unix socket path, `udp://host:port`, or `tcp://host:port`
+    #[serde(default = "default_syslog_address")]
+    pub address: String,
+    #[serde(default)]
+    pub facility: Facility,
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct OtlpConfig {
+    /// collector base url; spans are posted to `<endpoint>/v1/traces`
+    pub endpoint: String,
+    #[serde(default = "default_app_name")]
+    pub service_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct StatsdConfig {
+    /// `host:port` of the statsd or dogstatsd agent
+    pub address: String,
+    /// prepended to every metric name, eg. `rook.requests`
+    #[serde(default = "default_app_name")]
+    pub prefix: String,
+    /// sent with every metric
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// send tags; plain statsd servers don't understand them
+    #[serde(default = "default_true")]
+    pub dogstatsd: bool,
+}
+
+/// `[vault]`: where `secret_vault` secrets come from.  Unset fields fall back to `VAULT_ADDR`,
+/// `VAULT_NAMESPACE`, and `VAULT_TOKEN`.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(not(feature = "vault"), allow(dead_code))]
+pub struct VaultConfig {
+    /// eg. `https://vault.example.com:8200`
+    pub address: Option<String>,
+    /// Vault Enterprise namespace
+    pub namespace: Option<String>,
+    /// token auth: the token is read from this file
+    pub token_file: Option<String>,
+    /// AppRole auth, with `secret_id_file`; takes precedence over `token_file`
+    pub role_id: Option<String>,
+    pub secret_id_file: Option<String>,
+    #[serde(default = "default_approle_mount")]
+    pub approle_mount: String,
+    /// trusted CAs for https addresses
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// `[aws]`: how `secret_secretsmanager` and `secret_ssm` secrets are read.  Credentials are
+/// always ambient: the environment, the ECS task role, or the EC2 instance role.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(not(feature = "aws"), allow(dead_code))]
+pub struct AwsConfig {
+    /// for names that aren't ARNs; `AWS_REGION`, then the instance's own region, when unset
+    pub region: Option<String>,
+    /// trusted CAs for the AWS endpoints
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// `[age]`: the identity that decrypts `secret_age` secrets.
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "age"), allow(dead_code))]
+pub struct AgeConfig {
+    /// an `age-keygen` identity file; `ROOK_AGE_IDENTITY_FILE` when unset
+    pub identity_file: Option<String>,
+}
+
+/// `[nats]`: the server hooks with `executor = "nats"` publish to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "nats"), allow(dead_code))]
+pub struct NatsConfig {
+    /// `nats://host:port`
+    #[serde(default = "default_nats_url")]
+    pub url: String,
+    /// user auth, with `password_file`
+    pub user: Option<String>,
+    pub password_file: Option<String>,
+    /// token auth: the token is read from this file
+    pub token_file: Option<String>,
+}
+
+/// `[redis]`: the server hooks with `executor = "redis"` push to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+pub struct RedisConfig {
+    /// `redis://host:port/db`
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+    /// ACL user, with `password_file`
+    pub user: Option<String>,
+    /// the password is read from this file
+    pub password_file: Option<String>,
+}
+
+/// `[kafka]`: the cluster hooks with `executor = "kafka"` produce to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct KafkaConfig {
+    /// `host:port` of the brokers to bootstrap from
+    pub brokers: Vec<String>,
+    /// SASL auth, with `password_file`
+    pub user: Option<String>,
+    pub password_file: Option<String>,
+    /// `plain`, `scram-sha-256`, or `scram-sha-512`
+    #[serde(default = "default_sasl_mechanism")]
+    pub sasl_mechanism: String,
+}
+
+/// `[mqtt]`: the broker `"mqtt"` hooks subscribe through.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct MqttConfig {
+    /// `mqtt://host:port`
+    #[serde(default = "default_mqtt_url")]
+    pub url: String,
+    #[serde(default = "default_app_name")]
+    pub client_id: String,
+    /// with `password_file`
+    pub user: Option<String>,
+    pub password_file: Option<String>,
+    /// seconds between pings on an idle connection
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u16,
+}
+
+/// `[smtp]`: the mail server `notify_email` sends through.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "smtp"), allow(dead_code))]
+pub struct SmtpConfig {
+    /// `smtp://host:port`
+    #[serde(default = "default_smtp_url")]
+    pub url: String,
+    /// the sender address
+    pub from: String,
+    /// with `password_file`, for `AUTH PLAIN`
+    pub user: Option<String>,
+    pub password_file: Option<String>,
+}
+
+/// `[matrix]`: the room `notify_matrix` posts to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "matrix"), allow(dead_code))]
+pub struct MatrixConfig {
+    /// eg. `https://matrix.example.com`
+    pub homeserver: String,
+    /// the access token of the account rook posts as
+    pub access_token_file: String,
+    /// eg. `!abcdefg:example.com`; the account must have joined it
+    pub room_id: String,
+    /// trusted CAs for https homeservers
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// `[telegram]`: the bot and chat that hooks report to.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "telegram"), allow(dead_code))]
+pub struct TelegramConfig {
+    /// the token @BotFather gave the bot
+    pub bot_token_file: String,
+    /// a chat the bot can post in: its numeric id, or `@channelname`
+    #[serde(deserialize_with = "deserialize_chat_id")]
+    pub chat_id: String,
+    /// what every hook reports unless it sets `notify_telegram`
+    #[serde(default)]
+    pub notify: Vec<TelegramEvent>,
+    /// the Bot API server; only set for a self-hosted one
+    #[serde(default = "default_telegram_api_url")]
+    pub api_url: String,
+    /// trusted CAs for an https `api_url`
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// `[lock]`: how hooks with a `lock` name keep other rook instances from running them at the
+/// same time.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockConfig {
+    /// lock files go here, on storage every instance shares
+    pub dir: Option<String>,
+    /// take locks in the `[redis]` server instead of `dir`
+    #[serde(default)]
+    pub redis: bool,
+    /// seconds a command waits for its lock before it's given up on
+    #[serde(default = "default_lock_wait")]
+    pub wait: u64,
+    /// seconds before a redis lock expires, should the instance holding it die
+    #[serde(default = "default_lock_ttl")]
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub ttl: u64,
+}
+
+/// What a hook reports to Telegram.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TelegramEvent {
+    /// its command exited 0
+    Success,
+    /// its command exited nonzero, was killed by a signal, or couldn't start
+    Failure,
+    /// a delivery for it was turned away for its signature
+    Signature,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub struct SentryConfig {
+    pub dsn: String,
+    /// eg. `production`; sent with every event
+    pub environment: Option<String>,
+    /// trusted CAs for https dsns
+    #[serde(default = "default_ca_file")]
+    pub ca_file: String,
+}
+
+/// syslog facility codes from RFC 5424
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    User = 1,
+    #[default]
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
 }
 
 pub struct GithubHook {
+    /// `owner/name`, which may use `*` and `?` wildcards
     pub repo: String,
+    /// when set, only pushes to a ref one of these matches start the hook
+    pub refs: Vec<String>,
+    /// pushes to a ref one of these matches never start the hook
+    pub exclude_refs: Vec<String>,
+    /// repos `repo` matches that the hook still ignores
+    pub exclude_repos: Vec<String>,
     pub command: String,
-    pub secret: Vec<u8>,
+    pub verifier: Box<dyn Verifier>,
+    pub executor: Box<dyn Executor>,
+    pub options: HookOptions,
 }
 
 pub struct RookHook {
     pub command: String,
-    pub secret: Vec<u8>,
+    /// with `format = "json"`, each `ROOK_JSON_<NAME>` and the json pointer it's read from;
+    /// None for plain text bodies
+    pub json: Option<Vec<(String, String)>>,
+    /// `format = "binary"`: bodies needn't be utf-8, and commands read them from
+    /// `ROOK_INPUT_FILE` or stdin
+    pub binary: bool,
+    pub verifier: Box<dyn Verifier>,
+    pub executor: Box<dyn Executor>,
+    pub options: HookOptions,
+}
+
+/// Runs a command for messages an MQTT broker delivers on `topic`, a topic filter.
+pub struct MqttHook {
+    pub topic: String,
+    pub command: String,
+    /// messages start with a signature line; false for `trust = "topic"`
+    pub signed: bool,
+    /// checks the signature line, or accepts every message when unsigned
+    pub verifier: Box<dyn Verifier>,
+    pub executor: Box<dyn Executor>,
+    /// only the filters and rate limit apply
+    pub options: HookOptions,
+}
+
+/// Runs a command when files matching `watch`, a [`crate::glob`] pattern, appear.
+pub struct WatchHook {
+    pub watch: String,
+    pub command: String,
+    pub executor: Box<dyn Executor>,
+    /// only the filters and rate limit apply
+    pub options: HookOptions,
+}
+
+/// Accepts every delivery, for hooks whose source vouches for it: an `"mqtt"` hook with
+/// `trust = "topic"` relies on the broker's ACLs, and a `"watch"` hook on who can write to its
+/// directory.
+struct Trusted;
+
+impl Verifier for Trusted {
+    fn verify(&self, _: &HeaderMap<HeaderValue>, _: &[u8]) -> Result<(), VerifyError> {
+        Ok(())
+    }
+}
+
+/// Access to the options, signature check, and executor shared by every hook type.
+pub trait Hook {
+    fn options(&self) -> &HookOptions;
+    fn verifier(&self) -> &dyn Verifier;
+    fn executor(&self) -> &dyn Executor;
+}
+
+impl GithubHook {
+    /// Whether the hook listens to `repo`, an `owner/name`.
+    pub fn matches_repo(&self, repo: &str) -> bool {
+        glob::matches_text(&self.repo, repo)
+            && !self
+                .exclude_repos
+                .iter()
+                .any(|p| glob::matches_text(p, repo))
+    }
+
+    /// Whether a push to `reference` runs the hook.  Patterns match the whole ref
+    /// (`refs/heads/main`) or the branch or tag name alone (`main`).
+    pub fn matches_ref(&self, reference: &str) -> bool {
+        let name = reference
+            .strip_prefix("refs/heads/")
+            .or_else(|| reference.strip_prefix("refs/tags/"));
+        let matches = |p: &String| {
+            glob::matches_text(p, reference) || name.is_some_and(|n| glob::matches_text(p, n))
+        };
+        (self.refs.is_empty() || self.refs.iter().any(matches))
+            && !self.exclude_refs.iter().any(matches)
+    }
+}
+
+impl Hook for GithubHook {
+    fn options(&self) -> &HookOptions {
+        &self.options
+    }
+
+    fn verifier(&self) -> &dyn Verifier {
+        self.verifier.as_ref()
+    }
+
+    fn executor(&self) -> &dyn Executor {
+        self.executor.as_ref()
+    }
+}
+
+impl Hook for MqttHook {
+    fn options(&self) -> &HookOptions {
+        &self.options
+    }
+
+    fn verifier(&self) -> &dyn Verifier {
+        self.verifier.as_ref()
+    }
+
+    fn executor(&self) -> &dyn Executor {
+        self.executor.as_ref()
+    }
+}
+
+impl Hook for WatchHook {
+    fn options(&self) -> &HookOptions {
+        &self.options
+    }
+
+    fn verifier(&self) -> &dyn Verifier {
+        &Trusted
+    }
+
+    fn executor(&self) -> &dyn Executor {
+        self.executor.as_ref()
+    }
+}
+
+impl Hook for RookHook {
+    fn options(&self) -> &HookOptions {
+        &self.options
+    }
+
+    fn verifier(&self) -> &dyn Verifier {
+        self.verifier.as_ref()
+    }
+
+    fn executor(&self) -> &dyn Executor {
+        self.executor.as_ref()
+    }
+}
+
+/// Request constraints shared by every hook type.
+pub struct HookOptions {
+    /// what the hook is for and who owns it; shown by `rook routes` and logged when it starts
+    pub description: Option<String>,
+    /// lowercase, without port; None matches any host
+    pub host: Option<String>,
+    /// query parameters the request must carry with these values; empty matches any query
+    pub query: Vec<(String, String)>,
+    pub access: AccessList,
+    pub methods: Vec<Method>,
+    /// lowercase media types, or `type/*`; empty accepts anything
+    pub content_types: Vec<String>,
+    /// lowercase names of the request headers commands see as `ROOK_HEADER_<NAME>`
+    pub pass_headers: Vec<String>,
+    /// set on the url's responses, replacing global `response_headers` of the same name
+    pub response_headers: HeaderMap,
+    pub require_client_cert: bool,
+    /// run in order on verified deliveries; see [`crate::filter`]
+    pub filters: Vec<Arc<dyn Filter>>,
+    /// shapes the response for the hook's url; when hooks share a url the first one's is used
+    pub renderer: Arc<dyn Renderer>,
+    /// how often verified deliveries may start the hook
+    pub rate_limit: Option<RateLimit>,
+    /// re-delivers the payload once the hook starts
+    pub forward: Option<Forward>,
+    /// told when every hook on the url rejects a delivery's signature
+    pub alerts: Vec<Arc<dyn Alert>>,
+    /// keeps a copy of each verified delivery
+    pub archive: Option<Arc<Archive>>,
+    /// writes large bodies to disk instead of holding them; hooks on a url share one
+    pub spill: Option<Spill>,
+    /// how the url answers while it's paused; hooks on a url share these
+    pub maintenance: Maintenance,
+    /// when the url's commands may run; hooks on a url share it
+    pub allowed_hours: Option<Hours>,
+    pub outside_hours: OutsideHours,
+}
+
+impl RouteConfig {
+    /// Run every hook with `executor`, such as a [`crate::channel::Channel`], instead of
+    /// spawning its command.
+    pub fn set_executors<E: Executor + Clone + 'static>(&mut self, executor: E) {
+        for hook in self.gh_hooks.values_mut().flatten() {
+            hook.executor = Box::new(executor.clone());
+        }
+        for hook in self.rook_hooks.values_mut().flatten() {
+            hook.executor = Box::new(executor.clone());
+        }
+        for hook in &mut self.mqtt_hooks {
+            hook.executor = Box::new(executor.clone());
+        }
+        for hook in &mut self.watch_hooks {
+            hook.executor = Box::new(executor.clone());
+        }
+        if let Some(fallback) = &mut self.fallback {
+            fallback.executor = Box::new(executor);
+        }
+    }
+
+    /// Route and verify deliveries as usual, but only log what each hook would run, as
+    /// `dry_run = true` does.
+    pub fn set_dry_run(&mut self) {
+        self.dry_run = true;
+        self.set_executors(DryRun);
+        let gh = self.gh_hooks.values_mut().flatten().map(|h| &mut h.options);
+        let rook = self
+            .rook_hooks
+            .values_mut()
+            .flatten()
+            .map(|h| &mut h.options);
+        for options in gh.chain(rook) {
+            options.forward = options.forward.take().map(Forward::dry_run);
+        }
+    }
+
+    /// Answer requests for every hook's url with `renderer`; see [`crate::render`].
+    pub fn set_renderer(&mut self, renderer: impl Renderer + 'static) {
+        let renderer: Arc<dyn Renderer> = Arc::new(renderer);
+        let gh = self.gh_hooks.values_mut().flatten().map(|h| &mut h.options);
+        let rook = self
+            .rook_hooks
+            .values_mut()
+            .flatten()
+            .map(|h| &mut h.options);
+        for options in gh.chain(rook) {
+            options.renderer = renderer.clone();
+        }
+    }
+
+    /// Build a config in code rather than toml.  Starts from the same defaults as an empty
+    /// config file, listening on 127.0.0.1:9000 with no hooks.
+    ///
+    /// ```
+    /// let cfg = rook::config::RouteConfig::builder()
+    ///     .socket("0.0.0.0:9000".parse().unwrap())
+    ///     .github_hook("/deploy", "me/site", "hunter2", "/opt/deploy.sh")
+    ///     .rook_hook("/build", "hunter2", "/opt/build.sh")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(cfg.gh_hooks["/deploy"].len(), 1);
+    /// ```
+    pub fn builder() -> RouteConfigBuilder {
+        RouteConfigBuilder {
+            raw: _RookConfig {
+                addr: IpAddr::from([127, 0, 0, 1]),
+                port: 9000,
+                log_format: LogFormat::default(),
+                log_template: None,
+                log_level: None,
+                log_file: None,
+                syslog: None,
+                journald: false,
+                audit_log: None,
+                otlp: None,
+                statsd: None,
+                events: None,
+                sentry: None,
+                allow_cidrs: vec![],
+                deny_cidrs: vec![],
+                trusted_proxies: vec![],
+                proxy_protocol: false,
+                header_timeout: default_header_timeout(),
+                body_timeout: default_body_timeout(),
+                max_connections: default_max_connections(),
+                max_requests: default_max_requests(),
+                max_jobs: None,
+                shed_retry_after: default_shed_retry_after(),
+                response_headers: BTreeMap::new(),
+                path_prefix: String::new(),
+                tls: None,
+                admin: None,
+                history: None,
+                postgres: None,
+                dry_run: false,
+                strict_commands: false,
+                config_serial: None,
+                vault: None,
+                aws: None,
+                age: None,
+                nats: None,
+                redis: None,
+                kafka: None,
+                mqtt: None,
+                smtp: None,
+                matrix: None,
+                telegram: None,
+                lock: None,
+                fallback: None,
+                hooks: vec![],
+            },
+        }
+    }
+}
+
+/// Builds a [`RouteConfig`] in code; see [`RouteConfig::builder`].  [`build`] applies the same
+/// checks as loading a config file.
+///
+/// [`build`]: RouteConfigBuilder::build
+pub struct RouteConfigBuilder {
+    raw: _RookConfig,
+}
+
+impl RouteConfigBuilder {
+    pub fn socket(mut self, socket: SocketAddr) -> Self {
+        self.raw.addr = socket.ip();
+        self.raw.port = socket.port();
+        self
+    }
+
+    /// Add a `"github"` hook running `command` for pushes to `repo` signed with `secret`.
+    pub fn github_hook(
+        mut self,
+        url: &str,
+        repo: &str,
+        secret: impl Into<Vec<u8>>,
+        command: &str,
+    ) -> Self {
+        self.raw.hooks.push(_HookConfig::_GithubHook {
+            url: url.to_string(),
+            secret: _Secret::value(secret),
+            command: command.to_string(),
+            repo: repo.to_string(),
+            refs: vec![],
+            exclude_refs: vec![],
+            exclude_repos: vec![],
+            options: _HookOptions::default(),
+        });
+        self
+    }
+
+    /// Add a `"rook"` hook running `command` for payloads signed with `secret`.
+    pub fn rook_hook(mut self, url: &str, secret: impl Into<Vec<u8>>, command: &str) -> Self {
+        self.raw.hooks.push(_HookConfig::_RookHook {
+            url: url.to_string(),
+            secret: _Secret::value(secret),
+            command: command.to_string(),
+            replay_window: None,
+            auth: None,
+            signature_algorithms: vec![],
+            format: _Format::Text,
+            extract: BTreeMap::new(),
+            options: _HookOptions::default(),
+        });
+        self
+    }
+
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.raw.log_format = format;
+        self
+    }
+
+    pub fn allow_cidrs(mut self, cidrs: Vec<Cidr>) -> Self {
+        self.raw.allow_cidrs = cidrs;
+        self
+    }
+
+    pub fn deny_cidrs(mut self, cidrs: Vec<Cidr>) -> Self {
+        self.raw.deny_cidrs = cidrs;
+        self
+    }
+
+    pub fn trusted_proxies(mut self, cidrs: Vec<Cidr>) -> Self {
+        self.raw.trusted_proxies = cidrs;
+        self
+    }
+
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.raw.header_timeout = timeout.as_secs();
+        self
+    }
+
+    pub fn body_timeout(mut self, timeout: Duration) -> Self {
+        self.raw.body_timeout = timeout.as_secs();
+        self
+    }
+
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.raw.max_connections = max;
+        self
+    }
+
+    pub fn max_requests(mut self, max: usize) -> Self {
+        self.raw.max_requests = max;
+        self
+    }
+
+    pub fn max_jobs(mut self, max: usize) -> Self {
+        self.raw.max_jobs = Some(max);
+        self
+    }
+
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.raw.path_prefix = prefix.to_string();
+        self
+    }
+
+    /// Add `name: value` to every response.
+    pub fn response_header(mut self, name: &str, value: &str) -> Self {
+        self.raw
+            .response_headers
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Log what each hook would run, with its env, instead of running it.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.raw.dry_run = dry_run;
+        self
+    }
+
+    /// Fail to build when a hook's command doesn't exist or isn't executable.
+    pub fn strict_commands(mut self, strict: bool) -> Self {
+        self.raw.strict_commands = strict;
+        self
+    }
+
+    /// Serve the admin endpoints on `socket`.
+    pub fn admin(mut self, socket: SocketAddr) -> Self {
+        self.raw.admin = Some(_AdminConfig {
+            addr: socket.ip(),
+            port: socket.port(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<RouteConfig, ConfigError> {
+        build(self.raw)
+    }
 }
 
+/// github only sends json payloads to rook; form-encoded deliveries aren't supported
+const GITHUB_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Debug)]
 pub enum ConfigError {
     IoError(std::io::Error),
     DeError(toml::de::Error),
     BadConfig(String),
 }
 
+/// Load and validate the config file at `config_path`.
 pub fn from_file(config_path: &str) -> Result<RouteConfig, ConfigError> {
-    let cfg_str = fs::read_to_string(config_path)?;
-    let raw: _RookConfig = toml::from_str(&cfg_str)?;
+    let mut cfg = from_toml(&fs::read_to_string(config_path)?)?;
+    cfg.config_path = Some(config_path.to_string());
+    Ok(cfg)
+}
+
+/// Fetch, verify, and validate the config `source` names, from a url, Consul, or etcd.  See
+/// [`remote`](crate::remote).
+pub fn from_url(source: remote::Source) -> Result<RouteConfig, ConfigError> {
+    let source = Arc::new(source);
+    let fetching = source.clone();
+    let text = block_on_thread(async move { fetching.fetch().await })?;
+    from_remote(source, &text)
+}
+
+/// Validate `text`, fetched and verified from `source`.
+pub(crate) fn from_remote(
+    source: Arc<remote::Source>,
+    text: &str,
+) -> Result<RouteConfig, ConfigError> {
+    let mut cfg = from_toml(text)?;
+    cfg.config_path = Some(source.url().to_string());
+    source.check_serial(text, cfg.config_serial)?;
+    source.loaded(text, cfg.config_serial);
+    cfg.remote = Some(source);
+    Ok(cfg)
+}
 
+/// Validate a config given as toml, for embedders that don't keep it in a file.
+pub fn from_toml(cfg_str: &str) -> Result<RouteConfig, ConfigError> {
+    let raw: _RookConfig = toml::from_str(cfg_str)?;
+    build(raw)
+}
+
+/// Validate a parsed config; shared by [`from_toml`] and [`RouteConfigBuilder`].
+fn build(raw: _RookConfig) -> Result<RouteConfig, ConfigError> {
+    let strict_commands = raw.strict_commands;
+    let fallback = raw.fallback.map(fallback).transpose()?;
     let mut cfg = RouteConfig {
         socket: SocketAddr::new(raw.addr, raw.port),
+        log_format: raw.log_format,
+        log_template: raw.log_template,
+        log_level: raw.log_level,
+        log_file: raw.log_file,
+        syslog: raw.syslog,
+        journald: raw.journald,
+        audit_log: raw.audit_log,
+        otlp: raw.otlp,
+        statsd: raw.statsd,
+        events: raw.events,
+        sentry: raw.sentry,
+        access: AccessList {
+            allow: raw.allow_cidrs,
+            deny: raw.deny_cidrs,
+        },
+        trusted_proxies: raw.trusted_proxies,
+        proxy_protocol: raw.proxy_protocol,
+        header_timeout: Duration::from_secs(raw.header_timeout),
+        body_timeout: Duration::from_secs(raw.body_timeout),
+        max_connections: raw.max_connections,
+        max_requests: raw.max_requests,
+        max_jobs: raw.max_jobs,
+        shed_retry_after: raw.shed_retry_after,
+        response_headers: response_headers(&raw.response_headers)?,
+        path_prefix: raw.path_prefix.trim_end_matches('/').to_string(),
+        tls: raw.tls,
+        admin: raw.admin.map(|a| SocketAddr::new(a.addr, a.port)),
+        history: raw.history,
+        postgres: raw.postgres,
+        dry_run: raw.dry_run,
+        record: None,
+        fallback,
         gh_hooks: HashMap::new(),
         rook_hooks: HashMap::new(),
+        mqtt: raw.mqtt,
+        mqtt_hooks: vec![],
+        watch_hooks: vec![],
+        config_path: None,
+        remote: None,
+        config_serial: raw.config_serial,
+    };
+    if !(cfg.path_prefix.is_empty() || cfg.path_prefix.starts_with('/')) {
+        return Err(format!("path_prefix must start with '/': '{}'", cfg.path_prefix).into());
+    }
+    if cfg.log_template.is_some() && matches!(cfg.log_format, LogFormat::Json) {
+        return Err("log_template can't be combined with log_format = \"json\""
+            .to_string()
+            .into());
+    }
+    let outputs = [cfg.log_file.is_some(), cfg.syslog.is_some(), cfg.journald];
+    if outputs.iter().filter(|o| **o).count() > 1 {
+        return Err("choose one of log_file, syslog, or journald"
+            .to_string()
+            .into());
+    }
+    if cfg.max_connections == 0 || cfg.max_requests == 0 {
+        return Err("max_connections and max_requests must be at least 1"
+            .to_string()
+            .into());
+    }
+    if cfg.max_jobs == Some(0) {
+        return Err("max_jobs must be at least 1".to_string().into());
+    }
+    if cfg.tls.is_some() && !cfg!(feature = "tls") {
+        return Err("[tls] requires rook built with the `tls` feature"
+            .to_string()
+            .into());
+    }
+    if cfg.history.is_some() && !cfg!(feature = "sqlite") {
+        return Err("[history] requires rook built with the `sqlite` feature"
+            .to_string()
+            .into());
+    }
+    if cfg.postgres.is_some() && !cfg!(feature = "postgres") {
+        return Err("[postgres] requires rook built with the `postgres` feature"
+            .to_string()
+            .into());
+    }
+    if cfg.statsd.is_some() && !cfg!(feature = "metrics") {
+        return Err("[statsd] requires rook built with the `metrics` feature"
+            .to_string()
+            .into());
+    }
+    if cfg.admin.is_some_and(|a| a.port() == cfg.socket.port()) {
+        return Err("admin listener must not share the webhook port"
+            .to_string()
+            .into());
+    }
+    if raw.vault.is_some() && !cfg!(feature = "vault") {
+        return Err("[vault] requires rook built with the `vault` feature"
+            .to_string()
+            .into());
+    }
+    if raw.aws.is_some() && !cfg!(feature = "aws") {
+        return Err("[aws] requires rook built with the `aws` feature"
+            .to_string()
+            .into());
+    }
+    if raw.age.is_some() && !cfg!(feature = "age") {
+        return Err("[age] requires rook built with the `age` feature"
+            .to_string()
+            .into());
+    }
+    if raw.nats.is_some() && !cfg!(feature = "nats") {
+        return Err("[nats] requires rook built with the `nats` feature"
+            .to_string()
+            .into());
+    }
+    if raw.redis.is_some() && !cfg!(feature = "redis") {
+        return Err("[redis] requires rook built with the `redis` feature"
+            .to_string()
+            .into());
+    }
+    if raw.kafka.is_some() && !cfg!(feature = "kafka") {
+        return Err("[kafka] requires rook built with the `kafka` feature"
+            .to_string()
+            .into());
+    }
+    if raw.smtp.is_some() && !cfg!(feature = "smtp") {
+        return Err("[smtp] requires rook built with the `smtp` feature"
+            .to_string()
+            .into());
+    }
+    if raw.matrix.is_some() && !cfg!(feature = "matrix") {
+        return Err("[matrix] requires rook built with the `matrix` feature"
+            .to_string()
+            .into());
+    }
+    if raw.telegram.is_some() && !cfg!(feature = "telegram") {
+        return Err("[telegram] requires rook built with the `telegram` feature"
+            .to_string()
+            .into());
+    }
+    if let Some(lock) = &raw.lock {
+        match (&lock.dir, lock.redis) {
+            (Some(dir), false) if !Path::new(dir).is_dir() => {
+                return Err(format!("[lock] dir '{}' is not a directory", dir).into())
+            }
+            (Some(_), false) | (None, true) => {}
+            _ => return Err("[lock] needs one of dir or redis = true".to_string().into()),
+        }
+    }
+    if cfg.mqtt.is_some() && !cfg!(feature = "mqtt") {
+        return Err("[mqtt] requires rook built with the `mqtt` feature"
+            .to_string()
+            .into());
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &cfg.mqtt {
+        crate::mqtt::Broker::new(mqtt)?;
+    }
+    let mut references: Vec<_SecretRef> = vec![];
+    for hook in &raw.hooks {
+        if !hook.needs_secret() {
+            continue;
+        }
+        if let Some(reference) = hook.secret().reference(hook.url())? {
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+    }
+    let fetched = fetch_secrets(
+        raw.vault.as_ref(),
+        raw.aws.as_ref(),
+        raw.age.as_ref(),
+        references,
+    )?;
+    let client_certs = cfg.tls.as_ref().is_some_and(|t| t.client_ca_file.is_some());
+    let backends = Backends {
+        nats: raw.nats.as_ref(),
+        redis: raw.redis.as_ref(),
+        kafka: raw.kafka.as_ref(),
+        smtp: raw.smtp.as_ref(),
+        matrix: raw.matrix.as_ref(),
+        lock: raw.lock.as_ref(),
+        telegram_cfg: raw.telegram.as_ref(),
+        telegram: telegram(raw.telegram.as_ref())?,
+        jobs: postgres_jobs(cfg.postgres.as_ref())?,
     };
+    let mut formats: HashMap<String, (ResponseFormat, Option<_Success>)> = HashMap::new();
+    let mut shapes: HashMap<String, String> = HashMap::new();
+    let mut spills: HashMap<String, Option<_Spill>> = HashMap::new();
+    let mut headers: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+    let mut maintenance: HashMap<String, Option<_Maintenance>> = HashMap::new();
+    let mut windows: HashMap<String, (Option<String>, OutsideHours)> = HashMap::new();
     for hook in raw.hooks {
+        if let _HookConfig::_MqttHook { .. } = hook {
+            if cfg.mqtt.is_none() {
+                return Err("mqtt hooks need an [mqtt] section".to_string().into());
+            }
+            topic::validate(hook.url())?;
+        } else if let _HookConfig::_WatchHook { .. } = hook {
+            if !cfg!(feature = "watch") {
+                return Err("watch hooks require rook built with the `watch` feature"
+                    .to_string()
+                    .into());
+            }
+            glob::validate(hook.url())?;
+        } else if pattern::is_pattern(hook.url()) {
+            pattern::validate(hook.url())?;
+            let url = shapes
+                .entry(pattern::shape(hook.url()))
+                .or_insert_with(|| hook.url().to_string());
+            if url != hook.url() {
+                return Err(format!("hook urls '{}' and '{}' overlap", url, hook.url()).into());
+            }
+        }
+        if hook.options().require_client_cert && !client_certs {
+            return Err("require_client_cert needs tls.client_ca_file"
+                .to_string()
+                .into());
+        }
+        let format = (hook.options().response, hook.options().success.clone());
+        if hook.is_http()
+            && *formats
+                .entry(hook.url().to_string())
+                .or_insert_with(|| format.clone())
+                != format
+        {
+            return Err(format!(
+                "hooks for '{}' must use the same response and success",
+                hook.url()
+            )
+            .into());
+        }
+        let extra = hook.options().response_headers.clone();
+        if hook.is_http()
+            && *headers
+                .entry(hook.url().to_string())
+                .or_insert_with(|| extra.clone())
+                != extra
+        {
+            return Err(format!(
+                "hooks for '{}' must use the same response_headers",
+                hook.url()
+            )
+            .into());
+        }
+        let spill = hook.options().spill.clone();
+        if hook.is_http()
+            && *spills
+                .entry(hook.url().to_string())
+                .or_insert_with(|| spill.clone())
+                != spill
+        {
+            return Err(format!("hooks for '{}' must use the same spill", hook.url()).into());
+        }
+        let paused = hook.options().maintenance.clone();
+        if hook.is_http()
+            && *maintenance
+                .entry(hook.url().to_string())
+                .or_insert_with(|| paused.clone())
+                != paused
+        {
+            return Err(format!("hooks for '{}' must use the same maintenance", hook.url()).into());
+        }
+        let window = (
+            hook.options().allowed_hours.clone(),
+            hook.options().outside_hours,
+        );
+        if hook.is_http()
+            && *windows
+                .entry(hook.url().to_string())
+                .or_insert_with(|| window.clone())
+                != window
+        {
+            return Err(format!(
+                "hooks for '{}' must use the same allowed_hours and outside_hours",
+                hook.url()
+            )
+            .into());
+        }
+        let secret = if !hook.needs_secret() {
+            vec![]
+        } else {
+            match hook.secret().reference(hook.url())? {
+                Some(reference) => fetched[&reference].clone(),
+                // reference() already checked it's set
+                None => hook.secret().file.clone().unwrap_or_default(),
+            }
+        };
         match hook {
             _HookConfig::_GithubHook {
                 url,
-                secret,
                 command,
                 repo,
+                refs,
+                exclude_refs,
+                exclude_repos,
+                mut options,
+                ..
             } => {
-                if cfg.rook_hooks.contains_key(&url) {
-                    return Err(format!("hook path type conflict: '{}'", url).into());
+                let patterns = refs.iter().chain(&exclude_refs).chain(&exclude_repos);
+                if patterns.into_iter().any(String::is_empty) {
+                    return Err(format!(
+                        "hook '{}' refs, exclude_refs, and exclude_repos can't hold empty patterns",
+                        url
+                    )
+                    .into());
+                }
+                if options.content_types.is_empty() {
+                    options.content_types.push(GITHUB_CONTENT_TYPE.to_string());
                 }
+                if options.spill.is_some() {
+                    return Err(format!("hook '{}' spill is only for rook hooks", url).into());
+                }
+                let executor = hook_executor(&url, &command, &options, &backends)?;
+                let alerts = hook_alerts(&options, &backends);
+                let mut options = hook_options(options, &secret)?;
+                options.alerts = alerts;
                 cfg.gh_hooks
                     .entry(url.to_string())
-                    .or_insert_with(|| Vec::new())
+                    .or_default()
                     .push(GithubHook {
                         repo: repo.to_string(),
+                        refs,
+                        exclude_refs,
+                        exclude_repos,
                         command: command.to_string(),
-                        secret: secret.to_vec(),
+                        verifier: Box::new(HmacSha256::github(secret)),
+                        executor,
+                        options,
                     });
             }
             _HookConfig::_RookHook {
                 url,
-                secret,
+                secret: secret_cfg,
                 command,
+                replay_window,
+                auth,
+                signature_algorithms,
+                format,
+                extract,
+                options,
             } => {
-                if cfg.gh_hooks.contains_key(&url) {
-                    return Err(format!("hook path type conflict: '{}'", url).into());
+                let json = match format {
+                    _Format::Json => Some(json_fields(&url, extract, &options)?),
+                    _ if !extract.is_empty() => {
+                        return Err(format!("hook '{}' extract needs format = \"json\"", url).into())
+                    }
+                    _ => None,
+                };
+                let binary = format == _Format::Binary;
+                let executor = hook_executor(&url, &command, &options, &backends)?;
+                let alerts = hook_alerts(&options, &backends);
+                let mut options = hook_options(options, &secret)?;
+                options.alerts = alerts;
+                let verifier: Box<dyn Verifier> = match (auth, replay_window) {
+                    (Some(_), _) if secret_cfg.is_set() => {
+                        return Err(
+                            format!("hook '{}' must use one of auth or a secret", url).into()
+                        )
+                    }
+                    (Some(_), Some(_)) => {
+                        return Err(format!("hook '{}' replay_window needs a secret", url).into())
+                    }
+                    (Some(_), None) if !signature_algorithms.is_empty() => {
+                        return Err(
+                            format!("hook '{}' signature_algorithms needs a secret", url).into(),
+                        )
+                    }
+                    (Some(auth), None) => Box::new(Bearer::new(auth.bearer)),
+                    (None, Some(0)) => {
+                        return Err(
+                            format!("hook '{}' replay_window must be at least 1", url).into()
+                        )
+                    }
+                    (None, Some(secs)) => Box::new(
+                        HmacSha256::rook(secret)
+                            .algorithms(signature_algorithms)
+                            .replay_window(Duration::from_secs(secs)),
+                    ),
+                    (None, None) => {
+                        Box::new(HmacSha256::rook(secret).algorithms(signature_algorithms))
+                    }
+                };
+                let hooks = cfg.rook_hooks.entry(url.to_string()).or_default();
+                // text hooks can't run for a body that isn't utf-8
+                if hooks.first().is_some_and(|h| h.binary != binary) {
+                    return Err(format!(
+                        "hooks for '{}' must all use format = \"binary\", or none",
+                        url
+                    )
+                    .into());
                 }
-                cfg.rook_hooks
-                    .entry(url.to_string())
-                    .or_insert_with(|| Vec::new())
-                    .push(RookHook {
-                        command: command.to_string(),
-                        secret: secret.to_vec(),
-                    });
+                hooks.push(RookHook {
+                    command: command.to_string(),
+                    json,
+                    binary,
+                    verifier,
+                    executor,
+                    options,
+                });
+            }
+            _HookConfig::_MqttHook {
+                topic,
+                secret: secret_cfg,
+                command,
+                trust,
+                options,
+            } => {
+                let verifier: Box<dyn Verifier> = match trust {
+                    _Trust::Topic if secret_cfg.is_set() => {
+                        return Err(format!(
+                            "mqtt hook '{}' must use one of trust = \"topic\" or a secret",
+                            topic
+                        )
+                        .into())
+                    }
+                    _Trust::Topic => Box::new(Trusted),
+                    _Trust::Signature => Box::new(HmacSha256::rook(secret)),
+                };
+                let executor = hook_executor(&topic, &command, &options, &backends)?;
+                let alerts = hook_alerts(&options, &backends);
+                let mut options = local_options("mqtt", &topic, options)?;
+                options.alerts = alerts;
+                cfg.mqtt_hooks.push(MqttHook {
+                    topic,
+                    command,
+                    signed: trust == _Trust::Signature,
+                    verifier,
+                    executor,
+                    options,
+                });
+            }
+            _HookConfig::_WatchHook {
+                watch,
+                secret: secret_cfg,
+                command,
+                options,
+            } => {
+                if secret_cfg.is_set() {
+                    return Err(format!("watch hook '{}' takes no secret", watch).into());
+                }
+                let executor = hook_executor(&watch, &command, &options, &backends)?;
+                let options = local_options("watch", &watch, options)?;
+                cfg.watch_hooks.push(WatchHook {
+                    watch,
+                    command,
+                    executor,
+                    options,
+                });
             }
         };
     }
-    debug_routes(&cfg);
+    if let (true, Some(problem)) = (strict_commands, command_problems(&cfg, true).first()) {
+        return Err(problem.clone().into());
+    }
+    if cfg.dry_run {
+        cfg.set_dry_run();
+    }
     Ok(cfg)
 }
 
-#[cfg(not(debug_assertions))]
-fn debug_routes(_: &RouteConfig) {}
+/// Log each hook command that can't be run; with `strict_commands`, the config wouldn't have
+/// loaded.
+pub fn warn_commands(cfg: &RouteConfig) {
+    for problem in command_problems(cfg, false) {
+        log::warn!("{}", problem);
+    }
+}
+
+/// Why each hook command that can't be run can't, by url.  `strict` also refuses relative paths.
+fn command_problems(cfg: &RouteConfig, strict: bool) -> Vec<String> {
+    let mut commands: Vec<(&str, &str)> = vec![];
+    for (url, hooks) in &cfg.gh_hooks {
+        commands.extend(hooks.iter().map(|h| (url.as_str(), h.command.as_str())));
+    }
+    for (url, hooks) in &cfg.rook_hooks {
+        commands.extend(hooks.iter().map(|h| (url.as_str(), h.command.as_str())));
+    }
+    commands.extend(
+        cfg.mqtt_hooks
+            .iter()
+            .map(|h| (h.topic.as_str(), h.command.as_str())),
+    );
+    commands.extend(
+        cfg.watch_hooks
+            .iter()
+            .map(|h| (h.watch.as_str(), h.command.as_str())),
+    );
+    commands.sort_unstable();
+    commands.dedup();
+    let mut problems: Vec<String> = commands
+        .into_iter()
+        // hooks that only publish have no command
+        .filter(|(_, c)| !c.is_empty())
+        .filter_map(|(url, c)| {
+            let e = command::check(c, strict).err()?;
+            Some(format!("hook '{}' command_path '{}' {}", url, c, e))
+        })
+        .collect();
+    if let Some(c) = cfg.fallback.as_ref().and_then(|f| f.command.as_deref()) {
+        if let Err(e) = command::check(c, strict) {
+            problems.push(format!("fallback command_path '{}' {}", c, e));
+        }
+    }
+    problems
+}
 
-#[cfg(debug_assertions)]
-fn debug_routes(cfg: &RouteConfig) {
+pub fn debug_routes(cfg: &RouteConfig) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
     log::debug!("loaded config:");
     log::debug!(
         "port {} with {} routes",
         cfg.socket.port(),
         cfg.gh_hooks.len() + cfg.rook_hooks.len()
     );
+    if !(cfg.access.allow.is_empty() && cfg.access.deny.is_empty()) {
+        log::debug!(
+            "global access: allow {:?} deny {:?}",
+            cfg.access.allow,
+            cfg.access.deny
+        );
+    }
     for (path, handlers) in cfg.gh_hooks.iter() {
         log::debug!("{: >3} github {}", handlers.len(), path);
     }
     for (path, handlers) in cfg.rook_hooks.iter() {
         log::debug!("{: >3} rook   {}", handlers.len(), path);
     }
+    for hook in cfg.mqtt_hooks.iter() {
+        log::debug!("  1 mqtt   {}", hook.topic);
+    }
+    for hook in cfg.watch_hooks.iter() {
+        log::debug!("  1 watch  {}", hook.watch);
+    }
+}
+
+/// Every configured hook: HTTP hooks sorted by url, then mqtt hooks by topic, then watch hooks
+/// by path.  Secrets are never included.
+pub fn routes(cfg: &RouteConfig) -> Value {
+    let mut routes: Vec<Value> = Vec::new();
+    for (url, hooks) in cfg.gh_hooks.iter() {
+        for hook in hooks {
+            let mut route = json!({
+                "url": url,
+                "type": "github",
+                "repo": hook.repo,
+                "command": hook.command,
+                "enabled": enabled(cfg, url, &hook.options),
+            });
+            for (name, patterns) in [
+                ("refs", &hook.refs),
+                ("exclude_refs", &hook.exclude_refs),
+                ("exclude_repos", &hook.exclude_repos),
+            ] {
+                if !patterns.is_empty() {
+                    route[name] = json!(patterns);
+                }
+            }
+            merge_options(&mut route, &hook.options);
+            routes.push(route);
+        }
+    }
+    for (url, hooks) in cfg.rook_hooks.iter() {
+        for hook in hooks {
+            let mut route = json!({
+                "url": url,
+                "type": "rook",
+                "command": hook.command,
+                "enabled": enabled(cfg, url, &hook.options),
+            });
+            if hook.binary {
+                route["format"] = "binary".into();
+            }
+            if let Some(fields) = &hook.json {
+                route["format"] = "json".into();
+                if !fields.is_empty() {
+                    route["extract"] = json!(fields.iter().cloned().collect::<BTreeMap<_, _>>());
+                }
+            }
+            merge_options(&mut route, &hook.options);
+            routes.push(route);
+        }
+    }
+    for hook in cfg.mqtt_hooks.iter() {
+        let mut route = json!({
+            "topic": hook.topic,
+            "type": "mqtt",
+            "command": hook.command,
+            "enabled": true,
+        });
+        merge_options(&mut route, &hook.options);
+        routes.push(route);
+    }
+    for hook in cfg.watch_hooks.iter() {
+        let mut route = json!({
+            "watch": hook.watch,
+            "type": "watch",
+            "command": hook.command,
+            "enabled": true,
+        });
+        merge_options(&mut route, &hook.options);
+        routes.push(route);
+    }
+    routes.sort_by(|a, b| route_key(a).cmp(&route_key(b)));
+    Value::Array(routes)
+}
+
+/// Orders routes by url, then topic, then watched path, with each kind after the one before.
+fn route_key(route: &Value) -> [(bool, Option<&str>); 3] {
+    ["url", "topic", "watch"].map(|k| (route[k].is_null(), route[k].as_str()))
+}
+
+/// Whether `url`'s hooks would run a delivery now: not paused for maintenance, and inside their
+/// `allowed_hours`.  Until a server has loaded the config, `maintenance.paused` counts as paused.
+fn enabled(cfg: &RouteConfig, url: &str, options: &HookOptions) -> bool {
+    let paused = if maintenance::applied() {
+        maintenance::is_paused(url)
+    } else {
+        options.maintenance.paused
+    };
+    !paused && router::within_hours(cfg, url)
+}
+
+fn merge_options(route: &mut Value, options: &HookOptions) {
+    let fields = json!({
+        "description": options.description,
+        "host": options.host,
+        "query": options.query.iter().cloned().collect::<BTreeMap<_, _>>(),
+        "methods": options.methods.iter().map(Method::as_str).collect::<Vec<_>>(),
+        "content_types": options.content_types,
+        "pass_headers": options.pass_headers,
+        "response_headers": options
+            .response_headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), String::from_utf8_lossy(v.as_bytes())))
+            .collect::<BTreeMap<_, _>>(),
+        "allow_cidrs": options.access.allow.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+        "deny_cidrs": options.access.deny.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+        "require_client_cert": options.require_client_cert,
+        "filters": options.filters.len(),
+        "forward": options.forward.as_ref().map_or(&[][..], |f| f.urls()),
+        "archive_dir": options.archive.as_ref().map(|a| a.dir().display().to_string()),
+    });
+    if let (Value::Object(route), Value::Object(fields)) = (route, fields) {
+        route.extend(fields);
+    }
 }
 
-fn deserialize_secret<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+fn deserialize_secret<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s: &str = Deserialize::deserialize(deserializer)?;
     fs::read_to_string(s)
         .map_err(|_| de::Error::custom(format!("failed to read secret at '{}'", s)))
-        .map(|x| x.trim().as_bytes().to_vec())
+        .map(|x| Some(x.trim().as_bytes().to_vec()))
 }
 
-impl From<std::io::Error> for ConfigError {
-    fn from(e: std::io::Error) -> Self {
-        Self::IoError(e)
-    }
-}
+/// A bearer token, read from the file named in the config like `secret_file`.
+fn deserialize_token<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: &str = Deserialize::deserialize(deserializer)?;
+    let token = fs::read_to_string(s)
+        .map_err(|_| de::Error::custom(format!("failed to read bearer token at '{}'", s)))?;
+    match token.trim() {
+        "" => Err(de::Error::custom(format!(
+            "bearer token at '{}' is empty",
+            s
+        ))),
+        token => Ok(token.to_string()),
+    }
+}
+
+/// Secrets read from a secret store, keyed by reference.
+type Fetched = HashMap<String, Vec<u8>>;
+
+/// Fetch every secret kept outside the config, once each.
+fn fetch_secrets(
+    vault_cfg: Option<&VaultConfig>,
+    aws_cfg: Option<&AwsConfig>,
+    age_cfg: Option<&AgeConfig>,
+    references: Vec<_SecretRef>,
+) -> Result<HashMap<_SecretRef, Vec<u8>>, ConfigError> {
+    let (mut vault, mut secrets_manager, mut ssm, mut age) = (vec![], vec![], vec![], vec![]);
+    let (mut consul, mut etcd) = (vec![], vec![]);
+    for reference in references {
+        match reference {
+            _SecretRef::Vault(r) => vault.push(r),
+            _SecretRef::SecretsManager(r) => secrets_manager.push(r),
+            _SecretRef::Ssm(r) => ssm.push(r),
+            _SecretRef::Age(r) => age.push(r),
+            _SecretRef::Consul(r) => consul.push(r),
+            _SecretRef::Etcd(r) => etcd.push(r),
+        }
+    }
+    let mut fetched = HashMap::new();
+    if !age.is_empty() {
+        let secrets = age_secrets(age_cfg, age)?;
+        fetched.extend(secrets.into_iter().map(|(r, s)| (_SecretRef::Age(r), s)));
+    }
+    if !vault.is_empty() {
+        let secrets = vault_secrets(vault_cfg, vault)?;
+        fetched.extend(secrets.into_iter().map(|(r, s)| (_SecretRef::Vault(r), s)));
+    }
+    if !(secrets_manager.is_empty() && ssm.is_empty()) {
+        let (secrets_manager, ssm) = aws_secrets(aws_cfg, secrets_manager, ssm)?;
+        fetched.extend(
+            secrets_manager
+                .into_iter()
+                .map(|(r, s)| (_SecretRef::SecretsManager(r), s)),
+        );
+        fetched.extend(ssm.into_iter().map(|(r, s)| (_SecretRef::Ssm(r), s)));
+    }
+    if !(consul.is_empty() && etcd.is_empty()) {
+        let (consul, etcd) = kv_secrets(consul, etcd)?;
+        fetched.extend(consul.into_iter().map(|(r, s)| (_SecretRef::Consul(r), s)));
+        fetched.extend(etcd.into_iter().map(|(r, s)| (_SecretRef::Etcd(r), s)));
+    }
+    Ok(fetched)
+}
+
+#[cfg(feature = "age")]
+fn age_secrets(cfg: Option<&AgeConfig>, ciphertexts: Vec<String>) -> Result<Fetched, ConfigError> {
+    Ok(crate::age::decrypt(cfg, ciphertexts)?)
+}
+
+#[cfg(not(feature = "age"))]
+fn age_secrets(_: Option<&AgeConfig>, _: Vec<String>) -> Result<Fetched, ConfigError> {
+    Err("secret_age requires rook built with the `age` feature"
+        .to_string()
+        .into())
+}
+
+/// Run `fetch` to completion on its own thread and runtime, so configs load the same way
+/// inside and outside of tokio.
+fn block_on_thread<T: Send + 'static>(
+    fetch: impl std::future::Future<Output = Result<T, String>> + Send + 'static,
+) -> Result<T, ConfigError> {
+    let fetched = std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("failed to start http client: {}", e))?
+            .block_on(fetch)
+    })
+    .join()
+    .map_err(|_| "http client panicked".to_string())?;
+    Ok(fetched?)
+}
+
+#[cfg(feature = "vault")]
+fn vault_secrets(
+    cfg: Option<&VaultConfig>,
+    references: Vec<String>,
+) -> Result<Fetched, ConfigError> {
+    block_on_thread(crate::vault::load(cfg.cloned(), references))
+}
+
+#[cfg(not(feature = "vault"))]
+fn vault_secrets(_: Option<&VaultConfig>, _: Vec<String>) -> Result<Fetched, ConfigError> {
+    Err("secret_vault requires rook built with the `vault` feature"
+        .to_string()
+        .into())
+}
+
+#[cfg(feature = "aws")]
+fn aws_secrets(
+    cfg: Option<&AwsConfig>,
+    secrets_manager: Vec<String>,
+    ssm: Vec<String>,
+) -> Result<(Fetched, Fetched), ConfigError> {
+    block_on_thread(crate::aws::load(cfg.cloned(), secrets_manager, ssm))
+}
+
+#[cfg(not(feature = "aws"))]
+fn aws_secrets(
+    _: Option<&AwsConfig>,
+    _: Vec<String>,
+    _: Vec<String>,
+) -> Result<(Fetched, Fetched), ConfigError> {
+    Err(
+        "secret_secretsmanager and secret_ssm require rook built with the `aws` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+#[cfg(feature = "kv")]
+fn kv_secrets(consul: Vec<String>, etcd: Vec<String>) -> Result<(Fetched, Fetched), ConfigError> {
+    block_on_thread(crate::kv::load(consul, etcd))
+}
+
+#[cfg(not(feature = "kv"))]
+fn kv_secrets(_: Vec<String>, _: Vec<String>) -> Result<(Fetched, Fetched), ConfigError> {
+    Err(
+        "secret_consul and secret_etcd require rook built with the `kv` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+fn deserialize_algorithms<'de, D>(deserializer: D) -> Result<Vec<Algorithm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let names: Vec<String> = Deserialize::deserialize(deserializer)?;
+    names
+        .iter()
+        .map(|name| name.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+fn deserialize_methods<'de, D>(deserializer: D) -> Result<Vec<Method>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let names: Vec<String> = Deserialize::deserialize(deserializer)?;
+    if names.is_empty() {
+        return Err(de::Error::custom("methods must not be empty"));
+    }
+    names
+        .iter()
+        .map(|m| {
+            Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                .map_err(|_| de::Error::custom(format!("invalid method '{}'", m)))
+        })
+        .collect()
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
 impl From<toml::de::Error> for ConfigError {
     fn from(e: toml::de::Error) -> Self {
         Self::DeError(e)
@@ -135,33 +1773,1441 @@ impl Display for ConfigError {
     }
 }
 
+impl std::error::Error for ConfigError {}
+
 #[derive(Deserialize)]
 struct _RookConfig {
     addr: IpAddr,
     port: u16,
+    #[serde(default)]
+    log_format: LogFormat,
+    log_template: Option<AccessTemplate>,
+    log_level: Option<LogFilter>,
+    log_file: Option<LogFileConfig>,
+    syslog: Option<SyslogConfig>,
+    #[serde(default)]
+    journald: bool,
+    audit_log: Option<LogFileConfig>,
+    otlp: Option<OtlpConfig>,
+    statsd: Option<StatsdConfig>,
+    events: Option<EventsConfig>,
+    sentry: Option<SentryConfig>,
+    #[serde(default)]
+    allow_cidrs: Vec<Cidr>,
+    #[serde(default)]
+    deny_cidrs: Vec<Cidr>,
+    #[serde(default)]
+    trusted_proxies: Vec<Cidr>,
+    #[serde(default)]
+    proxy_protocol: bool,
+    #[serde(default = "default_header_timeout")]
+    header_timeout: u64,
+    #[serde(default = "default_body_timeout")]
+    body_timeout: u64,
+    #[serde(default = "default_max_connections")]
+    max_connections: usize,
+    #[serde(default = "default_max_requests")]
+    max_requests: usize,
+    max_jobs: Option<usize>,
+    #[serde(default = "default_shed_retry_after")]
+    shed_retry_after: u64,
+    #[serde(default)]
+    response_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    path_prefix: String,
+    tls: Option<TlsConfig>,
+    admin: Option<_AdminConfig>,
+    history: Option<HistoryConfig>,
+    postgres: Option<PostgresConfig>,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    strict_commands: bool,
+    config_serial: Option<u64>,
+    vault: Option<VaultConfig>,
+    aws: Option<AwsConfig>,
+    age: Option<AgeConfig>,
+    nats: Option<NatsConfig>,
+    redis: Option<RedisConfig>,
+    kafka: Option<KafkaConfig>,
+    mqtt: Option<MqttConfig>,
+    smtp: Option<SmtpConfig>,
+    matrix: Option<MatrixConfig>,
+    telegram: Option<TelegramConfig>,
+    lock: Option<LockConfig>,
+    fallback: Option<_FallbackConfig>,
     hooks: Vec<_HookConfig>,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct _FallbackConfig {
+    #[serde(default = "default_fallback_status")]
+    status: u16,
+    body_file: Option<String>,
+    #[serde(default = "default_fallback_content_type")]
+    content_type: String,
+    command_path: Option<String>,
+}
+
+/// Load the `[fallback]` response body and check its status.
+fn fallback(raw: _FallbackConfig) -> Result<Fallback, ConfigError> {
+    let status = StatusCode::from_u16(raw.status)
+        .map_err(|_| format!("fallback status {} is not an http status", raw.status))?;
+    let body = match &raw.body_file {
+        Some(path) => fs::read(path)
+            .map_err(|e| format!("failed to read fallback body_file '{}': {}", path, e))?,
+        None => vec![],
+    };
+    let content_type = HeaderValue::from_str(&raw.content_type)
+        .map_err(|_| format!("bad fallback content_type '{}'", raw.content_type))?;
+    Ok(Fallback {
+        status,
+        content_type,
+        body,
+        command: raw.command_path,
+        executor: Box::new(Spawn),
+    })
+}
+
+#[derive(Deserialize)]
+struct _AdminConfig {
+    #[serde(default = "default_admin_addr")]
+    addr: IpAddr,
+    port: u16,
+}
+
+fn default_header_timeout() -> u64 {
+    10
+}
+
+fn default_body_timeout() -> u64 {
+    30
+}
+
+fn default_syslog_address() -> String {
+    "/dev/log".to_string()
+}
+
+fn default_app_name() -> String {
+    "rook".to_string()
+}
+
+fn default_log_keep() -> usize {
+    5
+}
+
+fn default_max_connections() -> usize {
+    512
+}
+
+fn default_max_requests() -> usize {
+    64
+}
+
+fn default_shed_retry_after() -> u64 {
+    1
+}
+
+pub(crate) fn default_ca_file() -> String {
+    "/etc/ssl/certs/ca-certificates.crt".to_string()
+}
+
+fn default_approle_mount() -> String {
+    "approle".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retention_days() -> u64 {
+    30
+}
+
+fn default_lock_wait() -> u64 {
+    600
+}
+
+fn default_lock_ttl() -> u64 {
+    3600
+}
+
+fn default_postgres_deliveries_table() -> String {
+    "rook_deliveries".to_string()
+}
+
+fn default_postgres_jobs_table() -> String {
+    "rook_jobs".to_string()
+}
+
+fn default_fallback_status() -> u16 {
+    404
+}
+
+fn default_fallback_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+fn default_nats_url() -> String {
+    "nats://127.0.0.1:4222".to_string()
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+fn default_sasl_mechanism() -> String {
+    "plain".to_string()
+}
+
+fn default_mqtt_url() -> String {
+    "mqtt://127.0.0.1:1883".to_string()
+}
+
+fn default_keep_alive() -> u16 {
+    60
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_smtp_url() -> String {
+    "smtp://127.0.0.1:25".to_string()
+}
+
+fn default_admin_addr() -> IpAddr {
+    IpAddr::from([127, 0, 0, 1])
+}
+
+fn default_methods() -> Vec<Method> {
+    vec![Method::POST]
+}
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 enum _HookConfig {
     #[serde(rename = "github")]
     _GithubHook {
         url: String,
-        #[serde(rename = "secret_file")]
-        #[serde(deserialize_with = "deserialize_secret")]
-        secret: Vec<u8>,
-        #[serde(rename = "command_path")]
+        #[serde(flatten)]
+        secret: _Secret,
+        /// only for `executor = "spawn"`
+        #[serde(default, rename = "command_path")]
         command: String,
         repo: String,
+        /// ref globs; the hook only runs for pushes to one of these when set
+        #[serde(default)]
+        refs: Vec<String>,
+        #[serde(default)]
+        exclude_refs: Vec<String>,
+        #[serde(default)]
+        exclude_repos: Vec<String>,
+        #[serde(flatten)]
+        options: _HookOptions,
     },
     #[serde(rename = "rook")]
     _RookHook {
         url: String,
-        #[serde(rename = "secret_file")]
-        #[serde(deserialize_with = "deserialize_secret")]
-        secret: Vec<u8>,
-        #[serde(rename = "command_path")]
+        #[serde(flatten)]
+        secret: _Secret,
+        /// only for `executor = "spawn"`
+        #[serde(default, rename = "command_path")]
+        command: String,
+        /// seconds; requires stamped deliveries and rejects replays
+        #[serde(default)]
+        replay_window: Option<u64>,
+        /// a static credential instead of a signature
+        #[serde(default)]
+        auth: Option<_Auth>,
+        /// accepted, the first preferred; only sha256 when empty
+        #[serde(default, deserialize_with = "deserialize_algorithms")]
+        signature_algorithms: Vec<Algorithm>,
+        /// `"json"` turns away bodies that aren't json before anything runs; `"binary"` takes
+        /// any body, and hands it to commands in a file
+        #[serde(default)]
+        format: _Format,
+        /// env names and the json pointers their values are read from
+        #[serde(default)]
+        extract: BTreeMap<String, String>,
+        #[serde(flatten)]
+        options: _HookOptions,
+    },
+    #[serde(rename = "mqtt")]
+    _MqttHook {
+        /// a topic filter, with `+` and `#` wildcards
+        topic: String,
+        #[serde(flatten)]
+        secret: _Secret,
+        #[serde(default, rename = "command_path")]
+        command: String,
+        /// how a message proves where it came from
+        #[serde(default)]
+        trust: _Trust,
+        #[serde(flatten)]
+        options: _HookOptions,
+    },
+    #[serde(rename = "watch")]
+    _WatchHook {
+        /// a file pattern, with `*` and `?` wildcards in the file name
+        watch: String,
+        /// only so that setting one is an error
+        #[serde(flatten)]
+        secret: _Secret,
+        #[serde(default, rename = "command_path")]
         command: String,
+        #[serde(flatten)]
+        options: _HookOptions,
     },
 }
+
+/// `trust = "topic"`: an `"mqtt"` hook takes any message the broker lets through instead of
+/// checking a signature.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum _Trust {
+    #[default]
+    Signature,
+    Topic,
+}
+
+/// How a `"rook"` hook reads the body.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum _Format {
+    #[default]
+    Text,
+    Json,
+    Binary,
+}
+
+/// A hook's secret: the contents of `secret_file`, read as the config is parsed, or an
+/// encrypted `secret_age` or reference to a secret store, resolved once the whole config is
+/// parsed.
+#[derive(Deserialize)]
+struct _Secret {
+    #[serde(default, rename = "secret_file")]
+    #[serde(deserialize_with = "deserialize_secret")]
+    file: Option<Vec<u8>>,
+    #[serde(default, rename = "secret_vault")]
+    vault: Option<String>,
+    #[serde(default, rename = "secret_secretsmanager")]
+    secrets_manager: Option<String>,
+    #[serde(default, rename = "secret_ssm")]
+    ssm: Option<String>,
+    #[serde(default, rename = "secret_age")]
+    age: Option<String>,
+    #[serde(default, rename = "secret_consul")]
+    consul: Option<String>,
+    #[serde(default, rename = "secret_etcd")]
+    etcd: Option<String>,
+}
+
+/// `auth = { bearer_file = "..." }`: the token a hook's sender puts in `Authorization: Bearer`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct _Auth {
+    #[serde(rename = "bearer_file", deserialize_with = "deserialize_token")]
+    bearer: String,
+}
+
+/// A secret kept in a secret store.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum _SecretRef {
+    Vault(String),
+    SecretsManager(String),
+    Ssm(String),
+    Age(String),
+    Consul(String),
+    Etcd(String),
+}
+
+impl _Secret {
+    fn value(secret: impl Into<Vec<u8>>) -> Self {
+        _Secret {
+            file: Some(secret.into()),
+            vault: None,
+            secrets_manager: None,
+            ssm: None,
+            age: None,
+            consul: None,
+            etcd: None,
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.file.is_some()
+            || self.vault.is_some()
+            || self.secrets_manager.is_some()
+            || self.ssm.is_some()
+            || self.age.is_some()
+            || self.consul.is_some()
+            || self.etcd.is_some()
+    }
+
+    /// Where the secret has to be fetched from, or None when it's `secret_file`.  Exactly one
+    /// source must be set.
+    fn reference(&self, url: &str) -> Result<Option<_SecretRef>, ConfigError> {
+        let references = [
+            self.vault.clone().map(_SecretRef::Vault),
+            self.secrets_manager.clone().map(_SecretRef::SecretsManager),
+            self.ssm.clone().map(_SecretRef::Ssm),
+            self.age.clone().map(_SecretRef::Age),
+            self.consul.clone().map(_SecretRef::Consul),
+            self.etcd.clone().map(_SecretRef::Etcd),
+        ];
+        let mut references = references.into_iter().flatten();
+        match (self.file.is_some(), references.next(), references.next()) {
+            (true, None, _) => Ok(None),
+            (false, Some(reference), None) => Ok(Some(reference)),
+            (false, None, _) => Err(format!(
+                "hook '{}' needs one of secret_file, secret_age, secret_vault, \
+                 secret_secretsmanager, secret_ssm, secret_consul, or secret_etcd",
+                url
+            )
+            .into()),
+            _ => Err(format!("hook '{}' must set only one secret source", url).into()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct _HookOptions {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    query: BTreeMap<String, String>,
+    #[serde(default)]
+    allow_cidrs: Vec<Cidr>,
+    #[serde(default)]
+    deny_cidrs: Vec<Cidr>,
+    #[serde(default = "default_methods")]
+    #[serde(deserialize_with = "deserialize_methods")]
+    methods: Vec<Method>,
+    #[serde(default)]
+    content_types: Vec<String>,
+    /// request headers exported to the command as `ROOK_HEADER_<NAME>`
+    #[serde(default)]
+    pass_headers: Vec<String>,
+    #[serde(default)]
+    response_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    require_client_cert: bool,
+    #[serde(default)]
+    wasm_filter: Option<String>,
+    #[serde(default)]
+    filter_script: Option<String>,
+    #[serde(default)]
+    transform_script: Option<String>,
+    #[serde(default)]
+    response: ResponseFormat,
+    #[serde(default)]
+    success: Option<_Success>,
+    #[serde(default)]
+    rate_limit: Option<_RateLimit>,
+    #[serde(default)]
+    forward: Vec<String>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_secret")]
+    forward_secret_file: Option<Vec<u8>>,
+    #[serde(default = "default_forward_retries")]
+    forward_retries: u32,
+    /// directory verified deliveries are copied to
+    #[serde(default)]
+    archive_dir: Option<String>,
+    /// most deliveries to keep in `archive_dir`
+    #[serde(default)]
+    archive_keep: Option<usize>,
+    /// archived deliveries older than this are deleted
+    #[serde(default = "default_retention_days")]
+    archive_retention_days: u64,
+    #[serde(default = "default_executors")]
+    #[serde(deserialize_with = "deserialize_executors")]
+    executor: Vec<_ExecutorKind>,
+    #[serde(default)]
+    nats_subject: Option<String>,
+    #[serde(default)]
+    redis_list: Option<String>,
+    #[serde(default)]
+    redis_stream: Option<String>,
+    #[serde(default)]
+    kafka_topic: Option<String>,
+    /// addresses to email when the hook's command fails
+    #[serde(default)]
+    notify_email: Vec<String>,
+    /// post the hook's results to the `[matrix]` room
+    #[serde(default)]
+    notify_matrix: Option<_NotifyOn>,
+    /// what to report to `[telegram]`, instead of its `notify`
+    #[serde(default)]
+    notify_telegram: Option<Vec<TelegramEvent>>,
+    /// only one instance sharing the `[lock]` runs the command at a time
+    #[serde(default)]
+    lock: Option<String>,
+    /// a local file the command holds an exclusive `flock` on while it runs, shared with any
+    /// other tool that locks it
+    #[serde(default)]
+    lockfile: Option<String>,
+    /// seconds a command waits for `lockfile`; 0 skips it when the lock is held
+    #[serde(default)]
+    lockfile_wait: Option<u64>,
+    /// write bodies past a size to disk rather than holding them in memory
+    #[serde(default)]
+    spill: Option<_Spill>,
+    /// how the url answers while it's paused
+    #[serde(default)]
+    maintenance: Option<_Maintenance>,
+    /// `"Mon-Fri 08:00-18:00 Europe/Berlin"`; see [`crate::hours`]
+    #[serde(default)]
+    allowed_hours: Option<String>,
+    #[serde(default)]
+    outside_hours: OutsideHours,
+}
+
+/// Which of a hook's results a notifier is told about.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum _NotifyOn {
+    Failure,
+    Always,
+}
+
+/// What a hook does with a verified delivery; `executor = "nats"` or a list of them.
+#[derive(Clone, Copy, PartialEq)]
+enum _ExecutorKind {
+    Spawn,
+    Nats,
+    Redis,
+    Kafka,
+}
+
+fn default_executors() -> Vec<_ExecutorKind> {
+    vec![_ExecutorKind::Spawn]
+}
+
+/// `chat_id = -1001234567890` or `chat_id = "@channelname"`
+fn deserialize_chat_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ChatId {
+        Id(i64),
+        Name(String),
+    }
+    Ok(match ChatId::deserialize(deserializer)? {
+        ChatId::Id(id) => id.to_string(),
+        ChatId::Name(name) => name,
+    })
+}
+
+fn deserialize_executors<'de, D>(deserializer: D) -> Result<Vec<_ExecutorKind>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    let names = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    };
+    if names.is_empty() {
+        return Err(de::Error::custom("executor must not be empty"));
+    }
+    let mut kinds = vec![];
+    for name in names {
+        let kind = match name.as_str() {
+            "spawn" => _ExecutorKind::Spawn,
+            "nats" => _ExecutorKind::Nats,
+            "redis" => _ExecutorKind::Redis,
+            "kafka" => _ExecutorKind::Kafka,
+            _ => return Err(de::Error::custom(format!("unknown executor '{}'", name))),
+        };
+        if kinds.contains(&kind) {
+            return Err(de::Error::custom(format!(
+                "executor '{}' is listed twice",
+                name
+            )));
+        }
+        kinds.push(kind);
+    }
+    Ok(kinds)
+}
+
+fn default_forward_retries() -> u32 {
+    3
+}
+
+/// `success = { status = 202, body = '{"queued": true}', content_type = "application/json" }`
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct _Success {
+    #[serde(default = "default_success_status")]
+    status: u16,
+    body: Option<String>,
+    content_type: Option<String>,
+}
+
+fn default_success_status() -> u16 {
+    200
+}
+
+/// `spill = { above = 1048576, max_size = 268435456, dir = "/var/tmp/rook" }`
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct _Spill {
+    above: u64,
+    #[serde(default = "default_spill_max_size")]
+    max_size: u64,
+    #[serde(default)]
+    dir: Option<String>,
+}
+
+fn default_spill_max_size() -> u64 {
+    256 << 20
+}
+
+/// `maintenance = { paused = true, message = "deploys paused for the migration", queue = 20 }`
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct _Maintenance {
+    #[serde(default)]
+    paused: bool,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    queue: usize,
+}
+
+/// `rate_limit = { per_minute = 10 }`
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct _RateLimit {
+    per_minute: u32,
+}
+
+impl _HookConfig {
+    fn options(&self) -> &_HookOptions {
+        match self {
+            _HookConfig::_GithubHook { options, .. } => options,
+            _HookConfig::_RookHook { options, .. } => options,
+            _HookConfig::_MqttHook { options, .. } => options,
+            _HookConfig::_WatchHook { options, .. } => options,
+        }
+    }
+
+    /// The hook's url, an `"mqtt"` hook's topic, or a `"watch"` hook's pattern.
+    fn url(&self) -> &str {
+        match self {
+            _HookConfig::_GithubHook { url, .. } => url,
+            _HookConfig::_RookHook { url, .. } => url,
+            _HookConfig::_MqttHook { topic, .. } => topic,
+            _HookConfig::_WatchHook { watch, .. } => watch,
+        }
+    }
+
+    /// Whether deliveries arrive as http requests.
+    fn is_http(&self) -> bool {
+        matches!(
+            self,
+            _HookConfig::_GithubHook { .. } | _HookConfig::_RookHook { .. }
+        )
+    }
+
+    fn secret(&self) -> &_Secret {
+        match self {
+            _HookConfig::_GithubHook { secret, .. } => secret,
+            _HookConfig::_RookHook { secret, .. } => secret,
+            _HookConfig::_MqttHook { secret, .. } => secret,
+            _HookConfig::_WatchHook { secret, .. } => secret,
+        }
+    }
+
+    fn auth(&self) -> Option<&_Auth> {
+        match self {
+            _HookConfig::_RookHook { auth, .. } => auth.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the hook checks a signature, and so needs a secret.
+    fn needs_secret(&self) -> bool {
+        match self {
+            _HookConfig::_MqttHook { trust, .. } => *trust == _Trust::Signature,
+            _HookConfig::_WatchHook { .. } => false,
+            _ => self.auth().is_none(),
+        }
+    }
+}
+
+impl Default for _HookOptions {
+    fn default() -> Self {
+        _HookOptions {
+            description: None,
+            host: None,
+            query: BTreeMap::new(),
+            allow_cidrs: vec![],
+            deny_cidrs: vec![],
+            methods: default_methods(),
+            content_types: vec![],
+            pass_headers: vec![],
+            response_headers: BTreeMap::new(),
+            require_client_cert: false,
+            wasm_filter: None,
+            filter_script: None,
+            transform_script: None,
+            response: ResponseFormat::default(),
+            success: None,
+            rate_limit: None,
+            forward: vec![],
+            forward_secret_file: None,
+            forward_retries: default_forward_retries(),
+            archive_dir: None,
+            archive_keep: None,
+            archive_retention_days: default_retention_days(),
+            executor: default_executors(),
+            nats_subject: None,
+            redis_list: None,
+            redis_stream: None,
+            kafka_topic: None,
+            notify_email: vec![],
+            notify_matrix: None,
+            notify_telegram: None,
+            lock: None,
+            lockfile: None,
+            lockfile_wait: None,
+            spill: None,
+            maintenance: None,
+            allowed_hours: None,
+            outside_hours: OutsideHours::default(),
+        }
+    }
+}
+
+impl From<_HookOptions> for HookOptions {
+    fn from(raw: _HookOptions) -> Self {
+        HookOptions {
+            description: raw
+                .description
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty()),
+            host: raw
+                .host
+                .map(|h| h.trim().trim_end_matches('.').to_ascii_lowercase()),
+            query: raw.query.into_iter().collect(),
+            access: AccessList {
+                allow: raw.allow_cidrs,
+                deny: raw.deny_cidrs,
+            },
+            methods: raw.methods,
+            content_types: raw
+                .content_types
+                .iter()
+                .map(|t| t.trim().to_ascii_lowercase())
+                .collect(),
+            pass_headers: raw
+                .pass_headers
+                .iter()
+                .map(|h| h.trim().to_ascii_lowercase())
+                .collect(),
+            response_headers: HeaderMap::new(),
+            require_client_cert: raw.require_client_cert,
+            filters: vec![],
+            renderer: raw.response.renderer(),
+            rate_limit: raw.rate_limit.map(|r| RateLimit::per_minute(r.per_minute)),
+            forward: None,
+            alerts: vec![],
+            archive: None,
+            spill: None,
+            maintenance: raw.maintenance.map_or_else(Maintenance::default, |m| {
+                let default = Maintenance::default();
+                Maintenance {
+                    paused: m.paused,
+                    message: m.message.unwrap_or(default.message),
+                    queue: m.queue,
+                }
+            }),
+            allowed_hours: None,
+            outside_hours: raw.outside_hours,
+        }
+    }
+}
+
+/// [`HookOptions`] with the filters `raw` names loaded, forwarding signed with `secret` unless
+/// it sets its own.
+fn hook_options(raw: _HookOptions, secret: &[u8]) -> Result<HookOptions, ConfigError> {
+    if raw.rate_limit.is_some_and(|r| r.per_minute == 0) {
+        return Err("rate_limit.per_minute must be at least 1"
+            .to_string()
+            .into());
+    }
+    let forward = if raw.forward.is_empty() {
+        None
+    } else {
+        for url in &raw.forward {
+            let uri: Uri = url
+                .parse()
+                .map_err(|e| format!("bad forward url '{}': {}", url, e))?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+                return Err(format!("forward url '{}' must be http or https", url).into());
+            }
+        }
+        let secret = raw.forward_secret_file.clone().unwrap_or(secret.to_vec());
+        if secret.is_empty() {
+            return Err("forward needs a secret or forward_secret_file"
+                .to_string()
+                .into());
+        }
+        Some(Forward::new(
+            raw.forward.clone(),
+            secret,
+            raw.forward_retries,
+        ))
+    };
+    if raw.archive_keep == Some(0) {
+        return Err("archive_keep must be at least 1".to_string().into());
+    }
+    for name in &raw.pass_headers {
+        let name = name.trim().to_ascii_lowercase();
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(format!(
+                "pass_headers '{}' may only have letters, digits, '-', and '_'",
+                name
+            )
+            .into());
+        }
+        // credentials stay out of the environment, as they stay out of the history
+        if history::is_redacted(&name) {
+            return Err(format!("pass_headers can't include '{}'", name).into());
+        }
+    }
+    let archive = match &raw.archive_dir {
+        Some(dir) => Some(Arc::new(Archive::open(
+            dir,
+            raw.archive_keep,
+            raw.archive_retention_days,
+        )?)),
+        None if raw.archive_keep.is_some() => {
+            return Err("archive_keep needs archive_dir".to_string().into())
+        }
+        None => None,
+    };
+    let spill = match &raw.spill {
+        Some(spill) => {
+            let in_memory = raw.wasm_filter.is_some()
+                || raw.filter_script.is_some()
+                || raw.transform_script.is_some()
+                || forward.is_some()
+                || archive.is_some();
+            if in_memory {
+                return Err(
+                    "spill can't be used with filters, forward, or archive_dir, \
+                    which need the body in memory"
+                        .to_string()
+                        .into(),
+                );
+            }
+            if raw.executor != [_ExecutorKind::Spawn] {
+                return Err("spill needs executor \"spawn\" alone".to_string().into());
+            }
+            Some(Spill::new(
+                spill.above,
+                spill.max_size,
+                spill.dir.as_deref(),
+                MAX_BODY_LENGTH,
+            )?)
+        }
+        None => None,
+    };
+    let wasm = raw.wasm_filter.clone();
+    let scripts = (raw.filter_script.clone(), raw.transform_script.clone());
+    let allowed_hours = raw.allowed_hours.as_deref().map(Hours::parse).transpose()?;
+    if allowed_hours.is_none() && raw.outside_hours != OutsideHours::default() {
+        return Err("outside_hours needs allowed_hours".to_string().into());
+    }
+    let headers = response_headers(&raw.response_headers)?;
+    let success = raw.success.clone();
+    let mut options = HookOptions::from(raw);
+    options.response_headers = headers;
+    options.allowed_hours = allowed_hours;
+    options.forward = forward;
+    options.archive = archive;
+    options.spill = spill;
+    if let Some(success) = success {
+        options.renderer = Arc::new(success_renderer(success, options.renderer)?);
+    }
+    if let Some(path) = wasm {
+        options.filters.push(wasm_filter(&path)?);
+    }
+    if scripts.0.is_some() || scripts.1.is_some() {
+        options
+            .filters
+            .push(script_filter(scripts.0.as_deref(), scripts.1.as_deref())?);
+    }
+    Ok(options)
+}
+
+/// The sections executors and notifiers connect through, when configured.
+struct Backends<'a> {
+    nats: Option<&'a NatsConfig>,
+    redis: Option<&'a RedisConfig>,
+    kafka: Option<&'a KafkaConfig>,
+    smtp: Option<&'a SmtpConfig>,
+    matrix: Option<&'a MatrixConfig>,
+    lock: Option<&'a LockConfig>,
+    telegram_cfg: Option<&'a TelegramConfig>,
+    telegram: Option<Telegram>,
+    /// every hook that runs a command reports how it ended to `[postgres]`
+    jobs: Option<PostgresJobs>,
+}
+
+/// The executor for a hook's `executor` list: the one named, or a [`Fanout`] over several.
+fn hook_executor(
+    url: &str,
+    command: &str,
+    raw: &_HookOptions,
+    backends: &Backends,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    let spawns = raw.executor.contains(&_ExecutorKind::Spawn);
+    if spawns == command.is_empty() {
+        return Err(match spawns {
+            true => format!("hook '{}' needs command_path", url),
+            false => format!("hook '{}' command_path needs executor \"spawn\"", url),
+        }
+        .into());
+    }
+    let publishes = raw.executor.contains(&_ExecutorKind::Nats);
+    if publishes != raw.nats_subject.is_some() {
+        return Err(match publishes {
+            true => format!("hook '{}' needs nats_subject", url),
+            false => format!("hook '{}' nats_subject needs executor \"nats\"", url),
+        }
+        .into());
+    }
+    let produces = raw.executor.contains(&_ExecutorKind::Kafka);
+    if produces != raw.kafka_topic.is_some() {
+        return Err(match produces {
+            true => format!("hook '{}' needs kafka_topic", url),
+            false => format!("hook '{}' kafka_topic needs executor \"kafka\"", url),
+        }
+        .into());
+    }
+    let pushes = raw.executor.contains(&_ExecutorKind::Redis);
+    let redis_keys = raw.redis_list.is_some() as usize + raw.redis_stream.is_some() as usize;
+    match (pushes, redis_keys) {
+        (true, 0) => {
+            return Err(format!("hook '{}' needs one of redis_list or redis_stream", url).into())
+        }
+        (true, 2) => {
+            return Err(format!(
+                "hook '{}' must set only one of redis_list or redis_stream",
+                url
+            )
+            .into())
+        }
+        (false, 1..) => {
+            return Err(format!(
+                "hook '{}' redis_list and redis_stream need executor \"redis\"",
+                url
+            )
+            .into())
+        }
+        _ => {}
+    }
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+    if !raw.notify_email.is_empty() {
+        if !spawns {
+            return Err(format!("hook '{}' notify_email needs executor \"spawn\"", url).into());
+        }
+        notifiers.push(email_notifier(backends.smtp, &raw.notify_email)?);
+    }
+    if let Some(on) = raw.notify_matrix {
+        if !spawns {
+            return Err(format!("hook '{}' notify_matrix needs executor \"spawn\"", url).into());
+        }
+        notifiers.push(matrix_notifier(backends.matrix, on)?);
+    }
+    let events = telegram_events(raw, backends);
+    if !events.is_empty() {
+        let telegram = telegram_backend(backends)?;
+        let results = events.iter().any(|e| *e != TelegramEvent::Signature);
+        if results && spawns {
+            notifiers.push(telegram_notifier(telegram, events));
+        } else if results && raw.notify_telegram.is_some() {
+            return Err(format!(
+                "hook '{}' notify_telegram success and failure need executor \"spawn\"",
+                url
+            )
+            .into());
+        }
+        // the [telegram] default only reports the results of hooks that run a command
+    }
+    if let (Some(jobs), true) = (&backends.jobs, spawns) {
+        notifiers.push(postgres_notifier(jobs));
+    }
+    let mut lock = match (&raw.lock, &raw.lockfile) {
+        (Some(_), Some(_)) => {
+            return Err(format!("hook '{}' must use one of lock or lockfile", url).into())
+        }
+        (Some(_), _) | (_, Some(_)) if !spawns => {
+            return Err(format!("hook '{}' lock and lockfile need executor \"spawn\"", url).into())
+        }
+        (Some(name), _) => Some(hook_lock(name, backends)?),
+        (_, Some(path)) => Some(hook_lockfile(url, path, raw.lockfile_wait, backends)?),
+        (None, None) if raw.lockfile_wait.is_some() => {
+            return Err(format!("hook '{}' lockfile_wait needs a lockfile", url).into())
+        }
+        (None, None) => None,
+    };
+    let mut executors: Vec<Box<dyn Executor>> = vec![];
+    for kind in &raw.executor {
+        executors.push(match kind {
+            _ExecutorKind::Spawn if lock.is_some() => Box::new(SpawnLocked {
+                lock: lock.take().expect("checked above"),
+                notifiers: std::mem::take(&mut notifiers),
+            }),
+            _ExecutorKind::Spawn if notifiers.is_empty() => Box::new(Spawn),
+            _ExecutorKind::Spawn => Box::new(SpawnNotify(std::mem::take(&mut notifiers))),
+            _ExecutorKind::Nats => nats_executor(
+                backends.nats,
+                raw.nats_subject.as_deref().unwrap_or_default(),
+            )?,
+            _ExecutorKind::Redis => redis_executor(
+                backends.redis,
+                raw.redis_list.as_deref(),
+                raw.redis_stream.as_deref(),
+            )?,
+            _ExecutorKind::Kafka => kafka_executor(
+                backends.kafka,
+                raw.kafka_topic.as_deref().unwrap_or_default(),
+            )?,
+        });
+    }
+    match executors.len() {
+        1 => Ok(executors.remove(0)),
+        _ => Ok(Box::new(Fanout(executors))),
+    }
+}
+
+#[cfg(feature = "nats")]
+fn nats_executor(
+    cfg: Option<&NatsConfig>,
+    subject: &str,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    let cfg = cfg.ok_or_else(|| "executor \"nats\" needs a [nats] section".to_string())?;
+    Ok(Box::new(crate::nats::Nats::new(cfg, subject)?))
+}
+
+#[cfg(not(feature = "nats"))]
+fn nats_executor(_: Option<&NatsConfig>, _: &str) -> Result<Box<dyn Executor>, ConfigError> {
+    Err(
+        "executor \"nats\" requires rook built with the `nats` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+/// A redis executor for whichever of `list` or `stream` is set.
+#[cfg(feature = "redis")]
+fn redis_executor(
+    cfg: Option<&RedisConfig>,
+    list: Option<&str>,
+    stream: Option<&str>,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    use crate::redis::{Redis, Target};
+    let cfg = cfg.ok_or_else(|| "executor \"redis\" needs a [redis] section".to_string())?;
+    let target = match (list, stream) {
+        (Some(list), _) => Target::List(list.to_string()),
+        (_, stream) => Target::Stream(stream.unwrap_or_default().to_string()),
+    };
+    Ok(Box::new(Redis::new(cfg, target)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn redis_executor(
+    _: Option<&RedisConfig>,
+    _: Option<&str>,
+    _: Option<&str>,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    Err(
+        "executor \"redis\" requires rook built with the `redis` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_executor(
+    cfg: Option<&KafkaConfig>,
+    topic: &str,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    let cfg = cfg.ok_or_else(|| "executor \"kafka\" needs a [kafka] section".to_string())?;
+    Ok(Box::new(crate::kafka::Kafka::new(cfg, topic)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_executor(_: Option<&KafkaConfig>, _: &str) -> Result<Box<dyn Executor>, ConfigError> {
+    Err(
+        "executor \"kafka\" requires rook built with the `kafka` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+#[cfg(feature = "smtp")]
+fn email_notifier(
+    cfg: Option<&SmtpConfig>,
+    to: &[String],
+) -> Result<Box<dyn Notifier>, ConfigError> {
+    let cfg = cfg.ok_or_else(|| "notify_email needs an [smtp] section".to_string())?;
+    Ok(Box::new(crate::smtp::Email::new(cfg, to)?))
+}
+
+#[cfg(not(feature = "smtp"))]
+fn email_notifier(_: Option<&SmtpConfig>, _: &[String]) -> Result<Box<dyn Notifier>, ConfigError> {
+    Err("notify_email requires rook built with the `smtp` feature"
+        .to_string()
+        .into())
+}
+
+/// What a hook reports to Telegram: its own `notify_telegram`, or the `[telegram]` default.
+fn telegram_events<'a>(raw: &'a _HookOptions, backends: &Backends<'a>) -> &'a [TelegramEvent] {
+    match (&raw.notify_telegram, backends.telegram_cfg) {
+        (Some(events), _) => events,
+        (None, Some(cfg)) => &cfg.notify,
+        (None, None) => &[],
+    }
+}
+
+/// A `format = "json"` hook's `extract` as `ROOK_JSON_<NAME>` env names and json pointers.
+fn json_fields(
+    url: &str,
+    extract: BTreeMap<String, String>,
+    raw: &_HookOptions,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    if raw.spill.is_some() {
+        return Err(format!("hook '{}' can't spill a json body", url).into());
+    }
+    let mut fields = vec![];
+    for (name, pointer) in extract {
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!(
+                "hook '{}' extract name '{}' must be letters, digits, and _",
+                url, name
+            )
+            .into());
+        }
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            return Err(format!(
+                "hook '{}' extract '{}' must be a json pointer like '/a/b', not '{}'",
+                url, name, pointer
+            )
+            .into());
+        }
+        fields.push((format!("ROOK_JSON_{}", name.to_ascii_uppercase()), pointer));
+    }
+    Ok(fields)
+}
+
+/// The alerts a hook's rejected signatures go to.  Hooks share one, so a url's hooks send one
+/// alert between them.
+fn hook_alerts(raw: &_HookOptions, backends: &Backends) -> Vec<Arc<dyn Alert>> {
+    match &backends.telegram {
+        Some(telegram) if telegram_events(raw, backends).contains(&TelegramEvent::Signature) => {
+            vec![telegram_alert(telegram)]
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(feature = "telegram")]
+type Telegram = crate::telegram::Telegram;
+#[cfg(not(feature = "telegram"))]
+type Telegram = std::convert::Infallible;
+
+/// `[telegram]`, connected once for every hook that reports to it.
+#[cfg(feature = "telegram")]
+fn telegram(cfg: Option<&TelegramConfig>) -> Result<Option<Telegram>, ConfigError> {
+    Ok(cfg.map(Telegram::new).transpose()?)
+}
+
+/// `[telegram]` was already turned away
+#[cfg(not(feature = "telegram"))]
+fn telegram(_: Option<&TelegramConfig>) -> Result<Option<Telegram>, ConfigError> {
+    Ok(None)
+}
+
+#[cfg(feature = "telegram")]
+fn telegram_backend<'a>(backends: &'a Backends) -> Result<&'a Telegram, ConfigError> {
+    let telegram = backends.telegram.as_ref();
+    Ok(telegram.ok_or_else(|| "notify_telegram needs a [telegram] section".to_string())?)
+}
+
+#[cfg(not(feature = "telegram"))]
+fn telegram_backend<'a>(_: &'a Backends) -> Result<&'a Telegram, ConfigError> {
+    Err(
+        "notify_telegram requires rook built with the `telegram` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+#[cfg(feature = "telegram")]
+fn telegram_notifier(telegram: &Telegram, events: &[TelegramEvent]) -> Box<dyn Notifier> {
+    Box::new(telegram.results(
+        events.contains(&TelegramEvent::Success),
+        events.contains(&TelegramEvent::Failure),
+    ))
+}
+
+#[cfg(not(feature = "telegram"))]
+fn telegram_notifier(telegram: &Telegram, _: &[TelegramEvent]) -> Box<dyn Notifier> {
+    match *telegram {}
+}
+
+#[cfg(feature = "telegram")]
+fn telegram_alert(telegram: &Telegram) -> Arc<dyn Alert> {
+    telegram.alert()
+}
+
+#[cfg(not(feature = "telegram"))]
+fn telegram_alert(telegram: &Telegram) -> Arc<dyn Alert> {
+    match *telegram {}
+}
+
+/// The lock a hook takes before its command runs, from `[lock]`.
+fn hook_lock(name: &str, backends: &Backends) -> Result<Box<dyn Lock>, ConfigError> {
+    let cfg = backends
+        .lock
+        .ok_or_else(|| format!("lock '{}' needs a [lock] section", name))?;
+    if !lock::is_valid_name(name) {
+        return Err(format!(
+            "lock '{}' may only have letters, digits, '-', '_', and '.'",
+            name
+        )
+        .into());
+    }
+    match &cfg.dir {
+        Some(dir) => Ok(Box::new(lock::Flock::new(cfg, dir, name))),
+        None => redis_lock(backends.redis, cfg, name),
+    }
+}
+
+/// The `flock` a hook takes on its `lockfile` before its command runs.  It waits as long as
+/// `[lock]` commands do unless `lockfile_wait` says otherwise.
+fn hook_lockfile(
+    url: &str,
+    path: &str,
+    wait: Option<u64>,
+    backends: &Backends,
+) -> Result<Box<dyn Lock>, ConfigError> {
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err(format!(
+            "hook '{}' lockfile '{}' must be absolute",
+            url,
+            path.display()
+        )
+        .into());
+    }
+    if !path.parent().is_some_and(Path::is_dir) {
+        return Err(format!(
+            "hook '{}' lockfile '{}' is in a directory that doesn't exist",
+            url,
+            path.display()
+        )
+        .into());
+    }
+    let wait = wait.unwrap_or_else(|| backends.lock.map_or(default_lock_wait(), |l| l.wait));
+    Ok(Box::new(lock::Flock::at(path, Duration::from_secs(wait))))
+}
+
+#[cfg(feature = "redis")]
+fn redis_lock(
+    redis: Option<&RedisConfig>,
+    cfg: &LockConfig,
+    name: &str,
+) -> Result<Box<dyn Lock>, ConfigError> {
+    let redis = redis.ok_or_else(|| "[lock] redis = true needs a [redis] section".to_string())?;
+    Ok(Box::new(crate::redis::RedisLock::new(redis, cfg, name)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn redis_lock(
+    _: Option<&RedisConfig>,
+    _: &LockConfig,
+    _: &str,
+) -> Result<Box<dyn Lock>, ConfigError> {
+    Err(
+        "[lock] redis = true requires rook built with the `redis` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+#[cfg(feature = "postgres")]
+type PostgresJobs = crate::postgres::Jobs;
+#[cfg(not(feature = "postgres"))]
+type PostgresJobs = std::convert::Infallible;
+
+/// The `[postgres]` jobs table, read once for every hook that runs a command.
+#[cfg(feature = "postgres")]
+fn postgres_jobs(cfg: Option<&PostgresConfig>) -> Result<Option<PostgresJobs>, ConfigError> {
+    Ok(cfg.map(PostgresJobs::new).transpose()?)
+}
+
+/// `[postgres]` was already turned away
+#[cfg(not(feature = "postgres"))]
+fn postgres_jobs(_: Option<&PostgresConfig>) -> Result<Option<PostgresJobs>, ConfigError> {
+    Ok(None)
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_notifier(jobs: &PostgresJobs) -> Box<dyn Notifier> {
+    Box::new(jobs.clone())
+}
+
+#[cfg(not(feature = "postgres"))]
+fn postgres_notifier(jobs: &PostgresJobs) -> Box<dyn Notifier> {
+    match *jobs {}
+}
+
+#[cfg(feature = "matrix")]
+fn matrix_notifier(
+    cfg: Option<&MatrixConfig>,
+    on: _NotifyOn,
+) -> Result<Box<dyn Notifier>, ConfigError> {
+    let cfg = cfg.ok_or_else(|| "notify_matrix needs a [matrix] section".to_string())?;
+    Ok(Box::new(crate::matrix::Matrix::new(
+        cfg,
+        on == _NotifyOn::Always,
+    )?))
+}
+
+#[cfg(not(feature = "matrix"))]
+fn matrix_notifier(
+    _: Option<&MatrixConfig>,
+    _: _NotifyOn,
+) -> Result<Box<dyn Notifier>, ConfigError> {
+    Err(
+        "notify_matrix requires rook built with the `matrix` feature"
+            .to_string()
+            .into(),
+    )
+}
+
+/// [`HookOptions`] for an `"mqtt"` or `"watch"` hook, which only takes the options that don't
+/// depend on an http request.
+fn local_options(kind: &str, name: &str, raw: _HookOptions) -> Result<HookOptions, ConfigError> {
+    let http_only = raw.host.is_some()
+        || !raw.query.is_empty()
+        || !raw.allow_cidrs.is_empty()
+        || !raw.deny_cidrs.is_empty()
+        || raw.methods != default_methods()
+        || !raw.content_types.is_empty()
+        || !raw.pass_headers.is_empty()
+        || !raw.response_headers.is_empty()
+        || raw.require_client_cert
+        || raw.response != ResponseFormat::default()
+        || raw.success.is_some()
+        || !raw.forward.is_empty()
+        || raw.archive_dir.is_some()
+        || raw.spill.is_some()
+        || raw.maintenance.is_some()
+        || raw.allowed_hours.is_some();
+    if http_only {
+        return Err(format!(
+            "{} hook '{}' only takes executor, filter, rate_limit, and notify options",
+            kind, name
+        )
+        .into());
+    }
+    hook_options(raw, &[])
+}
+
+/// Parse `response_headers`, which can't set the headers rook manages itself.
+fn response_headers(raw: &BTreeMap<String, String>) -> Result<HeaderMap, ConfigError> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in raw {
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("bad response_headers name '{}'", name))?;
+        if RESERVED_RESPONSE_HEADERS.contains(&name.as_str()) {
+            return Err(format!("response_headers can't set '{}'", name).into());
+        }
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("bad response_headers value for '{}'", name))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Set per response by rook or hyper; `content-type` comes from `success.content_type`.
+const RESERVED_RESPONSE_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "content-type",
+    "transfer-encoding",
+    logging::REQUEST_ID_HEADER,
+];
+
+/// Wrap a hook's `response` renderer in its `success` one.
+fn success_renderer(raw: _Success, inner: Arc<dyn Renderer>) -> Result<Success, ConfigError> {
+    let status = StatusCode::from_u16(raw.status)
+        .ok()
+        .filter(|s| s.is_success())
+        .ok_or_else(|| format!("success status {} is not a 2xx status", raw.status))?;
+    if status == StatusCode::NO_CONTENT && raw.body.is_some() {
+        return Err("success status 204 can't have a body".to_string().into());
+    }
+    let content_type = raw
+        .content_type
+        .map(|t| HeaderValue::from_str(&t).map_err(|_| format!("bad success content_type '{}'", t)))
+        .transpose()?;
+    Ok(Success {
+        status,
+        body: raw.body,
+        content_type,
+        inner,
+    })
+}
+
+#[cfg(feature = "wasm")]
+fn wasm_filter(path: &str) -> Result<Arc<dyn Filter>, ConfigError> {
+    Ok(Arc::new(crate::wasm::WasmFilter::load(path)?))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn wasm_filter(_: &str) -> Result<Arc<dyn Filter>, ConfigError> {
+    Err("wasm_filter requires rook built with the `wasm` feature"
+        .to_string()
+        .into())
+}
+
+#[cfg(feature = "rhai")]
+fn script_filter(
+    filter: Option<&str>,
+    transform: Option<&str>,
+) -> Result<Arc<dyn Filter>, ConfigError> {
+    Ok(Arc::new(crate::script::ScriptFilter::compile(
+        filter, transform,
+    )?))
+}
+
+#[cfg(not(feature = "rhai"))]
+fn script_filter(_: Option<&str>, _: Option<&str>) -> Result<Arc<dyn Filter>, ConfigError> {
+    Err(
+        "filter_script and transform_script require rook built with the `rhai` feature"
+            .to_string()
+            .into(),
+    )
+}
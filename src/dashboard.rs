@@ -0,0 +1,130 @@
+//! `GET /admin`: a read-only html page of what `/admin/status` and `/admin/deliveries` serve as
+//! json, for a quick look from a browser.  Rendered on each request, with no scripts; it
+//! reloads itself every few seconds.
+use crate::{
+    config::RouteConfig,
+    encoding::escape_html,
+    exec, maintenance,
+    metrics::{Delivery, METRICS},
+};
+use std::fmt::Write;
+
+/// seconds between reloads
+const REFRESH: u32 = 5;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;\
+margin-bottom:2em}th,td{padding:.2em .8em;text-align:left;border-bottom:1px solid #ddd}\
+.bad{color:#b00}";
+
+pub fn render(cfg: &RouteConfig) -> String {
+    let mut page = format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\"><title>rook</title>\
+         <style>{}</style></head><body>\n<h1>rook</h1>\n",
+        REFRESH, STYLE
+    );
+    let _ = writeln!(
+        page,
+        "<p>connections {}/{} &middot; requests in flight {}/{} &middot; jobs running {}{}</p>",
+        METRICS.connections_open(),
+        cfg.max_connections,
+        METRICS.requests_in_flight(),
+        cfg.max_requests,
+        exec::running_count(),
+        cfg.max_jobs.map(|m| format!("/{}", m)).unwrap_or_default(),
+    );
+
+    for (route, held) in maintenance::paused() {
+        let _ = writeln!(
+            page,
+            "<p class=\"bad\">{} is paused, holding {} deliveries</p>",
+            escape_html(&route),
+            held,
+        );
+    }
+
+    page.push_str("<h2>Running jobs</h2>\n");
+    let jobs = exec::running();
+    if jobs.is_empty() {
+        page.push_str("<p>none</p>\n");
+    } else {
+        page.push_str(
+            "<table><tr><th>pid</th><th>runtime</th><th>route</th><th>command</th>\
+             <th>request id</th></tr>\n",
+        );
+        for job in jobs {
+            let _ = writeln!(
+                page,
+                "<tr><td>{}</td><td>{}s</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                job.pid,
+                job.started.elapsed().as_secs(),
+                escape_html(&job.route),
+                escape_html(&job.command),
+                escape_html(job.request_id.as_deref().unwrap_or("")),
+            );
+        }
+        page.push_str("</table>\n");
+    }
+
+    page.push_str("<h2>Hooks</h2>\n");
+    let outcomes = exec::outcomes();
+    if outcomes.is_empty() {
+        page.push_str("<p>no commands have finished yet</p>\n");
+    } else {
+        page.push_str(
+            "<table><tr><th>route</th><th>succeeded</th><th>failed</th><th>success rate</th>\
+             <th>last spawn error</th></tr>\n",
+        );
+        for (route, o) in outcomes {
+            let rate = o.succeeded * 100 / (o.succeeded + o.failed).max(1);
+            let _ = writeln!(
+                page,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td{}>{}%</td><td>{}</td></tr>",
+                escape_html(&route),
+                o.succeeded,
+                o.failed,
+                if o.failed > 0 { " class=\"bad\"" } else { "" },
+                rate,
+                o.last_spawn_error
+                    .map(|e| escape_html(&format!("{}: {}", e.command, e.error)))
+                    .unwrap_or_default(),
+            );
+        }
+        page.push_str("</table>\n");
+    }
+
+    page.push_str("<h2>Recent deliveries</h2>\n");
+    let deliveries = METRICS.history();
+    if deliveries.is_empty() {
+        page.push_str("<p>none</p>\n");
+    } else {
+        page.push_str(
+            "<table><tr><th>time</th><th>status</th><th>method</th><th>path</th>\
+             <th>client</th><th>request id</th></tr>\n",
+        );
+        for d in deliveries {
+            delivery_row(&mut page, &d);
+        }
+        page.push_str("</table>\n");
+    }
+    page.push_str("</body></html>\n");
+    page
+}
+
+fn delivery_row(page: &mut String, d: &Delivery) {
+    let _ = writeln!(
+        page,
+        "<tr><td>{}</td><td{}>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        escape_html(&d.time),
+        if d.status >= 400 {
+            " class=\"bad\""
+        } else {
+            ""
+        },
+        d.status,
+        escape_html(&d.method),
+        escape_html(&d.path),
+        d.client,
+        escape_html(&d.request_id),
+    );
+}
@@ -0,0 +1,74 @@
+//! Request bodies sent with a `Content-Encoding`, decompressed before signatures are checked
+//! and payloads parsed.
+//!
+//! `gzip` (or `x-gzip`) and `deflate` are supported, alone or listed in the order they were
+//! applied; `deflate` is the zlib format HTTP names, though raw deflate streams from senders
+//! that get it wrong are accepted too.  The decompressed body is held to the same size limit as
+//! an uncompressed one, so a small compressed body can't expand into an unbounded one.
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use hyper::header::HeaderValue;
+use std::io::{self, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// an encoding other than gzip, deflate, or identity
+    Unsupported,
+    /// larger than the limit once decompressed
+    TooLarge,
+    /// not valid data for its encoding
+    Malformed,
+}
+
+/// The encodings in a `Content-Encoding` header, in the order they were applied.  `identity`
+/// is dropped, so an empty list means the body is as sent.
+pub fn encodings(header: Option<&HeaderValue>) -> Result<Vec<Encoding>, DecodeError> {
+    let Some(header) = header else {
+        return Ok(vec![]);
+    };
+    let header = header.to_str().map_err(|_| DecodeError::Unsupported)?;
+    let mut encodings = vec![];
+    for name in header.split(',').map(str::trim) {
+        match name.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => encodings.push(Encoding::Gzip),
+            "deflate" => encodings.push(Encoding::Deflate),
+            "identity" => {}
+            _ => return Err(DecodeError::Unsupported),
+        }
+    }
+    Ok(encodings)
+}
+
+/// Undo `encodings` on `body`, failing once the result passes `limit` bytes.
+pub fn decode(encodings: &[Encoding], body: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = body.to_vec();
+    for encoding in encodings.iter().rev() {
+        decoded = match encoding {
+            Encoding::Gzip => read(MultiGzDecoder::new(decoded.as_slice()), limit)?,
+            Encoding::Deflate => match read(ZlibDecoder::new(decoded.as_slice()), limit) {
+                Err(DecodeError::Malformed) => {
+                    read(DeflateDecoder::new(decoded.as_slice()), limit)?
+                }
+                decoded => decoded?,
+            },
+        };
+    }
+    Ok(decoded)
+}
+
+fn read(decoder: impl Read, limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = vec![];
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|_: io::Error| DecodeError::Malformed)?;
+    if decoded.len() > limit {
+        return Err(DecodeError::TooLarge);
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,129 @@
+use crate::id;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    fs::OpenOptions,
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    process::ExitStatus,
+    sync::OnceLock,
+    time::Instant,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[derive(Deserialize)]
+pub struct EventsConfig {
+    /// json lines are appended here; a named pipe works too
+    pub path: String,
+}
+
+/// Unset unless `[events]` is configured.
+static EVENTS_PATH: OnceLock<String> = OnceLock::new();
+/// Unset unless the admin server's events feed is running.
+static FEED: OnceLock<OwnedFd> = OnceLock::new();
+
+/// One hook execution.  Start and finish events are written by the forked process that runs
+/// the hook, so nothing here may touch state shared with the server's threads.
+pub struct Job {
+    id: String,
+    request_id: Option<String>,
+    command: String,
+    started: Instant,
+}
+
+pub fn init(cfg: &EventsConfig) -> io::Result<()> {
+    // fail at startup rather than in every forked hook
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&cfg.path)?;
+    let _ = EVENTS_PATH.set(cfg.path.clone());
+    Ok(())
+}
+
+/// Also write every event to `feed`, the pipe behind `GET /admin/events`.
+#[cfg(feature = "server")]
+pub(crate) fn set_feed(feed: OwnedFd) {
+    let _ = FEED.set(feed);
+}
+
+/// The feed's descriptor, which forked processes keep open to report their hook's job.
+pub(crate) fn feed_fd() -> Option<RawFd> {
+    FEED.get().map(AsRawFd::as_raw_fd)
+}
+
+/// A new job for `command`, or None when events are disabled.
+pub fn job(command: &str, request_id: Option<&str>) -> Option<Job> {
+    if EVENTS_PATH.get().is_none() && FEED.get().is_none() {
+        return None;
+    }
+    Some(Job {
+        id: format!("{:016x}", id::random_u64()),
+        request_id: request_id.map(str::to_string),
+        command: command.to_string(),
+        started: Instant::now(),
+    })
+}
+
+impl Job {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn started(&self, pid: u32) {
+        self.write("hook_started", json!({ "pid": pid }));
+    }
+
+    /// The hook couldn't be forked or its command couldn't be started.
+    pub fn failed(&self, error: &str) {
+        self.write("hook_failed", json!({ "error": error }));
+    }
+
+    pub fn finished(&self, pid: u32, status: ExitStatus) {
+        self.write(
+            "hook_finished",
+            json!({
+                "pid": pid,
+                "exit_code": status.code(),
+                "signal": status.signal(),
+                "duration_ms": self.started.elapsed().as_millis() as u64,
+            }),
+        );
+    }
+
+    fn write(&self, event: &str, fields: Value) {
+        let mut record = json!({
+            "event": event,
+            "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "job_id": self.id,
+            "request_id": self.request_id,
+            "command": self.command,
+        });
+        if let (Value::Object(record), Value::Object(fields)) = (&mut record, fields) {
+            record.extend(fields);
+        }
+        let mut line = record.to_string();
+        line.push('\n');
+        // O_APPEND keeps lines whole when several hooks finish at once; opened per event so
+        // forked processes never share a handle (or its lock) with the server
+        if let Some(path) = EVENTS_PATH.get() {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| f.write_all(line.as_bytes()));
+        }
+        // a single write to a pipe is never interleaved with another's, for lines under
+        // PIPE_BUF; longer ones are rare and only the feed's reader would see them split
+        if let Some(feed) = FEED.get() {
+            let _ = feed
+                .try_clone()
+                .map(File::from)
+                .and_then(|mut f| f.write_all(line.as_bytes()));
+        }
+    }
+}
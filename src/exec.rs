@@ -0,0 +1,628 @@
+//! What happens to a delivery once its signature checks out.
+//!
+//! Every hook has an [`Executor`].  The default, [`Spawn`], runs the hook's command in a
+//! forked, detached process so a slow or misbehaving command never holds up the server.  The
+//! forked process stays to wait on the command, so the server can list what's still
+//! [`running`] and how each route's commands have turned out ([`outcomes`]), and reports how
+//! it exited when hook events are configured.  A command that can't be started, such as one
+//! that's missing or isn't executable, is logged with the reason once its forked process is
+//! reaped.
+use crate::{events, github::Event};
+use fork::Fork;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    path::{Path, PathBuf},
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+
+/// Commands started by [`Spawn`] that haven't exited, oldest first.
+static RUNNING: Mutex<Vec<RunningJob>> = Mutex::new(Vec::new());
+static OUTCOMES: Mutex<BTreeMap<String, Outcomes>> = Mutex::new(BTreeMap::new());
+/// how much of the end of a command's output [`Notifier`]s get
+pub const OUTPUT_TAIL: usize = 8 * 1024;
+
+/// A verified delivery's payload, parsed for its hook type.
+#[derive(Clone, Debug)]
+pub enum Payload {
+    Github(Box<Event>),
+    /// the body as text, trimmed
+    Rook(String),
+    /// an MQTT message's payload as text, trimmed
+    Mqtt(String),
+    /// the path of a file a `"watch"` hook saw appear
+    Watch(String),
+}
+
+impl Payload {
+    /// The hook type that accepted the delivery: `"github"`, `"rook"`, `"mqtt"`, or `"watch"`.
+    pub fn provider(&self) -> &'static str {
+        match self {
+            Payload::Github(_) => "github",
+            Payload::Rook(_) => "rook",
+            Payload::Mqtt(_) => "mqtt",
+            Payload::Watch(_) => "watch",
+        }
+    }
+}
+
+/// A verified delivery for one hook.
+pub struct Invocation<'a> {
+    /// the hook url the delivery was sent to
+    pub route: &'a str,
+    /// the hook's `command_path`
+    pub command: &'a str,
+    pub request_id: Option<&'a str>,
+    pub payload: &'a Payload,
+    /// the body as received; empty when it was spilled to `input_file`
+    pub body: &'a [u8],
+    /// a body too large to hold in memory, or a binary one, on disk; each command gets its own
+    /// link to it in `ROOK_INPUT_FILE`, and reads it on stdin
+    pub input_file: Option<&'a Path>,
+    /// hook data (`GITHUB_REPO`, `ROOK_INPUT`, ...) as commands see it in their environment
+    pub env: Vec<(String, String)>,
+    /// arguments for the command, from filters; empty unless a filter sets them
+    pub args: Vec<String>,
+}
+
+/// Runs a hook for a verified delivery.
+pub trait Executor: Send + Sync {
+    /// Start the hook.  This is called while the request is being answered, so anything slow
+    /// should be handed off rather than waited on.  Errors are only used to mark the hook as
+    /// failed; rook answers `500` when no hook for a delivery starts.
+    fn execute(&self, invocation: &Invocation) -> Result<(), String>;
+}
+
+/// Told how a hook's command ended, such as to send an email when it failed.
+///
+/// Notifiers are called in the forked process that waited on the command, which exits as soon
+/// as they return: a notifier finishes sending before it returns, and can't use the server's
+/// logger or async runtime.  The process writes a notifier's error to stderr instead.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, ended: &Ended) -> Result<(), String>;
+}
+
+/// Keeps rook instances that share the lock from running a hook's command at the same time,
+/// such as a `flock` on shared storage.
+///
+/// Like a [`Notifier`], a lock is used in the forked process that runs the command.
+pub trait Lock: Send + Sync {
+    /// Wait until the lock is free and take it.  It's held until the [`Held`] is dropped, once
+    /// the command exits.  An error means the command doesn't run.
+    fn acquire(&self) -> Result<Box<dyn Held>, String>;
+}
+
+/// A taken [`Lock`], released when dropped.
+pub trait Held {
+    /// A descriptor that must stay open while the lock is held.
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// How a hook's command ended, for [`Notifier`]s.
+pub struct Ended<'a> {
+    pub route: &'a str,
+    pub command: &'a str,
+    pub request_id: Option<&'a str>,
+    /// how the command exited, or why it couldn't be started
+    pub status: Result<ExitStatus, String>,
+    pub duration: Duration,
+    /// the end of what the command wrote to stdout and stderr
+    pub output: &'a [u8],
+}
+
+impl Ended<'_> {
+    pub fn failed(&self) -> bool {
+        !matches!(&self.status, Ok(status) if status.success())
+    }
+
+    /// `exited with status 1`, `killed by signal 9`, or why the command couldn't start.
+    pub fn describe(&self) -> String {
+        match &self.status {
+            Ok(status) => match (status.code(), status.signal()) {
+                (Some(code), _) => format!("exited with status {}", code),
+                (None, Some(signal)) => format!("killed by signal {}", signal),
+                (None, None) => status.to_string(),
+            },
+            Err(e) => format!("failed to start: {}", e),
+        }
+    }
+
+    /// [`output`](Self::output) as text, without the partial line it starts with when the
+    /// beginning was cut off.
+    pub fn output_text(&self) -> Cow<'_, str> {
+        let text = String::from_utf8_lossy(self.output);
+        if self.output.len() < OUTPUT_TAIL {
+            return text;
+        }
+        match text.split_once('\n') {
+            Some((_, rest)) => Cow::Owned(rest.to_string()),
+            None => text,
+        }
+    }
+}
+
+/// Run the hook's command in a forked, detached process, with the hook data in its
+/// environment.
+pub struct Spawn;
+
+impl Executor for Spawn {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        spawn(invocation, &[], None)
+    }
+}
+
+/// [`Spawn`], capturing the command's output so each [`Notifier`] can be told how the command
+/// ended.
+pub struct SpawnNotify(pub Vec<Box<dyn Notifier>>);
+
+impl Executor for SpawnNotify {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        spawn(invocation, &self.0, None)
+    }
+}
+
+/// [`SpawnNotify`], holding `lock` while the command runs.  Deliveries that arrive while the
+/// lock is taken wait for it, each in its own forked process.
+pub struct SpawnLocked {
+    pub lock: Box<dyn Lock>,
+    pub notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl Executor for SpawnLocked {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        spawn(invocation, &self.notifiers, Some(self.lock.as_ref()))
+    }
+}
+
+fn spawn(
+    invocation: &Invocation,
+    notifiers: &[Box<dyn Notifier>],
+    lock: Option<&dyn Lock>,
+) -> Result<(), String> {
+    let input = match invocation.input_file {
+        Some(path) => Some(
+            link_input(path).map_err(|e| format!("failed to link {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    let job = events::job(invocation.command, invocation.request_id);
+    let report = Report {
+        invocation,
+        notifiers,
+    };
+    // the forked process can't log, so it sends back why the command didn't start
+    let (failure, failed) = match pipe().and_then(|(r, w)| nonblocking(&r).map(|()| (r, w))) {
+        Ok((r, w)) => (Some(Arc::new(r)), Some(w)),
+        Err(_) => (None, None),
+    };
+    let input_path = input.as_deref();
+    let forked = fork_hook(
+        job.as_ref(),
+        Some(&report),
+        lock,
+        input_path,
+        failed,
+        |output| {
+            let (stdout, stderr) = match output {
+                Some(pipe) => (Stdio::from(pipe.try_clone()?), Stdio::from(pipe)),
+                None if cfg!(debug_assertions) => (Stdio::inherit(), Stdio::inherit()),
+                None => (Stdio::null(), Stdio::null()),
+            };
+            let stdin = match &input {
+                Some(input) => Stdio::from(fs::File::open(input)?),
+                None => Stdio::null(),
+            };
+            Command::new(invocation.command)
+                .stdin(stdin)
+                .stdout(stdout)
+                .stderr(stderr)
+                // https://security.stackexchange.com/a/14009
+                .args(&invocation.args)
+                .envs(invocation.env.iter().map(|(k, v)| (k, v)))
+                .envs(job.as_ref().map(|j| ("ROOK_JOB_ID", j.id())))
+                .envs(input.as_ref().map(|i| ("ROOK_INPUT_FILE", i)))
+                .spawn()
+        },
+    );
+    let Some(pid) = forked else {
+        if let Some(input) = &input {
+            let _ = fs::remove_file(input);
+        }
+        return Err("fork failed".to_string());
+    };
+    if let Ok(mut running) = RUNNING.lock() {
+        reap(&mut running);
+        running.push(RunningJob {
+            pid,
+            route: invocation.route.to_string(),
+            command: invocation.command.to_string(),
+            request_id: invocation.request_id.map(str::to_string),
+            started: Instant::now(),
+            failure,
+        });
+    }
+    Ok(())
+}
+
+/// A new link to a spilled body for one command, so it stays until that command exits.
+fn link_input(path: &Path) -> io::Result<PathBuf> {
+    let mut link = path.as_os_str().to_owned();
+    link.push(format!(".{:016x}", crate::id::random_u64()));
+    fs::hard_link(path, &link)?;
+    Ok(link.into())
+}
+
+/// The hook a forked process reports the end of.
+struct Report<'a> {
+    invocation: &'a Invocation<'a>,
+    notifiers: &'a [Box<dyn Notifier>],
+}
+
+impl Report<'_> {
+    fn notify(&self, status: Result<ExitStatus, String>, started: Instant, output: &[u8]) {
+        let ended = Ended {
+            route: self.invocation.route,
+            command: self.invocation.command,
+            request_id: self.invocation.request_id,
+            status,
+            duration: started.elapsed(),
+            output,
+        };
+        for notifier in self.notifiers {
+            if let Err(e) = notifier.notify(&ended) {
+                eprintln!("notify failed: route={} error={}", ended.route, e);
+            }
+        }
+    }
+}
+
+/// Run a hook with every one of several executors, such as spawning its command and
+/// publishing it to NATS.  Each one runs even if an earlier one fails; the hook fails if any
+/// of them does.
+pub struct Fanout(pub Vec<Box<dyn Executor>>);
+
+impl Executor for Fanout {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let errors: Vec<String> = self
+            .0
+            .iter()
+            .filter_map(|executor| executor.execute(invocation).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// A hook command that was still running when [`running`] was called.
+#[derive(Clone)]
+pub struct RunningJob {
+    /// the forked process waiting on the command
+    pub pid: i32,
+    pub route: String,
+    pub command: String,
+    pub request_id: Option<String>,
+    pub started: Instant,
+    /// read end of the pipe the forked process writes a spawn error to
+    failure: Option<Arc<OwnedFd>>,
+}
+
+/// The commands [`Spawn`] started that are still running, oldest first.
+pub fn running() -> Vec<RunningJob> {
+    match RUNNING.lock() {
+        Ok(mut running) => {
+            reap(&mut running);
+            running.clone()
+        }
+        Err(_) => vec![],
+    }
+}
+
+/// How many commands [`running`] would list.
+pub fn running_count() -> usize {
+    match RUNNING.lock() {
+        Ok(mut running) => {
+            reap(&mut running);
+            running.len()
+        }
+        Err(_) => 0,
+    }
+}
+
+/// How a route's commands have turned out since rook started.
+#[derive(Clone, Default)]
+pub struct Outcomes {
+    /// exited 0
+    pub succeeded: u64,
+    /// couldn't start, exited non-zero, or were killed
+    pub failed: u64,
+    /// the most recent command that couldn't be started
+    pub last_spawn_error: Option<SpawnError>,
+}
+
+/// Why a command couldn't be started, such as `No such file or directory (os error 2)`.
+#[derive(Clone)]
+pub struct SpawnError {
+    pub command: String,
+    pub error: String,
+    /// when the failure was noticed, which is when its forked process was reaped
+    pub at: OffsetDateTime,
+}
+
+/// The routes whose [`Spawn`]ed commands have finished, and how they turned out, by route.
+pub fn outcomes() -> BTreeMap<String, Outcomes> {
+    if let Ok(mut running) = RUNNING.lock() {
+        reap(&mut running);
+    }
+    OUTCOMES.lock().map(|o| o.clone()).unwrap_or_default()
+}
+
+/// Collect the forked processes that have exited, logging the commands that couldn't start.
+/// Also done whenever jobs are listed or counted.
+pub fn reap_finished() {
+    if let Ok(mut running) = RUNNING.lock() {
+        reap(&mut running);
+    }
+}
+
+/// Drop the jobs whose forked process has exited, collecting its exit status so it doesn't
+/// linger as a zombie.  The forked process exits 0 only when its command did.
+fn reap(running: &mut Vec<RunningJob>) {
+    running.retain(|job| {
+        let mut status = 0;
+        // 0: still running; the pid: exited and reaped; -1: not ours to wait on anymore
+        match unsafe { libc::waitpid(job.pid, &mut status, libc::WNOHANG) } {
+            0 => true,
+            -1 => false,
+            _ => {
+                if let Ok(mut outcomes) = OUTCOMES.lock() {
+                    let outcomes = outcomes.entry(job.route.clone()).or_default();
+                    if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
+                        outcomes.succeeded += 1;
+                    } else {
+                        outcomes.failed += 1;
+                    }
+                    if let Some(error) = job.failure.as_deref().and_then(spawn_error) {
+                        log::warn!(
+                            "hook failed to start: route={} command={} error={}",
+                            job.route,
+                            job.command,
+                            error
+                        );
+                        outcomes.last_spawn_error = Some(SpawnError {
+                            command: job.command.clone(),
+                            error,
+                            at: OffsetDateTime::now_utc(),
+                        });
+                    }
+                }
+                false
+            }
+        }
+    });
+}
+
+/// Log the command, arguments, and environment a hook would run with, and run nothing.
+#[derive(Clone)]
+pub struct DryRun;
+
+impl Executor for DryRun {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let input = invocation
+            .input_file
+            .map(|p| ("ROOK_INPUT_FILE", p.to_string_lossy()));
+        let env: Vec<String> = invocation
+            .env
+            .iter()
+            .map(|(k, v)| (k.as_str(), Cow::from(v.as_str())))
+            .chain(input)
+            .map(|(k, v)| format!("{}={:?}", k, v))
+            .collect();
+        log::info!(
+            "dry run: {} would run {} {:?} with {}",
+            invocation.route,
+            invocation.command,
+            invocation.args,
+            env.join(" ")
+        );
+        Ok(())
+    }
+}
+
+/// Run `f` (which should spawn the hook's command) in a forked, detached process.  Returns the
+/// forked process's pid, or None when the fork failed; the spawn itself happens in the child and
+/// isn't reported back.
+///
+/// be **very** careful that the forked function does not panic.
+///
+/// no logging on any failure, just one shot to run in a forked process
+/// process is detached with setsid after fork
+///
+/// the forked process stays to wait for the hook, and with a job reports how it exited
+pub fn run_forked<F>(job: Option<&events::Job>, f: F) -> Option<i32>
+where
+    F: Fn() -> io::Result<Child>,
+{
+    fork_hook(job, None, None, None, None, |_| f())
+}
+
+/// [`run_forked`], telling `report`'s notifiers how the command ended.  When there are any, `f`
+/// is handed a pipe for the command's stdout and stderr, and the end of what it writes is
+/// passed along.  With a `lock`, the command only starts once it's taken.  `input` is removed
+/// once the command exits.  If `f` fails, the error is written to `failed`.
+fn fork_hook<F>(
+    job: Option<&events::Job>,
+    report: Option<&Report>,
+    lock: Option<&dyn Lock>,
+    input: Option<&Path>,
+    failed: Option<OwnedFd>,
+    f: F,
+) -> Option<i32>
+where
+    F: Fn(Option<OwnedFd>) -> io::Result<Child>,
+{
+    let report = report.filter(|r| !r.notifiers.is_empty());
+    let started = Instant::now();
+    match fork::fork() {
+        Ok(Fork::Parent(pid)) => {
+            // we're in the parent process
+            drop(failed);
+            debug!("hook forked");
+            Some(pid)
+        }
+        Ok(Fork::Child) => {
+            // we're in the child process
+            let exit = |code| -> ! {
+                if let Some(input) = input {
+                    let _ = fs::remove_file(input);
+                }
+                process::exit(code)
+            };
+            if fork::setsid().is_err() {
+                // if we can't change our session id, don't try to start.
+                exit(1)
+            }
+            let held = match lock.map(Lock::acquire).transpose() {
+                Ok(held) => held,
+                Err(e) => {
+                    eprintln!("hook not started: {}", e);
+                    if let Some(job) = job {
+                        job.failed(&e);
+                    }
+                    if let Some(report) = report {
+                        report.notify(Err(e), started, &[]);
+                    }
+                    exit(1);
+                }
+            };
+            let (output, pipe) = match report.map(|_| pipe()) {
+                Some(Ok((output, pipe))) => (Some(output), Some(pipe)),
+                _ => (None, None),
+            };
+            match f(pipe) {
+                Ok(mut child) => {
+                    // don't hold rook's sockets open (and its port bound) while the hook runs
+                    let keep = [
+                        output.as_ref().map(|o| o.as_raw_fd()),
+                        held.as_ref().and_then(|h| h.fd()),
+                        events::feed_fd(),
+                    ];
+                    close_inherited_fds(&keep);
+                    let pid = child.id();
+                    if let Some(job) = job {
+                        job.started(pid);
+                    }
+                    // read until every copy of the pipe is closed, usually when the command exits
+                    let tail = output.map(|o| tail(File::from(o))).unwrap_or_default();
+                    let status = child.wait();
+                    drop(held);
+                    if let (Some(job), Ok(status)) = (job, &status) {
+                        job.finished(pid, *status);
+                    }
+                    let succeeded = matches!(&status, Ok(s) if s.success());
+                    if let Some(report) = report {
+                        report.notify(status.map_err(|e| e.to_string()), started, &tail);
+                    }
+                    exit(if succeeded { 0 } else { 1 });
+                }
+                Err(e) => {
+                    if let Some(failed) = failed {
+                        let _ = File::from(failed).write_all(e.to_string().as_bytes());
+                    }
+                    if let Some(job) = job {
+                        job.failed(&e.to_string());
+                    }
+                    if let Some(report) = report {
+                        report.notify(Err(e.to_string()), started, &[]);
+                    }
+                    exit(1);
+                }
+            }
+        }
+        Err(_) => {
+            // failed to fork
+            debug!("failed to fork");
+            if let Some(job) = job {
+                job.failed("fork failed");
+            }
+            None
+        }
+    }
+}
+
+/// The read and write ends of a new pipe, closed on exec.
+pub(crate) fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Everything read from `output` until it closes, keeping only the last [`OUTPUT_TAIL`] bytes.
+fn tail(mut output: File) -> Vec<u8> {
+    let mut tail = vec![];
+    let mut buf = [0; 4096];
+    loop {
+        match output.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => tail.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+        if tail.len() > 2 * OUTPUT_TAIL {
+            tail.drain(..tail.len() - OUTPUT_TAIL);
+        }
+    }
+    if tail.len() > OUTPUT_TAIL {
+        tail.drain(..tail.len() - OUTPUT_TAIL);
+    }
+    tail
+}
+
+/// Don't block reading from `fd`.
+fn nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The spawn error a forked process that has exited wrote, if any.  Other forked processes
+/// may still hold the pipe open, so this reads what's there rather than waiting for the end.
+fn spawn_error(failure: &OwnedFd) -> Option<String> {
+    let mut error = [0; 1024];
+    let read = unsafe { libc::read(failure.as_raw_fd(), error.as_mut_ptr().cast(), error.len()) };
+    let read = usize::try_from(read).ok().filter(|n| *n > 0)?;
+    Some(String::from_utf8_lossy(&error[..read]).into_owned())
+}
+
+/// Close everything but stdio, and `keep`, in a forked process.
+fn close_inherited_fds(keep: &[Option<RawFd>]) {
+    let fds: Vec<RawFd> = match fs::read_dir("/proc/self/fd") {
+        Ok(dir) => dir
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+            .filter(|fd| *fd > 2 && !keep.contains(&Some(*fd)))
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in fds {
+        // the read_dir handle itself is already closed, so this fails harmlessly on it
+        unsafe { libc::close(fd) };
+    }
+}
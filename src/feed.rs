@@ -0,0 +1,147 @@
+//! `GET /admin/events`: rook's activity as it happens, as server-sent events, so dashboards and
+//! other tools can follow it without polling.
+//!
+//! Each message's `event` names what happened and its `data` is a json object:
+//!
+//! * `delivery`: a request for a hook url was answered, with how many hooks verified its
+//!   signature and started, and why the signature was rejected when every hook rejected it
+//! * `hook_started`, `hook_finished`, `hook_failed`: a hook command's job, as [hook events]
+//!   writes them
+//!
+//! Job events come from the forked processes that wait on hook commands, over a pipe the
+//! server reads.  A client that falls behind misses events instead of holding rook up, and is
+//! told how many it missed in a comment.
+//!
+//! [hook events]: crate::events
+use crate::{
+    events, exec,
+    router::{Dispatch, MatchedRoute},
+};
+use hyper::{header, Body, Method, Response, StatusCode};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    net::IpAddr,
+    os::fd::{AsRawFd, OwnedFd},
+    sync::OnceLock,
+    thread,
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events held for each client that hasn't read them yet.
+const BACKLOG: usize = 256;
+/// A comment is sent this often, so proxies keep the stream open and closed clients are noticed.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+static FEED: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+
+#[derive(Clone)]
+struct Event {
+    name: String,
+    /// json
+    data: String,
+}
+
+/// Start the feed, and have hook jobs report to it.
+pub fn start() -> Result<(), String> {
+    let (read, write) = pipe().map_err(|e| format!("failed to open events feed pipe: {}", e))?;
+    let (tx, _) = broadcast::channel(BACKLOG);
+    if FEED.set(tx.clone()).is_err() {
+        return Ok(());
+    }
+    events::set_feed(write);
+    thread::spawn(move || {
+        for line in BufReader::new(read).lines() {
+            let Ok(line) = line else { break };
+            let name = serde_json::from_str::<Value>(&line)
+                .ok()
+                .and_then(|v| Some(v.get("event")?.as_str()?.to_string()));
+            if let Some(name) = name {
+                let _ = tx.send(Event { name, data: line });
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Tell the feed a request was answered, if it was for a hook url and anyone is listening.
+pub fn delivery(
+    request_id: &str,
+    client: IpAddr,
+    method: &Method,
+    path: &str,
+    res: &Response<Body>,
+) {
+    let Some(tx) = FEED.get().filter(|tx| tx.receiver_count() > 0) else {
+        return;
+    };
+    let Some(route) = res.extensions().get::<MatchedRoute>() else {
+        return;
+    };
+    let dispatch = res.extensions().get::<Dispatch>();
+    let data = json!({
+        "event": "delivery",
+        "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+        "request_id": request_id,
+        "client": client.to_string(),
+        "method": method.as_str(),
+        "path": path,
+        "route": route.0,
+        "status": res.status().as_u16(),
+        "verified": dispatch.map_or(0, |d| d.verified),
+        "started": dispatch.map_or(0, |d| d.started),
+        "rejection": dispatch.and_then(|d| d.rejection),
+    });
+    let _ = tx.send(Event {
+        name: "delivery".to_string(),
+        data: data.to_string(),
+    });
+}
+
+/// A `text/event-stream` response that follows the feed until the client goes away.
+pub fn response() -> Response<Body> {
+    let Some(mut rx) = FEED.get().map(broadcast::Sender::subscribe) else {
+        let mut resp = Response::new(Body::from("events feed not running"));
+        *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        return resp;
+    };
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut keepalive = tokio::time::interval(KEEPALIVE);
+        loop {
+            let message = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => format!("event: {}\ndata: {}\n\n", event.name, event.data),
+                    Err(RecvError::Lagged(missed)) => format!(": missed {} events\n\n", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+            };
+            if sender.send_data(message.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut resp = Response::new(body);
+    let headers = resp.headers_mut();
+    headers.insert(header::CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    headers.insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+    resp
+}
+
+/// The read end of a new pipe, and the write end, which doesn't block: a job event is dropped
+/// rather than hold up a hook's process if the pipe is ever full.
+fn pipe() -> io::Result<(File, OwnedFd)> {
+    let (read, write) = exec::pipe()?;
+    // SAFETY: fcntl only fails on a descriptor that isn't open
+    let flags = unsafe { libc::fcntl(write.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0
+        || unsafe { libc::fcntl(write.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((File::from(read), write))
+}
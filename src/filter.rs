@@ -0,0 +1,35 @@
+//! Checks that run on a verified delivery before its hook starts.
+//!
+//! A hook's filters run in order; each can turn the delivery away, which skips the hook
+//! without failing the request, or add environment variables for the command.  A filter that
+//! errors fails the hook, as if its command couldn't be started.
+use crate::exec::Invocation;
+
+/// What a [`Filter`] decided.
+#[derive(Debug, Default)]
+pub struct Verdict {
+    /// false skips the hook
+    pub allow: bool,
+    /// added to the hook's environment; names rook already sets are ignored
+    pub env: Vec<(String, String)>,
+    /// appended to the hook command's arguments
+    pub args: Vec<String>,
+}
+
+pub trait Filter: Send + Sync {
+    /// Decide whether the hook should run for `invocation`, which has the environment rook
+    /// and any earlier filters set.
+    fn filter(&self, invocation: &Invocation) -> Result<Verdict, String>;
+}
+
+/// Add `env` from a filter, keeping what's already set and skipping names the environment
+/// can't hold.
+#[cfg(feature = "server")]
+pub(crate) fn extend_env(env: &mut Vec<(String, String)>, extra: Vec<(String, String)>) {
+    for (name, value) in extra {
+        let valid = !name.is_empty() && !name.contains(['=', '\0']) && !value.contains('\0');
+        if valid && !env.iter().any(|(n, _)| *n == name) {
+            env.push((name, value));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Requests saved as they arrived, to be fed back through routing later: regression suites
+//! built from real provider traffic.
+//!
+//! `rook --record <dir>` saves every request as two files named like an
+//! [archived delivery](crate::archive): `<name>.body` holds the body as received, and
+//! `<name>.json` the method, uri, socket address, every header, and the status rook answered
+//! with.  Unlike the archive and the delivery history, headers aren't redacted, since a
+//! replayed request needs its signature; files are only readable by rook's user.  Bodies rook
+//! never read, because the request was turned away first or was spilled to disk, are saved
+//! empty.
+//!
+//! `rook replay CONFIG_FILE <dir>` sends each saved request through a config's routing, in
+//! name order, and reports those answered with a different status than when they were
+//! recorded.
+use crate::{archive, service::Connection};
+use hyper::{Body, Request};
+use serde_json::{json, Value};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+const BODY: &str = "body";
+const SIDECAR: &str = "json";
+
+/// Saves requests to a directory.
+pub struct Recorder {
+    dir: PathBuf,
+}
+
+impl Recorder {
+    /// Create `dir` if it's missing.
+    pub fn open(dir: &str) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create record dir '{}': {}", dir, e))?;
+        Ok(Recorder {
+            dir: PathBuf::from(dir),
+        })
+    }
+
+    /// Write `fixture` in the background.
+    pub fn record(&self, fixture: Fixture) {
+        let now = OffsetDateTime::now_utc();
+        let name = archive::name(now, fixture.request_id.as_deref());
+        let sidecar = json!({
+            "recorded": now.format(&Rfc3339).expect("formatting a utc timestamp"),
+            "request_id": fixture.request_id,
+            "remote": fixture.remote.to_string(),
+            "method": fixture.method,
+            "uri": fixture.uri,
+            "headers": fixture.headers,
+            "status": fixture.status,
+        });
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let sidecar = serde_json::to_vec_pretty(&sidecar).unwrap_or_default();
+            let (body, saved) = (
+                dir.join(format!("{}.{}", name, BODY)),
+                dir.join(format!("{}.{}", name, SIDECAR)),
+            );
+            let written =
+                write_private(&body, &fixture.body).and_then(|()| write_private(&saved, &sidecar));
+            if let Err(e) = written {
+                log::warn!(
+                    "record failed: dir={} name={} error={}",
+                    dir.display(),
+                    name,
+                    e
+                );
+            }
+        });
+    }
+}
+
+/// One saved request.
+pub struct Fixture {
+    /// the files' name, without an extension
+    pub name: String,
+    pub request_id: Option<String>,
+    /// the socket's peer address, before trusted forwarding headers
+    pub remote: IpAddr,
+    pub method: String,
+    /// path and query
+    pub uri: String,
+    /// every header, in the order received
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// what rook answered; None until the request is answered
+    pub status: Option<u16>,
+}
+
+impl Fixture {
+    /// Everything about `req` but its body and answer.
+    pub fn start(req: &Request<Body>, remote: IpAddr, request_id: &str) -> Self {
+        Fixture {
+            name: String::new(),
+            request_id: Some(request_id.to_string()),
+            remote,
+            method: req.method().to_string(),
+            uri: req.uri().to_string(),
+            headers: req
+                .headers()
+                .iter()
+                .map(|(k, v)| {
+                    let value = String::from_utf8_lossy(v.as_bytes()).to_string();
+                    (k.to_string(), value)
+                })
+                .collect(),
+            body: vec![],
+            status: None,
+        }
+    }
+
+    /// The request as it arrived, from the same address.
+    pub fn request(&self) -> Result<Request<Body>, String> {
+        let mut req = Request::builder()
+            .method(self.method.as_str())
+            .uri(self.uri.as_str());
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        let mut req = req
+            .body(Body::from(self.body.clone()))
+            .map_err(|e| format!("bad fixture '{}': {}", self.name, e))?;
+        req.extensions_mut().insert(Connection {
+            remote: SocketAddr::new(self.remote, 0),
+            client_cn: None,
+        });
+        Ok(req)
+    }
+}
+
+/// Every fixture in `dir`, in name order.
+pub fn load(dir: &str) -> Result<Vec<Fixture>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("failed to read '{}': {}", dir, e))?;
+    let mut names = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("failed to read '{}': {}", dir, e))?;
+        if let Some(name) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.strip_suffix(SIDECAR)?.strip_suffix('.'))
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort_unstable();
+    names
+        .into_iter()
+        .map(|name| read(Path::new(dir), name))
+        .collect()
+}
+
+fn read(dir: &Path, name: String) -> Result<Fixture, String> {
+    let sidecar = dir.join(format!("{}.{}", name, SIDECAR));
+    let bad = |reason: &str| format!("bad fixture '{}': {}", sidecar.display(), reason);
+    let text = fs::read(&sidecar).map_err(|e| bad(&e.to_string()))?;
+    let saved: Value = serde_json::from_slice(&text).map_err(|e| bad(&e.to_string()))?;
+    let body = dir.join(format!("{}.{}", name, BODY));
+    let body = fs::read(body).map_err(|e| bad(&e.to_string()))?;
+    let text = |key: &str| {
+        saved[key]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| bad(&format!("missing {}", key)))
+    };
+    let headers = saved["headers"]
+        .as_array()
+        .ok_or_else(|| bad("missing headers"))?
+        .iter()
+        .map(|pair| match (pair[0].as_str(), pair[1].as_str()) {
+            (Some(k), Some(v)) => Ok((k.to_string(), v.to_string())),
+            _ => Err(bad("headers must be [name, value] pairs")),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Fixture {
+        request_id: saved["request_id"].as_str().map(str::to_string),
+        remote: text("remote")?.parse().map_err(|_| bad("bad remote"))?,
+        method: text("method")?,
+        uri: text("uri")?,
+        headers,
+        body,
+        status: saved["status"].as_u64().and_then(|s| u16::try_from(s).ok()),
+        name,
+    })
+}
+
+/// Write `data` to `path`, which must not exist yet, readable only by rook's user.
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(data)
+}
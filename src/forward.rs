@@ -0,0 +1,120 @@
+//! Re-deliver verified payloads to other webhook urls, so one internet-facing rook can feed
+//! machines that aren't reachable from outside.
+//!
+//! Each delivery is signed again, with the hook's own secret or its `forward_secret_file`, and
+//! sent the way the original arrived: GitHub events as GitHub would, rook payloads as a rook
+//! sender would.  Sends run in the background and are retried with backoff, so a slow or down
+//! downstream never holds up the response.
+use crate::{
+    client::{self, Style},
+    exec::{Invocation, Payload},
+    logging,
+};
+use std::{sync::Arc, time::Duration};
+
+/// First wait between attempts; doubles after each one.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where a hook forwards its verified deliveries.
+#[derive(Clone)]
+pub struct Forward {
+    urls: Arc<[String]>,
+    secret: Arc<[u8]>,
+    /// attempts after the first
+    retries: u32,
+    /// only log what would be sent
+    dry_run: bool,
+}
+
+impl Forward {
+    pub fn new(urls: Vec<String>, secret: Vec<u8>, retries: u32) -> Self {
+        Forward {
+            urls: urls.into(),
+            secret: secret.into(),
+            retries,
+            dry_run: false,
+        }
+    }
+
+    /// The same urls, but only logged, as `dry_run = true` does.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    /// Send `invocation`'s body to every url in the background.
+    pub fn deliver(&self, invocation: &Invocation) {
+        let style = match invocation.payload {
+            Payload::Github(event) => Style::Github {
+                event: event.name().to_string(),
+            },
+            Payload::Rook(_) | Payload::Mqtt(_) | Payload::Watch(_) => Style::Rook,
+        };
+        let style = Arc::new(style);
+        for url in self.urls.iter() {
+            if self.dry_run {
+                log::info!("dry run: {} would forward to {}", invocation.route, url);
+                continue;
+            }
+            let (url, secret, style) = (url.clone(), self.secret.clone(), style.clone());
+            let body = invocation.body.to_vec();
+            let route = invocation.route.to_string();
+            let retries = self.retries;
+            let sending = async move {
+                send(&route, &url, &secret, body, &style, retries).await;
+            };
+            // keep the request id on the log lines of sends that outlive the request
+            match logging::current_request_id() {
+                Some(id) => tokio::spawn(logging::with_request_id(id, sending)),
+                None => tokio::spawn(sending),
+            };
+        }
+    }
+}
+
+/// Send until `url` accepts the delivery or `retries` more attempts have failed.  Connection
+/// errors, `429`, and `5xx` are retried; anything else is final.
+async fn send(route: &str, url: &str, secret: &[u8], body: Vec<u8>, style: &Style, retries: u32) {
+    let mut wait = BACKOFF;
+    for attempt in 0..=retries {
+        let error = match client::send(url, secret, body.clone(), style).await {
+            Ok(res) if res.status().is_success() => {
+                debug!("forwarded {} to {}: {}", route, url, res.status());
+                return;
+            }
+            Ok(res) if res.status().is_server_error() || res.status().as_u16() == 429 => {
+                res.status().to_string()
+            }
+            Ok(res) => {
+                log::warn!(
+                    "forward failed: path={} url={} status={}",
+                    route,
+                    url,
+                    res.status()
+                );
+                return;
+            }
+            Err(e) => e,
+        };
+        if attempt == retries {
+            log::warn!(
+                "forward failed: path={} url={} attempts={} error={}",
+                route,
+                url,
+                attempt + 1,
+                error
+            );
+            return;
+        }
+        debug!(
+            "forward to {} failed ({}), retrying in {:?}",
+            url, error, wait
+        );
+        tokio::time::sleep(wait).await;
+        wait *= 2;
+    }
+}
@@ -0,0 +1,85 @@
+use crate::cidr::Cidr;
+use hyper::header::{HeaderMap, HeaderValue};
+use std::net::{IpAddr, SocketAddr};
+
+/// Recover the original client address when the peer is a trusted reverse proxy.
+///
+/// Headers are consulted in order of preference: `Forwarded` (RFC 7239), `X-Forwarded-For`,
+/// then `X-Real-IP`.  Forwarding chains are walked right to left, skipping trusted proxies, so a
+/// client can't spoof its address by sending its own header through the proxy.  Untrusted peers
+/// and unparseable headers fall back to the address of the immediate peer.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap<HeaderValue>, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|c| c.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let chain = forwarded_chain(headers)
+        .or_else(|| x_forwarded_for_chain(headers))
+        .or_else(|| header_str(headers, "x-real-ip").map(|v| vec![parse_node(v)]));
+    let chain = match chain {
+        Some(c) => c,
+        None => return peer,
+    };
+    let mut client = peer;
+    for hop in chain.iter().rev() {
+        match hop {
+            Some(ip) if is_trusted(ip) => client = *ip,
+            Some(ip) => return *ip,
+            // obfuscated or malformed hop; the last trusted address is the best we know
+            None => return client,
+        }
+    }
+    client
+}
+
+fn header_str<'a>(headers: &'a HeaderMap<HeaderValue>, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok().map(str::trim)
+}
+
+/// every value of a (possibly repeated) comma-separated header, in order
+fn list_header<'a>(headers: &'a HeaderMap<HeaderValue>, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn forwarded_chain(headers: &HeaderMap<HeaderValue>) -> Option<Vec<Option<IpAddr>>> {
+    let chain: Vec<Option<IpAddr>> = list_header(headers, "forwarded")
+        .into_iter()
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(k, _)| k.eq_ignore_ascii_case("for"))
+                .and_then(|(_, v)| parse_node(v))
+        })
+        .collect();
+    (!chain.is_empty()).then_some(chain)
+}
+
+fn x_forwarded_for_chain(headers: &HeaderMap<HeaderValue>) -> Option<Vec<Option<IpAddr>>> {
+    let chain: Vec<Option<IpAddr>> = list_header(headers, "x-forwarded-for")
+        .into_iter()
+        .map(parse_node)
+        .collect();
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// parse `1.2.3.4`, `1.2.3.4:80`, `::1`, `[::1]`, `"[::1]:80"`; `unknown` and `_hidden` are None
+fn parse_node(v: &str) -> Option<IpAddr> {
+    let v = v.trim().trim_matches('"');
+    if let Ok(ip) = v.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(sock) = v.parse::<SocketAddr>() {
+        return Some(sock.ip());
+    }
+    v.strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .and_then(|v| v.parse().ok())
+}
@@ -0,0 +1,316 @@
+//! Payloads GitHub sends to `"github"` hooks.
+//!
+//! [`GithubPayload`] holds the few fields rook itself needs.  [`Event`] parses the whole
+//! delivery for the common events, keyed by the `x-github-event` header; only the fields most
+//! hooks care about are typed, and the raw body is always available alongside.
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Header naming the event a delivery is for.
+pub const EVENT_HEADER: &str = "x-github-event";
+
+/// The fields rook reads from every GitHub delivery.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GithubPayload {
+    /// the pushed ref, such as `refs/heads/main`
+    #[serde(rename = "ref")]
+    pub reference: String,
+    /// the commit the ref points to after the push
+    #[serde(rename = "after")]
+    pub commit: String,
+    #[serde(rename = "repository")]
+    pub repo: GithubRepo,
+}
+
+impl GithubPayload {
+    /// The branch a push to `refs/heads/<branch>` is for, if its name is [safe](safe_ref_name).
+    pub fn branch(&self) -> Option<&str> {
+        safe_ref_name(self.reference.strip_prefix("refs/heads/")?)
+    }
+
+    /// The tag a push to `refs/tags/<tag>` is for, if its name is [safe](safe_ref_name).
+    pub fn tag_name(&self) -> Option<&str> {
+        safe_ref_name(self.reference.strip_prefix("refs/tags/")?)
+    }
+}
+
+/// `name` if a shell or git can take it as it is: only ASCII letters, digits, and `._+-/`; not
+/// starting with `-`, `.`, or `/`; not ending with `/`, `.`, or `.lock`; and without `..` or
+/// `//`.  Git allows more, but a name outside these is more likely an attack than a branch.
+pub fn safe_ref_name(name: &str) -> Option<&str> {
+    let charset = name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"._+-/".contains(&b));
+    let safe = charset
+        && !name.starts_with(['-', '.', '/'])
+        && !name.ends_with(['/', '.'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//");
+    (safe && !name.is_empty()).then_some(name)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GithubRepo {
+    /// `owner/name`
+    pub full_name: String,
+}
+
+/// A delivery parsed according to its `x-github-event` header.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Push(PushEvent),
+    PullRequest(PullRequestEvent),
+    Release(ReleaseEvent),
+    Ping(PingEvent),
+    WorkflowRun(WorkflowRunEvent),
+    IssueComment(IssueCommentEvent),
+    /// any other event, or one whose payload didn't have the expected fields
+    Other {
+        name: String,
+        payload: Value,
+    },
+}
+
+impl Event {
+    /// Parse `body` as the event `name`.  Deliveries without an event header are treated as
+    /// pushes, which is all rook handled before events were typed.
+    pub fn parse(name: Option<&str>, body: &[u8]) -> Result<Event, serde_json::Error> {
+        let name = name.unwrap_or("push");
+        let payload: Value = serde_json::from_slice(body)?;
+        let typed = match name {
+            "push" => PushEvent::deserialize(&payload).map(Event::Push),
+            "pull_request" => PullRequestEvent::deserialize(&payload).map(Event::PullRequest),
+            "release" => ReleaseEvent::deserialize(&payload).map(Event::Release),
+            "ping" => PingEvent::deserialize(&payload).map(Event::Ping),
+            "workflow_run" => WorkflowRunEvent::deserialize(&payload).map(Event::WorkflowRun),
+            "issue_comment" => IssueCommentEvent::deserialize(&payload).map(Event::IssueComment),
+            _ => return Ok(Event::other(name, payload)),
+        };
+        Ok(typed.unwrap_or_else(|_| Event::other(name, payload)))
+    }
+
+    fn other(name: &str, payload: Value) -> Event {
+        Event::Other {
+            name: name.to_string(),
+            payload,
+        }
+    }
+
+    /// The event's name, as sent in `x-github-event`.
+    pub fn name(&self) -> &str {
+        match self {
+            Event::Push(_) => "push",
+            Event::PullRequest(_) => "pull_request",
+            Event::Release(_) => "release",
+            Event::Ping(_) => "ping",
+            Event::WorkflowRun(_) => "workflow_run",
+            Event::IssueComment(_) => "issue_comment",
+            Event::Other { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Repository {
+    pub id: u64,
+    /// `owner/name`
+    pub full_name: String,
+    pub name: String,
+    #[serde(default)]
+    pub private: bool,
+    pub html_url: String,
+    #[serde(default)]
+    pub clone_url: Option<String>,
+    #[serde(default)]
+    pub default_branch: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    pub id: u64,
+    pub login: String,
+}
+
+/// Author or committer of a pushed commit.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CommitAuthor {
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Commit {
+    pub id: String,
+    pub message: String,
+    pub timestamp: String,
+    pub url: String,
+    pub author: CommitAuthor,
+    #[serde(default)]
+    pub added: Vec<String>,
+    #[serde(default)]
+    pub removed: Vec<String>,
+    #[serde(default)]
+    pub modified: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PushEvent {
+    /// the pushed ref, such as `refs/heads/main`
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub before: String,
+    pub after: String,
+    #[serde(default)]
+    pub created: bool,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub forced: bool,
+    #[serde(default)]
+    pub commits: Vec<Commit>,
+    #[serde(default)]
+    pub head_commit: Option<Commit>,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+/// One side of a pull request.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Branch {
+    /// branch name
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub sha: String,
+    /// `owner:branch`
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PullRequest {
+    pub id: u64,
+    pub number: u64,
+    pub title: String,
+    /// `open` or `closed`
+    pub state: String,
+    pub html_url: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub merged: bool,
+    #[serde(default)]
+    pub merge_commit_sha: Option<String>,
+    pub head: Branch,
+    pub base: Branch,
+    pub user: User,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PullRequestEvent {
+    /// `opened`, `closed`, `synchronize`, ...
+    pub action: String,
+    pub number: u64,
+    pub pull_request: PullRequest,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Release {
+    pub id: u64,
+    pub tag_name: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    pub html_url: String,
+    pub target_commitish: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReleaseEvent {
+    /// `published`, `created`, `released`, ...
+    pub action: String,
+    pub release: Release,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+/// Sent when a webhook is created.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PingEvent {
+    pub zen: String,
+    pub hook_id: u64,
+    /// missing for organization and app hooks
+    #[serde(default)]
+    pub repository: Option<Repository>,
+    #[serde(default)]
+    pub sender: Option<User>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WorkflowRun {
+    pub id: u64,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub head_branch: Option<String>,
+    pub head_sha: String,
+    /// what triggered the run, such as `push`
+    pub event: String,
+    /// `queued`, `in_progress`, `completed`, ...
+    #[serde(default)]
+    pub status: Option<String>,
+    /// `success`, `failure`, ...; set once the run completes
+    #[serde(default)]
+    pub conclusion: Option<String>,
+    pub run_number: u64,
+    pub html_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WorkflowRunEvent {
+    /// `requested`, `in_progress`, or `completed`
+    pub action: String,
+    pub workflow_run: WorkflowRun,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Issue {
+    pub id: u64,
+    pub number: u64,
+    pub title: String,
+    pub state: String,
+    pub html_url: String,
+    pub user: User,
+    /// set when the issue is a pull request
+    #[serde(default)]
+    pub pull_request: Option<Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub body: String,
+    pub html_url: String,
+    pub user: User,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IssueCommentEvent {
+    /// `created`, `edited`, or `deleted`
+    pub action: String,
+    pub issue: Issue,
+    pub comment: Comment,
+    pub repository: Repository,
+    pub sender: User,
+}
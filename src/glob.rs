@@ -0,0 +1,69 @@
+//! File patterns for `"watch"` hooks: an absolute directory, then a file name where `*` matches
+//! any run of characters and `?` any one.  `/srv/incoming/*.json` matches
+//! `/srv/incoming/a.json` but not `/srv/incoming/sub/a.json`.  As in a shell, a wildcard doesn't
+//! match the leading `.` of a hidden file, so the temporary files many tools write before
+//! renaming into place are left alone.
+//!
+//! `"github"` hooks match repos and refs with the same wildcards, through [`matches_text`].
+
+/// Check `pattern` is an absolute path with wildcards only in its file name.
+pub fn validate(pattern: &str) -> Result<(), String> {
+    let Some((dir, name)) = pattern.rsplit_once('/') else {
+        return Err(format!("watch '{}' must be an absolute path", pattern));
+    };
+    if !pattern.starts_with('/') || pattern.contains('\0') {
+        return Err(format!("watch '{}' must be an absolute path", pattern));
+    }
+    if dir.contains(['*', '?']) {
+        return Err(format!(
+            "watch '{}' may only use wildcards in the file name",
+            pattern
+        ));
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("watch '{}' must end with a file name", pattern));
+    }
+    Ok(())
+}
+
+/// The directory a pattern's files appear in.
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub fn dir(pattern: &str) -> &str {
+    match pattern.rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((dir, _)) => dir,
+        None => pattern,
+    }
+}
+
+/// Whether `path` is one of the files `pattern` names.
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub fn matches(pattern: &str, path: &str) -> bool {
+    match (pattern.rsplit_once('/'), path.rsplit_once('/')) {
+        (Some((dir, name)), Some((path_dir, file))) if dir == path_dir => {
+            if file.starts_with('.') && !name.starts_with('.') {
+                return false;
+            }
+            wildcard(name.as_bytes(), file.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters (`/` included)
+/// and `?` any one: `"github"` hooks' `repo`, `refs`, and their exclusions.
+pub fn matches_text(pattern: &str, text: &str) -> bool {
+    wildcard(pattern.as_bytes(), text.as_bytes())
+}
+
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            wildcard(rest, name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => wildcard(rest, name),
+        (Some((p, rest)), Some((n, name))) if p == n => wildcard(rest, name),
+        _ => false,
+    }
+}
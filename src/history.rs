@@ -0,0 +1,312 @@
+use crate::{
+    config::HistoryConfig,
+    router::{Dispatch, MatchedRoute},
+};
+use hyper::{Body, Method, Response};
+use serde_json::Value;
+use std::net::IpAddr;
+
+/// One finished request, as stored by [`History::record`].
+#[derive(Clone)]
+#[cfg_attr(not(any(feature = "postgres", feature = "sqlite")), allow(dead_code))]
+pub struct Record {
+    pub request_id: String,
+    pub client: IpAddr,
+    pub method: String,
+    /// path and query string, as requested
+    pub path: String,
+    /// url of the hooks the request was routed to
+    pub route: Option<String>,
+    pub status: u16,
+    /// json object of request headers, credentials redacted
+    pub headers: Value,
+    pub body: Option<Vec<u8>>,
+    pub verified: usize,
+    pub started: usize,
+}
+
+impl Record {
+    /// Collect a finished request, taking the router's [`Dispatch`] out of the response.
+    pub fn new(
+        request_id: &str,
+        client: IpAddr,
+        method: &Method,
+        path: &str,
+        headers: Value,
+        res: &mut Response<Body>,
+    ) -> Record {
+        let dispatch = res
+            .extensions_mut()
+            .remove::<Dispatch>()
+            .unwrap_or_default();
+        Record {
+            request_id: request_id.to_string(),
+            client,
+            method: method.to_string(),
+            path: path.to_string(),
+            route: res.extensions().get::<MatchedRoute>().map(|m| m.0.clone()),
+            status: res.status().as_u16(),
+            headers,
+            body: dispatch.body,
+            verified: dispatch.verified,
+            started: dispatch.started,
+        }
+    }
+}
+
+/// headers that never reach the history database
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+pub fn headers_json(headers: &hyper::HeaderMap) -> Value {
+    let mut map = serde_json::Map::new();
+    for (name, value) in headers {
+        let value = if is_redacted(name.as_str()) {
+            "[redacted]".to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        map.insert(name.to_string(), Value::String(value));
+    }
+    Value::Object(map)
+}
+
+/// stored as `[redacted]`, so never replayed
+pub fn is_redacted(name: &str) -> bool {
+    REDACTED_HEADERS.contains(&name)
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::History;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{HistoryConfig, Record};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use std::net::IpAddr;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    };
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+    /// expired rows are deleted on startup and after this many inserts
+    const PRUNE_EVERY: u64 = 100;
+
+    /// Deliveries persisted in sqlite and pruned after `retention_days`.
+    pub struct History {
+        conn: Arc<Mutex<Connection>>,
+        retention_secs: i64,
+        store_body: bool,
+        inserts: AtomicU64,
+    }
+
+    impl History {
+        pub fn open(cfg: &HistoryConfig) -> Result<History, String> {
+            let conn = Connection::open(&cfg.path)
+                .map_err(|e| format!("failed to open history '{}': {}", cfg.path, e))?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS deliveries (
+                    id INTEGER PRIMARY KEY,
+                    time TEXT NOT NULL,
+                    unix INTEGER NOT NULL,
+                    request_id TEXT NOT NULL,
+                    client TEXT NOT NULL,
+                    method TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    route TEXT,
+                    status INTEGER NOT NULL,
+                    headers TEXT NOT NULL,
+                    body_sha256 TEXT,
+                    body BLOB,
+                    verified INTEGER NOT NULL,
+                    started INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS deliveries_unix ON deliveries (unix);
+                CREATE INDEX IF NOT EXISTS deliveries_request_id ON deliveries (request_id);",
+            )
+            .map_err(|e| format!("failed to create history tables: {}", e))?;
+            let history = History {
+                conn: Arc::new(Mutex::new(conn)),
+                retention_secs: (cfg.retention_days * 86400) as i64,
+                store_body: cfg.store_body,
+                inserts: AtomicU64::new(0),
+            };
+            if let Ok(conn) = history.conn.lock() {
+                prune(&conn, history.retention_secs);
+            }
+            Ok(history)
+        }
+
+        /// Store `record` off the async runtime.  Failures are logged and otherwise ignored.
+        pub async fn record(&self, record: Record) {
+            let conn = self.conn.clone();
+            let store_body = self.store_body;
+            let retention_secs = self.retention_secs;
+            let prune_now = self
+                .inserts
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(PRUNE_EVERY);
+            let stored = tokio::task::spawn_blocking(move || {
+                let conn = conn
+                    .lock()
+                    .map_err(|_| "history lock poisoned".to_string())?;
+                let now = OffsetDateTime::now_utc();
+                let body_sha256 = record.body.as_deref().map(sha256_hex);
+                conn.execute(
+                    "INSERT INTO deliveries (time, unix, request_id, client, method, path, route,
+                        status, headers, body_sha256, body, verified, started)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    params![
+                        now.format(&Rfc3339).unwrap_or_default(),
+                        now.unix_timestamp(),
+                        record.request_id,
+                        record.client.to_string(),
+                        record.method,
+                        record.path,
+                        record.route,
+                        record.status,
+                        record.headers.to_string(),
+                        body_sha256,
+                        record.body.filter(|_| store_body),
+                        record.verified as i64,
+                        record.started as i64,
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+                if prune_now {
+                    prune(&conn, retention_secs);
+                }
+                Ok::<_, String>(())
+            })
+            .await;
+            match stored {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::warn!("failed to record delivery: {}", e),
+                Err(e) => log::warn!("failed to record delivery: {}", e),
+            }
+        }
+
+        /// Newest first, optionally only one route's deliveries.
+        pub async fn list(&self, limit: usize, route: Option<String>) -> Result<Value, String> {
+            let conn = self.conn.clone();
+            tokio::task::spawn_blocking(move || {
+                let conn = conn.lock().map_err(|_| "history lock poisoned".to_string())?;
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT time, request_id, client, method, path, route, status, headers,
+                            body_sha256, body, verified, started
+                        FROM deliveries WHERE ?1 IS NULL OR route = ?1
+                        ORDER BY id DESC LIMIT ?2",
+                    )
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map(params![route, limit as i64], |row| {
+                        let headers: String = row.get(7)?;
+                        let body: Option<Vec<u8>> = row.get(9)?;
+                        let verified: i64 = row.get(10)?;
+                        let started: i64 = row.get(11)?;
+                        Ok(json!({
+                            "time": row.get::<_, String>(0)?,
+                            "request_id": row.get::<_, String>(1)?,
+                            "client": row.get::<_, String>(2)?,
+                            "method": row.get::<_, String>(3)?,
+                            "path": row.get::<_, String>(4)?,
+                            "route": row.get::<_, Option<String>>(5)?,
+                            "status": row.get::<_, u16>(6)?,
+                            "headers": serde_json::from_str::<Value>(&headers).unwrap_or(Value::Null),
+                            "body_sha256": row.get::<_, Option<String>>(8)?,
+                            "body": body.map(|b| String::from_utf8_lossy(&b).into_owned()),
+                            "verified": verified,
+                            "started": started,
+                        }))
+                    })
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map(Value::Array)
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+        }
+
+        /// The newest delivery with `request_id`, for replays.
+        pub async fn get(&self, request_id: String) -> Result<Option<Record>, String> {
+            let conn = self.conn.clone();
+            tokio::task::spawn_blocking(move || {
+                let conn = conn
+                    .lock()
+                    .map_err(|_| "history lock poisoned".to_string())?;
+                conn.query_row(
+                    "SELECT request_id, client, method, path, route, status, headers, body,
+                        verified, started
+                    FROM deliveries WHERE request_id = ?1 ORDER BY id DESC LIMIT 1",
+                    params![request_id],
+                    |row| {
+                        let client: String = row.get(1)?;
+                        let headers: String = row.get(6)?;
+                        let verified: i64 = row.get(8)?;
+                        let started: i64 = row.get(9)?;
+                        Ok(Record {
+                            request_id: row.get(0)?,
+                            client: client.parse().unwrap_or(IpAddr::from([0, 0, 0, 0])),
+                            method: row.get(2)?,
+                            path: row.get(3)?,
+                            route: row.get(4)?,
+                            status: row.get(5)?,
+                            headers: serde_json::from_str(&headers).unwrap_or(Value::Null),
+                            body: row.get(7)?,
+                            verified: verified as usize,
+                            started: started as usize,
+                        })
+                    },
+                )
+                .optional()
+                .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+        }
+    }
+
+    fn prune(conn: &Connection, retention_secs: i64) {
+        let cutoff = OffsetDateTime::now_utc().unix_timestamp() - retention_secs;
+        match conn.execute("DELETE FROM deliveries WHERE unix < ?1", params![cutoff]) {
+            Ok(0) => {}
+            Ok(n) => debug!("pruned {} deliveries from history", n),
+            Err(e) => log::warn!("failed to prune history: {}", e),
+        }
+    }
+
+    fn sha256_hex(body: &[u8]) -> String {
+        Sha256::digest(body)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// placeholder so callers don't need their own cfg gates; never constructed
+#[cfg(not(feature = "sqlite"))]
+pub enum History {}
+
+#[cfg(not(feature = "sqlite"))]
+impl History {
+    pub fn open(_: &HistoryConfig) -> Result<History, String> {
+        // config::from_file rejects [history] sections when the feature is disabled
+        Err("[history] requires rook built with the `sqlite` feature".to_string())
+    }
+
+    pub async fn record(&self, _: Record) {
+        match *self {}
+    }
+
+    pub async fn list(&self, _: usize, _: Option<String>) -> Result<Value, String> {
+        match *self {}
+    }
+
+    pub async fn get(&self, _: String) -> Result<Option<Record>, String> {
+        match *self {}
+    }
+}
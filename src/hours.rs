@@ -0,0 +1,122 @@
+//! `allowed_hours`: when a hook url's commands may run, such as production deploys only during
+//! business hours.
+//!
+//! A window is written `[DAYS] HH:MM-HH:MM [TIME ZONE]`: `Mon-Fri 08:00-18:00 Europe/Berlin`,
+//! `Sat,Sun 10:00-12:00`, or `22:00-06:00 America/New_York`.  Days are a comma-separated list of
+//! days or day ranges, and default to every day; the time zone is an IANA name read from the
+//! system's zoneinfo, and defaults to UTC.  A window that ends before it starts runs past
+//! midnight, into the day after each of its days.
+use jiff::{tz::TimeZone, Timestamp};
+use serde::Deserialize;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Clone)]
+pub struct Hours {
+    /// bit 0 is Monday
+    days: u8,
+    /// minutes after midnight
+    start: u16,
+    end: u16,
+    tz: TimeZone,
+}
+
+impl Hours {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bad = |why: &str| format!("bad allowed_hours '{}': {}", text, why);
+        let mut days = None;
+        let mut range = None;
+        let mut tz = None;
+        for token in text.split_whitespace() {
+            if range.is_none() && token.contains(':') {
+                range = Some(parse_range(token).ok_or_else(|| bad("expected HH:MM-HH:MM"))?);
+            } else if range.is_none() && days.is_none() {
+                days = Some(parse_days(token).ok_or_else(|| bad("unknown days"))?);
+            } else if range.is_some() && tz.is_none() {
+                tz = Some(TimeZone::get(token).map_err(|e| bad(&e.to_string()))?);
+            } else {
+                return Err(bad("expected [DAYS] HH:MM-HH:MM [TIME ZONE]"));
+            }
+        }
+        let (start, end) = range.ok_or_else(|| bad("missing HH:MM-HH:MM"))?;
+        if start == end {
+            return Err(bad("the window is empty"));
+        }
+        Ok(Hours {
+            days: days.unwrap_or(0x7f),
+            start,
+            end,
+            tz: tz.unwrap_or(TimeZone::UTC),
+        })
+    }
+
+    /// Whether the window is open now.
+    pub fn is_open(&self) -> bool {
+        self.contains(Timestamp::now())
+    }
+
+    fn contains(&self, at: Timestamp) -> bool {
+        let local = at.to_zoned(self.tz.clone());
+        let day = local.weekday().to_monday_zero_offset() as u8;
+        let yesterday = (day + 6) % 7;
+        let minute = local.hour() as u16 * 60 + local.minute() as u16;
+        let on = |day: u8| self.days & (1 << day) != 0;
+        if self.start < self.end {
+            on(day) && (self.start..self.end).contains(&minute)
+        } else {
+            (on(day) && minute >= self.start) || (on(yesterday) && minute < self.end)
+        }
+    }
+}
+
+/// `Mon-Fri` or `Mon,Wed,Fri-Sun` as a bit for each day
+fn parse_days(token: &str) -> Option<u8> {
+    let day = |name: &str| DAYS.iter().position(|d| name.eq_ignore_ascii_case(d));
+    let mut days = 0;
+    for part in token.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+        // Fri-Mon wraps around the weekend
+        let mut d = first;
+        loop {
+            days |= 1 << d;
+            if d == last {
+                break;
+            }
+            d = (d + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+/// `08:00-18:00` as minutes after midnight; the end may be `24:00`
+fn parse_range(token: &str) -> Option<(u16, u16)> {
+    let (start, end) = token.split_once('-')?;
+    let start = parse_time(start).filter(|m| *m < 24 * 60)?;
+    Some((start, parse_time(end)?))
+}
+
+fn parse_time(s: &str) -> Option<u16> {
+    let (h, m) = s.split_once(':')?;
+    if h.len() != 2 || m.len() != 2 {
+        return None;
+    }
+    let (h, m): (u16, u16) = (h.parse().ok()?, m.parse().ok()?);
+    match (h, m) {
+        (0..=23, 0..=59) | (24, 0) => Some(h * 60 + m),
+        _ => None,
+    }
+}
+
+/// What a hook url does with deliveries outside its `allowed_hours`.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutsideHours {
+    /// answer `503`
+    #[default]
+    Reject,
+    /// hold them, answering `202`, and run them once the window opens
+    Queue,
+}
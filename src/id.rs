@@ -0,0 +1,15 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Unpredictable (not cryptographically secure) ids for requests, jobs, and trace spans.
+pub fn random_u64() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    // RandomState is randomly keyed per process and thread, so hashing a counter gives
+    // unpredictable ids without another dependency
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(NEXT.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
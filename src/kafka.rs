@@ -0,0 +1,277 @@
+//! Produce verified deliveries to a Kafka topic, so rook can be the signed ingress of an event
+//! pipeline.
+//!
+//! Each delivery is one record: the body as received, keyed by the hook url, with the hook
+//! type, hook url, and request id in `Rook-Provider`, `Rook-Route`, and `Rook-Request-Id`
+//! headers.  Records with the same key go to the same partition, so each hook's deliveries stay
+//! in order.  Records are queued and produced by one background client per `[kafka]` cluster;
+//! a delivery that arrives while the queue is full fails its hook.
+use crate::{
+    config::KafkaConfig,
+    exec::{Executor, Invocation},
+};
+use rskafka::{
+    chrono::DateTime,
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        Client, ClientBuilder, Credentials, SaslConfig,
+    },
+    record::Record,
+    BackoffConfig,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, time::timeout};
+
+/// records waiting to be produced; deliveries fail while the cluster is this far behind
+const QUEUE: usize = 256;
+/// for connecting, and for each record, retries included
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Producers by cluster and credentials, so hooks on the same cluster share one client.
+static PRODUCERS: Mutex<Vec<(String, Weak<Producer>)>> = Mutex::new(Vec::new());
+
+/// Produce each delivery to `topic`.
+pub struct Kafka {
+    topic: String,
+    producer: Arc<Producer>,
+}
+
+impl Kafka {
+    pub fn new(cfg: &KafkaConfig, topic: &str) -> Result<Self, String> {
+        if topic.is_empty()
+            || topic.len() > 249
+            || !topic
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+        {
+            return Err(format!("bad kafka_topic '{}'", topic));
+        }
+        Ok(Kafka {
+            topic: topic.to_string(),
+            producer: producer(Cluster::new(cfg)?),
+        })
+    }
+}
+
+impl Executor for Kafka {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let mut headers = BTreeMap::new();
+        headers.insert(
+            "Rook-Provider".to_string(),
+            invocation.payload.provider().into(),
+        );
+        headers.insert("Rook-Route".to_string(), invocation.route.into());
+        if let Some(id) = invocation.request_id {
+            headers.insert("Rook-Request-Id".to_string(), id.into());
+        }
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let record = Record {
+            key: Some(invocation.route.into()),
+            value: Some(invocation.body.to_vec()),
+            headers,
+            timestamp: DateTime::from_timestamp_millis(millis).unwrap_or_default(),
+        };
+        self.producer.send(Message {
+            topic: self.topic.clone(),
+            record,
+        })
+    }
+}
+
+/// The brokers to bootstrap from, and how to authenticate to them.
+struct Cluster {
+    brokers: Vec<String>,
+    sasl: Option<SaslConfig>,
+    key: String,
+}
+
+impl Cluster {
+    fn new(cfg: &KafkaConfig) -> Result<Self, String> {
+        if cfg.brokers.is_empty() {
+            return Err("[kafka] brokers must not be empty".to_string());
+        }
+        for broker in &cfg.brokers {
+            if broker.contains("://") || !broker.contains(':') {
+                return Err(format!("kafka broker '{}' must be host:port", broker));
+            }
+        }
+        let sasl = match (&cfg.user, &cfg.password_file) {
+            (Some(user), Some(path)) => {
+                let password = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read kafka password_file '{}': {}", path, e))?;
+                let credentials = Credentials::new(user.clone(), password.trim().to_string());
+                Some(match cfg.sasl_mechanism.as_str() {
+                    "plain" => SaslConfig::Plain(credentials),
+                    "scram-sha-256" => SaslConfig::ScramSha256(credentials),
+                    "scram-sha-512" => SaslConfig::ScramSha512(credentials),
+                    other => return Err(format!("unknown kafka sasl_mechanism '{}'", other)),
+                })
+            }
+            (None, None) => None,
+            _ => return Err("[kafka] user and password_file go together".to_string()),
+        };
+        Ok(Cluster {
+            key: format!("{:?} {:?}", cfg.brokers, sasl),
+            brokers: cfg.brokers.clone(),
+            sasl,
+        })
+    }
+}
+
+/// A queue of records for one cluster, produced by a background task started on first use.
+struct Producer {
+    cluster: Arc<Cluster>,
+    tx: OnceLock<mpsc::Sender<Message>>,
+}
+
+struct Message {
+    topic: String,
+    record: Record,
+}
+
+/// The producer for `cluster`, shared with any other hook that produces there.
+fn producer(cluster: Cluster) -> Arc<Producer> {
+    let Ok(mut producers) = PRODUCERS.lock() else {
+        return Arc::new(Producer::new(cluster));
+    };
+    producers.retain(|(_, p)| p.strong_count() > 0);
+    if let Some(producer) = producers
+        .iter()
+        .find(|(k, _)| *k == cluster.key)
+        .and_then(|(_, p)| p.upgrade())
+    {
+        return producer;
+    }
+    let key = cluster.key.clone();
+    let producer = Arc::new(Producer::new(cluster));
+    producers.push((key, Arc::downgrade(&producer)));
+    producer
+}
+
+impl Producer {
+    fn new(cluster: Cluster) -> Self {
+        Producer {
+            cluster: Arc::new(cluster),
+            tx: OnceLock::new(),
+        }
+    }
+
+    fn send(&self, message: Message) -> Result<(), String> {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            // ends once the config holding this producer is dropped
+            tokio::spawn(run(self.cluster.clone(), rx));
+            tx
+        });
+        tx.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "kafka queue full".to_string(),
+            mpsc::error::TrySendError::Closed(_) => "kafka producer stopped".to_string(),
+        })
+    }
+}
+
+/// A connected client, and the partitions of each topic produced to so far.
+struct Connection {
+    client: Client,
+    partitions: HashMap<String, Vec<PartitionClient>>,
+}
+
+/// Produce queued records one at a time, each waiting for the broker to take it.
+async fn run(cluster: Arc<Cluster>, mut rx: mpsc::Receiver<Message>) {
+    let mut conn: Option<Connection> = None;
+    while let Some(message) = rx.recv().await {
+        let produced = match timeout(TIMEOUT, produce(&cluster, &mut conn, &message)).await {
+            Ok(produced) => produced,
+            Err(_) => Err("timed out".to_string()),
+        };
+        match produced {
+            Ok(offset) => debug!("produced to kafka {} at offset {}", message.topic, offset),
+            Err(e) => {
+                log::warn!(
+                    "kafka produce failed: brokers={} topic={} error={}",
+                    cluster.brokers.join(","),
+                    message.topic,
+                    e
+                );
+                // look the topic up again next time, in case its partitions moved
+                if let Some(conn) = conn.as_mut() {
+                    conn.partitions.remove(&message.topic);
+                }
+            }
+        }
+    }
+}
+
+/// Produce `message`, connecting and looking its topic up first if needed.  Returns the
+/// record's offset.
+async fn produce(
+    cluster: &Cluster,
+    conn: &mut Option<Connection>,
+    message: &Message,
+) -> Result<i64, String> {
+    let conn = match conn {
+        Some(conn) => conn,
+        None => conn.insert(connect(cluster).await?),
+    };
+    if !conn.partitions.contains_key(&message.topic) {
+        let partitions = partitions(&conn.client, &message.topic).await?;
+        conn.partitions.insert(message.topic.clone(), partitions);
+    }
+    let partitions = &conn.partitions[&message.topic];
+    let key = message.record.key.as_deref().unwrap_or_default();
+    let hash = blake3::hash(key);
+    let mut index = [0; 8];
+    index.copy_from_slice(&hash.as_bytes()[..8]);
+    let partition = &partitions[(u64::from_le_bytes(index) % partitions.len() as u64) as usize];
+    let offsets = partition
+        .produce(vec![message.record.clone()], Compression::NoCompression)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(offsets.first().copied().unwrap_or_default())
+}
+
+async fn connect(cluster: &Cluster) -> Result<Connection, String> {
+    let mut builder = ClientBuilder::new(cluster.brokers.clone())
+        .client_id("rook")
+        .backoff_config(BackoffConfig {
+            deadline: Some(TIMEOUT),
+            ..Default::default()
+        });
+    if let Some(sasl) = &cluster.sasl {
+        builder = builder.sasl_config(sasl.clone());
+    }
+    let client = builder.build().await.map_err(|e| e.to_string())?;
+    debug!("connected to kafka at {}", cluster.brokers.join(","));
+    Ok(Connection {
+        client,
+        partitions: HashMap::new(),
+    })
+}
+
+/// A client for each of `topic`'s partitions, in order.
+async fn partitions(client: &Client, topic: &str) -> Result<Vec<PartitionClient>, String> {
+    let topics = client.list_topics().await.map_err(|e| e.to_string())?;
+    let topic = topics
+        .into_iter()
+        .find(|t| t.name == topic)
+        .ok_or_else(|| format!("topic {} doesn't exist", topic))?;
+    let mut partitions = vec![];
+    for partition in topic.partitions {
+        let partition = client
+            .partition_client(topic.name.clone(), partition, UnknownTopicHandling::Retry)
+            .await
+            .map_err(|e| e.to_string())?;
+        partitions.push(partition);
+    }
+    if partitions.is_empty() {
+        return Err(format!("topic {} has no partitions", topic.name));
+    }
+    Ok(partitions)
+}
@@ -0,0 +1,299 @@
+//! Configs and hook secrets kept in Consul KV or etcd, for teams that already keep service
+//! config there.
+//!
+//! `rook consul:rook/config` or `rook etcd:/rook/config` loads the config from that key and
+//! watches it, reloading whenever it changes; see [`remote`](crate::remote).  Hooks read
+//! secrets with `secret_consul = "rook/secrets/deploy"` or `secret_etcd = "/rook/deploy"`,
+//! each key once, whenever the config loads.
+//!
+//! Consul is reached at `CONSUL_HTTP_ADDR` (default `http://127.0.0.1:8500`) with the token in
+//! `CONSUL_HTTP_TOKEN`, if any; etcd at the first of `ETCDCTL_ENDPOINTS` (default
+//! `http://127.0.0.1:2379`) through its JSON gateway, without auth.  https addresses trust the
+//! system's CA bundle.
+use crate::encoding;
+use hyper::{
+    body::{self, HttpBody},
+    header, Body, Client, Method, Request, StatusCode, Uri,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, env, time::Duration};
+
+#[cfg(feature = "tls")]
+type Connector = crate::tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = hyper::client::HttpConnector;
+
+const TOKEN_HEADER: &str = "x-consul-token";
+const INDEX_HEADER: &str = "x-consul-index";
+/// per request, other than watches
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// longest a watch waits for a change before asking again
+const WATCH: Duration = Duration::from_secs(300);
+/// largest value rook reads
+const MAX_LENGTH: usize = 4 * 1024 * 1024;
+
+/// A Consul or etcd cluster.
+pub enum Store {
+    Consul {
+        address: String,
+        token: Option<String>,
+    },
+    Etcd {
+        address: String,
+    },
+}
+
+impl Store {
+    /// Consul at `CONSUL_HTTP_ADDR`, with `CONSUL_HTTP_TOKEN`.
+    pub fn consul() -> Self {
+        let address = var("CONSUL_HTTP_ADDR").unwrap_or_else(|| "127.0.0.1:8500".to_string());
+        Store::Consul {
+            address: with_scheme(&address),
+            token: var("CONSUL_HTTP_TOKEN"),
+        }
+    }
+
+    /// etcd at the first of `ETCDCTL_ENDPOINTS`.
+    pub fn etcd() -> Self {
+        let endpoints = var("ETCDCTL_ENDPOINTS").unwrap_or_default();
+        let address = endpoints.split(',').next().unwrap_or_default().trim();
+        Store::Etcd {
+            address: with_scheme(if address.is_empty() {
+                "127.0.0.1:2379"
+            } else {
+                address
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Store::Consul { .. } => "consul",
+            Store::Etcd { .. } => "etcd",
+        }
+    }
+
+    fn address(&self) -> &str {
+        match self {
+            Store::Consul { address, .. } | Store::Etcd { address } => address,
+        }
+    }
+
+    fn client(&self) -> Result<Client<Connector>, String> {
+        let uri: Uri = self
+            .address()
+            .parse()
+            .map_err(|e| format!("bad {} address '{}': {}", self.name(), self.address(), e))?;
+        Ok(Client::builder().build(connector(self.name(), &uri)?))
+    }
+
+    /// The value at `key`, or None if there's no such key, and the index (Consul) or revision
+    /// (etcd) it was read at.  With `after`, waits up to a few minutes for the key to change
+    /// since that index first.
+    pub async fn get(
+        &self,
+        key: &str,
+        after: Option<u64>,
+    ) -> Result<(Option<Vec<u8>>, u64), String> {
+        let client = self.client()?;
+        match self {
+            Store::Consul { address, token } => {
+                let mut url = format!("{}/v1/kv/{}", address, key.trim_start_matches('/'));
+                if let Some(index) = after {
+                    url = format!("{}?index={}&wait={}s", url, index, WATCH.as_secs());
+                }
+                let mut req = Request::get(&url);
+                if let Some(token) = token {
+                    req = req.header(TOKEN_HEADER, token.as_str());
+                }
+                let req = req.body(Body::empty()).map_err(|e| e.to_string())?;
+                // consul answers a little after `wait`, with some jitter
+                let timeout = after.map_or(TIMEOUT, |_| WATCH + WATCH / 16 + TIMEOUT);
+                let (status, index, bytes) = call(&client, req, &url, timeout).await?;
+                let index =
+                    index.ok_or_else(|| format!("consul didn't say the index of '{}'", key))?;
+                match status {
+                    StatusCode::NOT_FOUND => Ok((None, index)),
+                    StatusCode::OK => {
+                        let entries: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+                        let value = match &entries[0]["Value"] {
+                            Value::Null => vec![],
+                            Value::String(v) => encoding::decode_base64(v)
+                                .ok_or_else(|| format!("consul sent a bad value for '{}'", key))?,
+                            _ => return Err(format!("consul sent a bad value for '{}'", key)),
+                        };
+                        Ok((Some(value), index))
+                    }
+                    status => Err(format!("consul answered {} for '{}'", status, key)),
+                }
+            }
+            Store::Etcd { address } => {
+                if let Some(revision) = after {
+                    // a watch that ends without a change just reads the key again
+                    let watched = watch(&client, address, key, revision);
+                    if let Ok(Err(e)) = tokio::time::timeout(WATCH, watched).await {
+                        return Err(e);
+                    }
+                }
+                let url = format!("{}/v3/kv/range", address);
+                let req = etcd_request(&url, json!({ "key": encoding::base64(key.as_bytes()) }))?;
+                let (status, _, bytes) = call(&client, req, &url, TIMEOUT).await?;
+                let res: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+                if status != StatusCode::OK {
+                    let message = res["message"].as_str().unwrap_or_default();
+                    return Err(format!(
+                        "etcd answered {} for '{}': {}",
+                        status, key, message
+                    ));
+                }
+                let revision = number(&res["header"]["revision"])
+                    .ok_or_else(|| format!("etcd didn't say the revision of '{}'", key))?;
+                let value = match &res["kvs"][0]["value"] {
+                    // etcd leaves out empty values, but not the key
+                    Value::Null if res["kvs"][0].is_object() => Some(vec![]),
+                    Value::Null => None,
+                    Value::String(v) => Some(
+                        encoding::decode_base64(v)
+                            .ok_or_else(|| format!("etcd sent a bad value for '{}'", key))?,
+                    ),
+                    _ => return Err(format!("etcd sent a bad value for '{}'", key)),
+                };
+                Ok((value, revision))
+            }
+        }
+    }
+}
+
+/// Read every key in `consul` and `etcd`, keyed by key, each trimmed the way `secret_file`s
+/// are.
+pub async fn load(
+    consul: Vec<String>,
+    etcd: Vec<String>,
+) -> Result<(HashMap<String, Vec<u8>>, HashMap<String, Vec<u8>>), String> {
+    Ok((
+        read_all(Store::consul(), consul).await?,
+        read_all(Store::etcd(), etcd).await?,
+    ))
+}
+
+async fn read_all(store: Store, keys: Vec<String>) -> Result<HashMap<String, Vec<u8>>, String> {
+    let mut secrets = HashMap::new();
+    for key in keys {
+        let (value, _) = store.get(&key, None).await?;
+        let value = value.ok_or_else(|| format!("no {} key '{}'", store.name(), key))?;
+        let value = String::from_utf8(value)
+            .map_err(|_| format!("{} key '{}' isn't utf-8", store.name(), key))?;
+        secrets.insert(key, value.trim().as_bytes().to_vec());
+    }
+    Ok(secrets)
+}
+
+/// Wait until etcd reports a change to `key` after `revision`.
+async fn watch(
+    client: &Client<Connector>,
+    address: &str,
+    key: &str,
+    revision: u64,
+) -> Result<(), String> {
+    let url = format!("{}/v3/watch", address);
+    let create = json!({ "create_request": {
+        "key": encoding::base64(key.as_bytes()),
+        "start_revision": (revision + 1).to_string(),
+    }});
+    let req = etcd_request(&url, create)?;
+    let res = tokio::time::timeout(TIMEOUT, client.request(req))
+        .await
+        .map_err(|_| format!("etcd watch '{}' timed out", key))?
+        .map_err(|e| format!("etcd watch '{}' failed: {}", key, e))?;
+    if res.status() != StatusCode::OK {
+        return Err(format!("etcd answered {} watching '{}'", res.status(), key));
+    }
+    // the gateway streams one json object per watch response; the first only says the watch
+    // was created
+    let mut body = res.into_body();
+    let mut seen = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| format!("etcd watch '{}' failed: {}", key, e))?;
+        seen.extend_from_slice(&chunk);
+        if seen.windows(8).any(|w| w == b"\"events\"") {
+            return Ok(());
+        }
+        if seen.len() > MAX_LENGTH {
+            seen.clear();
+        }
+    }
+    Err(format!("etcd closed the watch on '{}'", key))
+}
+
+fn etcd_request(url: &str, body: Value) -> Result<Request<Body>, String> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|e| format!("bad etcd request '{}': {}", url, e))
+}
+
+/// The status, Consul index, and body of `req`.
+async fn call(
+    client: &Client<Connector>,
+    req: Request<Body>,
+    url: &str,
+    timeout: Duration,
+) -> Result<(StatusCode, Option<u64>, Vec<u8>), String> {
+    let exchange = async {
+        let res = client.request(req).await?;
+        let status = res.status();
+        let index = res
+            .headers()
+            .get(INDEX_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        body::to_bytes(res.into_body())
+            .await
+            .map(|b| (status, index, b))
+    };
+    let (status, index, bytes) = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| format!("request '{}' timed out", url))?
+        .map_err(|e| format!("request '{}' failed: {}", url, e))?;
+    if bytes.len() > MAX_LENGTH {
+        return Err(format!("'{}' is over {} bytes", url, MAX_LENGTH));
+    }
+    Ok((status, index, bytes.to_vec()))
+}
+
+/// etcd's gateway writes 64-bit numbers as strings
+fn number(v: &Value) -> Option<u64> {
+    v.as_u64().or_else(|| v.as_str()?.parse().ok())
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// `host:port` as an http url; urls are kept as they are
+fn with_scheme(address: &str) -> String {
+    let address = address.trim_end_matches('/');
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("http://{}", address)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn connector(_: &str, _: &Uri) -> Result<Connector, String> {
+    crate::tls::https_connector(&crate::config::default_ca_file())
+}
+
+#[cfg(not(feature = "tls"))]
+fn connector(name: &str, uri: &Uri) -> Result<Connector, String> {
+    if uri.scheme_str() != Some("http") {
+        return Err(format!(
+            "https {} addresses need rook built with the `tls` feature",
+            name
+        ));
+    }
+    Ok(Connector::new())
+}
@@ -0,0 +1,165 @@
+//! rook: a tiny webhook server that runs commands when GitHub (or anything that can sign a
+//! payload) calls.
+//!
+//! The `rook` binary is a thin wrapper around this crate; services that want to embed rook's
+//! webhook handling can use the same pieces:
+//!
+//! * [`config`] loads and validates a rook config, from a file or a toml string
+//! * [`router::handle`] routes one request to its hooks and answers it, in the format
+//!   [`render`] chooses
+//! * [`verify`] checks GitHub and rook payload signatures
+//! * [`exec`] starts hook commands in a detached process
+//! * [`channel`] hands verified deliveries to the application instead
+//! * [`client`] sends signed deliveries, as GitHub or another service would
+//! * [`forward`] re-delivers verified payloads to other rook urls
+//! * [`service`] wraps rook's request handling as a tower `Service` and `Layer`, with
+//!   [`middleware`] around every request
+//! * `axum` (with the `axum` feature) mounts rook's hooks in an axum app
+//! * `wasm` and `script` (with the `wasm` and `rhai` features) filter deliveries before their
+//!   hooks start
+//! * [`init`] and [`serve`] run the whole server, exactly as the binary does; see [`serve`]
+//!   for an example
+//! * `testing` (with the `test-util` feature) serves a config in process or on an ephemeral
+//!   port, with signed requests, for integration tests
+//! * [`upgrade`] hands a running server's listeners to a new binary on `SIGUSR2`
+//!
+//! Everything but [`verify`], [`encoding`], and [`cidr`] is behind cargo features, on by
+//! default: `github` has the typed payloads, `exec` the executors, `client` the sender, `server`
+//! everything else, and `metrics` the Prometheus endpoint and statsd export.  An embedder that
+//! only checks signatures and parses payloads can use
+//! `default-features = false, features = ["github"]`, and a service that only triggers hooks
+//! `features = ["client"]`.
+
+/// Debug logging, filtered at runtime by `log_level` or `RUST_LOG`.
+macro_rules! debug {
+    ($($tts:tt)*) => {
+        log::debug!($($tts)*)
+    }
+}
+
+#[cfg(feature = "server")]
+mod admin;
+#[cfg(feature = "age")]
+mod age;
+#[cfg(feature = "server")]
+mod archive;
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(any(feature = "matrix", feature = "telegram"))]
+mod blocking;
+#[cfg(feature = "exec")]
+pub mod channel;
+pub mod cidr;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+mod command;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+mod dashboard;
+#[cfg(feature = "server")]
+mod decompress;
+pub mod encoding;
+#[cfg(feature = "exec")]
+pub mod events;
+#[cfg(feature = "exec")]
+pub mod exec;
+#[cfg(feature = "server")]
+mod feed;
+#[cfg(feature = "exec")]
+pub mod filter;
+#[cfg(feature = "server")]
+pub mod fixture;
+#[cfg(feature = "server")]
+pub mod forward;
+#[cfg(feature = "server")]
+mod forwarded;
+#[cfg(feature = "github")]
+pub mod github;
+#[cfg(feature = "server")]
+mod glob;
+#[cfg(feature = "server")]
+mod history;
+#[cfg(feature = "server")]
+mod hours;
+mod id;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "kv")]
+pub mod kv;
+#[cfg(feature = "server")]
+mod listener;
+#[cfg(feature = "server")]
+mod lock;
+#[cfg(feature = "server")]
+mod logfile;
+#[cfg(feature = "server")]
+pub mod logging;
+#[cfg(feature = "server")]
+mod maintenance;
+#[cfg(feature = "matrix")]
+mod matrix;
+#[cfg(feature = "server")]
+mod metrics;
+#[cfg(feature = "server")]
+pub mod middleware;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "server")]
+mod pattern;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "server")]
+pub mod ratelimit;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "server")]
+pub mod remote;
+#[cfg(feature = "server")]
+pub mod render;
+#[cfg(feature = "server")]
+pub mod router;
+#[cfg(feature = "rhai")]
+pub mod script;
+#[cfg(feature = "server")]
+mod sentry;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "server")]
+pub mod service;
+#[cfg(feature = "smtp")]
+mod smtp;
+#[cfg(feature = "server")]
+mod spill;
+#[cfg(feature = "server")]
+mod statsd;
+#[cfg(feature = "server")]
+mod syslog;
+#[cfg(feature = "telegram")]
+mod telegram;
+#[cfg(feature = "test-util")]
+pub mod testing;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "server")]
+mod topic;
+#[cfg(feature = "server")]
+mod trace;
+#[cfg(feature = "server")]
+pub mod upgrade;
+#[cfg(feature = "vault")]
+mod vault;
+pub mod verify;
+pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "server")]
+pub use server::{init, serve, serve_on};
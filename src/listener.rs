@@ -0,0 +1,324 @@
+use crate::{
+    config::RouteConfig,
+    metrics::{Gauge, METRICS},
+};
+use futures::stream::{self, Stream};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore},
+};
+
+const PROXY_V1_MAX_LEN: usize = 107;
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[cfg(feature = "tls")]
+pub type TlsAcceptor = tokio_rustls::TlsAcceptor;
+/// placeholder so callers don't need their own cfg gates; never constructed
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+pub enum TlsAcceptor {}
+
+/// An accepted connection with the address of the client it represents.  When the listener
+/// speaks the PROXY protocol this is the address reported by the load balancer.
+pub struct Conn {
+    io: Io,
+    remote: SocketAddr,
+    client_cn: Option<String>,
+    /// held for the life of the connection; see `max_connections`
+    slot: Option<Slot>,
+}
+
+/// A connection's place under `max_connections`, counted as open until dropped.
+struct Slot {
+    _permit: OwnedSemaphorePermit,
+    _open: Gauge,
+}
+
+enum Io {
+    Plain(BufReader<TcpStream>),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<BufReader<TcpStream>>>),
+}
+
+impl Conn {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// CN of the verified client certificate, see [`crate::tls::client_cn`]
+    pub fn client_cn(&self) -> Option<&str> {
+        self.client_cn.as_deref()
+    }
+}
+
+#[cfg(feature = "tls")]
+pub fn tls_acceptor(cfg: &RouteConfig) -> Result<Option<TlsAcceptor>, String> {
+    cfg.tls.as_ref().map(crate::tls::acceptor).transpose()
+}
+
+#[cfg(not(feature = "tls"))]
+pub fn tls_acceptor(_: &RouteConfig) -> Result<Option<TlsAcceptor>, String> {
+    // config::from_file rejects [tls] sections when the feature is disabled
+    Ok(None)
+}
+
+/// Accept connections on `listener`, optionally consuming a PROXY protocol v1 or v2 header and
+/// performing a TLS handshake before handing each to hyper.  Both happen off the accept loop so
+/// a slow client can't stall other connections; connections that fail either step, or don't
+/// finish them within `header_timeout`, are dropped.  Connections beyond `max_connections`
+/// (including those still being prepared) are closed as soon as they're accepted.
+///
+/// Once `stop` is notified no more connections are accepted, and `stopped` is notified when
+/// every connection already accepted has been handed on; the stream then stays pending rather
+/// than ending, so a graceful shutdown waiting on `stopped` drains them.
+pub fn incoming(
+    listener: TcpListener,
+    proxy_protocol: bool,
+    tls: Option<TlsAcceptor>,
+    header_timeout: Duration,
+    max_connections: usize,
+    stop: Arc<Notify>,
+    stopped: Arc<Notify>,
+) -> impl Stream<Item = io::Result<Conn>> {
+    let (tx, rx) = mpsc::channel::<io::Result<Conn>>(64);
+    let slots = Arc::new(Semaphore::new(max_connections));
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = stop.notified() => return,
+            };
+            let (tcp, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // transient (EMFILE, ECONNABORTED); back off briefly like hyper's AddrIncoming
+                    debug!("accept error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let slot = match slots.clone().try_acquire_owned() {
+                Ok(permit) => Slot {
+                    _permit: permit,
+                    _open: METRICS.connection_opened(),
+                },
+                Err(_) => {
+                    debug!("connection limit reached, closing {}", peer);
+                    METRICS.connection_rejected();
+                    continue;
+                }
+            };
+            let _ = tcp.set_nodelay(true);
+            let io = BufReader::new(tcp);
+            if !proxy_protocol && tls.is_none() {
+                let conn = Conn {
+                    io: Io::Plain(io),
+                    remote: peer,
+                    client_cn: None,
+                    slot: Some(slot),
+                };
+                if tx.send(Ok(conn)).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            let tx = tx.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let prepared = prepare(io, peer, proxy_protocol, tls.as_ref());
+                match tokio::time::timeout(header_timeout, prepared).await {
+                    Ok(Ok(mut conn)) => {
+                        conn.slot = Some(slot);
+                        let _ = tx.send(Ok(conn)).await;
+                    }
+                    Ok(Err(e)) => debug!("dropping connection from {}: {}", peer, e),
+                    Err(_) => debug!("timed out preparing connection from {}", peer),
+                }
+            });
+        }
+    });
+    let mut rx = rx;
+    stream::poll_fn(move |cx| match rx.poll_recv(cx) {
+        Poll::Ready(None) => {
+            stopped.notify_one();
+            Poll::Pending
+        }
+        polled => polled,
+    })
+}
+
+async fn prepare(
+    mut io: BufReader<TcpStream>,
+    mut remote: SocketAddr,
+    proxy_protocol: bool,
+    tls: Option<&TlsAcceptor>,
+) -> io::Result<Conn> {
+    if proxy_protocol {
+        match read_proxy_header(&mut io).await? {
+            Some(addr) => remote = addr,
+            None => debug!("proxy header from {} carried no address", remote),
+        }
+    }
+    match tls {
+        Some(acceptor) => handshake(io, remote, acceptor).await,
+        None => Ok(Conn {
+            io: Io::Plain(io),
+            remote,
+            client_cn: None,
+            slot: None,
+        }),
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn handshake(
+    io: BufReader<TcpStream>,
+    remote: SocketAddr,
+    acceptor: &TlsAcceptor,
+) -> io::Result<Conn> {
+    let stream = acceptor.accept(io).await?;
+    let client_cn = crate::tls::client_cn(stream.get_ref().1);
+    Ok(Conn {
+        io: Io::Tls(Box::new(stream)),
+        remote,
+        client_cn,
+        slot: None,
+    })
+}
+
+#[cfg(not(feature = "tls"))]
+async fn handshake(
+    _: BufReader<TcpStream>,
+    _: SocketAddr,
+    acceptor: &TlsAcceptor,
+) -> io::Result<Conn> {
+    match *acceptor {}
+}
+
+/// Returns the source address from the header, or None for `UNKNOWN`/`LOCAL` headers
+/// (health checks from the load balancer itself).
+async fn read_proxy_header<R: AsyncBufReadExt + Unpin>(
+    r: &mut R,
+) -> io::Result<Option<SocketAddr>> {
+    let mut sig = [0u8; 12];
+    r.read_exact(&mut sig).await?;
+    if &sig == PROXY_V2_SIGNATURE {
+        read_proxy_v2(r).await
+    } else if sig.starts_with(b"PROXY ") {
+        let mut line = sig.to_vec();
+        (&mut *r)
+            .take((PROXY_V1_MAX_LEN - sig.len()) as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        parse_proxy_v1(&line)
+    } else {
+        Err(malformed("missing proxy protocol signature"))
+    }
+}
+
+fn parse_proxy_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|l| l.strip_suffix("\r\n"))
+        .ok_or_else(|| malformed("unterminated v1 header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src
+                .parse()
+                .map_err(|_| malformed("bad v1 source address"))?;
+            let port: u16 = sport.parse().map_err(|_| malformed("bad v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(malformed("bad v1 header")),
+    }
+}
+
+async fn read_proxy_v2<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 4];
+    r.read_exact(&mut head).await?;
+    let (ver_cmd, family) = (head[0], head[1]);
+    let len = u16::from_be_bytes([head[2], head[3]]) as usize;
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload).await?;
+    if ver_cmd >> 4 != 2 {
+        return Err(malformed("unsupported v2 version"));
+    }
+    if ver_cmd & 0x0f == 0 {
+        // LOCAL: connection established by the proxy itself
+        return Ok(None);
+    }
+    // address family in the high nibble, transport (stream/dgram) in the low
+    match family >> 4 {
+        1 if len >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        2 if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&payload[..16]);
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // AF_UNSPEC or AF_UNIX; nothing useful to report
+        0 | 3 => Ok(None),
+        _ => Err(malformed("bad v2 address block")),
+    }
+}
+
+fn malformed(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.io {
+            Io::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Io::Tls(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.io {
+            Io::Plain(io) => Pin::new(io).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Io::Tls(io) => Pin::new(io).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.io {
+            Io::Plain(io) => Pin::new(io).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Io::Tls(io) => Pin::new(io).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.io {
+            Io::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Io::Tls(io) => Pin::new(io).poll_shutdown(cx),
+        }
+    }
+}
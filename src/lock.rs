@@ -0,0 +1,101 @@
+//! Locks that keep rook instances behind a load balancer from running the same hook's command
+//! at the same time, for hooks that set `lock = "name"`.
+//!
+//! A `[lock]` with a `dir` takes an exclusive `flock` on `<dir>/<name>.lock`, which every
+//! instance must see on shared storage; the lock goes away with the process holding it.  With
+//! `redis = true` the lock is a key in the `[redis]` server, which expires after `ttl` in case
+//! the instance holding it dies.  Either way the forked process for a delivery waits up to
+//! `wait` seconds for the lock before giving up on the command.
+//!
+//! A hook's `lockfile` is a [`Flock`] on a file of its own, usually local, so the command never
+//! overlaps with itself or with anything else that locks the same file, like `flock(1)` in a
+//! cron job or a manual deploy.
+use crate::{
+    config::LockConfig,
+    exec::{Held, Lock},
+};
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// how often a taken lock is tried again
+pub const RETRY: Duration = Duration::from_millis(500);
+
+/// Whether `name` can be used for a lock file and a redis key as it is.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// An exclusive `flock` on a file in a shared directory.
+pub struct Flock {
+    path: PathBuf,
+    wait: Duration,
+}
+
+impl Flock {
+    pub fn new(cfg: &LockConfig, dir: &str, name: &str) -> Self {
+        Flock::at(
+            &PathBuf::from(dir).join(format!("{}.lock", name)),
+            Duration::from_secs(cfg.wait),
+        )
+    }
+
+    /// A lock on `path`, waited on for up to `wait`; with no wait, a held lock is given up on
+    /// at once.
+    pub fn at(path: &Path, wait: Duration) -> Self {
+        Flock {
+            path: path.to_path_buf(),
+            wait,
+        }
+    }
+}
+
+impl Lock for Flock {
+    fn acquire(&self) -> Result<Box<dyn Held>, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| format!("failed to open lock '{}': {}", self.path.display(), e))?;
+        let deadline = Instant::now() + self.wait;
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+                return Ok(Box::new(Flocked(file)));
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(format!("failed to lock '{}': {}", self.path.display(), e));
+            }
+            if self.wait.is_zero() {
+                return Err(format!("lock '{}' is held; skipped", self.path.display()));
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "lock '{}' still held after {}s",
+                    self.path.display(),
+                    self.wait.as_secs()
+                ));
+            }
+            thread::sleep(RETRY);
+        }
+    }
+}
+
+/// Closing the file releases the lock.
+struct Flocked(File);
+
+impl Held for Flocked {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.0.as_raw_fd())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{
+    config::{LogFileConfig, Rotation},
+    logging::Sink,
+};
+use log::Level;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+use time::OffsetDateTime;
+
+/// An append-only log file that is renamed to `<path>.1` (shifting older files up to
+/// `<path>.<keep>`) when it grows past `max_size_mb` or when the rotation period ends.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    rotation: Option<Rotation>,
+    period: (i32, u16, u8),
+    keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(cfg: &LogFileConfig) -> io::Result<RotatingFile> {
+        let path = PathBuf::from(&cfg.path);
+        let file = open_append(&path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let rotation = cfg.rotate;
+        // an existing file from an earlier period rotates on the first write after a restart
+        let modified = metadata
+            .modified()
+            .map_or_else(|_| OffsetDateTime::now_utc(), Into::into);
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size: cfg.max_size_mb.map(|mb| mb * 1024 * 1024),
+            rotation,
+            period: period(rotation, modified),
+            keep: cfg.keep,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let now = period(self.rotation, OffsetDateTime::now_utc());
+        let full = self.max_size.is_some_and(|max| self.size >= max);
+        if (full || now != self.period) && self.size > 0 {
+            self.rotate()?;
+        }
+        self.period = now;
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // the oldest file falls off the end when it's overwritten by the rename below
+            for n in (1..self.keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Sink for RotatingFile {
+    fn write(&mut self, _: Level, line: &str) -> io::Result<()> {
+        self.write_line(line)
+    }
+}
+
+fn open_append(path: &PathBuf) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// (year, day of year, hour) truncated to the rotation period; constant without time rotation
+fn period(rotation: Option<Rotation>, now: OffsetDateTime) -> (i32, u16, u8) {
+    match rotation {
+        None => (0, 0, 0),
+        Some(Rotation::Daily) => (now.year(), now.ordinal(), 0),
+        Some(Rotation::Hourly) => (now.year(), now.ordinal(), now.hour()),
+    }
+}
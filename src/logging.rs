@@ -1,8 +1,37 @@
-use hyper::{Body, Method, Request, Response, StatusCode, Version};
+use crate::{
+    config::RouteConfig,
+    github, id,
+    logfile::RotatingFile,
+    router::MatchedRoute,
+    syslog::{Journald, Syslog},
+};
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderValue},
+    Body, HeaderMap, Method, Request, Response, StatusCode, Version,
+};
 pub use log::info;
 use log::{Level, LevelFilter, Metadata, Record};
-use std::{convert::Infallible, net::SocketAddr, process};
-use time::{format_description::FormatItem, macros::format_description, OffsetDateTime};
+use serde::{
+    de::{self, Deserializer},
+    Deserialize,
+};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    env,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    process,
+    str::FromStr,
+    sync::{Mutex, OnceLock, RwLock},
+};
+use time::{
+    format_description::{well_known::Rfc3339, FormatItem},
+    macros::format_description,
+    OffsetDateTime,
+};
 
 // time crate does not support strftime
 // original: "%d/%b/%Y:%H:%M:%S %z"
@@ -13,44 +42,322 @@ use time::{format_description::FormatItem, macros::format_description, OffsetDat
 const CLF_TIME_FORMAT: &[FormatItem] = format_description!("[day]/[month repr:short]/[year]:[hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]");
 
 #[cfg(debug_assertions)]
-const LOG_LEVEL: Level = Level::Debug;
-#[cfg(debug_assertions)]
-const LOG_FILTER_LEVEL: LevelFilter = LevelFilter::Debug;
-
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
 #[cfg(not(debug_assertions))]
-const LOG_LEVEL: Level = Level::Info;
-#[cfg(not(debug_assertions))]
-const LOG_FILTER_LEVEL: LevelFilter = LevelFilter::Info;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// env var that overrides `log_level` from the config
+const LOG_ENV: &str = "RUST_LOG";
+
+/// log target for access lines, which are already formatted and printed verbatim
+const ACCESS_TARGET: &str = "rook::access";
+/// log target for signature failures; see [`audit`]
+const AUDIT_TARGET: &str = "rook::audit";
+const GITHUB_DELIVERY_HEADER: &str = "x-github-delivery";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// longer incoming ids are replaced rather than trusted into logs and hook environments
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// id of the request being handled, tagged onto every log line it produces
+    static REQUEST_ID: String;
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// CLF access lines and plain messages
+    #[default]
+    Clf,
+    /// CLF plus the referer and user agent, as written by apache and nginx
+    Combined,
+    /// one json object per line
+    Json,
+}
+
+/// A custom access line such as `"%addr %path %status %duration %hook"`.  Placeholders are
+/// replaced with the request's values (`-` when missing) and `%%` is a literal `%`.
+#[derive(Clone, Debug)]
+pub struct AccessTemplate(Vec<Piece>);
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Addr,
+    Time,
+    Method,
+    Path,
+    Version,
+    Status,
+    Bytes,
+    /// microseconds
+    Duration,
+    Hook,
+    RequestId,
+    UserAgent,
+    Referer,
+    Delivery,
+    Event,
+}
+
+const TEMPLATE_FIELDS: &[(&str, Field)] = &[
+    ("addr", Field::Addr),
+    ("time", Field::Time),
+    ("method", Field::Method),
+    ("path", Field::Path),
+    ("version", Field::Version),
+    ("status", Field::Status),
+    ("bytes", Field::Bytes),
+    ("duration", Field::Duration),
+    ("hook", Field::Hook),
+    ("request_id", Field::RequestId),
+    ("user_agent", Field::UserAgent),
+    ("referer", Field::Referer),
+    ("delivery", Field::Delivery),
+    ("event", Field::Event),
+];
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+static TEMPLATE: OnceLock<AccessTemplate> = OnceLock::new();
+/// stdout when unset
+static OUTPUT: OnceLock<Mutex<Box<dyn Sink + Send>>> = OnceLock::new();
+/// the main log output when unset
+static AUDIT_OUTPUT: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+/// Somewhere other than stdout to send log lines.
+pub trait Sink {
+    fn write(&mut self, level: Level, line: &str) -> io::Result<()>;
+}
+
+/// A `RUST_LOG`-style filter: a default level and per-module overrides, eg.
+/// `"info,rook::router=debug"`.  The longest matching module prefix wins.
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+static FILTER: RwLock<LogFilter> = RwLock::new(LogFilter {
+    default: DEFAULT_LEVEL,
+    modules: Vec::new(),
+});
 
 #[derive(Clone)]
 pub struct LoggingCtx {
     addr: SocketAddr,
+    client: Option<IpAddr>,
     req_method: Option<Method>,
     req_path: Option<String>,
     req_version: Option<Version>,
     resp_status: Option<StatusCode>,
-    resp_size: Option<u32>,
+    resp_size: Option<u64>,
+    /// url of the route that handled the request
+    hook: Option<String>,
+    request_id: Option<String>,
+    delivery: Option<String>,
+    /// GitHub's `X-GitHub-Event`, eg. `push`
+    event: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
     timing_start: Option<OffsetDateTime>,
     timing_end: Option<OffsetDateTime>,
 }
 
 pub fn init_logging() {
     log::set_logger(&LOGGER)
-        .map(|_| log::set_max_level(LOG_FILTER_LEVEL))
+        .map(|_| log::set_max_level(DEFAULT_LEVEL))
         .unwrap_or_else(|_| {
             eprintln!("failed to init logging");
             process::exit(1);
+        });
+    if let Ok(spec) = env::var(LOG_ENV) {
+        match spec.parse() {
+            Ok(filter) => set_filter(filter),
+            Err(e) => {
+                eprintln!("bad {}: {}", LOG_ENV, e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Apply the config's `log_level`, unless `RUST_LOG` already set one.
+pub fn configure_level(filter: Option<&LogFilter>) {
+    if let (Some(filter), Err(_)) = (filter, env::var(LOG_ENV)) {
+        set_filter(filter.clone());
+    }
+}
+
+/// Send all further output to the configured log file, syslog, or journald instead of stdout,
+/// and signature failures to the audit log when one is configured.
+pub fn set_output(cfg: &RouteConfig) -> Result<(), String> {
+    let sink: Option<Box<dyn Sink + Send>> = if let Some(file) = &cfg.log_file {
+        let file = RotatingFile::open(file)
+            .map_err(|e| format!("failed to open log file '{}': {}", file.path, e))?;
+        Some(Box::new(file))
+    } else if let Some(syslog) = &cfg.syslog {
+        let syslog = Syslog::connect(syslog)
+            .map_err(|e| format!("failed to connect to syslog '{}': {}", syslog.address, e))?;
+        Some(Box::new(syslog))
+    } else if cfg.journald {
+        let journald = Journald::connect("rook")
+            .map_err(|e| format!("failed to connect to journald: {}", e))?;
+        Some(Box::new(journald))
+    } else {
+        None
+    };
+    if let Some(sink) = sink {
+        let _ = OUTPUT.set(Mutex::new(sink));
+    }
+    if let Some(audit) = &cfg.audit_log {
+        let file = RotatingFile::open(audit)
+            .map_err(|e| format!("failed to open audit log '{}': {}", audit.path, e))?;
+        let _ = AUDIT_OUTPUT.set(Mutex::new(file));
+    }
+    Ok(())
+}
+
+fn emit(level: Level, line: &str) {
+    match OUTPUT.get().map(Mutex::lock) {
+        Some(Ok(mut sink)) => {
+            if let Err(e) = sink.write(level, line) {
+                eprintln!("failed to write log: {}", e);
+                println!("{}", line);
+            }
+        }
+        _ => println!("{}", line),
+    }
+}
+
+/// Told when every hook on a url rejects a delivery's signature, such as to message someone.
+/// Called while the request is being answered, so anything slow should be handed off.
+pub trait Alert: Send + Sync {
+    fn signature_failure(&self, client: IpAddr, path: &str, reason: &str);
+}
+
+/// Record a rejected signature as one stable line for fail2ban, crowdsec and the like:
+///
+/// `signature failure: client=<ip> path=<url> reason="<reason>" request_id=<id>`
+///
+/// Lines go to the audit log if configured (prefixed with an RFC 3339 timestamp), otherwise to
+/// the main log at warn level.
+pub fn audit(client: IpAddr, path: &str, reason: &str) {
+    let request_id = REQUEST_ID.try_with(String::clone).unwrap_or_default();
+    let line = match format() {
+        LogFormat::Json => json!({
+            "timestamp": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "level": "warn",
+            "event": "signature_failure",
+            "client": client.to_string(),
+            "path": path,
+            "reason": reason,
+            "request_id": request_id,
         })
+        .to_string(),
+        LogFormat::Clf | LogFormat::Combined => format!(
+            "signature failure: client={} path={} reason=\"{}\" request_id={}",
+            client, path, reason, request_id
+        ),
+    };
+    match AUDIT_OUTPUT.get().map(Mutex::lock) {
+        Some(Ok(mut file)) => {
+            let line = match format() {
+                LogFormat::Json => line,
+                LogFormat::Clf | LogFormat::Combined => format!(
+                    "{} {}",
+                    OffsetDateTime::now_utc()
+                        .format(&Rfc3339)
+                        .unwrap_or_default(),
+                    line
+                ),
+            };
+            if let Err(e) = file.write(Level::Warn, &line) {
+                eprintln!("failed to write audit log: {}", e);
+            }
+        }
+        _ => log::warn!(target: AUDIT_TARGET, "{}", line),
+    }
+}
+
+fn set_filter(filter: LogFilter) {
+    log::set_max_level(filter.max());
+    if let Ok(mut current) = FILTER.write() {
+        *current = filter;
+    }
+}
+
+/// Switch the output format once the config is loaded.  Lines logged before this use CLF.
+/// A template replaces the format's access lines.
+pub fn set_format(format: LogFormat, template: Option<&AccessTemplate>) {
+    let _ = FORMAT.set(format);
+    if let Some(template) = template {
+        let _ = TEMPLATE.set(template.clone());
+    }
+}
+
+fn format() -> LogFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Write the access line for a finished request in the configured format.
+pub fn access(ctx: &LoggingCtx) {
+    let line = match (TEMPLATE.get(), format()) {
+        (Some(template), _) => ctx.render(template),
+        (None, LogFormat::Clf) => ctx.clf_with_timing(),
+        (None, LogFormat::Combined) => ctx.combined_with_timing(),
+        (None, LogFormat::Json) => ctx.json(),
+    };
+    info!(target: ACCESS_TARGET, "{}", line);
+}
+
+/// Reuse the id a proxy assigned (`X-Request-Id`) or GitHub's delivery id, otherwise generate
+/// one.  Incoming ids must be short printable ascii.
+pub fn request_id(headers: &HeaderMap<HeaderValue>) -> String {
+    [REQUEST_ID_HEADER, GITHUB_DELIVERY_HEADER]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok())
+        .find(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map_or_else(new_request_id, str::to_string)
+}
+
+pub fn new_request_id() -> String {
+    format!("{:016x}", id::random_u64())
+}
+
+/// Id of the request being handled on this task, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Run `f` with every log line it produces tagged with `id`.
+pub async fn with_request_id<F: Future>(id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
 }
 
 pub fn log_context(remote: &SocketAddr) -> LoggingCtx {
     LoggingCtx {
-        addr: remote.clone(),
+        addr: *remote,
+        client: None,
         req_method: None,
         req_path: None,
         req_version: None,
         resp_status: None,
         resp_size: None,
+        hook: None,
+        request_id: None,
+        delivery: None,
+        event: None,
+        user_agent: None,
+        referer: None,
         timing_start: None,
         timing_end: None,
     }
@@ -69,50 +376,277 @@ impl LoggingCtx {
         self.req_method = Some(req.method().clone());
         self.req_path = Some(req.uri().path().to_string());
         self.req_version = Some(req.version());
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        self.delivery = header(GITHUB_DELIVERY_HEADER);
+        self.event = header(github::EVENT_HEADER);
+        self.user_agent = header(header::USER_AGENT.as_str());
+        self.referer = header(header::REFERER.as_str());
+        self
+    }
+    /// Client address recovered from a trusted proxy's forwarding headers.  Replaces the peer
+    /// address (and port) in the log line when it differs.
+    pub fn request_id(&mut self, id: &str) -> &mut Self {
+        self.request_id = Some(id.to_string());
+        self
+    }
+    pub fn client(&mut self, ip: IpAddr) -> &mut Self {
+        self.client = (ip != self.addr.ip()).then_some(ip);
         self
     }
     pub fn res(&mut self, res: &Result<Response<Body>, Infallible>) -> &mut Self {
-        self.resp_status = match res.as_ref() {
-            Ok(r) => Some(r.status()),
-            Err(_) => None,
+        let r = match res {
+            Ok(r) => r,
+            Err(e) => match *e {},
         };
-        // response bytes are opaque here, would need to use body::to_bytes()
-        self.resp_size = None;
+        self.resp_status = Some(r.status());
+        self.hook = r.extensions().get::<MatchedRoute>().map(|m| m.0.clone());
+        // every response is built from a fixed buffer, so its length is known up front
+        self.resp_size = r.body().size_hint().exact();
         self
     }
 
-    fn internal_clf_with_timing(&self) -> Result<String, &'static str> {
+    fn internal_clf_with_timing(&self, combined: bool) -> Result<String, &'static str> {
         let start = self.timing_start.ok_or("start timing not set")?;
         let end = self.timing_end.ok_or("end timing not set")?;
         let method = self.req_method.as_ref().ok_or("method not set")?;
         let path = self.req_path.as_ref().ok_or("path not set")?;
         let version = self.req_version.ok_or("version not set")?;
         let status = match self.resp_status {
-            Some(s) => s.to_string(),
+            Some(s) => s.as_u16().to_string(),
             None => "-".to_string(),
         };
+        // CLF writes "-" rather than 0 for an empty body
+        let size = match self.resp_size {
+            Some(n) if n > 0 => n.to_string(),
+            _ => "-".to_string(),
+        };
         let elapsed = (end - start).whole_microseconds();
+        let addr = self.client.unwrap_or(self.addr.ip());
+        // the github fields tell GitHub's retries (same delivery) from probes (no delivery)
+        let agent = if combined {
+            format!(
+                r#" "{}" "{}" "{}" "{}""#,
+                quoted(self.referer.as_deref()),
+                quoted(self.user_agent.as_deref()),
+                quoted(self.event.as_deref()),
+                quoted(self.delivery.as_deref())
+            )
+        } else {
+            String::new()
+        };
 
         Ok(format!(
-            r#"{} - - [{}] "{} {} {:?}" {} - {}µs"#,
-            self.addr,
+            r#"{} - - [{}] "{} {} {:?}" {} {}{} {}µs {}"#,
+            addr,
             end.format(CLF_TIME_FORMAT)
                 .map_err(|_| "bad time fmt str")?,
             method,
             path,
             version,
             status,
-            elapsed
+            size,
+            agent,
+            elapsed,
+            self.request_id.as_deref().unwrap_or("-")
         ))
     }
 
+    /// Render the request context as a single-line json object.
+    pub fn json(&self) -> String {
+        let end = self.timing_end.unwrap_or_else(OffsetDateTime::now_utc);
+        let elapsed = self
+            .timing_start
+            .map(|start| (end - start).whole_microseconds());
+        let remote = match self.client {
+            Some(ip) => ip.to_string(),
+            None => self.addr.to_string(),
+        };
+        json!({
+            "timestamp": end.format(&Rfc3339).unwrap_or_default(),
+            "level": "info",
+            "remote": remote,
+            "method": self.req_method.as_ref().map(Method::as_str),
+            "path": self.req_path,
+            "status": self.resp_status.map(|s| s.as_u16()),
+            "bytes": self.resp_size,
+            "duration_us": elapsed.map(|us| us as u64),
+            "hook": self.hook,
+            "request_id": self.request_id,
+            "delivery": self.delivery,
+            "event": self.event,
+            "user_agent": self.user_agent,
+            "referer": self.referer,
+        })
+        .to_string()
+    }
+
     /// Render the request context in [CLF](https://en.wikipedia.org/wiki/Common_Log_Format)
     /// with an extra field for timing information.
     pub fn clf_with_timing(&self) -> String {
-        self.internal_clf_with_timing()
+        self.internal_clf_with_timing(false)
             .map_err(|e| panic!("error formatting log line: {}", e))
             .unwrap()
     }
+
+    /// Combined Log Format: CLF with the quoted referer and user agent after the size, then the
+    /// GitHub event and delivery id, then the same timing and request id fields as
+    /// [`Self::clf_with_timing`].
+    pub fn combined_with_timing(&self) -> String {
+        self.internal_clf_with_timing(true)
+            .map_err(|e| panic!("error formatting log line: {}", e))
+            .unwrap()
+    }
+
+    /// Render the request context with a custom `log_template`.
+    pub fn render(&self, template: &AccessTemplate) -> String {
+        let end = self.timing_end.unwrap_or_else(OffsetDateTime::now_utc);
+        let mut line = String::new();
+        for piece in &template.0 {
+            let value = match piece {
+                Piece::Literal(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Piece::Field(Field::Addr) => {
+                    Some(self.client.unwrap_or(self.addr.ip()).to_string())
+                }
+                Piece::Field(Field::Time) => end.format(&Rfc3339).ok(),
+                Piece::Field(Field::Method) => self.req_method.as_ref().map(Method::to_string),
+                Piece::Field(Field::Path) => self.req_path.clone(),
+                Piece::Field(Field::Version) => self.req_version.map(|v| format!("{:?}", v)),
+                Piece::Field(Field::Status) => self.resp_status.map(|s| s.as_u16().to_string()),
+                Piece::Field(Field::Bytes) => self.resp_size.map(|n| n.to_string()),
+                Piece::Field(Field::Duration) => self
+                    .timing_start
+                    .map(|start| (end - start).whole_microseconds().to_string()),
+                Piece::Field(Field::Hook) => self.hook.clone(),
+                Piece::Field(Field::RequestId) => self.request_id.clone(),
+                Piece::Field(Field::UserAgent) => self.user_agent.clone(),
+                Piece::Field(Field::Referer) => self.referer.clone(),
+                Piece::Field(Field::Delivery) => self.delivery.clone(),
+                Piece::Field(Field::Event) => self.event.clone(),
+            };
+            line.push_str(value.as_deref().unwrap_or("-"));
+        }
+        line
+    }
+}
+
+/// header value for a quoted log field, `-` when missing
+fn quoted(value: Option<&str>) -> String {
+    value.map_or_else(
+        || "-".to_string(),
+        |v| v.replace('\\', "\\\\").replace('"', "\\\""),
+    )
+}
+
+impl FromStr for AccessTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(at) = rest.find('%') {
+            literal.push_str(&rest[..at]);
+            rest = &rest[at + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+                continue;
+            }
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..len];
+            let field = TEMPLATE_FIELDS
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, f)| *f)
+                .ok_or_else(|| format!("unknown log_template placeholder '%{}'", name))?;
+            if !literal.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(Piece::Field(field));
+            rest = &rest[len..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(AccessTemplate(pieces))
+    }
+}
+
+impl<'de> Deserialize<'de> for AccessTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl LogFilter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module
+                    || target
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter {
+            default: DEFAULT_LEVEL,
+            modules: Vec::new(),
+        };
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level = |level: &str| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format!("unknown log level '{}'", level))
+            };
+            match directive.split_once('=') {
+                Some((module, level)) => filter
+                    .modules
+                    .push((module.trim().to_string(), parse_level(level.trim())?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl<'de> Deserialize<'de> for LogFilter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
 }
 
 struct SimpleLogger;
@@ -120,17 +654,44 @@ static LOGGER: SimpleLogger = SimpleLogger;
 
 impl log::Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= LOG_LEVEL
+        FILTER
+            .read()
+            .map_or(DEFAULT_LEVEL, |f| f.level(metadata.target()))
+            >= metadata.level()
     }
     fn log(&self, record: &Record) {
-        #[cfg(not(debug_assertions))]
-        if self.enabled(record.metadata()) {
-            println!("{}", record.args());
-        }
-        #[cfg(debug_assertions)]
-        if self.enabled(record.metadata()) {
-            println!("{}:{}", record.level(), record.args());
+        if !self.enabled(record.metadata()) {
+            return;
         }
+        // access and audit lines are preformatted and carry the id as a field of their own
+        let verbatim = matches!(record.target(), ACCESS_TARGET | AUDIT_TARGET);
+        let request_id = REQUEST_ID
+            .try_with(String::clone)
+            .ok()
+            .filter(|_| !verbatim);
+        let line = match format() {
+            LogFormat::Json if verbatim => record.args().to_string(),
+            LogFormat::Json => json!({
+                "timestamp": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+                "level": record.level().as_str().to_ascii_lowercase(),
+                "request_id": request_id,
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+            LogFormat::Clf | LogFormat::Combined => {
+                let mut line = String::new();
+                // release builds keep access lines bare so they stay valid CLF
+                if cfg!(debug_assertions) || record.level() != Level::Info {
+                    line.push_str(&format!("{}:", record.level()));
+                }
+                if let Some(id) = request_id {
+                    line.push_str(&format!("[{}] ", id));
+                }
+                line.push_str(&record.args().to_string());
+                line
+            }
+        };
+        emit(record.level(), &line);
     }
     fn flush(&self) {}
 }
@@ -1,57 +1,97 @@
-mod config;
-mod logging;
-mod router;
-use hyper::{
-    server::conn::AddrStream,
-    service::{make_service_fn, service_fn},
-    Server,
-};
-use std::{convert::Infallible, env, process, sync::Arc};
+mod cli;
+mod replay;
+mod selftest;
+mod top;
 
-#[tokio::main]
-async fn main() {
+use rook::{config, logging, remote};
+use std::{env, future::Future, process, sync::Arc};
+use tokio::signal::unix::{signal, SignalKind};
+
+fn main() {
     logging::init_logging();
-    let cfg_path = env::args().nth(1).unwrap_or_else(|| {
-        eprintln!(
-            "usage: {} your_config_file.toml",
-            env::args().nth(0).unwrap()
-        );
-        process::exit(1);
-    });
-    let cfg = match config::from_file(&cfg_path) {
-        Ok(c) => Arc::new(c),
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("send") => block_on(cli::send(&args[1..])),
+        Some("secret") => cli::secret(&args[1..]),
+        Some("routes") => cli::routes(&args[1..]),
+        Some("sign") => cli::sign(&args[1..]),
+        Some("selftest") => block_on(selftest::selftest(&args[1..])),
+        Some("replay") => block_on(replay::replay(&args[1..])),
+        Some("top") => block_on(top::top(&args[1..])),
+        Some("--version") => {
+            cli::version();
+            Ok(())
         }
+        Some(_) => serve(&args),
+        None => Err(cli::USAGE.to_string()),
     };
-    let svc_cfg = cfg.clone();
-    let make_svc = make_service_fn(move |conn: &AddrStream| {
-        let conn_cfg = svc_cfg.clone();
-        let log = logging::log_context(&conn.remote_addr());
-        async {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                let req_cfg = conn_cfg.clone();
-                let mut log = log.clone();
-                async move {
-                    log.start().req(&req);
-                    let res = router::handle(req, &req_cfg).await;
-                    log.res(&res).end();
-                    logging::info!("{}", log.clf_with_timing());
-                    res
-                }
-            }))
-        }
-    });
-    let server = Server::bind(&cfg.socket).serve(make_svc);
-    logging::info!("listening on {}:{}", cfg.socket.ip(), cfg.socket.port());
-    match server.await {
-        Ok(_) => {
-            println!("shutting down");
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// The runtime is only started once any daemonizing fork is done.
+fn block_on<F: Future<Output = Result<(), String>>>(f: F) -> Result<(), String> {
+    tokio::runtime::Runtime::new()
+        .map_err(|e| format!("failed to start runtime: {}", e))?
+        .block_on(f)
+}
+
+fn serve(args: &[String]) -> Result<(), String> {
+    let args = cli::Args::parse(
+        args,
+        &["--dry-run", "--daemon"],
+        &[
+            "--pidfile",
+            "--config-sha256",
+            "--config-secret",
+            "--config-refresh",
+            "--record",
+        ],
+    )?;
+    let [cfg_path] = args.positional.as_slice() else {
+        return Err(cli::USAGE.to_string());
+    };
+    let mut cfg = if remote::is_url(cfg_path) {
+        config::from_url(cli::config_source(cfg_path, &args)?)
+    } else if remote::is_kv(cfg_path) {
+        config::from_url(remote::Source::kv(cfg_path)?)
+    } else {
+        config::from_file(cfg_path)
+    }
+    .map_err(|e| e.to_string())?;
+    if args.has("--dry-run") {
+        cfg.set_dry_run();
+    }
+    cfg.record = args.value("--record").map(str::to_string);
+    let mut pidfile = args
+        .value("--pidfile")
+        .map(cli::Pidfile::check)
+        .transpose()?;
+    if args.has("--daemon") {
+        cli::daemonize(&cfg)?;
+    }
+    let cfg = Arc::new(cfg);
+    let served = block_on(async {
+        rook::init(&cfg)?;
+        if let Some(pidfile) = &mut pidfile {
+            pidfile.write()?;
         }
-        Err(e) => {
-            eprintln!("server error: {}", e);
-            process::exit(1);
+        let mut terminate = signal(SignalKind::terminate()).map_err(|e| e.to_string())?;
+        let mut interrupt = signal(SignalKind::interrupt()).map_err(|e| e.to_string())?;
+        tokio::select! {
+            served = rook::serve(cfg) => served,
+            _ = terminate.recv() => Ok(()),
+            _ = interrupt.recv() => Ok(()),
         }
+    });
+    drop(pidfile);
+    match &served {
+        Ok(()) => logging::info!("shutting down"),
+        // stderr is gone once daemonized; the configured log output isn't
+        Err(e) if args.has("--daemon") => log::error!("{}", e),
+        Err(_) => {}
     }
+    served
 }
@@ -0,0 +1,268 @@
+//! Pausing a hook url, such as during a database migration that auto-deploys mustn't run in.
+//!
+//! A paused url answers `503` with its hooks' `maintenance.message`, or, with
+//! `maintenance.queue` set, holds up to that many deliveries (answering `202`) and runs them in
+//! the order they arrived once the url is resumed.  Held deliveries aren't checked until then:
+//! signatures, filters, and rate limits apply when they run, under the config of the time.
+//! Urls are paused and resumed on the admin listener, and `maintenance.paused` pauses a url
+//! whenever the config loads.  Held deliveries are lost if rook stops.
+//!
+//! Urls with `outside_hours = "queue"` hold deliveries that arrive outside their
+//! `allowed_hours` the same way, and the server runs them once the window opens.
+use crate::{
+    config::RouteConfig,
+    logging,
+    metrics::METRICS,
+    router::{self, Peer, RequestId},
+    service::RookService,
+};
+use hyper::{http::request::Parts, Body, HeaderMap, Method, Request, Uri};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How a hook url behaves while it's paused; hooks on a url share these.
+#[derive(Clone)]
+pub struct Maintenance {
+    /// pause the url whenever the config loads
+    pub paused: bool,
+    /// the body of the `503` paused urls answer with
+    pub message: String,
+    /// most deliveries held until the url is resumed; 0 turns them all away
+    pub queue: usize,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            paused: false,
+            message: "paused for maintenance".to_string(),
+            queue: 0,
+        }
+    }
+}
+
+/// A delivery to run once its url is resumed.
+struct Held {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    client: IpAddr,
+    client_cn: Option<String>,
+    request_id: String,
+}
+
+impl Held {
+    fn new(parts: &Parts, body: Vec<u8>, peer: &Peer, request_id: Option<&str>) -> Self {
+        Held {
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            headers: parts.headers.clone(),
+            body,
+            client: peer.ip,
+            client_cn: peer.client_cn.clone(),
+            request_id: request_id.map_or_else(logging::new_request_id, str::to_string),
+        }
+    }
+}
+
+/// Whether a config's `maintenance.paused` urls have been paused in this process.
+static APPLIED: AtomicBool = AtomicBool::new(false);
+/// Paused urls, and the deliveries each is holding.
+static PAUSED: Mutex<BTreeMap<String, Vec<Held>>> = Mutex::new(BTreeMap::new());
+/// Deliveries waiting for their url's `allowed_hours`, by url.
+static WAITING: Mutex<BTreeMap<String, Vec<Held>>> = Mutex::new(BTreeMap::new());
+/// most deliveries a url holds until its `allowed_hours` open
+pub const WAITING_MAX: usize = 100;
+/// how often urls holding deliveries are checked for an open window
+const WAITING_CHECK: Duration = Duration::from_secs(15);
+
+/// Pause `url`.  False if it already was.
+pub fn pause(url: &str) -> bool {
+    let Ok(mut paused) = PAUSED.lock() else {
+        return false;
+    };
+    if paused.contains_key(url) {
+        return false;
+    }
+    paused.insert(url.to_string(), vec![]);
+    true
+}
+
+pub fn is_paused(url: &str) -> bool {
+    PAUSED.lock().is_ok_and(|p| p.contains_key(url))
+}
+
+/// Whether [`apply`] has run, so [`is_paused`] reflects the loaded config.
+pub fn applied() -> bool {
+    APPLIED.load(Ordering::Relaxed)
+}
+
+/// The paused urls and how many deliveries each is holding.
+pub fn paused() -> Vec<(String, usize)> {
+    PAUSED
+        .lock()
+        .map(|p| {
+            p.iter()
+                .map(|(url, held)| (url.clone(), held.len()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Pause the urls `cfg` starts paused.
+pub fn apply(cfg: &RouteConfig) {
+    APPLIED.store(true, Ordering::Relaxed);
+    let gh = cfg
+        .gh_hooks
+        .iter()
+        .map(|(url, h)| (url, h.first().map(|h| &h.options)));
+    let rook = cfg
+        .rook_hooks
+        .iter()
+        .map(|(url, h)| (url, h.first().map(|h| &h.options)));
+    for (url, options) in gh.chain(rook) {
+        if options.is_some_and(|o| o.maintenance.paused) && pause(url) {
+            logging::info!("'{}' paused for maintenance", url);
+        }
+    }
+}
+
+/// Hold a delivery for `url` until it's resumed.  False when the url already holds `limit`
+/// deliveries, or was resumed since the request arrived.
+pub fn hold(
+    url: &str,
+    parts: &Parts,
+    body: Vec<u8>,
+    peer: &Peer,
+    request_id: Option<&str>,
+    limit: usize,
+) -> bool {
+    let Ok(mut paused) = PAUSED.lock() else {
+        return false;
+    };
+    match paused.get_mut(url) {
+        Some(held) if held.len() < limit => {
+            held.push(Held::new(parts, body, peer, request_id));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Hold a delivery for `url` until its `allowed_hours` open.  False when the url already holds
+/// [`WAITING_MAX`] deliveries.
+pub fn wait(
+    url: &str,
+    parts: &Parts,
+    body: Vec<u8>,
+    peer: &Peer,
+    request_id: Option<&str>,
+) -> bool {
+    let Ok(mut waiting) = WAITING.lock() else {
+        return false;
+    };
+    let held = waiting.entry(url.to_string()).or_default();
+    if held.len() >= WAITING_MAX {
+        return false;
+    }
+    held.push(Held::new(parts, body, peer, request_id));
+    true
+}
+
+/// The urls holding deliveries until their `allowed_hours` open, and how many each holds.
+pub fn waiting() -> Vec<(String, usize)> {
+    WAITING
+        .lock()
+        .map(|w| {
+            w.iter()
+                .map(|(url, held)| (url.clone(), held.len()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Run the deliveries waiting for `allowed_hours` once their url's window opens, with the
+/// config of the time.  Urls that are paused keep theirs until they're resumed.
+pub async fn release(rook: RookService) {
+    let mut check = tokio::time::interval(WAITING_CHECK);
+    loop {
+        check.tick().await;
+        let cfg = rook.config();
+        let Ok(mut waiting) = WAITING.lock() else {
+            continue;
+        };
+        let open: Vec<String> = waiting
+            .keys()
+            // urls that lost their allowed_hours in a reload count as open, so nothing waits
+            // forever
+            .filter(|url| !is_paused(url) && router::within_hours(&cfg, url))
+            .cloned()
+            .collect();
+        for url in open {
+            if let Some(held) = waiting.remove(&url) {
+                logging::info!(
+                    "'{}' allowed hours opened, running {} held",
+                    url,
+                    held.len()
+                );
+                run_all(cfg.clone(), held);
+            }
+        }
+    }
+}
+
+/// Resume `url`, running the deliveries it held with `cfg` in the background.  How many there
+/// are, or None if the url wasn't paused.
+pub fn resume(url: &str, cfg: Arc<RouteConfig>) -> Option<usize> {
+    let held = PAUSED.lock().ok()?.remove(url)?;
+    let count = held.len();
+    run_all(cfg, held);
+    Some(count)
+}
+
+/// Run `held` in order, in the background.
+fn run_all(cfg: Arc<RouteConfig>, held: Vec<Held>) {
+    tokio::spawn(async move {
+        for held in held {
+            let request_id = held.request_id.clone();
+            logging::with_request_id(request_id, run(&cfg, held)).await;
+        }
+    });
+}
+
+/// Send a held delivery through the router, under the request id it was answered with.
+async fn run(cfg: &RouteConfig, held: Held) {
+    let mut req = Request::new(Body::from(held.body));
+    *req.method_mut() = held.method.clone();
+    *req.uri_mut() = held.uri.clone();
+    *req.headers_mut() = held.headers;
+    req.extensions_mut()
+        .insert(RequestId(held.request_id.clone()));
+    let peer = Peer {
+        ip: held.client,
+        client_cn: held.client_cn,
+    };
+    let started = Instant::now();
+    let res = match router::handle(req, cfg, &peer).await {
+        Ok(r) => r,
+        Err(e) => match e {},
+    };
+    let path = held.uri.path();
+    logging::info!("ran held delivery for {}: {}", path, res.status().as_u16());
+    METRICS.response(
+        &held.request_id,
+        peer.ip,
+        &held.method,
+        path,
+        res.status(),
+        started.elapsed(),
+    );
+}
@@ -0,0 +1,141 @@
+//! Post a hook's results to a Matrix room, for self-hosters who run a homeserver instead of
+//! a chat service.
+//!
+//! Each result is one `m.text` message naming the hook and how its command ended; failures
+//! carry the end of the command's output.  It's sent from the forked process that waited on
+//! the command, once, with no retries.
+use crate::{
+    blocking::Client,
+    config::MatrixConfig,
+    encoding,
+    exec::{Ended, Notifier},
+    id,
+};
+use hyper::Uri;
+use serde_json::{json, Value};
+use std::fs;
+
+/// Post a hook's results to a room.
+pub struct Matrix {
+    room_id: String,
+    /// the room's send endpoint, without the transaction id
+    send_url: String,
+    auth: String,
+    client: Client,
+    /// successes too, not only failures
+    always: bool,
+}
+
+impl Matrix {
+    pub fn new(cfg: &MatrixConfig, always: bool) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .homeserver
+            .parse()
+            .map_err(|e| format!("bad matrix homeserver '{}': {}", cfg.homeserver, e))?;
+        match (uri.scheme_str(), uri.host(), uri.path()) {
+            (Some("http" | "https"), Some(_), "" | "/") => {}
+            _ => {
+                return Err(format!(
+                    "matrix homeserver '{}' must be https://host[:port]",
+                    cfg.homeserver
+                ))
+            }
+        }
+        if !cfg.room_id.starts_with('!') || !cfg.room_id.contains(':') {
+            return Err(format!(
+                "matrix room_id '{}' must look like !abcdefg:example.com",
+                cfg.room_id
+            ));
+        }
+        let token = fs::read_to_string(&cfg.access_token_file).map_err(|e| {
+            format!(
+                "failed to read matrix access_token_file '{}': {}",
+                cfg.access_token_file, e
+            )
+        })?;
+        let token = token.trim();
+        if token.is_empty() || token.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            return Err(format!(
+                "bad matrix access token in '{}'",
+                cfg.access_token_file
+            ));
+        }
+        Ok(Matrix {
+            room_id: cfg.room_id.clone(),
+            send_url: format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message/",
+                cfg.homeserver.trim_end_matches('/'),
+                encoding::encode_path_segment(&cfg.room_id)
+            ),
+            auth: format!("Bearer {}", token),
+            client: Client::new(&uri, &cfg.ca_file)?,
+            always,
+        })
+    }
+
+    /// The `m.room.message` content for `ended`, as plain text and html.
+    fn message(&self, ended: &Ended) -> Value {
+        let (describe, took) = (ended.describe(), ended.duration.as_secs_f64());
+        let mut body = format!("rook: {} {} after {:.1}s", ended.route, describe, took);
+        let mut html = format!(
+            "<b>rook</b>: <code>{}</code> {} after {:.1}s",
+            encoding::escape_html(ended.route),
+            encoding::escape_html(&describe),
+            took
+        );
+        if let Some(id) = ended.request_id {
+            body.push_str(&format!(" (request {})", id));
+            html.push_str(&format!(
+                " (request <code>{}</code>)",
+                encoding::escape_html(id)
+            ));
+        }
+        let output = ended.output_text();
+        if ended.failed() && !output.is_empty() {
+            body.push_str(&format!("\n\n{}", output));
+            html.push_str(&format!(
+                "<pre><code>{}</code></pre>",
+                encoding::escape_html(&output)
+            ));
+        }
+        json!({
+            "msgtype": "m.text",
+            "body": body,
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        })
+    }
+
+    fn send(&self, ended: &Ended) -> Result<(), String> {
+        let res = self.client.send(
+            "PUT",
+            &format!("{}{:016x}", self.send_url, id::random_u64()),
+            &[
+                ("authorization", &self.auth),
+                ("content-type", "application/json"),
+            ],
+            self.message(ended).to_string().as_bytes(),
+        )?;
+        if (200..300).contains(&res.status) {
+            return Ok(());
+        }
+        // errors are json like {"errcode": "M_FORBIDDEN", "error": "..."}
+        let error = serde_json::from_slice::<Value>(&res.body)
+            .ok()
+            .and_then(|v| v["errcode"].as_str().map(str::to_string));
+        Err(match error {
+            Some(code) => format!("{} {}", res.status, code),
+            None => res.status.to_string(),
+        })
+    }
+}
+
+impl Notifier for Matrix {
+    fn notify(&self, ended: &Ended) -> Result<(), String> {
+        if !self.always && !ended.failed() {
+            return Ok(());
+        }
+        self.send(ended)
+            .map_err(|e| format!("matrix room {}: {}", self.room_id, e))
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{exec, statsd};
+use hyper::{Method, StatusCode};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// how many recent deliveries are kept for `/admin/deliveries`
+const HISTORY_LEN: usize = 100;
+
+/// Process-wide counters, rendered in the Prometheus text format on the admin listener and
+/// mirrored to statsd when configured.
+pub struct Metrics {
+    responses: Mutex<BTreeMap<u16, u64>>,
+    signature_failures: Mutex<BTreeMap<&'static str, u64>>,
+    hooks_started: AtomicU64,
+    hook_start_failures: AtomicU64,
+    hooks_rate_limited: AtomicU64,
+    connections_rejected: AtomicU64,
+    requests_shed: AtomicU64,
+    connections_open: AtomicU64,
+    requests_in_flight: AtomicU64,
+    history: Mutex<VecDeque<Delivery>>,
+}
+
+/// Counts toward one of the gauges until dropped.
+pub struct Gauge(&'static AtomicU64);
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One request to the webhook listener, newest first in `/admin/deliveries`.
+#[derive(Clone, Serialize)]
+pub struct Delivery {
+    pub time: String,
+    pub request_id: String,
+    pub client: IpAddr,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+pub static METRICS: Metrics = Metrics {
+    responses: Mutex::new(BTreeMap::new()),
+    signature_failures: Mutex::new(BTreeMap::new()),
+    hooks_started: AtomicU64::new(0),
+    hook_start_failures: AtomicU64::new(0),
+    hooks_rate_limited: AtomicU64::new(0),
+    connections_rejected: AtomicU64::new(0),
+    requests_shed: AtomicU64::new(0),
+    connections_open: AtomicU64::new(0),
+    requests_in_flight: AtomicU64::new(0),
+    history: Mutex::new(VecDeque::new()),
+};
+
+impl Metrics {
+    pub fn response(
+        &self,
+        request_id: &str,
+        client: IpAddr,
+        method: &Method,
+        path: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        if let Ok(mut responses) = self.responses.lock() {
+            *responses.entry(status.as_u16()).or_default() += 1;
+        }
+        let tags = [("status", status.as_str())];
+        statsd::count("requests", &tags);
+        statsd::timing("request.duration", elapsed, &tags);
+        let delivery = Delivery {
+            time: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            request_id: request_id.to_string(),
+            client,
+            method: method.to_string(),
+            path: path.to_string(),
+            status: status.as_u16(),
+        };
+        if let Ok(mut history) = self.history.lock() {
+            if history.len() == HISTORY_LEN {
+                history.pop_back();
+            }
+            history.push_front(delivery);
+        }
+    }
+
+    pub fn signature_failure(&self, reason: &'static str) {
+        if let Ok(mut failures) = self.signature_failures.lock() {
+            *failures.entry(reason).or_default() += 1;
+        }
+        statsd::count("signature_failures", &[("reason", reason)]);
+    }
+
+    pub fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+        statsd::count("connections.rejected", &[]);
+    }
+
+    pub fn request_shed(&self) {
+        self.requests_shed.fetch_add(1, Ordering::Relaxed);
+        statsd::count("requests.shed", &[]);
+    }
+
+    /// Hold for the life of an accepted connection, including its PROXY/TLS preamble.
+    pub fn connection_opened(&'static self) -> Gauge {
+        self.connections_open.fetch_add(1, Ordering::Relaxed);
+        Gauge(&self.connections_open)
+    }
+
+    /// Hold while a request counts toward `max_requests`.
+    pub fn request_started(&'static self) -> Gauge {
+        self.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+        Gauge(&self.requests_in_flight)
+    }
+
+    pub fn connections_open(&self) -> u64 {
+        self.connections_open.load(Ordering::Relaxed)
+    }
+
+    pub fn requests_in_flight(&self) -> u64 {
+        self.requests_in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn history(&self) -> Vec<Delivery> {
+        self.history
+            .lock()
+            .map(|h| h.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// `elapsed` covers handing the hook to its executor (the fork, for commands); hooks run
+    /// detached, so their own run time isn't seen
+    pub fn hook_started(&self, elapsed: Duration) {
+        self.hooks_started.fetch_add(1, Ordering::Relaxed);
+        statsd::count("hooks.started", &[]);
+        statsd::timing("hooks.fork", elapsed, &[]);
+    }
+
+    pub fn hook_start_failed(&self) {
+        self.hook_start_failures.fetch_add(1, Ordering::Relaxed);
+        statsd::count("hooks.start_failures", &[]);
+    }
+
+    pub fn rate_limited(&self) {
+        self.hooks_rate_limited.fetch_add(1, Ordering::Relaxed);
+        statsd::count("hooks.rate_limited", &[]);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# TYPE rook_http_responses_total counter\n");
+        if let Ok(responses) = self.responses.lock() {
+            for (status, count) in responses.iter() {
+                let _ = writeln!(
+                    out,
+                    "rook_http_responses_total{{status=\"{}\"}} {}",
+                    status, count
+                );
+            }
+        }
+        out.push_str("# TYPE rook_signature_failures_total counter\n");
+        if let Ok(failures) = self.signature_failures.lock() {
+            for (reason, count) in failures.iter() {
+                let _ = writeln!(
+                    out,
+                    "rook_signature_failures_total{{reason=\"{}\"}} {}",
+                    reason, count
+                );
+            }
+        }
+        let counters = [
+            ("rook_hooks_started_total", &self.hooks_started),
+            ("rook_hook_start_failures_total", &self.hook_start_failures),
+            ("rook_hooks_rate_limited_total", &self.hooks_rate_limited),
+            (
+                "rook_connections_rejected_total",
+                &self.connections_rejected,
+            ),
+            ("rook_requests_shed_total", &self.requests_shed),
+        ];
+        for (name, counter) in counters {
+            let value = counter.load(Ordering::Relaxed);
+            let _ = writeln!(out, "# TYPE {} counter\n{} {}", name, name, value);
+        }
+        let gauges = [
+            ("rook_connections_open", self.connections_open()),
+            ("rook_requests_in_flight", self.requests_in_flight()),
+            ("rook_hooks_running", exec::running().len() as u64),
+        ];
+        for (name, value) in gauges {
+            let _ = writeln!(out, "# TYPE {} gauge\n{} {}", name, name, value);
+        }
+        out
+    }
+}
@@ -0,0 +1,61 @@
+//! Code that runs around every request: before it's routed and verified, and after its hooks
+//! are dispatched.
+//!
+//! Middleware is registered on a [`crate::service::RookService`] with
+//! [`with_middleware`](crate::service::RookService::with_middleware).  `before` runs in
+//! registration order and `after` in reverse, so the first middleware registered sees the
+//! request first and the response last.  rook's own per-request bookkeeping (metrics and the
+//! request id header) is registered ahead of everything else.
+use crate::{logging, metrics::METRICS};
+use hyper::{header::HeaderValue, Body, Method, Request, Response};
+use std::{net::IpAddr, time::Instant};
+
+/// What [`Middleware::after`] knows about the request that was answered.
+pub struct Exchange<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    /// after PROXY protocol and trusted forwarding headers are applied
+    pub client: IpAddr,
+    pub request_id: &'a str,
+    /// when rook started handling the request
+    pub started: Instant,
+}
+
+pub trait Middleware: Send + Sync {
+    /// Runs before the request is routed.  The request can be changed (headers rewritten,
+    /// extensions added); returning a response answers the request without routing it, and
+    /// skips any later middleware's `before`.
+    fn before(&self, _req: &mut Request<Body>, _client: IpAddr) -> Option<Response<Body>> {
+        None
+    }
+
+    /// Runs once the response is ready, including responses from `before`.
+    fn after(&self, _exchange: &Exchange, _res: &mut Response<Body>) {}
+}
+
+/// Counts and times every response.
+pub(crate) struct Metrics;
+
+impl Middleware for Metrics {
+    fn after(&self, exchange: &Exchange, res: &mut Response<Body>) {
+        METRICS.response(
+            exchange.request_id,
+            exchange.client,
+            exchange.method,
+            exchange.path,
+            res.status(),
+            exchange.started.elapsed(),
+        );
+    }
+}
+
+/// Echoes the request id back in [`logging::REQUEST_ID_HEADER`].
+pub(crate) struct RequestIdHeader;
+
+impl Middleware for RequestIdHeader {
+    fn after(&self, exchange: &Exchange, res: &mut Response<Body>) {
+        if let Ok(id) = HeaderValue::from_str(exchange.request_id) {
+            res.headers_mut().insert(logging::REQUEST_ID_HEADER, id);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Subscribe to an MQTT broker and run `"mqtt"` hooks for the messages it delivers, so devices
+//! that only speak MQTT can trigger commands.
+//!
+//! One connection subscribes to every hook's topic at QoS 1, and each message is acknowledged
+//! once its hooks have been handed to their executors.  Retained messages are skipped: they
+//! were published before rook connected, and running them again on every reconnect would
+//! repeat old work.  The connection pings the broker every `keep_alive` and reconnects with
+//! backoff when it goes away.
+use crate::{
+    config::{MqttConfig, RouteConfig},
+    logging, router,
+};
+use hyper::Uri;
+use std::{
+    convert::Infallible,
+    fs,
+    net::IpAddr,
+    ops::Range,
+    str,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{interval_at, sleep, timeout},
+};
+
+const DEFAULT_PORT: u16 = 1883;
+/// for connecting, the handshake, and each write
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// First wait between connection attempts; doubles after each failure, up to `MAX_BACKOFF`.
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// a 2 MiB payload, as over http, plus the longest topic
+const MAX_PACKET: usize = (1 << 21) + (1 << 16);
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+
+/// Where to connect, and the `CONNECT` packet that authenticates.
+pub struct Broker {
+    url: String,
+    addr: String,
+    keep_alive: Duration,
+    connect: Vec<u8>,
+}
+
+impl Broker {
+    pub fn new(cfg: &MqttConfig) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .url
+            .parse()
+            .map_err(|e| format!("bad mqtt url '{}': {}", cfg.url, e))?;
+        let host = match (uri.scheme_str(), uri.host(), uri.path()) {
+            (Some("mqtt"), Some(host), "" | "/") => host,
+            _ => return Err(format!("mqtt url '{}' must be mqtt://host:port", cfg.url)),
+        };
+        if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+            return Err(format!(
+                "mqtt url '{}' must not carry credentials; use user and password_file",
+                cfg.url
+            ));
+        }
+        if cfg.client_id.is_empty() || cfg.client_id.len() > u16::MAX as usize {
+            return Err(format!("bad mqtt client_id '{}'", cfg.client_id));
+        }
+        if cfg.keep_alive == 0 {
+            return Err("[mqtt] keep_alive must be at least 1".to_string());
+        }
+        let mut flags = 0x02; // clean session
+        let mut payload = string(&cfg.client_id);
+        match (&cfg.user, &cfg.password_file) {
+            (Some(user), Some(path)) => {
+                let password = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read mqtt password_file '{}': {}", path, e))?;
+                flags |= 0x80 | 0x40;
+                payload.extend(string(user));
+                payload.extend(string(password.trim()));
+            }
+            (None, None) => {}
+            _ => return Err("[mqtt] user and password_file go together".to_string()),
+        }
+        let mut body = string("MQTT");
+        body.push(4); // 3.1.1
+        body.push(flags);
+        body.extend(cfg.keep_alive.to_be_bytes());
+        body.extend(payload);
+        Ok(Broker {
+            url: cfg.url.clone(),
+            addr: format!("{}:{}", host, uri.port_u16().unwrap_or(DEFAULT_PORT)),
+            keep_alive: Duration::from_secs(cfg.keep_alive.into()),
+            connect: packet(CONNECT << 4, &body),
+        })
+    }
+}
+
+/// Stay subscribed to every `"mqtt"` hook's topic for as long as `cfg` is served.
+pub async fn subscribe(cfg: Arc<RouteConfig>) {
+    let Some(mqtt) = &cfg.mqtt else { return };
+    let broker = match Broker::new(mqtt) {
+        Ok(broker) => broker,
+        Err(e) => {
+            log::error!("mqtt: {}", e);
+            return;
+        }
+    };
+    let mut filters: Vec<&str> = cfg.mqtt_hooks.iter().map(|h| h.topic.as_str()).collect();
+    filters.sort_unstable();
+    filters.dedup();
+    let mut wait = BACKOFF;
+    loop {
+        let Err(e) = session(&cfg, &broker, &filters, &mut wait).await;
+        log::warn!(
+            "mqtt connection failed: url={} error={} retry_in={:?}",
+            broker.url,
+            e,
+            wait
+        );
+        sleep(wait).await;
+        wait = (wait * 2).min(MAX_BACKOFF);
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    broker: IpAddr,
+    /// packets read but not yet handled; a read interrupted by a ping leaves what it got here
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    /// The next whole packet: its type and flags byte, and its body.
+    async fn next(&mut self) -> Result<(u8, Vec<u8>), String> {
+        loop {
+            if let Some((header, range)) = frame(&self.buf)? {
+                let body = self.buf[range.clone()].to_vec();
+                self.buf.drain(..range.end);
+                return Ok((header, body));
+            }
+            match self.stream.read_buf(&mut self.buf).await {
+                Ok(0) => return Err("broker closed the connection".to_string()),
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        match timeout(TIMEOUT, self.stream.write_all(bytes)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("write timed out".to_string()),
+        }
+    }
+}
+
+/// Connect, subscribe, and handle messages until the connection fails.  Resets `wait` once
+/// subscribed.
+async fn session(
+    cfg: &RouteConfig,
+    broker: &Broker,
+    filters: &[&str],
+    wait: &mut Duration,
+) -> Result<Infallible, String> {
+    let mut conn = match timeout(TIMEOUT, handshake(broker, filters)).await {
+        Ok(conn) => conn?,
+        Err(_) => return Err("connect timed out".to_string()),
+    };
+    log::info!(
+        "mqtt subscribed to {} topics at {}",
+        filters.len(),
+        broker.url
+    );
+    *wait = BACKOFF;
+    let mut ping = interval_at(
+        (Instant::now() + broker.keep_alive).into(),
+        broker.keep_alive,
+    );
+    let mut heard = Instant::now();
+    loop {
+        tokio::select! {
+            packet = conn.next() => {
+                let (header, body) = packet?;
+                heard = Instant::now();
+                if header >> 4 == PUBLISH {
+                    deliver(cfg, &mut conn, header, &body).await?;
+                }
+                // PINGRESP, and acks rook doesn't wait on, need nothing
+            }
+            _ = ping.tick() => {
+                if heard.elapsed() > broker.keep_alive * 2 {
+                    return Err("broker stopped answering pings".to_string());
+                }
+                conn.write(&[PINGREQ << 4, 0]).await?;
+            }
+        }
+    }
+}
+
+/// Connect, wait for the broker to accept `CONNECT`, and subscribe to `filters`.
+async fn handshake(broker: &Broker, filters: &[&str]) -> Result<Connection, String> {
+    let stream = TcpStream::connect(&broker.addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut conn = Connection {
+        broker: stream.peer_addr().map_err(|e| e.to_string())?.ip(),
+        stream,
+        buf: vec![],
+    };
+    conn.write(&broker.connect).await?;
+    let (header, body) = conn.next().await?;
+    if header >> 4 != CONNACK || body.len() != 2 {
+        return Err("broker didn't send CONNACK".to_string());
+    }
+    match body[1] {
+        0 => {}
+        1 => return Err("broker doesn't support mqtt 3.1.1".to_string()),
+        2 => return Err("broker rejected the client_id".to_string()),
+        3 => return Err("broker unavailable".to_string()),
+        4 => return Err("bad user or password".to_string()),
+        5 => return Err("not authorized".to_string()),
+        code => return Err(format!("broker refused the connection: {}", code)),
+    }
+    let mut body = 1u16.to_be_bytes().to_vec();
+    for filter in filters {
+        body.extend(string(filter));
+        body.push(1); // QoS
+    }
+    conn.write(&packet(SUBSCRIBE << 4 | 0x02, &body)).await?;
+    let (header, body) = conn.next().await?;
+    if header >> 4 != SUBACK || body.len() != 2 + filters.len() {
+        return Err("broker didn't send SUBACK".to_string());
+    }
+    for (filter, code) in filters.iter().zip(&body[2..]) {
+        if *code == 0x80 {
+            return Err(format!("broker refused the subscription to '{}'", filter));
+        }
+    }
+    debug!("connected to mqtt at {}", broker.url);
+    Ok(conn)
+}
+
+/// Run the hooks for a `PUBLISH`, then acknowledge it if the broker asked.
+async fn deliver(
+    cfg: &RouteConfig,
+    conn: &mut Connection,
+    header: u8,
+    body: &[u8],
+) -> Result<(), String> {
+    const MALFORMED: &str = "malformed PUBLISH";
+    let qos = (header >> 1) & 0x03;
+    let len = body
+        .get(..2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        .ok_or(MALFORMED)?;
+    let topic = body
+        .get(2..2 + len)
+        .and_then(|t| str::from_utf8(t).ok())
+        .ok_or(MALFORMED)?;
+    let (id, message) = match qos {
+        0 => (None, &body[2 + len..]),
+        1 => {
+            let id = body.get(2 + len..4 + len).ok_or(MALFORMED)?;
+            (Some([id[0], id[1]]), &body[4 + len..])
+        }
+        _ => return Err(format!("broker sent QoS {} on a QoS 1 subscription", qos)),
+    };
+    if header & 0x01 != 0 {
+        debug!("skipping retained mqtt message on {}", topic);
+    } else {
+        let broker = conn.broker;
+        logging::with_request_id(logging::new_request_id(), async {
+            router::mqtt_message(cfg, broker, topic, message)
+        })
+        .await;
+    }
+    match id {
+        Some([hi, lo]) => conn.write(&[PUBACK << 4, 2, hi, lo]).await,
+        None => Ok(()),
+    }
+}
+
+/// The first whole packet in `buf`: its type and flags byte, and where its body is.
+fn frame(buf: &[u8]) -> Result<Option<(u8, Range<usize>)>, String> {
+    let mut len = 0;
+    for i in 0..4 {
+        let Some(byte) = buf.get(1 + i) else {
+            return Ok(None);
+        };
+        len |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            if len > MAX_PACKET {
+                return Err(format!("{} byte packet is over the limit", len));
+            }
+            let start = 2 + i;
+            return Ok((buf.len() >= start + len).then_some((buf[0], start..start + len)));
+        }
+    }
+    Err("bad packet length".to_string())
+}
+
+/// A packet with `header` for its type and flags.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+/// A length-prefixed utf-8 string, as MQTT encodes them.
+fn string(s: &str) -> Vec<u8> {
+    let mut out = (s.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(s.as_bytes());
+    out
+}
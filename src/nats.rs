@@ -0,0 +1,384 @@
+//! Publish verified deliveries to a NATS subject, so a fleet of workers can consume them
+//! instead of (or as well as) a command on this machine.
+//!
+//! The body is published as received, with the hook type, hook url, and request id in
+//! `Rook-Provider`, `Rook-Route`, and `Rook-Request-Id` headers.  Publishes are queued and
+//! written by one background connection per `[nats]` server, which reconnects when the server
+//! goes away.  Like any core NATS publish, delivery is at most once: a message written just as
+//! the connection drops is lost, and a delivery that arrives while the queue is full fails its
+//! hook.
+use crate::{
+    config::NatsConfig,
+    exec::{Executor, Invocation},
+};
+use hyper::Uri;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc,
+    time::timeout,
+};
+
+const DEFAULT_PORT: u16 = 4222;
+/// messages waiting to be written; deliveries fail while the server is this far behind
+const QUEUE: usize = 256;
+/// for connecting, the handshake, and each write
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Publishers by server and credentials, so hooks on the same server share one connection.
+static PUBLISHERS: Mutex<Vec<(String, Weak<Publisher>)>> = Mutex::new(Vec::new());
+
+/// Publish each delivery to `subject`.
+pub struct Nats {
+    subject: String,
+    publisher: Arc<Publisher>,
+}
+
+impl Nats {
+    pub fn new(cfg: &NatsConfig, subject: &str) -> Result<Self, String> {
+        if subject.is_empty()
+            || subject
+                .split('.')
+                .any(|t| t.is_empty() || t == "*" || t == ">")
+            || !subject.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(format!("bad nats_subject '{}'", subject));
+        }
+        Ok(Nats {
+            subject: subject.to_string(),
+            publisher: publisher(Server::new(cfg)?),
+        })
+    }
+}
+
+impl Executor for Nats {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let mut headers = String::from("NATS/1.0\r\n");
+        let fields = [
+            ("Rook-Provider", Some(invocation.payload.provider())),
+            ("Rook-Route", Some(invocation.route)),
+            ("Rook-Request-Id", invocation.request_id),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                // header values can't break the frame
+                let value: String = value.chars().filter(|c| !c.is_control()).collect();
+                headers.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        headers.push_str("\r\n");
+        let size = headers.len() + invocation.body.len();
+        let mut frame =
+            format!("HPUB {} {} {}\r\n", self.subject, headers.len(), size).into_bytes();
+        frame.extend_from_slice(headers.as_bytes());
+        frame.extend_from_slice(invocation.body);
+        frame.extend_from_slice(b"\r\n");
+        self.publisher.send(Message {
+            subject: self.subject.clone(),
+            size,
+            frame,
+        })
+    }
+}
+
+/// Where to connect, and the `CONNECT` line that authenticates.
+struct Server {
+    url: String,
+    addr: String,
+    connect: String,
+}
+
+impl Server {
+    fn new(cfg: &NatsConfig) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .url
+            .parse()
+            .map_err(|e| format!("bad nats url '{}': {}", cfg.url, e))?;
+        let host = match (uri.scheme_str(), uri.host()) {
+            (Some("nats"), Some(host)) => host,
+            _ => return Err(format!("nats url '{}' must be nats://host:port", cfg.url)),
+        };
+        if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+            return Err(format!(
+                "nats url '{}' must not carry credentials; use user and password_file or \
+                 token_file",
+                cfg.url
+            ));
+        }
+        let read = |name: &str, path: &str| {
+            fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .map_err(|e| format!("failed to read nats {} '{}': {}", name, path, e))
+        };
+        let mut connect = json!({
+            "verbose": false,
+            "pedantic": false,
+            "headers": true,
+            "protocol": 1,
+            "name": "rook",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        match (&cfg.user, &cfg.password_file, &cfg.token_file) {
+            (Some(user), Some(password), None) => {
+                connect["user"] = json!(user);
+                connect["pass"] = json!(read("password_file", password)?);
+            }
+            (None, None, Some(token)) => {
+                connect["auth_token"] = json!(read("token_file", token)?);
+            }
+            (None, None, None) => {}
+            (_, _, Some(_)) => {
+                return Err(
+                    "[nats] must use one of token_file or user and password_file".to_string(),
+                )
+            }
+            _ => return Err("[nats] user and password_file go together".to_string()),
+        }
+        Ok(Server {
+            url: cfg.url.clone(),
+            addr: format!("{}:{}", host, uri.port_u16().unwrap_or(DEFAULT_PORT)),
+            connect: format!("CONNECT {}\r\nPING\r\n", connect),
+        })
+    }
+
+    fn key(&self) -> String {
+        format!("{} {}", self.addr, self.connect)
+    }
+}
+
+/// A queue of messages for one server, written by a background task started on first use.
+struct Publisher {
+    server: Arc<Server>,
+    tx: OnceLock<mpsc::Sender<Message>>,
+}
+
+struct Message {
+    subject: String,
+    /// headers and body, as the server counts them against `max_payload`
+    size: usize,
+    frame: Vec<u8>,
+}
+
+/// The publisher for `server`, shared with any other hook that publishes there.
+fn publisher(server: Server) -> Arc<Publisher> {
+    let Ok(mut publishers) = PUBLISHERS.lock() else {
+        return Arc::new(Publisher::new(server));
+    };
+    publishers.retain(|(_, p)| p.strong_count() > 0);
+    let key = server.key();
+    if let Some(publisher) = publishers
+        .iter()
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, p)| p.upgrade())
+    {
+        return publisher;
+    }
+    let publisher = Arc::new(Publisher::new(server));
+    publishers.push((key, Arc::downgrade(&publisher)));
+    publisher
+}
+
+impl Publisher {
+    fn new(server: Server) -> Self {
+        Publisher {
+            server: Arc::new(server),
+            tx: OnceLock::new(),
+        }
+    }
+
+    fn send(&self, message: Message) -> Result<(), String> {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            // ends once the config holding this publisher is dropped
+            tokio::spawn(run(self.server.clone(), rx));
+            tx
+        });
+        tx.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "nats queue full".to_string(),
+            mpsc::error::TrySendError::Closed(_) => "nats publisher stopped".to_string(),
+        })
+    }
+}
+
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    /// the server's `max_payload`
+    max_payload: usize,
+    /// a line being read; a read interrupted by a publish leaves what it got here
+    line: String,
+}
+
+/// Write queued messages, answering the server's pings in between so it keeps the connection.
+async fn run(server: Arc<Server>, mut rx: mpsc::Receiver<Message>) {
+    let mut conn: Option<Connection> = None;
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some(message) = message else { return };
+                publish(&server, &mut conn, &message).await;
+            }
+            line = read_line(&mut conn) => {
+                let alive = match (line, conn.as_mut()) {
+                    (Some(line), Some(c)) => match answer(&server, &line) {
+                        Some(reply) => write(c, reply).await.is_ok(),
+                        None => true,
+                    },
+                    _ => false,
+                };
+                if !alive {
+                    debug!("nats connection to {} closed", server.url);
+                    conn = None;
+                }
+            }
+        }
+    }
+}
+
+/// The next whole line from the server, or None once the connection is gone; never resolves
+/// while disconnected.
+async fn read_line(conn: &mut Option<Connection>) -> Option<String> {
+    let Some(c) = conn else {
+        return std::future::pending().await;
+    };
+    match c.reader.read_line(&mut c.line).await {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let line = c.line.trim_end().to_string();
+            c.line.clear();
+            Some(line)
+        }
+    }
+}
+
+/// What to send back for a line the server sent.
+fn answer(server: &Server, line: &str) -> Option<&'static [u8]> {
+    match line {
+        "PING" => Some(b"PONG\r\n"),
+        _ => {
+            if let Some(e) = line.strip_prefix("-ERR ") {
+                log::warn!("nats error: url={} error={}", server.url, e);
+            }
+            // PONG, +OK, and INFO updates need nothing
+            None
+        }
+    }
+}
+
+/// Write `message`, connecting first if needed.  A write to a connection that turns out to be
+/// dead is tried once more on a new one.
+async fn publish(server: &Server, conn: &mut Option<Connection>, message: &Message) {
+    let mut error = String::new();
+    for _ in 0..2 {
+        let c = match conn {
+            Some(c) => c,
+            None => match connect(server).await {
+                Ok(c) => conn.insert(c),
+                Err(e) => {
+                    error = e;
+                    break;
+                }
+            },
+        };
+        if message.size > c.max_payload {
+            error = format!("{} bytes is over the server's max_payload", message.size);
+            break;
+        }
+        match write(c, &message.frame).await {
+            Ok(()) => {
+                debug!("published {} bytes to {}", message.size, message.subject);
+                return;
+            }
+            Err(e) => {
+                error = e;
+                *conn = None;
+            }
+        }
+    }
+    log::warn!(
+        "nats publish failed: url={} subject={} error={}",
+        server.url,
+        message.subject,
+        error
+    );
+}
+
+async fn write(c: &mut Connection, bytes: &[u8]) -> Result<(), String> {
+    match timeout(TIMEOUT, c.writer.write_all(bytes)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("write timed out".to_string()),
+    }
+}
+
+/// Connect and authenticate, waiting for the `PONG` that confirms the server took `CONNECT`.
+async fn connect(server: &Server) -> Result<Connection, String> {
+    match timeout(TIMEOUT, handshake(server)).await {
+        Ok(conn) => conn,
+        Err(_) => Err("connect timed out".to_string()),
+    }
+}
+
+async fn handshake(server: &Server) -> Result<Connection, String> {
+    let stream = TcpStream::connect(&server.addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .map_err(|e| e.to_string())?;
+    let info: Value = line
+        .trim_end()
+        .strip_prefix("INFO ")
+        .and_then(|info| serde_json::from_str(info).ok())
+        .ok_or("server didn't send INFO")?;
+    if info["tls_required"] == true {
+        return Err("server requires tls, which rook's nats client doesn't support".to_string());
+    }
+    if info["headers"] != true {
+        return Err("server doesn't support headers (nats-server 2.2 or newer does)".to_string());
+    }
+    let mut conn = Connection {
+        reader,
+        writer,
+        max_payload: info["max_payload"].as_u64().unwrap_or(1 << 20) as usize,
+        line: String::new(),
+    };
+    write(&mut conn, server.connect.as_bytes()).await?;
+    loop {
+        line.clear();
+        if conn
+            .reader
+            .read_line(&mut line)
+            .await
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Err("server closed the connection".to_string());
+        }
+        match line.trim_end() {
+            "PONG" => break,
+            "PING" => write(&mut conn, b"PONG\r\n").await?,
+            line => {
+                if let Some(e) = line.strip_prefix("-ERR ") {
+                    return Err(e.to_string());
+                }
+            }
+        }
+    }
+    debug!("connected to nats at {}", server.url);
+    Ok(conn)
+}
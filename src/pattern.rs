@@ -0,0 +1,109 @@
+//! Hook urls with `:name` segments, which match any one segment, and a final `*` or `*name`,
+//! which matches the rest of the path.  `/hooks/:project/deploy` answers `/hooks/web/deploy`
+//! with `project = "web"`.
+//!
+//! When more than one url matches a path, an exact url wins, then the one whose segments are
+//! more specific from the left: a literal segment beats `:name`, which beats `*`.
+
+/// What an unnamed `*` is captured as.
+const WILDCARD: &str = "wildcard";
+
+/// Whether `url` has any `:name` or `*` segments.
+pub fn is_pattern(url: &str) -> bool {
+    url.split('/').any(|s| s.starts_with([':', '*']))
+}
+
+/// Check the segment names in `url` can be env var suffixes and don't repeat, and that `*`
+/// only ends it.
+pub fn validate(url: &str) -> Result<(), String> {
+    let segments: Vec<&str> = url.split('/').skip(1).collect();
+    let mut names = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        let name = match segment.as_bytes().first() {
+            Some(b':') => &segment[1..],
+            Some(b'*') if i + 1 < segments.len() => {
+                return Err(format!("hook url '{}' may only end with '*'", url))
+            }
+            Some(b'*') if segment.len() == 1 => WILDCARD,
+            Some(b'*') => &segment[1..],
+            _ => continue,
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!(
+                "hook url '{}' has a bad segment name '{}'",
+                url, segment
+            ));
+        }
+        let name = name.to_ascii_uppercase();
+        if names.contains(&name) {
+            return Err(format!(
+                "hook url '{}' repeats segment name '{}'",
+                url, segment
+            ));
+        }
+        names.push(name);
+    }
+    Ok(())
+}
+
+/// The segments of `path` that `pattern` captures, by name, or None if it doesn't match.
+pub fn captures(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+    let mut captured = vec![];
+    // what's left of the path after each segment; None once it runs out
+    let mut rest = Some(path.strip_prefix('/')?);
+    for segment in pattern.strip_prefix('/')?.split('/') {
+        let remaining = rest?;
+        if let Some(name) = segment.strip_prefix('*') {
+            if remaining.is_empty() {
+                return None;
+            }
+            let name = if name.is_empty() { WILDCARD } else { name };
+            captured.push((name.to_string(), remaining.to_string()));
+            return Some(captured);
+        }
+        let value = match remaining.split_once('/') {
+            Some((value, tail)) => {
+                rest = Some(tail);
+                value
+            }
+            None => rest.take()?,
+        };
+        match segment.strip_prefix(':') {
+            Some(_) if value.is_empty() => return None,
+            Some(name) => captured.push((name.to_string(), value.to_string())),
+            None if segment != value => return None,
+            None => {}
+        }
+    }
+    rest.is_none().then_some(captured)
+}
+
+/// Sorts more specific patterns first.
+pub fn specificity(pattern: &str) -> Vec<u8> {
+    pattern
+        .split('/')
+        .map(|s| match s.as_bytes().first() {
+            Some(b':') => 1,
+            Some(b'*') => 2,
+            _ => 0,
+        })
+        .collect()
+}
+
+/// `pattern` without its segment names; two patterns with the same shape match the same paths.
+pub fn shape(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|s| match s.as_bytes().first() {
+            Some(b':') => ":",
+            Some(b'*') => "*",
+            _ => s,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The env var a captured segment is passed to hook commands in.
+pub fn env_name(name: &str) -> String {
+    format!("ROOK_PATH_{}", name.to_ascii_uppercase())
+}
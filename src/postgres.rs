@@ -0,0 +1,649 @@
+//! Insert verified deliveries and how hook commands ended into PostgreSQL tables, so
+//! deployment history can be queried with SQL and joined against other data on `request_id`.
+//!
+//! Rook speaks the Postgres wire protocol itself over a blocking connection: password or
+//! SCRAM-SHA-256 authentication, optionally over TLS, and one parameterized `INSERT` per row.
+//! Deliveries are inserted off the request's thread over a connection the server keeps open;
+//! job outcomes are inserted by the forked process that waited on the command, over a
+//! connection of its own.  A failed insert is logged and dropped, after one more try on a new
+//! connection when the kept one may have gone stale.
+use crate::{
+    config::PostgresConfig,
+    encoding,
+    exec::{Ended, Notifier},
+    history::Record,
+    id,
+};
+use hmac::{Hmac, Mac};
+use hyper::Uri;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::process::ExitStatusExt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const DEFAULT_PORT: u16 = 5432;
+/// for connecting, and each read and write
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// more than any reply to an insert needs
+const MAX_MESSAGE: usize = 1024 * 1024;
+const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+
+#[cfg(feature = "tls")]
+type TlsConfig = Arc<tokio_rustls::rustls::ClientConfig>;
+#[cfg(not(feature = "tls"))]
+type TlsConfig = std::convert::Infallible;
+
+/// Where to connect and how to log in.
+pub struct Server {
+    host: String,
+    port: u16,
+    user: String,
+    password: Option<String>,
+    database: String,
+    tls: Option<TlsConfig>,
+    deliveries_table: String,
+    jobs_table: String,
+}
+
+impl Server {
+    pub fn new(cfg: &PostgresConfig) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .url
+            .parse()
+            .map_err(|e| format!("bad postgres url '{}': {}", cfg.url, e))?;
+        let host = match (uri.scheme_str(), uri.host()) {
+            (Some("postgres" | "postgresql"), Some(host)) => host,
+            _ => {
+                return Err(format!(
+                    "postgres url '{}' must be postgres://user@host:port/database",
+                    cfg.url
+                ))
+            }
+        };
+        let user = match uri.authority().and_then(|a| a.as_str().rsplit_once('@')) {
+            Some((user, _)) if user.contains(':') => {
+                return Err(format!(
+                    "postgres url '{}' must not carry a password; use password_file",
+                    cfg.url
+                ))
+            }
+            Some((user, _)) if !user.is_empty() => user.to_string(),
+            _ => return Err(format!("postgres url '{}' needs a user", cfg.url)),
+        };
+        let database = match uri.path().trim_start_matches('/') {
+            "" => user.clone(),
+            database => database.to_string(),
+        };
+        let password = match &cfg.password_file {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .map_err(|e| {
+                        format!("failed to read postgres password_file '{}': {}", path, e)
+                    })?
+                    .trim()
+                    .to_string(),
+            ),
+            None => None,
+        };
+        for table in [&cfg.deliveries_table, &cfg.jobs_table] {
+            if !is_table_name(table) {
+                return Err(format!(
+                    "postgres table '{}' must be a name like rook_jobs or schema.rook_jobs",
+                    table
+                ));
+            }
+        }
+        Ok(Server {
+            host: host.to_string(),
+            port: uri.port_u16().unwrap_or(DEFAULT_PORT),
+            user,
+            password,
+            database,
+            tls: match cfg.tls {
+                true => Some(tls_config(&cfg.ca_file)?),
+                false => None,
+            },
+            deliveries_table: cfg.deliveries_table.clone(),
+            jobs_table: cfg.jobs_table.clone(),
+        })
+    }
+
+    fn connect(&self) -> Result<Conn, String> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("no address")?;
+        let mut tcp = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+        tcp.set_read_timeout(Some(TIMEOUT))
+            .and_then(|()| tcp.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        let stream: Box<dyn Stream> = match &self.tls {
+            None => Box::new(tcp),
+            Some(config) => {
+                let mut request = 8i32.to_be_bytes().to_vec();
+                request.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+                tcp.write_all(&request).map_err(|e| e.to_string())?;
+                let mut answer = [0];
+                tcp.read_exact(&mut answer).map_err(|e| e.to_string())?;
+                if answer != *b"S" {
+                    return Err("server doesn't accept tls".to_string());
+                }
+                start_tls(config, &self.host, tcp)?
+            }
+        };
+        let mut conn = Conn { stream };
+        conn.startup(self)?;
+        Ok(conn)
+    }
+}
+
+/// Deliveries go here.  Opened once, when the server starts.
+pub struct Postgres {
+    server: Arc<Server>,
+    conn: Arc<Mutex<Option<Conn>>>,
+    store_body: bool,
+}
+
+impl Postgres {
+    /// Connect and create the tables if they're missing.  Fails if the server can't be reached.
+    pub fn open(cfg: &PostgresConfig) -> Result<Self, String> {
+        let server = Server::new(cfg)?;
+        let mut conn = server
+            .connect()
+            .map_err(|e| format!("failed to connect to postgres: {}", e))?;
+        let (deliveries, jobs) = (&server.deliveries_table, &server.jobs_table);
+        conn.simple_query(&format!(
+            "CREATE TABLE IF NOT EXISTS {deliveries} (
+                id bigserial PRIMARY KEY,
+                received timestamptz NOT NULL DEFAULT now(),
+                request_id text NOT NULL,
+                client inet NOT NULL,
+                method text NOT NULL,
+                path text NOT NULL,
+                route text,
+                status smallint NOT NULL,
+                headers jsonb NOT NULL,
+                body_sha256 text,
+                body bytea,
+                verified integer NOT NULL,
+                started integer NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {d}_request_id ON {deliveries} (request_id);
+            CREATE TABLE IF NOT EXISTS {jobs} (
+                id bigserial PRIMARY KEY,
+                finished timestamptz NOT NULL DEFAULT now(),
+                request_id text,
+                route text NOT NULL,
+                command text NOT NULL,
+                succeeded boolean NOT NULL,
+                exit_code integer,
+                signal integer,
+                error text,
+                duration_ms bigint NOT NULL,
+                output text NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {j}_request_id ON {jobs} (request_id);",
+            d = unqualified(deliveries),
+            j = unqualified(jobs),
+        ))
+        .map_err(|e| format!("failed to create postgres tables: {}", e))?;
+        Ok(Postgres {
+            server: Arc::new(server),
+            conn: Arc::new(Mutex::new(Some(conn))),
+            store_body: cfg.store_body,
+        })
+    }
+
+    /// Insert `record` off the async runtime.  Failures are logged and otherwise ignored.
+    pub async fn record(&self, record: Record) {
+        let (server, conn) = (self.server.clone(), self.conn.clone());
+        let body = record.body.as_deref();
+        let params = vec![
+            Some(record.request_id.clone()),
+            Some(record.client.to_string()),
+            Some(record.method.clone()),
+            Some(record.path.clone()),
+            record.route.clone(),
+            Some(record.status.to_string()),
+            Some(record.headers.to_string()),
+            body.map(|b| encoding::hex(&Sha256::digest(b))),
+            body.filter(|_| self.store_body)
+                .map(|b| format!("\\x{}", encoding::hex(b))),
+            Some(record.verified.to_string()),
+            Some(record.started.to_string()),
+        ];
+        let stored = tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| "postgres lock poisoned".to_string())?;
+            let sql = format!(
+                "INSERT INTO {} (request_id, client, method, path, route, status, headers,
+                    body_sha256, body, verified, started)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                server.deliveries_table
+            );
+            // the server may have closed a connection that sat idle; try once more on a new one
+            let reused = conn.is_some();
+            let inserted = insert(&server, &mut conn, &sql, &params);
+            match inserted {
+                Err(_) if reused => insert(&server, &mut conn, &sql, &params),
+                inserted => inserted,
+            }
+        })
+        .await;
+        match stored {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("failed to record delivery in postgres: {}", e),
+            Err(e) => log::warn!("failed to record delivery in postgres: {}", e),
+        }
+    }
+}
+
+/// Run `sql` on the open connection, opening one first if needed.  A connection that fails is
+/// dropped.
+fn insert(
+    server: &Server,
+    conn: &mut Option<Conn>,
+    sql: &str,
+    params: &[Option<String>],
+) -> Result<(), String> {
+    let open = match conn {
+        Some(open) => open,
+        None => conn.insert(server.connect()?),
+    };
+    let inserted = open.execute(sql, params);
+    if inserted.is_err() {
+        *conn = None;
+    }
+    inserted
+}
+
+/// Inserts how a hook's command ended into the jobs table.
+#[derive(Clone)]
+pub struct Jobs(Arc<Server>);
+
+impl Jobs {
+    pub fn new(cfg: &PostgresConfig) -> Result<Self, String> {
+        Ok(Jobs(Arc::new(Server::new(cfg)?)))
+    }
+}
+
+impl Notifier for Jobs {
+    fn notify(&self, ended: &Ended) -> Result<(), String> {
+        let (status, error) = match &ended.status {
+            Ok(status) => (Some(status), None),
+            Err(e) => (None, Some(text(e))),
+        };
+        let params = [
+            ended.request_id.map(text),
+            Some(text(ended.route)),
+            Some(text(ended.command)),
+            Some((!ended.failed()).to_string()),
+            status.and_then(|s| s.code()).map(|c| c.to_string()),
+            status.and_then(|s| s.signal()).map(|s| s.to_string()),
+            error,
+            Some(ended.duration.as_millis().to_string()),
+            Some(text(&ended.output_text())),
+        ];
+        let sql = format!(
+            "INSERT INTO {} (request_id, route, command, succeeded, exit_code, signal, error,
+                duration_ms, output)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            self.0.jobs_table
+        );
+        self.0
+            .connect()
+            .and_then(|mut conn| {
+                conn.execute(&sql, &params)?;
+                conn.terminate();
+                Ok(())
+            })
+            .map_err(|e| format!("postgres table {}: {}", self.0.jobs_table, e))
+    }
+}
+
+/// Postgres text can't hold NUL.
+fn text(s: &str) -> String {
+    s.replace('\0', "")
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+#[cfg(feature = "tls")]
+fn tls_config(ca_file: &str) -> Result<TlsConfig, String> {
+    crate::tls::client_config(ca_file)
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_config(_: &str) -> Result<TlsConfig, String> {
+    Err("postgres tls requires rook built with the `tls` feature".to_string())
+}
+
+#[cfg(feature = "tls")]
+fn start_tls(config: &TlsConfig, host: &str, tcp: TcpStream) -> Result<Box<dyn Stream>, String> {
+    Ok(Box::new(crate::tls::connect_blocking(config, host, tcp)?))
+}
+
+#[cfg(not(feature = "tls"))]
+fn start_tls(never: &TlsConfig, _: &str, _: TcpStream) -> Result<Box<dyn Stream>, String> {
+    match *never {}
+}
+
+/// An authenticated connection, ready for queries.
+struct Conn {
+    stream: Box<dyn Stream>,
+}
+
+impl Conn {
+    fn startup(&mut self, server: &Server) -> Result<(), String> {
+        let mut body = PROTOCOL_VERSION.to_be_bytes().to_vec();
+        for (name, value) in [
+            ("user", server.user.as_str()),
+            ("database", server.database.as_str()),
+            ("application_name", "rook"),
+        ] {
+            cstring(&mut body, name);
+            cstring(&mut body, value);
+        }
+        body.push(0);
+        let mut startup = ((body.len() + 4) as i32).to_be_bytes().to_vec();
+        startup.extend_from_slice(&body);
+        self.stream.write_all(&startup).map_err(|e| e.to_string())?;
+        let mut scram = None;
+        loop {
+            let (tag, body) = self.read()?;
+            match tag {
+                b'R' => self.authenticate(server, &body, &mut scram)?,
+                b'E' => return Err(error_message(&body)),
+                b'Z' => return Ok(()),
+                // parameter status, backend key, notices
+                _ => {}
+            }
+        }
+    }
+
+    /// Answer one authentication request.
+    fn authenticate(
+        &mut self,
+        server: &Server,
+        body: &[u8],
+        scram: &mut Option<Scram>,
+    ) -> Result<(), String> {
+        let kind = body
+            .get(..4)
+            .map_or(-1, |b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let password = || {
+            server
+                .password
+                .as_deref()
+                .ok_or_else(|| "the server asked for a password; set password_file".to_string())
+        };
+        match kind {
+            // ok
+            0 => Ok(()),
+            // cleartext
+            3 => {
+                let mut reply = vec![];
+                cstring(&mut reply, password()?);
+                self.send(b'p', &reply)
+            }
+            // SASL
+            10 => {
+                let mut mechanisms = body[4..].split(|b| *b == 0);
+                if !mechanisms.any(|m| m == b"SCRAM-SHA-256") {
+                    return Err("the server offers no supported SASL mechanism".to_string());
+                }
+                let started = Scram::new(password()?);
+                let first = started.client_first();
+                let mut reply = vec![];
+                cstring(&mut reply, "SCRAM-SHA-256");
+                reply.extend_from_slice(&(first.len() as i32).to_be_bytes());
+                reply.extend_from_slice(first.as_bytes());
+                *scram = Some(started);
+                self.send(b'p', &reply)
+            }
+            // SASL continue
+            11 => {
+                let scram = scram.as_mut().ok_or("unexpected SASL message")?;
+                let server_first = std::str::from_utf8(&body[4..]).map_err(|e| e.to_string())?;
+                let reply = scram.client_final(server_first)?;
+                self.send(b'p', reply.as_bytes())
+            }
+            // SASL final
+            12 => {
+                let scram = scram.as_ref().ok_or("unexpected SASL message")?;
+                let server_final = std::str::from_utf8(&body[4..]).map_err(|e| e.to_string())?;
+                scram.verify(server_final)
+            }
+            5 => Err("md5 password auth isn't supported; use scram-sha-256".to_string()),
+            other => Err(format!("unsupported postgres auth method {}", other)),
+        }
+    }
+
+    /// Run statements without parameters, as table setup does.
+    fn simple_query(&mut self, sql: &str) -> Result<(), String> {
+        let mut body = vec![];
+        cstring(&mut body, sql);
+        self.send(b'Q', &body)?;
+        self.finish()
+    }
+
+    /// Run one statement with text parameters; None is NULL.
+    fn execute(&mut self, sql: &str, params: &[Option<String>]) -> Result<(), String> {
+        let mut parse = vec![];
+        cstring(&mut parse, "");
+        cstring(&mut parse, sql);
+        parse.extend_from_slice(&0i16.to_be_bytes());
+        let mut bind = vec![0, 0];
+        bind.extend_from_slice(&0i16.to_be_bytes());
+        bind.extend_from_slice(&(params.len() as i16).to_be_bytes());
+        for param in params {
+            match param {
+                Some(value) => {
+                    bind.extend_from_slice(&(value.len() as i32).to_be_bytes());
+                    bind.extend_from_slice(value.as_bytes());
+                }
+                None => bind.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        bind.extend_from_slice(&0i16.to_be_bytes());
+        let mut execute = vec![0];
+        execute.extend_from_slice(&0i32.to_be_bytes());
+        let mut batch = message(b'P', &parse);
+        batch.extend(message(b'B', &bind));
+        batch.extend(message(b'E', &execute));
+        batch.extend(message(b'S', &[]));
+        self.stream.write_all(&batch).map_err(|e| e.to_string())?;
+        self.finish()
+    }
+
+    /// Read until the server is ready for the next query, returning its first error.
+    fn finish(&mut self) -> Result<(), String> {
+        let mut error = None;
+        loop {
+            let (tag, body) = self.read()?;
+            match tag {
+                b'E' => {
+                    error.get_or_insert_with(|| error_message(&body));
+                }
+                b'Z' => return error.map_or(Ok(()), Err),
+                _ => {}
+            }
+        }
+    }
+
+    fn terminate(mut self) {
+        let _ = self.send(b'X', &[]);
+    }
+
+    fn send(&mut self, tag: u8, body: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(&message(tag, body))
+            .map_err(|e| e.to_string())
+    }
+
+    fn read(&mut self) -> Result<(u8, Vec<u8>), String> {
+        let mut head = [0; 5];
+        self.stream
+            .read_exact(&mut head)
+            .map_err(|e| e.to_string())?;
+        let len = i32::from_be_bytes([head[1], head[2], head[3], head[4]]);
+        let len = usize::try_from(len - 4)
+            .ok()
+            .filter(|len| *len <= MAX_MESSAGE)
+            .ok_or("bad postgres message length")?;
+        let mut body = vec![0; len];
+        self.stream
+            .read_exact(&mut body)
+            .map_err(|e| e.to_string())?;
+        Ok((head[0], body))
+    }
+}
+
+/// A tagged message: the tag, then its length (counting itself) and body.
+fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    out.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+    out.extend_from_slice(body);
+    out
+}
+
+fn cstring(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+/// `relation "rook_jobs" does not exist (42P01)` from an ErrorResponse's fields.
+fn error_message(body: &[u8]) -> String {
+    let (mut text, mut code) = ("", "");
+    for field in body.split(|b| *b == 0) {
+        let value = std::str::from_utf8(field.get(1..).unwrap_or_default()).unwrap_or_default();
+        match field.first() {
+            Some(b'M') => text = value,
+            Some(b'C') => code = value,
+            _ => {}
+        }
+    }
+    format!("{} ({})", text, code)
+}
+
+/// SCRAM-SHA-256, as RFC 7677 describes and Postgres uses it: no channel binding, and the
+/// user name left to the startup message.
+struct Scram {
+    password: String,
+    client_first_bare: String,
+    /// the auth message and salted password, once the server's been heard from
+    exchange: Option<(String, [u8; 32])>,
+}
+
+impl Scram {
+    fn new(password: &str) -> Self {
+        let nonce = format!(
+            "{:016x}{:016x}{:016x}",
+            id::random_u64(),
+            id::random_u64(),
+            id::random_u64()
+        );
+        Scram {
+            password: password.to_string(),
+            client_first_bare: format!("n=,r={}", nonce),
+            exchange: None,
+        }
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    fn client_final(&mut self, server_first: &str) -> Result<String, String> {
+        let attr = |name: &str| {
+            server_first
+                .split(',')
+                .find_map(|a| a.strip_prefix(name)?.strip_prefix('='))
+                .ok_or_else(|| format!("bad SCRAM server message: no {}", name))
+        };
+        let nonce = attr("r")?;
+        let salt = encoding::decode_base64(attr("s")?).ok_or("bad SCRAM salt")?;
+        let iterations: u32 = attr("i")?.parse().map_err(|_| "bad SCRAM iterations")?;
+        if !nonce.starts_with(&self.client_first_bare[5..]) || iterations == 0 {
+            return Err("bad SCRAM server message".to_string());
+        }
+        let salted = salted_password(self.password.as_bytes(), &salt, iterations);
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+        let client_key = hmac(&salted, b"Client Key");
+        let signature = hmac(&Sha256::digest(client_key), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        self.exchange = Some((auth_message, salted));
+        Ok(format!("{},p={}", without_proof, encoding::base64(&proof)))
+    }
+
+    /// Check the server knew the password too.
+    fn verify(&self, server_final: &str) -> Result<(), String> {
+        let (auth_message, salted) = self.exchange.as_ref().ok_or("unexpected SASL message")?;
+        let expected = hmac(&hmac(salted, b"Server Key"), auth_message.as_bytes());
+        match server_final
+            .strip_prefix("v=")
+            .and_then(encoding::decode_base64)
+        {
+            Some(signature) if signature == expected => Ok(()),
+            _ => Err("the server's SCRAM signature doesn't match".to_string()),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// PBKDF2-HMAC-SHA256 with a single output block.
+fn salted_password(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&1u32.to_be_bytes());
+    let mut u = hmac(password, &block);
+    let mut out = u;
+    for _ in 1..iterations {
+        u = hmac(password, &u);
+        for (o, b) in out.iter_mut().zip(u) {
+            *o ^= b;
+        }
+    }
+    out
+}
+
+/// `name` or `schema.name`, with nothing that needs quoting.
+fn is_table_name(table: &str) -> bool {
+    let part = |p: &str| {
+        p.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    match table.split_once('.') {
+        Some((schema, name)) => part(schema) && part(name),
+        None => part(table),
+    }
+}
+
+/// The table's name without its schema, for naming its index.
+fn unqualified(table: &str) -> &str {
+    table.rsplit('.').next().unwrap_or(table)
+}
@@ -0,0 +1,50 @@
+//! Per-hook limits on how often verified deliveries may start a hook, so a compromised or
+//! misconfigured sender can't run its command hundreds of times.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A token bucket holding up to `per_minute` starts, refilled evenly over each minute.
+pub struct RateLimit {
+    per_minute: u32,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// Starts with a full bucket, so a burst of up to `per_minute` goes through at once.
+    pub fn per_minute(per_minute: u32) -> Self {
+        RateLimit {
+            per_minute,
+            bucket: Mutex::new(Bucket {
+                tokens: per_minute as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.per_minute
+    }
+
+    /// Take one start, or return how long until the next one is available.
+    pub fn acquire(&self) -> Result<(), Duration> {
+        let rate = self.per_minute as f64 / 60.0;
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let refilled = bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = refilled.min(self.per_minute as f64);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
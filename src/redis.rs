@@ -0,0 +1,455 @@
+//! Push verified deliveries onto a Redis list or stream, for job runners that already consume
+//! from Redis.
+//!
+//! A list gets the body as received, with `LPUSH`, so `BRPOP` takes deliveries oldest first.
+//! A stream gets an entry with `XADD`, with `provider`, `route`, `request_id`, and `body`
+//! fields.  Commands are queued and sent by one background connection per `[redis]` server,
+//! which reconnects when the server goes away; a delivery that arrives while the queue is full
+//! fails its hook.
+//!
+//! [`RedisLock`] is the `[lock]` with `redis = true`: a key set with `SET NX`, over a blocking
+//! connection from the forked process that runs the command.
+use crate::{
+    config::{LockConfig, RedisConfig},
+    exec::{Executor, Held, Invocation, Lock},
+    id, lock,
+};
+use hyper::Uri;
+use std::{
+    fs,
+    io::{self, BufRead, Read as _, Write as _},
+    net::ToSocketAddrs,
+    sync::{Arc, Mutex, OnceLock, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time::timeout,
+};
+
+const DEFAULT_PORT: u16 = 6379;
+/// commands waiting to be sent; deliveries fail while the server is this far behind
+const QUEUE: usize = 256;
+/// for connecting, and each command and its reply
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pushers by server and credentials, so hooks on the same server share one connection.
+static PUSHERS: Mutex<Vec<(String, Weak<Pusher>)>> = Mutex::new(Vec::new());
+
+/// Where a hook's deliveries go.
+pub enum Target {
+    List(String),
+    Stream(String),
+}
+
+/// Push each delivery onto a list or stream.
+pub struct Redis {
+    target: Target,
+    pusher: Arc<Pusher>,
+}
+
+impl Redis {
+    pub fn new(cfg: &RedisConfig, target: Target) -> Result<Self, String> {
+        Ok(Redis {
+            target,
+            pusher: pusher(Server::new(cfg)?),
+        })
+    }
+}
+
+impl Executor for Redis {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        let command = match &self.target {
+            Target::List(key) => command(&[b"LPUSH", key.as_bytes(), invocation.body]),
+            Target::Stream(key) => command(&[
+                b"XADD",
+                key.as_bytes(),
+                b"*",
+                b"provider",
+                invocation.payload.provider().as_bytes(),
+                b"route",
+                invocation.route.as_bytes(),
+                b"request_id",
+                invocation.request_id.unwrap_or_default().as_bytes(),
+                b"body",
+                invocation.body,
+            ]),
+        };
+        let key = match &self.target {
+            Target::List(key) | Target::Stream(key) => key.clone(),
+        };
+        self.pusher.send(Message { key, command })
+    }
+}
+
+/// A `[lock]` held as a key in the server, with a random value so only its holder deletes it.
+pub struct RedisLock {
+    server: Arc<Server>,
+    key: String,
+    ttl: Duration,
+    wait: Duration,
+}
+
+impl RedisLock {
+    pub fn new(cfg: &RedisConfig, lock: &LockConfig, name: &str) -> Result<Self, String> {
+        Ok(RedisLock {
+            server: Arc::new(Server::new(cfg)?),
+            key: format!("rook:lock:{}", name),
+            ttl: Duration::from_secs(lock.ttl),
+            wait: Duration::from_secs(lock.wait),
+        })
+    }
+}
+
+impl Lock for RedisLock {
+    fn acquire(&self) -> Result<Box<dyn Held>, String> {
+        let token = format!("{:016x}{:016x}", id::random_u64(), id::random_u64());
+        let ttl = self.ttl.as_millis().to_string();
+        let set = command(&[
+            b"SET",
+            self.key.as_bytes(),
+            token.as_bytes(),
+            b"NX",
+            b"PX",
+            ttl.as_bytes(),
+        ]);
+        let deadline = Instant::now() + self.wait;
+        let mut conn = connect_blocking(&self.server)?;
+        loop {
+            if command_blocking(&mut conn, &set)? == "OK" {
+                return Ok(Box::new(RedisHeld {
+                    server: self.server.clone(),
+                    key: self.key.clone(),
+                    token,
+                }));
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "lock '{}' still held after {}s",
+                    self.key,
+                    self.wait.as_secs()
+                ));
+            }
+            thread::sleep(lock::RETRY);
+        }
+    }
+}
+
+/// Deletes the key when dropped, if it's still ours.
+struct RedisHeld {
+    server: Arc<Server>,
+    key: String,
+    token: String,
+}
+
+impl Held for RedisHeld {}
+
+impl Drop for RedisHeld {
+    fn drop(&mut self) {
+        const RELEASE: &[u8] = b"if redis.call('get', KEYS[1]) == ARGV[1] then \
+            return redis.call('del', KEYS[1]) else return 0 end";
+        let release = command(&[
+            b"EVAL",
+            RELEASE,
+            b"1",
+            self.key.as_bytes(),
+            self.token.as_bytes(),
+        ]);
+        let released = connect_blocking(&self.server)
+            .and_then(|mut conn| command_blocking(&mut conn, &release));
+        if let Err(e) = released {
+            // the forked process can't use the server's logger; the key still expires
+            eprintln!("redis unlock failed: key={} error={}", self.key, e);
+        }
+    }
+}
+
+/// A command in RESP, the way clients send them: an array of bulk strings.
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Where to connect, and the commands that authenticate and pick the database.
+struct Server {
+    url: String,
+    addr: String,
+    setup: Vec<Vec<u8>>,
+}
+
+impl Server {
+    fn new(cfg: &RedisConfig) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .url
+            .parse()
+            .map_err(|e| format!("bad redis url '{}': {}", cfg.url, e))?;
+        let host = match (uri.scheme_str(), uri.host()) {
+            (Some("redis"), Some(host)) => host,
+            _ => {
+                return Err(format!(
+                    "redis url '{}' must be redis://host:port/db",
+                    cfg.url
+                ))
+            }
+        };
+        if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+            return Err(format!(
+                "redis url '{}' must not carry credentials; use user and password_file",
+                cfg.url
+            ));
+        }
+        let mut setup = vec![];
+        match (&cfg.user, &cfg.password_file) {
+            (user, Some(path)) => {
+                let password = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read redis password_file '{}': {}", path, e))?;
+                let password = password.trim().as_bytes();
+                setup.push(match user {
+                    Some(user) => command(&[b"AUTH", user.as_bytes(), password]),
+                    None => command(&[b"AUTH", password]),
+                });
+            }
+            (Some(_), None) => return Err("[redis] user needs password_file".to_string()),
+            (None, None) => {}
+        }
+        match uri.path().trim_start_matches('/') {
+            "" => {}
+            db if db.parse::<u16>().is_ok() => setup.push(command(&[b"SELECT", db.as_bytes()])),
+            _ => return Err(format!("redis url '{}' has a bad database number", cfg.url)),
+        }
+        Ok(Server {
+            url: cfg.url.clone(),
+            addr: format!("{}:{}", host, uri.port_u16().unwrap_or(DEFAULT_PORT)),
+            setup,
+        })
+    }
+
+    fn key(&self) -> String {
+        format!("{} {:?}", self.addr, self.setup)
+    }
+}
+
+/// A queue of commands for one server, sent by a background task started on first use.
+struct Pusher {
+    server: Arc<Server>,
+    tx: OnceLock<mpsc::Sender<Message>>,
+}
+
+struct Message {
+    /// the list or stream, for logging
+    key: String,
+    command: Vec<u8>,
+}
+
+/// The pusher for `server`, shared with any other hook that pushes there.
+fn pusher(server: Server) -> Arc<Pusher> {
+    let Ok(mut pushers) = PUSHERS.lock() else {
+        return Arc::new(Pusher::new(server));
+    };
+    pushers.retain(|(_, p)| p.strong_count() > 0);
+    let key = server.key();
+    if let Some(pusher) = pushers
+        .iter()
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, p)| p.upgrade())
+    {
+        return pusher;
+    }
+    let pusher = Arc::new(Pusher::new(server));
+    pushers.push((key, Arc::downgrade(&pusher)));
+    pusher
+}
+
+impl Pusher {
+    fn new(server: Server) -> Self {
+        Pusher {
+            server: Arc::new(server),
+            tx: OnceLock::new(),
+        }
+    }
+
+    fn send(&self, message: Message) -> Result<(), String> {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            // ends once the config holding this pusher is dropped
+            tokio::spawn(run(self.server.clone(), rx));
+            tx
+        });
+        tx.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => "redis queue full".to_string(),
+            mpsc::error::TrySendError::Closed(_) => "redis pusher stopped".to_string(),
+        })
+    }
+}
+
+type Connection = BufReader<TcpStream>;
+
+/// Send queued commands one at a time, each waiting for its reply.
+async fn run(server: Arc<Server>, mut rx: mpsc::Receiver<Message>) {
+    let mut conn: Option<Connection> = None;
+    while let Some(message) = rx.recv().await {
+        push(&server, &mut conn, &message).await;
+    }
+}
+
+/// Send `message`, connecting first if needed.  A command that couldn't be written to a
+/// connection that turns out to be dead is tried once more on a new one; once written, it
+/// isn't sent again, so a lost reply can't push a delivery twice.
+async fn push(server: &Server, conn: &mut Option<Connection>, message: &Message) {
+    if conn.as_ref().is_some_and(closed) {
+        *conn = None;
+    }
+    let mut error = String::new();
+    for _ in 0..2 {
+        let c = match conn {
+            Some(c) => c,
+            None => match connect(server).await {
+                Ok(c) => conn.insert(c),
+                Err(e) => {
+                    error = e;
+                    break;
+                }
+            },
+        };
+        if let Err(e) = write(c, &message.command).await {
+            error = e;
+            *conn = None;
+            continue;
+        }
+        match reply(c).await {
+            Ok(Ok(reply)) => {
+                debug!("pushed to redis {}: {}", message.key, reply);
+                return;
+            }
+            // the server answered; the connection is fine
+            Ok(Err(e)) => error = e,
+            Err(e) => {
+                error = e;
+                *conn = None;
+            }
+        }
+        break;
+    }
+    log::warn!(
+        "redis push failed: url={} key={} error={}",
+        server.url,
+        message.key,
+        error
+    );
+}
+
+/// Whether the server has closed an idle connection.  Replies are always read in full, so
+/// anything readable between commands is the end of the stream.
+fn closed(c: &Connection) -> bool {
+    !matches!(c.get_ref().try_read(&mut [0]), Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+async fn write(c: &mut Connection, bytes: &[u8]) -> Result<(), String> {
+    match timeout(TIMEOUT, c.get_mut().write_all(bytes)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("write timed out".to_string()),
+    }
+}
+
+/// The reply to a command: the outer error when the connection failed, the inner one when the
+/// server answered with an error.
+async fn reply(c: &mut Connection) -> Result<Result<String, String>, String> {
+    match timeout(TIMEOUT, read_reply(c)).await {
+        Ok(reply) => reply,
+        Err(_) => Err("reply timed out".to_string()),
+    }
+}
+
+/// Read one reply.  The commands rook sends only get simple strings, errors, integers, or bulk
+/// strings back.
+async fn read_reply(c: &mut Connection) -> Result<Result<String, String>, String> {
+    let mut line = String::new();
+    if c.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+        return Err("server closed the connection".to_string());
+    }
+    let line = line.trim_end();
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" | ":" => Ok(Ok(rest.to_string())),
+        "-" => Ok(Err(rest.to_string())),
+        "$" if rest == "-1" => Ok(Ok(String::new())),
+        "$" => {
+            let len: usize = rest.parse().map_err(|_| format!("bad reply '{}'", line))?;
+            let mut bulk = vec![0; len + 2];
+            c.read_exact(&mut bulk).await.map_err(|e| e.to_string())?;
+            bulk.truncate(len);
+            Ok(Ok(String::from_utf8_lossy(&bulk).into_owned()))
+        }
+        _ => Err(format!("unexpected reply '{}'", line)),
+    }
+}
+
+type BlockingConnection = io::BufReader<std::net::TcpStream>;
+
+/// [`connect`], over a std socket.
+fn connect_blocking(server: &Server) -> Result<BlockingConnection, String> {
+    let addr = server
+        .addr
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("no address")?;
+    let stream = std::net::TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+    let mut conn = io::BufReader::new(stream);
+    for command in &server.setup {
+        command_blocking(&mut conn, command)?;
+    }
+    Ok(conn)
+}
+
+/// Send `command` and read its reply, which is an error if the server answered with one.
+fn command_blocking(c: &mut BlockingConnection, command: &[u8]) -> Result<String, String> {
+    c.get_mut().write_all(command).map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    if c.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Err("server closed the connection".to_string());
+    }
+    let line = line.trim_end();
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" | ":" => Ok(rest.to_string()),
+        "-" => Err(rest.to_string()),
+        "$" if rest == "-1" => Ok(String::new()),
+        "$" => {
+            let len: usize = rest.parse().map_err(|_| format!("bad reply '{}'", line))?;
+            let mut bulk = vec![0; len + 2];
+            c.read_exact(&mut bulk).map_err(|e| e.to_string())?;
+            bulk.truncate(len);
+            Ok(String::from_utf8_lossy(&bulk).into_owned())
+        }
+        _ => Err(format!("unexpected reply '{}'", line)),
+    }
+}
+
+/// Connect, then authenticate and select the database.
+async fn connect(server: &Server) -> Result<Connection, String> {
+    let stream = match timeout(TIMEOUT, TcpStream::connect(&server.addr)).await {
+        Ok(stream) => stream.map_err(|e| e.to_string())?,
+        Err(_) => return Err("connect timed out".to_string()),
+    };
+    let mut conn = BufReader::new(stream);
+    for command in &server.setup {
+        write(&mut conn, command).await?;
+        reply(&mut conn).await??;
+    }
+    debug!("connected to redis at {}", server.url);
+    Ok(conn)
+}
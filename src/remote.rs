@@ -0,0 +1,336 @@
+//! Config files served over http(s), for fleets of rooks managed from one place:
+//! `rook --config-secret config.key https://config.example.com/rook.toml`.
+//!
+//! A remote config only loads once it's verified.  With a [`Check::Sha256`] pin the body must
+//! have that digest, so it never changes and isn't polled.  With a [`Check::Signed`] secret,
+//! `<url>.sig` must hold the config's signature as `rook sign` prints it, and the config is
+//! fetched again every [`Source::refresh`] and reloaded whenever it changes; a config that
+//! fails to fetch, verify, or load is logged and the current one stays.  A signed config sets
+//! `config_serial`, which a changed config must raise, so an older signed config can't be
+//! served again to roll the server back.
+//!
+//! Configs can also be kept in Consul KV or etcd, named `consul:<key>` or `etcd:<key>`; see
+//! [`kv`](crate::kv).  Those aren't verified, and are watched rather than polled.
+#[cfg(feature = "kv")]
+use crate::kv;
+use crate::{encoding, logging, service::RookService, verify};
+use hyper::{body::HttpBody, header, Body, Client, Request, StatusCode, Uri};
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[cfg(feature = "tls")]
+type Connector = crate::tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = hyper::client::HttpConnector;
+
+/// per request, the signature included
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// largest config rook fetches
+const MAX_LENGTH: usize = 4 * 1024 * 1024;
+/// wait after a failed watch before watching again
+#[cfg(feature = "kv")]
+const WATCH_RETRY: Duration = Duration::from_secs(10);
+
+/// How a fetched config is verified before it loads.
+pub enum Check {
+    /// the hex sha256 of the only config that loads
+    Sha256(String),
+    /// the secret `<url>.sig` is signed with
+    Signed(Vec<u8>),
+}
+
+/// Where a config is fetched from, and how it's verified.
+pub struct Source {
+    url: String,
+    location: Location,
+    refresh: Duration,
+    /// sha256 of the config last loaded, so polls only reload a changed one
+    loaded: Mutex<Option<String>>,
+    /// `config_serial` of the config last loaded
+    serial: Mutex<Option<u64>>,
+}
+
+enum Location {
+    Url {
+        check: Check,
+        ca_file: String,
+    },
+    #[cfg(feature = "kv")]
+    Kv {
+        store: kv::Store,
+        key: String,
+    },
+}
+
+impl Source {
+    /// A config at `url`, polled every 5 minutes when it's signed.
+    pub fn new(url: &str, check: Check) -> Self {
+        Source {
+            url: url.to_string(),
+            location: Location::Url {
+                check,
+                ca_file: crate::config::default_ca_file(),
+            },
+            refresh: Duration::from_secs(300),
+            loaded: Mutex::new(None),
+            serial: Mutex::new(None),
+        }
+    }
+
+    /// A config kept at `consul:<key>` or `etcd:<key>`.
+    #[cfg(feature = "kv")]
+    pub fn kv(path: &str) -> Result<Self, String> {
+        let (store, key) = match path.split_once(':') {
+            Some(("consul", key)) => (kv::Store::consul(), key),
+            Some(("etcd", key)) => (kv::Store::etcd(), key),
+            _ => return Err(format!("'{}' isn't consul:<key> or etcd:<key>", path)),
+        };
+        if key.trim_matches('/').is_empty() {
+            return Err(format!("'{}' needs a key", path));
+        }
+        Ok(Source {
+            url: path.to_string(),
+            location: Location::Kv {
+                store,
+                key: key.to_string(),
+            },
+            refresh: Duration::from_secs(300),
+            loaded: Mutex::new(None),
+            serial: Mutex::new(None),
+        })
+    }
+
+    /// A config kept at `consul:<key>` or `etcd:<key>`.
+    #[cfg(not(feature = "kv"))]
+    pub fn kv(_: &str) -> Result<Self, String> {
+        Err("consul and etcd configs need rook built with the `kv` feature".to_string())
+    }
+
+    /// How often a signed config is fetched again.
+    pub fn refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Trust the CAs in `ca_file` for https urls instead of the system bundle.
+    pub fn ca_file(mut self, ca_file: &str) -> Self {
+        match &mut self.location {
+            Location::Url { ca_file: ca, .. } => *ca = ca_file.to_string(),
+            #[cfg(feature = "kv")]
+            Location::Kv { .. } => {}
+        }
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Fetch and verify the config.
+    pub async fn fetch(&self) -> Result<String, String> {
+        let (check, ca_file) = match &self.location {
+            Location::Url { check, ca_file } => (check, ca_file),
+            #[cfg(feature = "kv")]
+            Location::Kv { store, key } => {
+                let (value, _) = store.get(key, None).await?;
+                return kv_text(store, key, value);
+            }
+        };
+        let uri: Uri = self
+            .url
+            .parse()
+            .map_err(|e| format!("bad config url '{}': {}", self.url, e))?;
+        let client = Client::builder().build(connector(ca_file, &uri)?);
+        let text = get(&client, uri).await?;
+        match check {
+            Check::Sha256(pin) => {
+                let digest = digest(&text);
+                if !digest.eq_ignore_ascii_case(pin.trim()) {
+                    return Err(format!(
+                        "config '{}' has sha256 {}, not the pinned {}",
+                        self.url, digest, pin
+                    ));
+                }
+            }
+            Check::Signed(secret) => {
+                let sig_url = format!("{}.sig", self.url);
+                let sig_uri = sig_url
+                    .parse()
+                    .map_err(|e| format!("bad config url '{}': {}", sig_url, e))?;
+                let signature = get(&client, sig_uri).await?;
+                verify::check_signed(secret, text.as_bytes(), &signature).map_err(|e| {
+                    format!("config '{}' failed verification: {}", self.url, e.reason())
+                })?;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Whether `text`, with `config_serial` set to `serial`, may replace the config that
+    /// loaded: a signed config needs a serial, above the current one unless it's unchanged.
+    pub(crate) fn check_serial(&self, text: &str, serial: Option<u64>) -> Result<(), String> {
+        let signed = matches!(
+            self.location,
+            Location::Url {
+                check: Check::Signed(_),
+                ..
+            }
+        );
+        if !signed {
+            return Ok(());
+        }
+        let serial =
+            serial.ok_or_else(|| format!("signed config '{}' needs config_serial", self.url))?;
+        match self.serial.lock().ok().and_then(|s| *s) {
+            Some(current) if serial <= current && !self.is_loaded(text) => Err(format!(
+                "config '{}' has config_serial {}, not above the loaded {}",
+                self.url, serial, current
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record `text`, with `config_serial` set to `serial`, as the config that loaded.
+    pub(crate) fn loaded(&self, text: &str, serial: Option<u64>) {
+        if let Ok(mut loaded) = self.loaded.lock() {
+            *loaded = Some(digest(text));
+        }
+        if let Ok(mut current) = self.serial.lock() {
+            *current = serial;
+        }
+    }
+
+    fn is_loaded(&self, text: &str) -> bool {
+        self.loaded
+            .lock()
+            .is_ok_and(|loaded| loaded.as_deref() == Some(digest(text).as_str()))
+    }
+}
+
+/// Whether `path` names a config to fetch rather than a file.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Whether `path` names a config kept in Consul or etcd.
+pub fn is_kv(path: &str) -> bool {
+    path.starts_with("consul:") || path.starts_with("etcd:")
+}
+
+/// Reload `rook`'s remote config whenever it changes: signed urls are fetched every
+/// [`Source::refresh`], and Consul and etcd keys are watched.
+pub async fn poll(rook: RookService) {
+    let Some(source) = rook.config().remote.clone() else {
+        return;
+    };
+    match &source.location {
+        Location::Url {
+            check: Check::Signed(_),
+            ..
+        } => {
+            let mut refresh = tokio::time::interval(source.refresh);
+            // the first tick is immediate, and the config was just loaded
+            refresh.tick().await;
+            loop {
+                refresh.tick().await;
+                reload_changed(&rook, &source, source.fetch().await);
+            }
+        }
+        Location::Url { .. } => {}
+        #[cfg(feature = "kv")]
+        Location::Kv { store, key } => {
+            let mut index = None;
+            loop {
+                match store.get(key, index).await {
+                    Ok((value, at)) => {
+                        // an index going backwards means the store was rebuilt; start over
+                        index = Some(if index.is_some_and(|i| at < i) { 0 } else { at });
+                        reload_changed(&rook, &source, kv_text(store, key, value));
+                    }
+                    Err(e) => {
+                        log::warn!("config refresh failed: {}", e);
+                        tokio::time::sleep(WATCH_RETRY).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Load `fetched` unless it's the config already loaded.
+fn reload_changed(rook: &RookService, source: &Arc<Source>, fetched: Result<String, String>) {
+    let text = match fetched {
+        Ok(text) if source.is_loaded(&text) => return,
+        Ok(text) => text,
+        Err(e) => {
+            log::warn!("config refresh failed: {}", e);
+            return;
+        }
+    };
+    match rook.install_remote(source, &text) {
+        Ok(_) => logging::info!("reloaded changed config from {}", source.url),
+        Err(e) => log::warn!("config refresh failed: {}", e),
+    }
+}
+
+#[cfg(feature = "kv")]
+fn kv_text(store: &kv::Store, key: &str, value: Option<Vec<u8>>) -> Result<String, String> {
+    let value = value.ok_or_else(|| format!("no {} key '{}'", store.name(), key))?;
+    String::from_utf8(value).map_err(|_| format!("{} key '{}' isn't utf-8", store.name(), key))
+}
+
+fn digest(text: &str) -> String {
+    encoding::hex(&Sha256::digest(text.as_bytes()))
+}
+
+/// The body of a `200` from `uri`, read no further than [`MAX_LENGTH`].
+async fn get(client: &Client<Connector>, uri: Uri) -> Result<String, String> {
+    let url = uri.to_string();
+    let req = Request::get(uri)
+        .body(Body::empty())
+        .map_err(|e| format!("bad config request '{}': {}", url, e))?;
+    let failed = |e: hyper::Error| format!("config request '{}' failed: {}", url, e);
+    let too_large = || format!("'{}' is over {} bytes", url, MAX_LENGTH);
+    let exchange = async {
+        let res = client.request(req).await.map_err(failed)?;
+        if res.status() != StatusCode::OK {
+            return Err(format!("'{}' answered {}", url, res.status()));
+        }
+        let length = res
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+        if length.is_some_and(|n| n > MAX_LENGTH as u64) {
+            return Err(too_large());
+        }
+        let mut body = res.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.map_err(failed)?);
+            if bytes.len() > MAX_LENGTH {
+                return Err(too_large());
+            }
+        }
+        Ok(bytes)
+    };
+    let bytes = tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("config request '{}' timed out", url))??;
+    String::from_utf8(bytes).map_err(|_| format!("'{}' isn't utf-8", url))
+}
+
+#[cfg(feature = "tls")]
+fn connector(ca_file: &str, _: &Uri) -> Result<Connector, String> {
+    crate::tls::https_connector(ca_file)
+}
+
+#[cfg(not(feature = "tls"))]
+fn connector(_: &str, uri: &Uri) -> Result<Connector, String> {
+    if uri.scheme_str() != Some("http") {
+        return Err("https config urls need rook built with the `tls` feature".to_string());
+    }
+    Ok(Connector::new())
+}
@@ -0,0 +1,122 @@
+//! How rook words its answer to a request for one of its routes.
+//!
+//! rook builds every response itself (status, `Allow`, `Retry-After`, a short plain-text body)
+//! and then hands it to the route's [`Renderer`], which can rewrite the body and headers.
+//! Requests that never matched a route, such as a `404` for an unknown path, always get the
+//! plain-text response.
+use hyper::{
+    header::{self, HeaderValue},
+    Body, Response, StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+/// What rook knows about the request it answered.
+pub struct Outcome<'a> {
+    /// the hook url, without `path_prefix`
+    pub route: &'a str,
+    pub request_id: Option<&'a str>,
+    /// why the request failed, such as `signature mismatch`, or `ok`
+    pub reason: &'a str,
+    /// hook commands started
+    pub started: usize,
+}
+
+pub trait Renderer: Send + Sync {
+    /// Rewrite `res`, which already has its status and rook's plain-text body.
+    fn render(&self, outcome: &Outcome, res: &mut Response<Body>);
+}
+
+/// The response format a hook's `response` option names.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// empty on success, a short reason on failure
+    #[default]
+    Text,
+    /// see [`Json`]
+    Json,
+}
+
+impl ResponseFormat {
+    pub fn renderer(self) -> Arc<dyn Renderer> {
+        match self {
+            ResponseFormat::Text => Arc::new(Text),
+            ResponseFormat::Json => Arc::new(Json),
+        }
+    }
+}
+
+/// Leaves rook's response as it is.
+pub struct Text;
+
+impl Renderer for Text {
+    fn render(&self, _outcome: &Outcome, _res: &mut Response<Body>) {}
+}
+
+/// Answers with a json object, for hooks driven by other services:
+///
+/// ```text
+/// {"accepted": true, "request_id": "6c2b1f0e9d8a7b43", "started": 1}
+/// {"accepted": false, "request_id": "6c2b1f0e9d8a7b43", "error": "signature mismatch"}
+/// ```
+///
+/// `request_id` is the `$ROOK_REQUEST_ID` the hook's command sees.
+pub struct Json;
+
+impl Renderer for Json {
+    fn render(&self, outcome: &Outcome, res: &mut Response<Body>) {
+        let body = if res.status().is_success() {
+            json!({
+                "accepted": true,
+                "request_id": outcome.request_id,
+                "started": outcome.started,
+            })
+        } else {
+            json!({
+                "accepted": false,
+                "request_id": outcome.request_id,
+                "error": outcome.reason,
+            })
+        };
+        *res.body_mut() = Body::from(body.to_string());
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+    }
+}
+
+/// Answers successful requests with the status and body a hook's `success` option sets, for
+/// senders that check them.  Everything else is left to `inner`, the hook's `response` format.
+pub struct Success {
+    /// a 2xx status
+    pub status: StatusCode,
+    /// replaces the body `inner` sends; None keeps it
+    pub body: Option<String>,
+    pub content_type: Option<HeaderValue>,
+    pub inner: Arc<dyn Renderer>,
+}
+
+impl Renderer for Success {
+    fn render(&self, outcome: &Outcome, res: &mut Response<Body>) {
+        self.inner.render(outcome, res);
+        if !res.status().is_success() {
+            return;
+        }
+        *res.status_mut() = self.status;
+        if self.status == StatusCode::NO_CONTENT {
+            *res.body_mut() = Body::empty();
+            res.headers_mut().remove(header::CONTENT_TYPE);
+            return;
+        }
+        if let Some(body) = &self.body {
+            *res.body_mut() = Body::from(body.clone());
+        }
+        if let Some(content_type) = &self.content_type {
+            res.headers_mut()
+                .insert(header::CONTENT_TYPE, content_type.clone());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! `rook replay`: send the requests `rook --record` saved through a config's routing, in
+//! process and in dry-run mode, and report which were answered with a different status than
+//! when they were recorded.
+//!
+//! Deliveries signed with a timestamp are usually past their hook's `replay_window` by the
+//! time they're replayed, and are rejected.
+use crate::cli::{Args, USAGE};
+use rook::{config, fixture, service::RookService};
+use std::sync::Arc;
+use tower_service::Service;
+
+pub async fn replay(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["--run", "--verbose"], &[])?;
+    let [cfg_path, dir] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut cfg = config::from_file(cfg_path).map_err(|e| e.to_string())?;
+    if !args.has("--verbose") {
+        // keep the access and dry-run lines out of the report
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    if !args.has("--run") {
+        cfg.set_dry_run();
+    }
+    // replays aren't new deliveries
+    cfg.history = None;
+    cfg.postgres = None;
+    let fixtures = fixture::load(dir)?;
+    let mut rook = RookService::new(Arc::new(cfg))?;
+    let total = fixtures.len();
+    let mut failed = 0;
+    for fixture in fixtures {
+        let res = rook
+            .call(fixture.request()?)
+            .await
+            .unwrap_or_else(|e| match e {});
+        let status = res.status().as_u16();
+        let request = format!("{} {}", fixture.method, fixture.uri);
+        match fixture.status {
+            Some(recorded) if recorded != status => {
+                failed += 1;
+                println!(
+                    "FAIL  {}: {} answered {}, recorded {}",
+                    fixture.name, request, status, recorded
+                );
+            }
+            _ => println!("ok    {}: {} answered {}", fixture.name, request, status),
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} requests failed", failed, total)),
+    }
+}
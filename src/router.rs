@@ -1,74 +1,449 @@
-use crate::config::{GithubHook, RookHook, RouteConfig};
-use fork::Fork;
+#[cfg(feature = "watch")]
+use crate::glob;
+use crate::{
+    archive::{Archive, Delivery},
+    config::{Fallback, GithubHook, Hook, HookOptions, RookHook, RouteConfig},
+    decompress::{self, DecodeError, Encoding},
+    encoding::decode_query,
+    exec::{Invocation, Payload},
+    filter,
+    github::{self, Event, GithubPayload},
+    history,
+    hours::{Hours, OutsideHours},
+    logging::{self, Alert},
+    maintenance,
+    metrics::METRICS,
+    pattern,
+    render::{Outcome, Renderer},
+    sentry,
+    spill::{self, Spill, SpillFile},
+    trace::{self, SpanGuard},
+    verify::{ChunkedVerify, VerifyError, GITHUB_SIGNATURE_HEADER},
+};
+#[cfg(feature = "mqtt")]
+use crate::{topic, verify::ROOK_SIGNATURE_HEADER};
 use futures::stream::TryStreamExt;
-use hmac::{Hmac, Mac};
 use hyper::{
-    header::{HeaderMap, HeaderValue},
-    Body, Request, Response, StatusCode,
+    header::{self, HeaderMap, HeaderValue},
+    http::request::Parts,
+    Body, Method, Request, Response, StatusCode, Uri,
 };
-use serde::Deserialize;
-use serde_json;
-use sha2::Sha256;
 use std::{
     convert::Infallible,
     fmt,
-    process::{self, Command, Stdio},
+    net::IpAddr,
     str::{self, FromStr},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 type Headers = HeaderMap<HeaderValue>;
 
-macro_rules! debug {
-    ($($tts:tt)*) => {
-        #[cfg(debug_assertions)]
-        log::debug!($($tts)*)
-    }
+/// Response extension naming the route (hook url) that handled a request, for the access log.
+pub struct MatchedRoute(pub String);
+
+/// Response extension describing what happened to a request's body once it was read.
+#[derive(Default)]
+pub struct Dispatch {
+    /// None when the request was rejected before the body was read, or it was spilled to disk
+    pub body: Option<Vec<u8>>,
+    /// hooks whose signature check passed
+    pub verified: usize,
+    /// hook commands started
+    pub started: usize,
+    /// why the signature was rejected, for the audit log
+    pub rejection: Option<&'static str>,
+}
+
+/// Request extension carrying the id from [`crate::logging::request_id`].
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+/// The client on the other end of a request.
+pub struct Peer {
+    /// after PROXY protocol and trusted forwarding headers are applied
+    pub ip: IpAddr,
+    /// set when the client presented a verified certificate; empty if it had no CN
+    pub client_cn: Option<String>,
 }
 
-pub async fn handle(req: Request<Body>, cfg: &RouteConfig) -> Result<Response<Body>, Infallible> {
-    Ok::<_, Infallible>(match route(req, cfg).await {
+pub async fn handle(
+    req: Request<Body>,
+    cfg: &RouteConfig,
+    peer: &Peer,
+) -> Result<Response<Body>, Infallible> {
+    Ok::<_, Infallible>(match route(req, cfg, peer).await {
         Ok(o) => o,
         Err(e) => e,
     })
 }
 
-async fn route(req: Request<Body>, cfg: &RouteConfig) -> Result<Response<Body>, Response<Body>> {
+/// Whether `path` (before `path_prefix` is stripped) is one of `cfg`'s hook urls.
+pub fn is_route(cfg: &RouteConfig, path: &str) -> bool {
+    strip_prefix(path, &cfg.path_prefix).is_some_and(|p| resolve(cfg, p).is_some())
+}
+
+/// Response for requests over `max_requests` or `max_jobs`, sent without touching the body.
+/// Asks the sender to try again in `retry_after` seconds.
+pub fn overloaded(retry_after: u64) -> Response<Body> {
+    HttpResponse::ServiceUnavailable(retry_after).into()
+}
+
+async fn route(
+    req: Request<Body>,
+    cfg: &RouteConfig,
+    peer: &Peer,
+) -> Result<Response<Body>, Response<Body>> {
     const OK_EMPTY: HttpResponse = HttpResponse::Ok("");
 
     let (parts, body) = req.into_parts();
     let path = parts.uri.path().to_string();
     let headers = &parts.headers;
+    let request_id = parts.extensions.get::<RequestId>().map(|id| id.0.as_str());
 
     debug!("incoming request");
     debug!("<<<{} {}", parts.method, path);
-    #[cfg(debug_assertions)]
     for (k, v) in headers {
-        log::debug!("<<<{}: {:?}", k, v);
+        if history::is_redacted(k.as_str()) {
+            debug!("<<<{}: [redacted]", k);
+        } else {
+            debug!("<<<{}: {:?}", k, v);
+        }
     }
 
-    guard_content_length(headers)?;
-    let body = &parse_body(body).await?;
-    let resp = if let Some(hooks) = cfg.gh_hooks.get(&path) {
-        debug!("dispatch '{}' as github", path);
-        exec_gh_hooks(hooks, headers, body).await
-    } else if let Some(hooks) = cfg.rook_hooks.get(&path) {
-        debug!("dispatch '{}' as rook", path);
-        exec_rook_hooks(hooks, headers, body).await
-    } else {
-        debug!("no route for '{}'", path);
-        Err(BAD_ROUTE)
+    if !cfg.access.permits(&peer.ip) {
+        debug!("{} rejected by global access list", peer.ip);
+        return Err(FORBIDDEN.into());
+    }
+    let stripped = strip_prefix(&path, &cfg.path_prefix);
+    if let (Some(fallback), None) = (&cfg.fallback, stripped.and_then(|p| resolve(cfg, p))) {
+        return Ok(
+            answer_fallback(fallback, &parts, body, peer, request_id, cfg.body_timeout).await,
+        );
+    }
+    let path = stripped.ok_or_else(|| {
+        debug!("'{}' is outside path prefix '{}'", path, cfg.path_prefix);
+        Response::from(BAD_ROUTE)
+    })?;
+    let host = request_host(&parts.uri, headers);
+    // resolve the route and drop hooks that don't accept this client before reading the body
+    let mut matched = None;
+    let mut dispatch = Dispatch::default();
+    let query = parse_query(parts.uri.query());
+    let resp = match resolve(cfg, path).map(|route| Route { query, ..route }) {
+        Some(route) if maintenance::is_paused(route.url) => {
+            debug!("'{}' is paused", route.url);
+            matched = Some(route.url);
+            let paused = url_options(cfg, route.url)
+                .map(|o| o.maintenance.clone())
+                .unwrap_or_default();
+            async {
+                // turn away who the hooks would, before their bodies take a place in the queue
+                guard_url(cfg, &route, &parts, host.as_deref(), peer)?;
+                if paused.queue == 0 {
+                    return Err(HttpResponse::Paused(paused.message));
+                }
+                guard_content_length(headers, MAX_BODY_LENGTH)?;
+                let body = parse_body(body, cfg.body_timeout).await?;
+                if maintenance::hold(route.url, &parts, body, peer, request_id, paused.queue) {
+                    Err(DELIVERY_HELD)
+                } else {
+                    Err(HttpResponse::Paused(paused.message))
+                }
+            }
+            .await
+        }
+        Some(route) if !within_hours(cfg, route.url) => {
+            debug!("'{}' is outside its allowed hours", route.url);
+            matched = Some(route.url);
+            let queue =
+                url_options(cfg, route.url).is_some_and(|o| o.outside_hours == OutsideHours::Queue);
+            async {
+                guard_url(cfg, &route, &parts, host.as_deref(), peer)?;
+                if !queue {
+                    return Err(OUTSIDE_HOURS);
+                }
+                guard_content_length(headers, MAX_BODY_LENGTH)?;
+                let body = parse_body(body, cfg.body_timeout).await?;
+                if maintenance::wait(route.url, &parts, body, peer, request_id) {
+                    Err(DELIVERY_WAITING)
+                } else {
+                    Err(OUTSIDE_HOURS)
+                }
+            }
+            .await
+        }
+        Some(route) if is_github(cfg, route.url, headers) => {
+            let hooks = &cfg.gh_hooks[route.url];
+            debug!("dispatch '{}' as github", route.url);
+            matched = Some(route.url);
+            async {
+                let hooks = guard(hooks, &route, &parts, host.as_deref(), peer)?;
+                guard_content_length(headers, MAX_BODY_LENGTH)?;
+                let encodings = guard_content_encoding(headers)?;
+                let received = read_body(body, &hooks, headers, cfg.body_timeout, None).await?;
+                let received = decode_body(&encodings, received, &hooks, headers)?;
+                let res = exec_gh_hooks(
+                    &route,
+                    &hooks,
+                    headers,
+                    &received,
+                    peer,
+                    request_id,
+                    &mut dispatch,
+                )
+                .await;
+                dispatch.body = Some(received.raw);
+                res
+            }
+            .await
+        }
+        Some(route) => {
+            let hooks = &cfg.rook_hooks[route.url];
+            debug!("dispatch '{}' as rook", route.url);
+            matched = Some(route.url);
+            async {
+                let hooks = guard(hooks, &route, &parts, host.as_deref(), peer)?;
+                // hooks on a url all spill or all don't
+                let spill = hooks.first().and_then(|h| h.options.spill.as_ref());
+                let limit = spill.map_or(MAX_BODY_LENGTH, |s| s.max_size);
+                let length = guard_content_length(headers, limit)?;
+                let encodings = guard_content_encoding(headers)?;
+                let spill = match spill {
+                    Some(spill) if length > spill.above && encodings.is_empty() => Some(spill),
+                    // compressed bodies are never spilled
+                    _ if length > MAX_BODY_LENGTH => return Err(BODY_TOO_LARGE),
+                    _ => None,
+                };
+                let received = read_body(body, &hooks, headers, cfg.body_timeout, spill).await?;
+                let received = decode_body(&encodings, received, &hooks, headers)?;
+                let res = exec_rook_hooks(
+                    &route,
+                    &hooks,
+                    headers,
+                    &received,
+                    peer,
+                    request_id,
+                    &mut dispatch,
+                )
+                .await;
+                dispatch.body = received.spilled.is_none().then_some(received.raw);
+                res
+            }
+            .await
+        }
+        None => {
+            debug!("no route for '{}'", path);
+            Err(BAD_ROUTE)
+        }
     };
     // using Result<T,E> for early exit control flow, flatten both branches
-    match resp {
+    let (reason, mut resp) = match resp {
         Ok(_) => {
             debug!("path dispatched successfully");
-            Ok(OK_EMPTY.into())
+            (OK_EMPTY.reason(), Ok(Response::from(OK_EMPTY)))
+        }
+        Err(e @ HttpResponse::Accepted(_)) => {
+            debug!("delivery held until the url is resumed");
+            (e.reason(), Ok(Response::from(e)))
         }
         Err(e) => {
             debug!("path dispatch failed: {:?}", e);
-            Err(e.into())
+            (e.reason(), Err(Response::from(e)))
+        }
+    };
+    if let (Some(path), Ok(r) | Err(r)) = (matched, &mut resp) {
+        let outcome = Outcome {
+            route: path,
+            request_id,
+            reason,
+            started: dispatch.started,
+        };
+        renderer(cfg, path).render(&outcome, r);
+        if let Some(options) = url_options(cfg, path) {
+            r.headers_mut().extend(options.response_headers.clone());
+        }
+    }
+    if let (Some(path), Some(reason)) = (matched, dispatch.rejection) {
+        logging::audit(peer.ip, path, reason);
+        METRICS.signature_failure(reason);
+    }
+    if let (Some(path), Ok(r) | Err(r)) = (matched, &mut resp) {
+        r.extensions_mut().insert(MatchedRoute(path.to_string()));
+        r.extensions_mut().insert(dispatch);
+    }
+    resp
+}
+
+/// Whether `url`'s hooks may run now, by their `allowed_hours`.  True for urls without them,
+/// or that aren't in `cfg`.
+pub(crate) fn within_hours(cfg: &RouteConfig, url: &str) -> bool {
+    url_options(cfg, url)
+        .and_then(|o| o.allowed_hours.as_ref())
+        .is_none_or(Hours::is_open)
+}
+
+/// The options of `url`'s first hook, for the ones every hook on a url shares.
+pub(crate) fn url_options<'a>(cfg: &'a RouteConfig, url: &str) -> Option<&'a HookOptions> {
+    let gh = cfg
+        .gh_hooks
+        .get(url)
+        .and_then(|h| h.first())
+        .map(|h| &h.options);
+    let rook = cfg
+        .rook_hooks
+        .get(url)
+        .and_then(|h| h.first())
+        .map(|h| &h.options);
+    gh.or(rook)
+}
+
+/// Answer a request no hook url matches with `[fallback]`, running its command if it has one.
+async fn answer_fallback(
+    fallback: &Fallback,
+    parts: &Parts,
+    body: Body,
+    peer: &Peer,
+    request_id: Option<&str>,
+    timeout: Duration,
+) -> Response<Body> {
+    let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    log::info!(
+        "fallback: client={} method={} path={}",
+        peer.ip,
+        parts.method,
+        path
+    );
+    if let Some(command) = &fallback.command {
+        // unlike hooks, a body is optional: browsers and probes rarely send one
+        let body = if parts.headers.contains_key(header::CONTENT_LENGTH) {
+            let read = async {
+                guard_content_length(&parts.headers, MAX_BODY_LENGTH)?;
+                parse_body(body, timeout).await
+            };
+            match read.await {
+                Ok(body) => body,
+                Err(e) => return e.into(),
+            }
+        } else {
+            vec![]
+        };
+        let input = String::from_utf8_lossy(&body).trim().to_string();
+        let mut env = vec![
+            ("ROOK_INPUT".to_string(), input.clone()),
+            ("ROOK_METHOD".to_string(), parts.method.to_string()),
+            ("ROOK_PATH".to_string(), path.to_string()),
+            ("ROOK_CLIENT_IP".to_string(), peer.ip.to_string()),
+        ];
+        env.extend(common_env(peer, request_id, None));
+        let invocation = Invocation {
+            route: path,
+            command,
+            request_id,
+            payload: &Payload::Rook(input),
+            body: &body,
+            env,
+            input_file: None,
+            args: vec![],
+        };
+        if let Err(e) = fallback.executor.execute(&invocation) {
+            log::warn!("fallback command failed: {}", e);
         }
     }
+    let mut resp = Response::new(Body::from(fallback.body.clone()));
+    *resp.status_mut() = fallback.status;
+    resp.headers_mut()
+        .insert(header::CONTENT_TYPE, fallback.content_type.clone());
+    resp
+}
+
+/// A hook url that answers a request path, with the segments a pattern url captured.
+struct Route<'a> {
+    url: &'a str,
+    params: Vec<(String, String)>,
+    /// the request's query parameters, decoded
+    query: Vec<(String, String)>,
+}
+
+impl Route<'_> {
+    /// `ROOK_PATH_<NAME>` for each captured segment, and `ROOK_QUERY_<NAME>` for each query
+    /// parameter whose name can be one.
+    fn env(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self
+            .params
+            .iter()
+            .map(|(name, value)| (pattern::env_name(name), value.clone()))
+            .collect();
+        let query = self
+            .query
+            .iter()
+            .filter(|(name, _)| {
+                !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+            })
+            .map(|(name, value)| {
+                let name = format!("ROOK_QUERY_{}", name.to_ascii_uppercase());
+                (name, value.clone())
+            });
+        filter::extend_env(&mut env, query.collect());
+        env
+    }
+}
+
+/// Whether a request for `url` goes to its `"github"` hooks.  When both hook types share the
+/// url, GitHub's signature header picks them; anything else is for the `"rook"` hooks.
+fn is_github(cfg: &RouteConfig, url: &str, headers: &Headers) -> bool {
+    cfg.gh_hooks.contains_key(url)
+        && (!cfg.rook_hooks.contains_key(url) || headers.contains_key(GITHUB_SIGNATURE_HEADER))
+}
+
+/// The hook url for `path`: the url itself, or else the most specific pattern matching it.
+fn resolve<'a>(cfg: &'a RouteConfig, path: &str) -> Option<Route<'a>> {
+    let exact = cfg.gh_hooks.get_key_value(path).map(|(url, _)| url);
+    if let Some(url) = exact.or_else(|| cfg.rook_hooks.get_key_value(path).map(|(url, _)| url)) {
+        return Some(Route {
+            url,
+            params: vec![],
+            query: vec![],
+        });
+    }
+    cfg.gh_hooks
+        .keys()
+        .chain(cfg.rook_hooks.keys())
+        .filter(|url| pattern::is_pattern(url))
+        .filter_map(|url| Some((url, pattern::captures(url, path)?)))
+        .min_by_key(|(url, _)| pattern::specificity(url))
+        .map(|(url, params)| Route {
+            url,
+            params,
+            query: vec![],
+        })
+}
+
+/// The renderer for a matched route: its first hook's.
+fn renderer<'a>(cfg: &'a RouteConfig, path: &str) -> &'a dyn Renderer {
+    let gh = cfg
+        .gh_hooks
+        .get(path)
+        .and_then(|h| h.first())
+        .map(|h| &h.options);
+    let rook = cfg
+        .rook_hooks
+        .get(path)
+        .and_then(|h| h.first())
+        .map(|h| &h.options);
+    match gh.or(rook) {
+        Some(options) => options.renderer.as_ref(),
+        None => &crate::render::Text,
+    }
+}
+
+/// `/rook/hooks/gh` -> `/hooks/gh` for prefix `/rook`, but `/rookery` is not under `/rook`
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
 }
 
 fn get_header<T: FromStr>(headers: &Headers, key: &str) -> Result<T, HttpResponse> {
@@ -76,272 +451,1074 @@ fn get_header<T: FromStr>(headers: &Headers, key: &str) -> Result<T, HttpRespons
 
     headers
         .get(key)
-        .ok_or_else(|| HEADER_MISSING)?
+        .ok_or(HEADER_MISSING)?
         .to_str()
         .map_err(|_| HEADER_MALFORMED)?
         .parse()
         .map_err(|_| HEADER_MALFORMED)
 }
 
-fn guard_content_length(headers: &Headers) -> Result<(), HttpResponse> {
-    const MAX_BODY_LENGTH: u32 = 1 << 21; // 2 MiB is enough for anyone
-    const BODY_TOO_LARGE: HttpResponse = HttpResponse::BadRequest("body too large");
+/// Keep the hooks whose access list and client certificate requirements this peer satisfies.
+fn permitted<'a, H: Hook>(hooks: Vec<&'a H>, peer: &Peer) -> Result<Vec<&'a H>, HttpResponse> {
+    const CLIENT_CERT_REQUIRED: HttpResponse =
+        HttpResponse::Unauthorized("client certificate required");
 
-    let len: u32 = get_header(headers, "content-length")?;
-    if len > MAX_BODY_LENGTH {
+    let hooks: Vec<&H> = hooks
+        .into_iter()
+        .filter(|h| h.options().access.permits(&peer.ip))
+        .collect();
+    if hooks.is_empty() {
+        debug!("{} rejected by every hook's access list", peer.ip);
+        return Err(FORBIDDEN);
+    }
+    let hooks: Vec<&H> = hooks
+        .into_iter()
+        .filter(|h| peer.client_cn.is_some() || !h.options().require_client_cert)
+        .collect();
+    if hooks.is_empty() {
+        debug!("{} did not present a client certificate", peer.ip);
+        return Err(CLIENT_CERT_REQUIRED);
+    }
+    Ok(hooks)
+}
+
+/// The request's host from the absolute uri or `Host` header: lowercase, without port or
+/// trailing dot.
+fn request_host(uri: &Uri, headers: &Headers) -> Option<String> {
+    let authority = match uri.authority() {
+        Some(authority) => authority.clone(),
+        None => headers.get(header::HOST)?.to_str().ok()?.parse().ok()?,
+    };
+    Some(authority.host().trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// `a=1&b=two+words` into its decoded pairs, in order.  Pairs that don't decode are dropped.
+fn parse_query(query: Option<&str>) -> Vec<(String, String)> {
+    query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((decode_query(name)?, decode_query(value)?))
+        })
+        .collect()
+}
+
+/// Keep the hooks that accept the request by everything checked before its body is read: the
+/// host, query, method, client, and content type.
+fn guard<'a, H: Hook>(
+    hooks: &'a [H],
+    route: &Route,
+    parts: &Parts,
+    host: Option<&str>,
+    peer: &Peer,
+) -> Result<Vec<&'a H>, HttpResponse> {
+    let hooks = guard_host(hooks, host)?;
+    let hooks = guard_query(hooks, &route.query)?;
+    let hooks = guard_method(hooks, &parts.method)?;
+    let hooks = permitted(hooks, peer)?;
+    guard_content_type(hooks, &parts.headers)
+}
+
+/// [`guard`] the hooks a request for `route` would be dispatched to, for deliveries that are
+/// held rather than run: at least one of them must accept it.
+fn guard_url(
+    cfg: &RouteConfig,
+    route: &Route,
+    parts: &Parts,
+    host: Option<&str>,
+    peer: &Peer,
+) -> Result<(), HttpResponse> {
+    if is_github(cfg, route.url, &parts.headers) {
+        guard(&cfg.gh_hooks[route.url], route, parts, host, peer).map(drop)
+    } else {
+        guard(&cfg.rook_hooks[route.url], route, parts, host, peer).map(drop)
+    }
+}
+
+/// Keep the hooks whose `query` conditions the request meets.  Like [`guard_host`], hooks
+/// without conditions only serve requests that no hook with conditions claims.
+fn guard_query<'a, H: Hook>(
+    hooks: Vec<&'a H>,
+    query: &[(String, String)],
+) -> Result<Vec<&'a H>, HttpResponse> {
+    let (conditional, fallback): (Vec<&H>, Vec<&H>) = hooks
+        .into_iter()
+        .partition(|h| !h.options().query.is_empty());
+    let matched: Vec<&H> = conditional
+        .into_iter()
+        .filter(|h| {
+            h.options()
+                .query
+                .iter()
+                .all(|condition| query.contains(condition))
+        })
+        .collect();
+    if !matched.is_empty() {
+        return Ok(matched);
+    }
+    if fallback.is_empty() {
+        debug!("no hook for query {:?}", query);
+        return Err(BAD_ROUTE);
+    }
+    Ok(fallback)
+}
+
+/// Keep the hooks bound to this host.  Hooks without a `host` only serve requests that no
+/// hook on the path claims by name.
+fn guard_host<'a, H: Hook>(hooks: &'a [H], host: Option<&str>) -> Result<Vec<&'a H>, HttpResponse> {
+    let named: Vec<&H> = hooks
+        .iter()
+        .filter(|h| h.options().host.is_some() && h.options().host.as_deref() == host)
+        .collect();
+    if !named.is_empty() {
+        return Ok(named);
+    }
+    let fallback: Vec<&H> = hooks
+        .iter()
+        .filter(|h| h.options().host.is_none())
+        .collect();
+    if fallback.is_empty() {
+        debug!("no hook for host {:?}", host);
+        return Err(BAD_ROUTE);
+    }
+    Ok(fallback)
+}
+
+/// Keep the hooks that accept `method`.  When none do, the 405 lists every method accepted on
+/// this path in its `Allow` header.
+fn guard_method<'a, H: Hook>(
+    hooks: Vec<&'a H>,
+    method: &Method,
+) -> Result<Vec<&'a H>, HttpResponse> {
+    let matched: Vec<&H> = hooks
+        .iter()
+        .copied()
+        .filter(|h| h.options().methods.contains(method))
+        .collect();
+    if !matched.is_empty() {
+        return Ok(matched);
+    }
+    debug!("no hook accepts {}", method);
+    let mut allowed: Vec<&str> = hooks
+        .iter()
+        .flat_map(|h| &h.options().methods)
+        .map(Method::as_str)
+        .collect();
+    allowed.sort_unstable();
+    allowed.dedup();
+    Err(HttpResponse::MethodNotAllowed(allowed.join(", ")))
+}
+
+/// Keep the hooks that accept the request's media type (parameters like `charset` are ignored).
+fn guard_content_type<'a, H: Hook>(
+    hooks: Vec<&'a H>,
+    headers: &Headers,
+) -> Result<Vec<&'a H>, HttpResponse> {
+    const UNSUPPORTED_MEDIA_TYPE: HttpResponse =
+        HttpResponse::UnsupportedMediaType("unsupported content-type");
+
+    let media_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase());
+    let accepts = |expected: &String| match (&media_type, expected.strip_suffix("/*")) {
+        (Some(actual), Some(prefix)) => actual.split('/').next() == Some(prefix),
+        (Some(actual), None) => actual == expected,
+        (None, _) => false,
+    };
+    let hooks: Vec<&H> = hooks
+        .into_iter()
+        .filter(|h| {
+            let expected = &h.options().content_types;
+            expected.is_empty() || expected.iter().any(accepts)
+        })
+        .collect();
+    if hooks.is_empty() {
+        debug!("no hook accepts content-type {:?}", media_type);
+        return Err(UNSUPPORTED_MEDIA_TYPE);
+    }
+    Ok(hooks)
+}
+
+/// The body's length, when it's no longer than `limit`.
+fn guard_content_length(headers: &Headers, limit: u64) -> Result<u64, HttpResponse> {
+    const LENGTH_REQUIRED: HttpResponse = HttpResponse::LengthRequired("missing content-length");
+
+    if !headers.contains_key("content-length") {
+        return Err(LENGTH_REQUIRED);
+    }
+    let len: u64 = get_header(headers, "content-length")?;
+    if len > limit {
         return Err(BODY_TOO_LARGE);
     }
-    Ok(())
+    Ok(len)
 }
 
-async fn parse_body(body: Body) -> Result<Vec<u8>, HttpResponse> {
-    const BODY_READ_FAILED: HttpResponse = HttpResponse::BadRequest("body read error");
+/// Turn away encodings rook can't decompress before reading the body.
+fn guard_content_encoding(headers: &Headers) -> Result<Vec<Encoding>, HttpResponse> {
+    const UNSUPPORTED_ENCODING: HttpResponse =
+        HttpResponse::UnsupportedMediaType("unsupported content encoding");
+
+    decompress::encodings(headers.get(header::CONTENT_ENCODING)).map_err(|_| UNSUPPORTED_ENCODING)
+}
 
+/// A request body as sent, and decompressed when it had a `Content-Encoding`.
+struct Received {
+    raw: Vec<u8>,
+    decoded: Option<Vec<u8>>,
+    /// a body too large to hold in memory, written to disk as it was read; `raw` is empty
+    spilled: Option<SpillFile>,
+    /// each hook's signature check, by its position in the list the body was read for
+    verified: Vec<Result<(), VerifyError>>,
+}
+
+impl Received {
+    /// The payload hooks see.
+    fn body(&self) -> &[u8] {
+        self.decoded.as_deref().unwrap_or(&self.raw)
+    }
+}
+
+/// Decompress a body sent with a `Content-Encoding`, and check `hooks`' signatures over the
+/// decompressed body, keeping the check over the bytes as sent when that one fails: senders
+/// differ in which one they sign.
+fn decode_body<H: Hook>(
+    encodings: &[Encoding],
+    received: Received,
+    hooks: &[&H],
+    headers: &Headers,
+) -> Result<Received, HttpResponse> {
+    if encodings.is_empty() {
+        return Ok(received);
+    }
+    let mut span = trace::span("decompress body");
+    match decompress::decode(encodings, &received.raw, MAX_BODY_LENGTH as usize) {
+        Ok(decoded) => {
+            span.attr("rook.body.decoded_size", decoded.len().into());
+            let mut checks = Checks::start(hooks, headers);
+            checks.update(&decoded);
+            let verified = checks
+                .finish()
+                .into_iter()
+                .zip(received.verified)
+                .map(|(decoded, raw)| decoded.or_else(|e| raw.map_err(|_| e)))
+                .collect();
+            Ok(Received {
+                decoded: Some(decoded),
+                verified,
+                ..received
+            })
+        }
+        Err(e) => {
+            debug!("failed to decompress body: {:?}", e);
+            span.error();
+            Err(match e {
+                DecodeError::TooLarge => BODY_TOO_LARGE,
+                _ => BODY_MALFORMED,
+            })
+        }
+    }
+}
+
+async fn parse_body(body: Body, timeout: Duration) -> Result<Vec<u8>, HttpResponse> {
+    let mut span = trace::span("read body");
     // avoid a mutable ref to the req object.  compare to:
     //   let bytes = body::to_bytes(req.body_mut()).await?.to_vec();
-    body.try_fold(Vec::new(), |mut data, chunk| async move {
+    let read = body.try_fold(Vec::new(), |mut data, chunk| async move {
         data.extend_from_slice(&chunk);
         Ok(data)
+    });
+    let body = tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| BODY_TIMEOUT)
+        .and_then(|read| read.map_err(|_| BODY_READ_FAILED));
+    match &body {
+        Ok(body) => span.attr("http.request.body.size", body.len().into()),
+        Err(_) => span.error(),
+    };
+    body
+}
+
+/// Read the body of a delivery for `hooks`, updating each one's signature check with every
+/// chunk as it arrives rather than hashing the whole body again per hook once it's read.  With
+/// `spill`, the body is written to a new file instead of held in memory.
+async fn read_body<H: Hook>(
+    mut body: Body,
+    hooks: &[&H],
+    headers: &Headers,
+    timeout: Duration,
+    spill: Option<&Spill>,
+) -> Result<Received, HttpResponse> {
+    let mut span = trace::span(if spill.is_some() {
+        "spill body"
+    } else {
+        "read body"
+    });
+    let failed = |e: std::io::Error| {
+        log::warn!("failed to spill body: {}", e);
+        SERVER_ERR
+    };
+    let mut checks = Checks::start(hooks, headers);
+    let mut spilled = match spill {
+        Some(spill) => Some(spill.create().await.map_err(failed)?),
+        None => None,
+    };
+    let mut raw = vec![];
+    let read = async {
+        let mut size = 0;
+        while let Some(chunk) = body.try_next().await.map_err(|_| BODY_READ_FAILED)? {
+            checks.update(&chunk);
+            match &mut spilled {
+                Some(file) => file.write(&chunk).await.map_err(failed)?,
+                None => raw.extend_from_slice(&chunk),
+            }
+            size += chunk.len();
+        }
+        if let Some(file) = &mut spilled {
+            file.flush().await.map_err(failed)?;
+        }
+        Ok(size)
+    };
+    let size = tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| BODY_TIMEOUT)
+        .and_then(|read| read)
+        .inspect_err(|_| {
+            span.error();
+        })?;
+    span.attr("http.request.body.size", size.into());
+    if let Some(file) = &spilled {
+        debug!("spilled {}b to {}", size, file.path().display());
+    }
+    Ok(Received {
+        raw,
+        decoded: None,
+        spilled,
+        verified: checks.finish(),
     })
-    .await
-    .map_err(|_| BODY_READ_FAILED)
+}
+
+/// Signature checks for the hooks a body is read for, fed the body a chunk at a time.  Hooks
+/// whose verifiers make the same check, such as hooks sharing a secret, share one, so each
+/// distinct secret's MAC is computed once per request.
+struct Checks<'a> {
+    checks: Vec<Box<dyn ChunkedVerify + 'a>>,
+    /// the check each hook uses, by its position
+    shared: Vec<usize>,
+}
+
+impl<'a> Checks<'a> {
+    fn start<H: Hook>(hooks: &[&'a H], headers: &'a Headers) -> Self {
+        let mut keys: Vec<Option<Vec<u8>>> = vec![];
+        let mut checks = vec![];
+        let mut shared = vec![];
+        for hook in hooks {
+            let key = hook.verifier().check_key();
+            let same = key
+                .as_ref()
+                .and_then(|k| keys.iter().position(|o| o.as_ref() == Some(k)));
+            shared.push(same.unwrap_or_else(|| {
+                checks.push(hook.verifier().verify_chunked(headers));
+                keys.push(key);
+                checks.len() - 1
+            }));
+        }
+        Checks { checks, shared }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        for check in &mut self.checks {
+            check.update(chunk);
+        }
+    }
+
+    /// Each hook's result, by its position.
+    fn finish(self) -> Vec<Result<(), VerifyError>> {
+        let results: Vec<_> = self
+            .checks
+            .into_iter()
+            .map(|check| {
+                let mut span = trace::span("verify signature");
+                check.finish().inspect_err(|_| {
+                    span.error();
+                })
+            })
+            .collect();
+        self.shared.into_iter().map(|i| results[i]).collect()
+    }
 }
 
 async fn exec_gh_hooks(
-    hooks: &[GithubHook],
+    route: &Route<'_>,
+    hooks: &[&GithubHook],
     headers: &Headers,
-    body: &[u8],
+    received: &Received,
+    peer: &Peer,
+    request_id: Option<&str>,
+    dispatch: &mut Dispatch,
 ) -> Result<(), HttpResponse> {
-    const GH_DIGEST_HEADER: &'static str = "x-hub-signature-256";
     struct State {
-        m: usize, // matching hooks
-        v: usize, // verified hmac
-        s: usize, // started cmd
+        m: usize,            // matching hooks
+        v: usize,            // verified hmac
+        s: usize,            // started cmd
+        f: usize,            // filtered out
+        l: Option<Duration>, // shortest wait of the rate limited
     }
 
-    let payload: GithubPayload = serde_json::from_slice(body).map_err(|_| BODY_MALFORMED)?;
+    let body = received.body();
+    let github: GithubPayload = {
+        let mut span = trace::span("parse payload");
+        serde_json::from_slice(body).map_err(|_| {
+            span.error();
+            BODY_MALFORMED
+        })?
+    };
     debug!(
         "github payload: ({}, {}, {})",
-        payload.repo.full_name,
-        payload.commit,
-        payload.reference
+        github.repo.full_name, github.commit, github.reference
     );
-    let hmac_claim = extract_hmac(headers, GH_DIGEST_HEADER, DIGEST_PREFIX)?;
-    let mut state = State { m: 0, v: 0, s: 0 };
-    for hook in hooks.iter().filter(|h| h.repo == payload.repo.full_name) {
+    let event = headers
+        .get(github::EVENT_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let payload = Payload::Github(Box::new(
+        Event::parse(event, body).map_err(|_| BODY_MALFORMED)?,
+    ));
+    let delivery = Delivery {
+        request_id,
+        client: peer.ip,
+        url: route.url,
+        headers,
+        body: &received.raw,
+    };
+    let mut archived = vec![];
+    let mut rejected = None;
+    let mut state = State {
+        m: 0,
+        v: 0,
+        s: 0,
+        f: 0,
+        l: None,
+    };
+    let repo = github.repo.full_name.as_str();
+    for (i, hook) in hooks
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| h.matches_repo(repo))
+    {
         debug!("matched repo {}", hook.repo);
         state.m += 1;
 
-        if let Ok(_) = check_hmac(&hook.secret, body, &hmac_claim) {
-            state.v += 1;
-        } else {
+        match received.verified[i] {
+            Ok(()) => state.v += 1,
+            Err(e) => {
+                rejected.get_or_insert(e);
+                continue;
+            }
+        }
+        if !hook.matches_ref(&github.reference) {
+            debug!("ref {} filtered out", github.reference);
+            state.f += 1;
             continue;
         }
+        archive(&mut archived, hook.options(), &delivery);
 
-        if run_forked(|| {
-            Command::new(&hook.command)
-                .stdin(Stdio::null())
-                .stdout(
-                    if cfg!(debug_assertions) {
-                        Stdio::inherit()
-                    } else {
-                        Stdio::null()
-                })
-                .stderr(
-                    if cfg!(debug_assertions) {
-                        Stdio::inherit()
-                    } else {
-                        Stdio::null()
-                })
-                // https://security.stackexchange.com/a/14009
-                .env("GITHUB_REPO", &payload.repo.full_name)
-                .env("GITHUB_COMMIT", &payload.commit)
-                .env("GITHUB_REF", &payload.reference)
-                .spawn()
-        }) {
-            state.s += 1;
+        let mut span = trace::span("exec hook");
+        span.attr("rook.command", hook.command.as_str().into());
+        let traceparent = span.traceparent();
+        let mut env = vec![
+            ("GITHUB_REPO".to_string(), github.repo.full_name.clone()),
+            ("GITHUB_COMMIT".to_string(), github.commit.clone()),
+            ("GITHUB_REF".to_string(), github.reference.clone()),
+        ];
+        match (github.branch(), github.tag_name()) {
+            (Some(branch), _) => env.push(("GITHUB_BRANCH".to_string(), branch.to_string())),
+            (_, Some(tag)) => env.push(("GITHUB_TAG_NAME".to_string(), tag.to_string())),
+            _ => {}
+        }
+        env.extend(route.env());
+        env.extend(header_env(&hook.options, headers));
+        env.extend(common_env(peer, request_id, traceparent));
+        let invocation = Invocation {
+            route: route.url,
+            command: &hook.command,
+            request_id,
+            payload: &payload,
+            body,
+            env,
+            input_file: None,
+            args: vec![],
+        };
+        match start_hook(*hook, invocation, &mut span) {
+            Started::Yes => state.s += 1,
+            Started::Filtered => state.f += 1,
+            Started::Limited(wait) => state.l = Some(state.l.map_or(wait, |l| l.min(wait))),
+            Started::Failed => {}
         }
     }
-    match state {
+    dispatch.verified = state.v;
+    dispatch.started = state.s;
+    match (state, rejected) {
         // no hooks listening for this event's repo
-        State { m: 0, v: _v, s: _s } => Err(BAD_ROUTE),
+        (State { m: 0, .. }, _) => Err(BAD_ROUTE),
         // some listening but every signature check failed
-        State { m: _m, v: 0, s: _s } => Err(SIGNATURE_MISMATCH),
+        (State { v: 0, .. }, Some(e)) => {
+            let rejected = hooks.iter().filter(|h| h.matches_repo(repo));
+            alert(
+                rejected.map(|h| h.options()),
+                peer.ip,
+                route.url,
+                e.reason(),
+            );
+            Err(rejection(e, dispatch))
+        }
+        // nothing started because every verified hook is over its rate limit
+        (
+            State {
+                s: 0,
+                f: 0,
+                l: Some(wait),
+                ..
+            },
+            _,
+        ) => Err(HttpResponse::TooManyRequests(wait.as_secs() + 1)),
         // some signature checks passed but we failed to start any processes
-        State { m: _m, v: _v, s: 0 } => Err(SERVER_ERR),
-        // some processes started
+        (State { s: 0, f: 0, .. }, _) => Err(SERVER_ERR),
+        // some processes started, or filters turned the delivery away
         _ => Ok(()),
     }
 }
 
 async fn exec_rook_hooks(
-    hooks: &[RookHook],
+    route: &Route<'_>,
+    hooks: &[&RookHook],
     headers: &Headers,
-    body: &[u8],
+    received: &Received,
+    peer: &Peer,
+    request_id: Option<&str>,
+    dispatch: &mut Dispatch,
 ) -> Result<(), HttpResponse> {
-    const ROOK_DIGEST_HEADER: &'static str = "x-rook-signature-256";
     struct State {
-        v: usize, // verified hmac
-        s: usize, // started cmd
+        v: usize,            // verified hmac
+        s: usize,            // started cmd
+        f: usize,            // filtered out
+        l: Option<Duration>, // shortest wait of the rate limited
     }
 
-    let body_string = str::from_utf8(body).map_err(|_| BODY_MALFORMED)?.trim();
+    let body = received.body();
+    // hooks on a url are all binary or all aren't
+    let binary = hooks.first().is_some_and(|h| h.binary);
+    // commands read a spilled or binary body from ROOK_INPUT_FILE, whatever it holds
+    let body_string = match &received.spilled {
+        None if !binary => str::from_utf8(body).map_err(|_| BODY_MALFORMED)?.trim(),
+        _ => "",
+    };
+    // a binary body held in memory is written out once, and removed when the request is answered
+    let verified = received.verified.iter().any(Result::is_ok);
+    let written = match &received.spilled {
+        None if binary && verified => Some(spill::to_file(body).await.map_err(|e| {
+            log::warn!("failed to write the body for {}: {}", route.url, e);
+            SERVER_ERR
+        })?),
+        _ => None,
+    };
+    let input_file = received
+        .spilled
+        .as_ref()
+        .or(written.as_ref())
+        .map(SpillFile::path);
     debug!("rook payload ({}b): {:?}", body_string.len(), body_string);
-    let hmac_claim = extract_hmac(headers, ROOK_DIGEST_HEADER, DIGEST_PREFIX)?;
-    let mut state = State { v: 0, s: 0 };
-    for hook in hooks {
-        if let Ok(_) = check_hmac(&hook.secret, body, &hmac_claim) {
-            state.v += 1;
-        } else {
-            continue;
+    // a json body is parsed once, and turned away before any hook starts
+    let wants_json = hooks
+        .iter()
+        .zip(&received.verified)
+        .any(|(hook, verified)| hook.json.is_some() && verified.is_ok());
+    let json = match wants_json {
+        true => Some(serde_json::from_str(body_string).map_err(|_| BODY_MALFORMED)?),
+        false => None,
+    };
+    let payload = Payload::Rook(body_string.to_string());
+    let delivery = Delivery {
+        request_id,
+        client: peer.ip,
+        url: route.url,
+        headers,
+        body: &received.raw,
+    };
+    let mut archived = vec![];
+    let mut rejected = None;
+    let mut state = State {
+        v: 0,
+        s: 0,
+        f: 0,
+        l: None,
+    };
+    for (i, hook) in hooks.iter().enumerate() {
+        match received.verified[i] {
+            Ok(()) => state.v += 1,
+            Err(e) => {
+                rejected.get_or_insert(e);
+                continue;
+            }
         }
+        archive(&mut archived, hook.options(), &delivery);
 
-        if run_forked(|| {
-            Command::new(&hook.command)
-                .stdin(Stdio::null())
-                .stdout(
-                    if cfg!(debug_assertions) {
-                        Stdio::inherit()
-                    } else {
-                        Stdio::null()
-                })
-                .stderr(
-                    if cfg!(debug_assertions) {
-                        Stdio::inherit()
-                    } else {
-                        Stdio::null()
-                })
-                .env("ROOK_INPUT", body_string)
-                .spawn()
-        }) {
-            state.s += 1;
+        let mut span = trace::span("exec hook");
+        span.attr("rook.command", hook.command.as_str().into());
+        let traceparent = span.traceparent();
+        let mut env = vec![("ROOK_INPUT".to_string(), body_string.to_string())];
+        env.extend(route.env());
+        env.extend(header_env(&hook.options, headers));
+        if let (Some(fields), Some(json)) = (&hook.json, &json) {
+            env.extend(json_env(fields, json));
+        }
+        env.extend(common_env(peer, request_id, traceparent));
+        let invocation = Invocation {
+            route: route.url,
+            command: &hook.command,
+            request_id,
+            payload: &payload,
+            body,
+            env,
+            input_file,
+            args: vec![],
+        };
+        match start_hook(*hook, invocation, &mut span) {
+            Started::Yes => state.s += 1,
+            Started::Filtered => state.f += 1,
+            Started::Limited(wait) => state.l = Some(state.l.map_or(wait, |l| l.min(wait))),
+            Started::Failed => {}
         }
     }
-    match state {
+    dispatch.verified = state.v;
+    dispatch.started = state.s;
+    match (state, rejected) {
         // every signature check failed
-        State { v: 0, s: _s } => Err(SIGNATURE_MISMATCH),
+        (State { v: 0, .. }, Some(e)) => {
+            alert(
+                hooks.iter().map(|h| h.options()),
+                peer.ip,
+                route.url,
+                e.reason(),
+            );
+            Err(rejection(e, dispatch))
+        }
+        // nothing started because every verified hook is over its rate limit
+        (
+            State {
+                s: 0,
+                f: 0,
+                l: Some(wait),
+                ..
+            },
+            _,
+        ) => Err(HttpResponse::TooManyRequests(wait.as_secs() + 1)),
         // some signature checks passed but we failed to start any processes
-        State { v: _v, s: 0 } => Err(SERVER_ERR),
-        // some processes started
+        (State { s: 0, f: 0, .. }, _) => Err(SERVER_ERR),
+        // some processes started, or filters turned the delivery away
         _ => Ok(()),
     }
 }
 
-fn extract_hmac(
-    headers: &Headers,
-    name: &'static str,
-    prefix: &'static str,
-) -> Result<Vec<u8>, HttpResponse> {
-    let header: String = get_header(headers, name)?;
-    if !header.starts_with(prefix) {
-        return Err(HEADER_MALFORMED);
-    }
-    if (header.len() - prefix.len()) % 2 != 0 {
-        return Err(HEADER_MALFORMED);
-    }
-    (prefix.len()..header.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&header[i..i + 2], 16).map_err(|_| HEADER_MALFORMED))
+/// The value at each of a `format = "json"` hook's pointers: strings as they are, other values
+/// as compact json, and nothing for nulls and missing values.
+fn json_env(fields: &[(String, String)], json: &serde_json::Value) -> Vec<(String, String)> {
+    fields
+        .iter()
+        .filter_map(|(name, pointer)| match json.pointer(pointer)? {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some((name.clone(), s.clone())),
+            value => Some((name.clone(), value.to_string())),
+        })
         .collect()
 }
 
-fn check_hmac(secret: &[u8], body: &[u8], signature: &[u8]) -> Result<(), HttpResponse> {
-    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("error initializing hmac");
-    mac.update(body);
-    match mac.verify_slice(signature) {
-        Ok(_) => {
-            debug!("hmac check success");
-            Ok(())
-        }
-        Err(_) => {
-            debug!("hmac check failed");
-            Err(SIGNATURE_MISMATCH)
-        }
-    }
-}
-
-/// be **very** careful that the forked function does not panic.
-///
-/// no logging on any failure, just one shot to run in a forked process
-/// process is detached with setsid after fork
-fn run_forked<F, T>(f: F) -> bool
-where
-    F: Fn() -> std::io::Result<T>,
-{
-    match fork::fork() {
-        Ok(Fork::Parent(_)) => {
-            // we're in the parent process
-            debug!("hook forked");
-            return true;
-        }
-        Ok(Fork::Child) => {
-            // we're in the child process
-            if fork::setsid().is_err() {
-                // if we can't change our session id, don't try to start.
-                process::exit(0)
+/// Run the `"mqtt"` hooks subscribed to `topic` for a message the broker at `broker` delivered.
+/// A signed message's first line is its signature, as [`ROOK_SIGNATURE_HEADER`] would carry it
+/// over http, and the rest is the body.
+#[cfg(feature = "mqtt")]
+pub(crate) fn mqtt_message(cfg: &RouteConfig, broker: IpAddr, topic: &str, message: &[u8]) {
+    let request_id = logging::current_request_id();
+    let (mut matched, mut verified, mut started) = (0, 0, 0);
+    let (mut rejected, mut reason) = (vec![], "");
+    for hook in cfg
+        .mqtt_hooks
+        .iter()
+        .filter(|h| topic::matches(&h.topic, topic))
+    {
+        matched += 1;
+        let mut headers = Headers::new();
+        let body = match message.iter().position(|b| *b == b'\n') {
+            Some(end) if hook.signed => {
+                let line = message[..end]
+                    .strip_suffix(b"\r")
+                    .unwrap_or(&message[..end]);
+                if let Ok(value) = HeaderValue::from_bytes(line) {
+                    headers.insert(ROOK_SIGNATURE_HEADER, value);
+                }
+                &message[end + 1..]
             }
-            // discard the result since we're always exiting the child thread
-            let _unused: Result<T, _> = f();
-            process::exit(0);
+            // the signature check fails for want of a header
+            None if hook.signed => &[],
+            _ => message,
+        };
+        if let Err(e) = verify(hook, &headers, body) {
+            logging::audit(broker, topic, e.reason());
+            METRICS.signature_failure(e.reason());
+            rejected.push(hook.options());
+            reason = e.reason();
+            continue;
+        }
+        verified += 1;
+        let Ok(body_string) = str::from_utf8(body).map(str::trim) else {
+            log::warn!("mqtt message isn't utf-8: topic={}", topic);
+            continue;
+        };
+        let mut span = trace::span("exec hook");
+        span.attr("rook.command", hook.command.as_str().into());
+        let traceparent = span.traceparent();
+        let mut env = vec![
+            ("ROOK_INPUT".to_string(), body_string.to_string()),
+            ("ROOK_MQTT_TOPIC".to_string(), topic.to_string()),
+        ];
+        let peer = Peer {
+            ip: broker,
+            client_cn: None,
+        };
+        env.extend(common_env(&peer, request_id.as_deref(), traceparent));
+        let payload = Payload::Mqtt(body_string.to_string());
+        let invocation = Invocation {
+            route: &hook.topic,
+            command: &hook.command,
+            request_id: request_id.as_deref(),
+            payload: &payload,
+            body,
+            env,
+            input_file: None,
+            args: vec![],
+        };
+        if let Started::Yes = start_hook(hook, invocation, &mut span) {
+            started += 1;
         }
-        Err(_) => {
-            // failed to fork
-            debug!("failed to fork");
-            return false;
+    }
+    if verified == 0 {
+        alert(rejected, broker, topic, reason);
+    }
+    log::info!(
+        "mqtt: topic={} hooks={} verified={} started={}",
+        topic,
+        matched,
+        verified,
+        started
+    );
+}
+
+/// Run the `"watch"` hooks whose pattern matches `path`, a file that just appeared.
+#[cfg(feature = "watch")]
+pub(crate) fn watched_file(cfg: &RouteConfig, path: &str) {
+    let request_id = logging::current_request_id();
+    let payload = Payload::Watch(path.to_string());
+    let (mut matched, mut started) = (0, 0);
+    for hook in cfg
+        .watch_hooks
+        .iter()
+        .filter(|h| glob::matches(&h.watch, path))
+    {
+        matched += 1;
+        let mut span = trace::span("exec hook");
+        span.attr("rook.command", hook.command.as_str().into());
+        let traceparent = span.traceparent();
+        let mut env = vec![("ROOK_WATCH_FILE".to_string(), path.to_string())];
+        env.extend(
+            request_id
+                .clone()
+                .map(|id| ("ROOK_REQUEST_ID".to_string(), id)),
+        );
+        env.extend(traceparent.map(|tp| ("TRACEPARENT".to_string(), tp)));
+        let invocation = Invocation {
+            route: &hook.watch,
+            command: &hook.command,
+            request_id: request_id.as_deref(),
+            payload: &payload,
+            body: path.as_bytes(),
+            env,
+            input_file: None,
+            args: vec![],
+        };
+        if let Started::Yes = start_hook(hook, invocation, &mut span) {
+            started += 1;
         }
     }
+    if matched > 0 {
+        log::info!("watch: file={} hooks={} started={}", path, matched, started);
+    }
+}
+
+/// `ROOK_HEADER_<NAME>` for each of the hook's `pass_headers` the request has, with `-`s as
+/// `_`s.  Repeated headers are joined with `, `, and values that aren't text are left out.
+fn header_env(options: &HookOptions, headers: &Headers) -> Vec<(String, String)> {
+    options
+        .pass_headers
+        .iter()
+        .filter_map(|name| {
+            let values: Option<Vec<&str>> = headers
+                .get_all(name.as_str())
+                .iter()
+                .map(|v| v.to_str().ok())
+                .collect();
+            let values = values.filter(|v| !v.is_empty())?;
+            let name = format!(
+                "ROOK_HEADER_{}",
+                name.to_ascii_uppercase().replace('-', "_")
+            );
+            Some((name, values.join(", ")))
+        })
+        .collect()
+}
+
+/// Hook data both hook types pass along.
+fn common_env(
+    peer: &Peer,
+    request_id: Option<&str>,
+    traceparent: Option<String>,
+) -> Vec<(String, String)> {
+    let mut env = vec![];
+    env.extend(
+        peer.client_cn
+            .clone()
+            .map(|cn| ("ROOK_CLIENT_CN".to_string(), cn)),
+    );
+    env.extend(request_id.map(|id| ("ROOK_REQUEST_ID".to_string(), id.to_string())));
+    env.extend(traceparent.map(|tp| ("TRACEPARENT".to_string(), tp)));
+    env
+}
+
+/// Check the delivery's signature with `hook`'s verifier.
+#[cfg(feature = "mqtt")]
+fn verify<H: Hook>(hook: &H, headers: &Headers, body: &[u8]) -> Result<(), VerifyError> {
+    let mut span = trace::span("verify signature");
+    hook.verifier().verify(headers, body).inspect_err(|_| {
+        span.error();
+    })
+}
+
+/// What became of a verified hook.
+enum Started {
+    Yes,
+    /// a filter turned the delivery away
+    Filtered,
+    /// over the hook's `rate_limit`; it has another start after the wait
+    Limited(Duration),
+    Failed,
+}
+
+/// Run `hook`'s filters, then hand the delivery to its executor.
+fn start_hook<H: Hook>(hook: &H, mut invocation: Invocation, span: &mut SpanGuard) -> Started {
+    for filter in &hook.options().filters {
+        match filter.filter(&invocation) {
+            Ok(verdict) if verdict.allow => {
+                filter::extend_env(&mut invocation.env, verdict.env);
+                invocation.args.extend(verdict.args);
+            }
+            Ok(_) => {
+                debug!("hook filtered out");
+                return Started::Filtered;
+            }
+            Err(e) => {
+                debug!("hook filter failed: {}", e);
+                span.error();
+                return Started::Failed;
+            }
+        }
+    }
+    if let Some(limit) = &hook.options().rate_limit {
+        if let Err(wait) = limit.acquire() {
+            log::warn!(
+                "rate limited: path={} command={} limit={}/min",
+                invocation.route,
+                invocation.command,
+                limit.limit()
+            );
+            METRICS.rate_limited();
+            return Started::Limited(wait);
+        }
+    }
+    let starting = Instant::now();
+    match hook.executor().execute(&invocation) {
+        Ok(()) => {
+            METRICS.hook_started(starting.elapsed());
+            if let Some(description) = &hook.options().description {
+                logging::info!(
+                    "started {} for {}: {}",
+                    invocation.command,
+                    invocation.route,
+                    description
+                );
+            }
+            sentry::hook_started(invocation.command);
+            if let Some(forward) = &hook.options().forward {
+                forward.deliver(&invocation);
+            }
+            Started::Yes
+        }
+        Err(e) => {
+            debug!("hook failed to start: {}", e);
+            METRICS.hook_start_failed();
+            sentry::hook_failed(invocation.command, &e);
+            span.error();
+            Started::Failed
+        }
+    }
+}
+
+/// Copy a verified delivery to its hook's `archive_dir`, unless a hook before it on the url
+/// already wrote it there.
+fn archive<'a>(
+    archived: &mut Vec<&'a Arc<Archive>>,
+    options: &'a HookOptions,
+    delivery: &Delivery,
+) {
+    if let Some(archive) = &options.archive {
+        if !archived.iter().any(|a| a.dir() == archive.dir()) {
+            archive.store(delivery);
+            archived.push(archive);
+        }
+    }
+}
+
+/// Tell the alerts of hooks that all rejected a delivery's signature, each alert once.
+fn alert<'a>(
+    rejected: impl IntoIterator<Item = &'a HookOptions>,
+    client: IpAddr,
+    path: &str,
+    reason: &str,
+) {
+    let mut told: Vec<&Arc<dyn Alert>> = vec![];
+    for alert in rejected.into_iter().flat_map(|options| &options.alerts) {
+        if !told.iter().any(|t| Arc::ptr_eq(t, alert)) {
+            alert.signature_failure(client, path, reason);
+            told.push(alert);
+        }
+    }
+}
+
+/// The response for a signature every hook rejected, noting why for the audit log.
+/// When hooks disagree the first hook's reason is used.
+fn rejection(e: VerifyError, dispatch: &mut Dispatch) -> HttpResponse {
+    dispatch.rejection = Some(e.reason());
+    match e {
+        VerifyError::Missing => SIGNATURE_MISSING,
+        VerifyError::Malformed => HEADER_MALFORMED,
+        VerifyError::Mismatch => SIGNATURE_MISMATCH,
+        VerifyError::Unstamped => TIMESTAMP_MISSING,
+        VerifyError::Stale => TIMESTAMP_STALE,
+        VerifyError::Replayed => NONCE_REPLAYED,
+        VerifyError::Unsupported => ALGORITHM_UNSUPPORTED,
+    }
 }
 
-const DIGEST_PREFIX: &'static str = "sha256=";
 const SERVER_ERR: HttpResponse = HttpResponse::ServerError;
-const BAD_ROUTE: HttpResponse = HttpResponse::BadRequest("bad route");
-const SIGNATURE_MISMATCH: HttpResponse = HttpResponse::BadRequest("signature mismatch");
+const BAD_ROUTE: HttpResponse = HttpResponse::NotFound("bad route");
+const FORBIDDEN: HttpResponse = HttpResponse::Forbidden("forbidden");
+const SIGNATURE_MISSING: HttpResponse = HttpResponse::Unauthorized("missing signature");
+const SIGNATURE_MISMATCH: HttpResponse = HttpResponse::Forbidden("signature mismatch");
+const TIMESTAMP_MISSING: HttpResponse = HttpResponse::Unauthorized("missing timestamp");
+const TIMESTAMP_STALE: HttpResponse = HttpResponse::Forbidden("stale timestamp");
+const NONCE_REPLAYED: HttpResponse = HttpResponse::Forbidden("replayed nonce");
+const ALGORITHM_UNSUPPORTED: HttpResponse = HttpResponse::BadRequest("unsupported algorithm");
 const HEADER_MALFORMED: HttpResponse = HttpResponse::BadRequest("malformed header");
 const BODY_MALFORMED: HttpResponse = HttpResponse::BadRequest("malformed body");
+const BODY_TOO_LARGE: HttpResponse = HttpResponse::PayloadTooLarge("body too large");
+const BODY_READ_FAILED: HttpResponse = HttpResponse::BadRequest("body read error");
+const BODY_TIMEOUT: HttpResponse = HttpResponse::RequestTimeout("body read timed out");
+const DELIVERY_HELD: HttpResponse = HttpResponse::Accepted("held until resumed");
+const DELIVERY_WAITING: HttpResponse = HttpResponse::Accepted("held until allowed hours");
+const OUTSIDE_HOURS: HttpResponse = HttpResponse::Unavailable("outside allowed hours");
+/// 2 MiB is enough for anyone, before or after decompressing, unless the hook spills to disk
+pub(crate) const MAX_BODY_LENGTH: u64 = 1 << 21;
 
-impl From<HttpResponse> for Response<Body> {
-    fn from(error: HttpResponse) -> Self {
-        let (status, body) = match error {
+impl HttpResponse {
+    fn status(&self) -> (StatusCode, &'static str) {
+        match self {
             HttpResponse::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            HttpResponse::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            HttpResponse::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            HttpResponse::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            HttpResponse::MethodNotAllowed(_) => {
+                (StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            HttpResponse::RequestTimeout(msg) => (StatusCode::REQUEST_TIMEOUT, msg),
+            HttpResponse::LengthRequired(msg) => (StatusCode::LENGTH_REQUIRED, msg),
+            HttpResponse::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
+            HttpResponse::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            HttpResponse::TooManyRequests(_) => (StatusCode::TOO_MANY_REQUESTS, "rate limited"),
             HttpResponse::ServerError => (StatusCode::INTERNAL_SERVER_ERROR, ""),
+            HttpResponse::ServiceUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "overloaded"),
+            HttpResponse::Paused(_) => (StatusCode::SERVICE_UNAVAILABLE, "paused"),
+            HttpResponse::Unavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            HttpResponse::Accepted(msg) => (StatusCode::ACCEPTED, msg),
             HttpResponse::Ok(msg) => (StatusCode::OK, msg),
-        };
-        Response::builder()
-            .status(status)
-            .body(body.into())
-            .expect("error building body")
+        }
+    }
+
+    /// The body text, or a description when the body is empty.
+    fn reason(&self) -> &'static str {
+        match self {
+            HttpResponse::ServerError => "internal error",
+            HttpResponse::Ok(_) => "ok",
+            other => other.status().1,
+        }
+    }
+}
+
+impl From<HttpResponse> for Response<Body> {
+    fn from(error: HttpResponse) -> Self {
+        let (status, body) = error.status();
+        let mut builder = Response::builder().status(status);
+        if let HttpResponse::MethodNotAllowed(allow) = &error {
+            builder = builder.header(header::ALLOW, allow.as_str());
+        }
+        if let HttpResponse::ServiceUnavailable(secs) = &error {
+            builder = builder.header(header::RETRY_AFTER, *secs);
+        }
+        if let HttpResponse::TooManyRequests(secs) = &error {
+            builder = builder.header(header::RETRY_AFTER, *secs);
+        }
+        if let HttpResponse::Paused(message) = error {
+            return builder.body(message.into()).expect("error building body");
+        }
+        builder.body(body.into()).expect("error building body")
     }
 }
 
 #[derive(Clone)]
 enum HttpResponse {
     BadRequest(&'static str),
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    /// carries the value of the `Allow` header
+    MethodNotAllowed(String),
+    RequestTimeout(&'static str),
+    LengthRequired(&'static str),
+    PayloadTooLarge(&'static str),
+    UnsupportedMediaType(&'static str),
+    /// carries the value of the `Retry-After` header, in seconds
+    TooManyRequests(u64),
     ServerError,
+    /// carries the value of the `Retry-After` header, in seconds
+    ServiceUnavailable(u64),
+    /// carries the body: the paused url's `maintenance.message`
+    Paused(String),
+    Unavailable(&'static str),
+    Accepted(&'static str),
     Ok(&'static str),
 }
 
 impl fmt::Debug for HttpResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match self {
-            HttpResponse::BadRequest(msg) => msg,
-            HttpResponse::ServerError => "internal error",
-            HttpResponse::Ok(_) => "ok",
-        };
-        write!(f, "HttpResponse<{}>", msg)
+        write!(f, "HttpResponse<{}>", self.reason())
     }
 }
-
-#[derive(Deserialize)]
-struct GithubPayload {
-    #[serde(rename = "ref")]
-    reference: String,
-    #[serde(rename = "after")]
-    commit: String,
-    #[serde(rename = "repository")]
-    repo: GithubRepo,
-}
-
-#[derive(Deserialize)]
-struct GithubRepo {
-    full_name: String,
-}
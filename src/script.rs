@@ -0,0 +1,117 @@
+//! [`Filter`]s written in [rhai](https://rhai.rs), from a hook's `filter_script` and
+//! `transform_script`.
+//!
+//! Both scripts see the delivery as variables:
+//!
+//! * `provider`: `"github"` or `"rook"`
+//! * `route`: the hook url
+//! * `event`: the GitHub event name, or `()` for rook hooks
+//! * `payload`: the GitHub json as a map, or the rook payload as a string
+//! * `env`: the variables rook set for the command
+//!
+//! `filter_script` returns whether the hook should run.  `transform_script` returns a map
+//! whose entries become environment variables, except `args`, an array of command arguments.
+//! Scripts are cut off after [`MAX_OPERATIONS`] operations.
+use crate::{
+    exec::{Invocation, Payload},
+    filter::{Filter, Verdict},
+};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use serde_json::Value;
+
+/// operations a script may run per delivery
+pub const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct ScriptFilter {
+    engine: Engine,
+    filter: Option<AST>,
+    transform: Option<AST>,
+}
+
+impl ScriptFilter {
+    /// Compile the scripts; either may be missing.
+    pub fn compile(filter: Option<&str>, transform: Option<&str>) -> Result<ScriptFilter, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(1024 * 1024);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        let filter = filter
+            .map(|s| engine.compile(s))
+            .transpose()
+            .map_err(|e| format!("bad filter_script: {}", e))?;
+        let transform = transform
+            .map(|s| engine.compile(s))
+            .transpose()
+            .map_err(|e| format!("bad transform_script: {}", e))?;
+        Ok(ScriptFilter {
+            engine,
+            filter,
+            transform,
+        })
+    }
+
+    fn scope(invocation: &Invocation) -> Scope<'static> {
+        let (event, payload) = match invocation.payload {
+            Payload::Github(event) => (
+                Dynamic::from(event.name().to_string()),
+                serde_json::from_slice::<Value>(invocation.body)
+                    .ok()
+                    .and_then(|v| rhai::serde::to_dynamic(v).ok())
+                    .unwrap_or(Dynamic::UNIT),
+            ),
+            Payload::Rook(text) | Payload::Mqtt(text) | Payload::Watch(text) => {
+                (Dynamic::UNIT, Dynamic::from(text.clone()))
+            }
+        };
+        let env: Map = invocation
+            .env
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), Dynamic::from(v.clone())))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push_constant("provider", invocation.payload.provider().to_string());
+        scope.push_constant("route", invocation.route.to_string());
+        scope.push_constant("event", event);
+        scope.push_constant("payload", payload);
+        scope.push_constant("env", env);
+        scope
+    }
+}
+
+impl Filter for ScriptFilter {
+    fn filter(&self, invocation: &Invocation) -> Result<Verdict, String> {
+        let mut scope = Self::scope(invocation);
+        if let Some(filter) = &self.filter {
+            let allow: bool = self
+                .engine
+                .eval_ast_with_scope(&mut scope, filter)
+                .map_err(|e| format!("filter_script failed: {}", e))?;
+            if !allow {
+                return Ok(Verdict::default());
+            }
+        }
+        let mut verdict = Verdict {
+            allow: true,
+            ..Verdict::default()
+        };
+        if let Some(transform) = &self.transform {
+            let out: Map = self
+                .engine
+                .eval_ast_with_scope(&mut scope, transform)
+                .map_err(|e| format!("transform_script failed: {}", e))?;
+            for (name, value) in out {
+                if name == "args" {
+                    let args = value
+                        .into_typed_array::<Dynamic>()
+                        .map_err(|_| "transform_script args must be an array".to_string())?;
+                    verdict.args = args.into_iter().map(|a| a.to_string()).collect();
+                } else {
+                    verdict.env.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+        Ok(verdict)
+    }
+}
@@ -0,0 +1,249 @@
+//! `rook selftest`: serve a config on an ephemeral port in dry-run mode, send every hook one
+//! correctly signed synthetic delivery, and report which hooks accepted theirs.
+//!
+//! This checks what the config decides (routing, signatures, methods, content types, filters),
+//! not the network around it: TLS, the PROXY protocol, access lists, and client certificates
+//! are switched off for the test server.
+use crate::cli::{Args, USAGE};
+use hyper::{body, header, Body, Client, Method, Request};
+use rook::{
+    cidr::AccessList,
+    config::{self, HookOptions, RouteConfig},
+    exec::{DryRun, Executor, Invocation},
+    github,
+    render::Text,
+    verify::Verifier,
+};
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+/// Logs what its hook would run, like [`DryRun`], and records that it ran.
+struct Probe {
+    id: usize,
+    ran: Arc<Mutex<HashSet<usize>>>,
+}
+
+impl Executor for Probe {
+    fn execute(&self, invocation: &Invocation) -> Result<(), String> {
+        if let Ok(mut ran) = self.ran.lock() {
+            ran.insert(self.id);
+        }
+        DryRun.execute(invocation)
+    }
+}
+
+/// One hook's synthetic delivery.
+struct Case {
+    id: usize,
+    /// `/hooks/deploy (github me/repo)`
+    name: String,
+    command: String,
+    /// None when the hook's verifier can't sign one
+    request: Option<Request<Body>>,
+}
+
+pub async fn selftest(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["--verbose"], &[])?;
+    let [cfg_path] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let mut cfg = config::from_file(cfg_path).map_err(|e| e.to_string())?;
+    if !args.has("--verbose") {
+        // keep the server's access and dry-run lines out of the report
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    let tcp = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("failed to bind an ephemeral port: {}", e))?;
+    let addr = tcp.local_addr().map_err(|e| e.to_string())?;
+    let ran = Arc::new(Mutex::new(HashSet::new()));
+    let mut cases = prepare(&mut cfg, addr, &ran)?;
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    let server = tokio::spawn(rook::serve_on(Arc::new(cfg), tcp));
+
+    let client = Client::new();
+    let total = cases.len();
+    let mut failed = 0;
+    for case in cases {
+        let result = match case.request {
+            Some(req) => check(&client, req, case.id, &ran).await,
+            None => Err("can't sign a delivery for this hook's verifier".to_string()),
+        };
+        match result {
+            Ok(()) => println!("ok    {}: {}", case.name, case.command),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {}: {}: {}", case.name, case.command, e);
+            }
+        }
+    }
+    server.abort();
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} hooks failed", failed, total)),
+    }
+}
+
+/// Turn `cfg` into the test server's config, and build each hook's delivery to `addr`.
+fn prepare(
+    cfg: &mut RouteConfig,
+    addr: SocketAddr,
+    ran: &Arc<Mutex<HashSet<usize>>>,
+) -> Result<Vec<Case>, String> {
+    cfg.dry_run = true;
+    cfg.tls = None;
+    cfg.proxy_protocol = false;
+    cfg.access = AccessList::default();
+    cfg.admin = None;
+    cfg.history = None;
+    cfg.set_renderer(Text);
+    let base = format!("http://{}{}", addr, cfg.path_prefix);
+    let mut cases = vec![];
+    for (url, hooks) in cfg.gh_hooks.iter_mut() {
+        for hook in hooks {
+            let id = cases.len();
+            let body = push_event(&hook.repo).to_string().into_bytes();
+            let headers = [
+                (github::EVENT_HEADER, "push".to_string()),
+                ("x-github-delivery", format!("selftest-{}", id)),
+            ];
+            cases.push(Case {
+                id,
+                name: format!("{} (github {})", url, hook.repo),
+                command: hook.command.clone(),
+                request: delivery(
+                    &base,
+                    url,
+                    &hook.options,
+                    hook.verifier.as_ref(),
+                    &headers,
+                    body,
+                )?,
+            });
+            open_up(&mut hook.options);
+            hook.executor = Box::new(Probe {
+                id,
+                ran: ran.clone(),
+            });
+        }
+    }
+    for (url, hooks) in cfg.rook_hooks.iter_mut() {
+        for hook in hooks {
+            let id = cases.len();
+            let body = b"rook selftest".to_vec();
+            cases.push(Case {
+                id,
+                name: format!("{} (rook)", url),
+                command: hook.command.clone(),
+                request: delivery(&base, url, &hook.options, hook.verifier.as_ref(), &[], body)?,
+            });
+            open_up(&mut hook.options);
+            hook.executor = Box::new(Probe {
+                id,
+                ran: ran.clone(),
+            });
+        }
+    }
+    Ok(cases)
+}
+
+/// Accept the test client whatever the hook's access list and certificate requirements.
+fn open_up(options: &mut HookOptions) {
+    options.access = AccessList::default();
+    options.require_client_cert = false;
+}
+
+/// A signed request for `url` with a method, content type, and host the hook accepts, plus
+/// `headers`.
+fn delivery(
+    base: &str,
+    url: &str,
+    options: &HookOptions,
+    verifier: &dyn Verifier,
+    headers: &[(&'static str, String)],
+    body: Vec<u8>,
+) -> Result<Option<Request<Body>>, String> {
+    let Some(signature) = verifier.sign(&body) else {
+        return Ok(None);
+    };
+    let method = match options.methods.first() {
+        Some(first) if !options.methods.contains(&Method::POST) => first.clone(),
+        _ => Method::POST,
+    };
+    let mut req = Request::builder()
+        .method(method)
+        .uri(format!("{}{}", base, url))
+        .header(
+            header::CONTENT_TYPE,
+            content_type(&options.content_types, &body),
+        );
+    for (name, value) in signature.iter().chain(headers) {
+        req = req.header(*name, value.as_str());
+    }
+    if let Some(host) = &options.host {
+        req = req.header(header::HOST, host.as_str());
+    }
+    req.body(Body::from(body))
+        .map(Some)
+        .map_err(|e| format!("bad delivery for '{}': {}", url, e))
+}
+
+/// The type rook's own clients send when the hook accepts it, otherwise the first one it does.
+fn content_type(accepted: &[String], body: &[u8]) -> String {
+    let preferred = if body.starts_with(b"{") {
+        "application/json"
+    } else {
+        "text/plain"
+    };
+    let kind = preferred.split('/').next().unwrap_or_default();
+    let accepts = |t: &String| t == preferred || *t == format!("{}/*", kind);
+    match accepted.first() {
+        Some(first) if !accepted.iter().any(accepts) => first.replace('*', "octet-stream"),
+        _ => preferred.to_string(),
+    }
+}
+
+/// The smallest push GitHub could send for `repo`.
+fn push_event(repo: &str) -> serde_json::Value {
+    let name = repo.rsplit('/').next().unwrap_or(repo);
+    json!({
+        "ref": "refs/heads/main",
+        "before": "0".repeat(40),
+        "after": "0".repeat(40),
+        "commits": [],
+        "head_commit": null,
+        "repository": {"id": 0, "full_name": repo, "name": name},
+        "sender": {"id": 0, "login": "rook-selftest"},
+    })
+}
+
+/// Send `req` and check the hook with `id` ran.
+async fn check(
+    client: &Client<hyper::client::HttpConnector>,
+    req: Request<Body>,
+    id: usize,
+    ran: &Mutex<HashSet<usize>>,
+) -> Result<(), String> {
+    let res = client
+        .request(req)
+        .await
+        .map_err(|e| format!("request failed: {}", e))?;
+    let status = res.status();
+    let body = body::to_bytes(res.into_body()).await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!(
+            "{} {}",
+            status.as_u16(),
+            String::from_utf8_lossy(&body).trim()
+        ));
+    }
+    match ran.lock() {
+        Ok(ran) if ran.contains(&id) => Ok(()),
+        _ => Err("accepted, but the hook didn't run (filtered out?)".to_string()),
+    }
+}
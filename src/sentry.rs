@@ -0,0 +1,253 @@
+use crate::{config::SentryConfig, id, logging};
+use hyper::{header, Body, Client, Method, Request, Uri};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fs, panic,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::mpsc;
+
+/// events waiting to be sent; more are dropped while Sentry is this far behind
+const SEND_QUEUE: usize = 64;
+/// how long a panicking process waits for its report before aborting
+const PANIC_SEND_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[cfg(feature = "tls")]
+type Connector = crate::tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = hyper::client::HttpConnector;
+
+static SENTRY: OnceLock<Sentry> = OnceLock::new();
+/// consecutive start failures per hook command, reset when the hook starts again
+static HOOK_FAILURES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+struct Sentry {
+    envelope_uri: Uri,
+    auth: String,
+    dsn: String,
+    environment: Option<String>,
+    server_name: String,
+    connector: Connector,
+    client: Client<Connector>,
+    tx: mpsc::Sender<Value>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Error,
+    Fatal,
+}
+
+/// Report problems to the Sentry project in `cfg.dsn` and install a panic hook that reports
+/// panics before the process aborts.
+pub fn init(cfg: &SentryConfig) -> Result<(), String> {
+    let dsn: Uri = cfg
+        .dsn
+        .parse()
+        .map_err(|e| format!("bad sentry dsn '{}': {}", cfg.dsn, e))?;
+    let bad_dsn = || format!("bad sentry dsn '{}'", cfg.dsn);
+    let authority = dsn.authority().ok_or_else(bad_dsn)?;
+    let (key, host) = authority.as_str().split_once('@').ok_or_else(bad_dsn)?;
+    // the public key; the deprecated `key:secret` form is still accepted
+    let key = key.split(':').next().unwrap_or_default();
+    let (prefix, project) = dsn.path().rsplit_once('/').ok_or_else(bad_dsn)?;
+    if project.is_empty() {
+        return Err(bad_dsn());
+    }
+    let envelope_uri: Uri = format!(
+        "{}://{}{}/api/{}/envelope/",
+        dsn.scheme_str().unwrap_or("https"),
+        host,
+        prefix,
+        project
+    )
+    .parse()
+    .map_err(|_| bad_dsn())?;
+    let connector = connector(cfg, &envelope_uri)?;
+    let (tx, rx) = mpsc::channel(SEND_QUEUE);
+    let sentry = Sentry {
+        envelope_uri,
+        auth: format!(
+            "Sentry sentry_version=7, sentry_key={}, sentry_client=rook/{}",
+            key,
+            env!("CARGO_PKG_VERSION")
+        ),
+        dsn: cfg.dsn.clone(),
+        environment: cfg.environment.clone(),
+        server_name: fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|h| h.trim().to_string())
+            .unwrap_or_default(),
+        client: Client::builder().build(connector.clone()),
+        connector,
+        tx,
+    };
+    if SENTRY.set(sentry).is_err() {
+        return Ok(());
+    }
+    tokio::spawn(send_queued(rx));
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => info
+                .payload()
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "panic".to_string()),
+        };
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        report_panic(&message, &location);
+        default_hook(info);
+    }));
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+fn connector(cfg: &SentryConfig, _: &Uri) -> Result<Connector, String> {
+    crate::tls::https_connector(&cfg.ca_file)
+}
+
+#[cfg(not(feature = "tls"))]
+fn connector(_: &SentryConfig, uri: &Uri) -> Result<Connector, String> {
+    if uri.scheme_str() != Some("http") {
+        return Err("https sentry dsns need rook built with the `tls` feature".to_string());
+    }
+    Ok(Connector::new())
+}
+
+/// Report `message` with `tags`.  A no-op unless `[sentry]` is configured.
+pub fn capture(level: Level, message: &str, tags: &[(&str, &str)]) {
+    if let Some(sentry) = SENTRY.get() {
+        let _ = sentry.tx.try_send(sentry.event(level, message, tags));
+    }
+}
+
+/// Report a hook that failed to start.  Repeats are rolled up: the first failure is reported,
+/// then the 10th, 100th, and so on in a row.
+pub fn hook_failed(command: &str, error: &str) {
+    if SENTRY.get().is_none() {
+        return;
+    }
+    let count = match HOOK_FAILURES.lock() {
+        Ok(mut failures) => {
+            let count = failures.entry(command.to_string()).or_default();
+            *count += 1;
+            *count
+        }
+        Err(_) => return,
+    };
+    if count == 10u64.pow(count.ilog10()) {
+        let count = count.to_string();
+        let message = format!("hook failed to start: {}", error);
+        capture(
+            Level::Error,
+            &message,
+            &[("command", command), ("consecutive_failures", &count)],
+        );
+    }
+}
+
+/// A hook started, ending any run of failures.
+pub fn hook_started(command: &str) {
+    if SENTRY.get().is_none() {
+        return;
+    }
+    if let Ok(mut failures) = HOOK_FAILURES.lock() {
+        failures.remove(command);
+    }
+}
+
+/// Panics abort the process, so the report is sent from a thread of its own and waited on.
+fn report_panic(message: &str, location: &str) {
+    let Some(sentry) = SENTRY.get() else {
+        return;
+    };
+    let exception = json!({
+        "values": [{
+            "type": "panic",
+            "value": message,
+            "mechanism": { "type": "panic", "handled": false },
+        }],
+    });
+    let mut event = sentry.event(Level::Fatal, message, &[("location", location)]);
+    event["exception"] = exception;
+    let sending = thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build();
+        if let Ok(runtime) = runtime {
+            runtime.block_on(async {
+                // pooled connections belong to the main runtime, which may be going down with us
+                let client = Client::builder()
+                    .pool_max_idle_per_host(0)
+                    .build(sentry.connector.clone());
+                let sent = sentry.send(&client, event);
+                let _ = tokio::time::timeout(PANIC_SEND_TIMEOUT, sent).await;
+            });
+        }
+    });
+    let _ = sending.join();
+}
+
+async fn send_queued(mut rx: mpsc::Receiver<Value>) {
+    while let Some(event) = rx.recv().await {
+        if let Some(sentry) = SENTRY.get() {
+            sentry.send(&sentry.client, event).await;
+        }
+    }
+}
+
+impl Sentry {
+    fn event(&self, level: Level, message: &str, tags: &[(&str, &str)]) -> Value {
+        let mut tags: serde_json::Map<String, Value> = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), json!(v)))
+            .collect();
+        if let Some(id) = logging::current_request_id() {
+            tags.insert("request_id".to_string(), json!(id));
+        }
+        json!({
+            "event_id": format!("{:016x}{:016x}", id::random_u64(), id::random_u64()),
+            "timestamp": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "platform": "other",
+            "level": match level {
+                Level::Error => "error",
+                Level::Fatal => "fatal",
+            },
+            "logger": "rook",
+            "server_name": self.server_name,
+            "release": concat!("rook@", env!("CARGO_PKG_VERSION")),
+            "environment": self.environment,
+            "message": { "formatted": message },
+            "tags": tags,
+        })
+    }
+
+    async fn send(&self, client: &Client<Connector>, event: Value) {
+        let header = json!({
+            "event_id": event["event_id"],
+            "dsn": self.dsn,
+            "sent_at": event["timestamp"],
+        });
+        let body = format!("{}\n{}\n{}\n", header, json!({ "type": "event" }), event);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.envelope_uri.clone())
+            .header(header::CONTENT_TYPE, "application/x-sentry-envelope")
+            .header("x-sentry-auth", &self.auth)
+            .body(Body::from(body))
+            .expect("error building body");
+        match client.request(req).await {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => debug!("sentry rejected event: {}", res.status()),
+            Err(e) => debug!("sentry send failed: {}", e),
+        }
+    }
+}
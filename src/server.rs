@@ -0,0 +1,246 @@
+use crate::{
+    admin,
+    config::RouteConfig,
+    events, exec, feed, listener, logging, maintenance, remote, sentry,
+    service::{Connection, RookService},
+    statsd, trace,
+    upgrade::{self, Inherited},
+};
+use hyper::{
+    server::{accept, conn::AddrStream},
+    service::{make_service_fn, service_fn},
+    Body, Request, Server,
+};
+use std::{convert::Infallible, os::fd::AsRawFd, sync::Arc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tower_service::Service;
+
+/// how often finished hook commands are collected
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Set up everything process-wide that `cfg` configures: log output, format and level, and
+/// the trace, Sentry, statsd, and hook event sinks.  Call once, before [`serve`].
+///
+/// Process-wide settings can only be set once; later calls keep the first config's sinks.
+pub fn init(cfg: &RouteConfig) -> Result<(), String> {
+    logging::set_output(cfg)?;
+    logging::set_format(cfg.log_format, cfg.log_template.as_ref());
+    logging::configure_level(cfg.log_level.as_ref());
+    crate::config::debug_routes(cfg);
+    crate::config::warn_commands(cfg);
+    if let Some(otlp) = &cfg.otlp {
+        trace::init(otlp)?;
+    }
+    if let Some(sentry) = &cfg.sentry {
+        sentry::init(sentry)?;
+    }
+    if let Some(statsd) = &cfg.statsd {
+        statsd::init(statsd)
+            .map_err(|e| format!("failed to set up statsd '{}': {}", statsd.address, e))?;
+    }
+    if let Some(events) = &cfg.events {
+        events::init(events)
+            .map_err(|e| format!("failed to open events '{}': {}", events.path, e))?;
+    }
+    Ok(())
+}
+
+/// Listen on `cfg.socket` (and `cfg.admin`, when set) and route deliveries to hooks until the
+/// server fails.  Must run inside a tokio runtime.
+///
+/// A config loaded with [`from_file`](crate::config::from_file) or
+/// [`from_url`](crate::config::from_url) is loaded again on SIGHUP and `POST /admin/reload`;
+/// see [`RookService::reload`].  Signed remote configs are also polled for changes.  SIGUSR2
+/// hands the listeners to a new process started with the same arguments, then returns `Ok` once
+/// the requests in flight are answered; see [`upgrade`](crate::upgrade).
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     rook::logging::init_logging();
+///     let cfg = rook::config::from_file("rook.toml")?;
+///     rook::init(&cfg)?;
+///     rook::serve(Arc::new(cfg)).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn serve(cfg: Arc<RouteConfig>) -> Result<(), String> {
+    let mut inherited = upgrade::take()?;
+    let tcp = match inherited.listener.take() {
+        Some(l) => {
+            let addr = l.local_addr().map_err(|e| e.to_string())?;
+            if addr != cfg.socket {
+                return Err(format!(
+                    "the upgraded listener is on {}, not {}; restart rook to move it",
+                    addr, cfg.socket
+                ));
+            }
+            TcpListener::from_std(l).map_err(|e| e.to_string())?
+        }
+        None => match TcpListener::bind(&cfg.socket).await {
+            Ok(l) => l,
+            Err(e) => return Err(format!("failed to bind {}: {}", cfg.socket, e)),
+        },
+    };
+    run(cfg, tcp, inherited).await
+}
+
+/// [`serve`] on a listener that's already bound, such as one on an ephemeral port; `cfg.socket`
+/// is ignored.
+pub async fn serve_on(cfg: Arc<RouteConfig>, tcp: TcpListener) -> Result<(), String> {
+    run(cfg, tcp, Inherited::default()).await
+}
+
+async fn run(
+    cfg: Arc<RouteConfig>,
+    tcp: TcpListener,
+    mut inherited: Inherited,
+) -> Result<(), String> {
+    let rook = RookService::new(cfg.clone())?;
+    let admin_rook = rook.clone();
+    tokio::spawn(maintenance::release(rook.clone()));
+    // commands that fail to start are logged when reaped, so don't wait for a listing
+    tokio::spawn(async {
+        let mut reap = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            reap.tick().await;
+            exec::reap_finished();
+        }
+    });
+    if cfg.remote.is_some() {
+        tokio::spawn(remote::poll(rook.clone()));
+    }
+    if cfg.config_path.is_some() {
+        let mut hangup = signal(SignalKind::hangup()).map_err(|e| e.to_string())?;
+        let rook = rook.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match rook.reload().await {
+                    Ok(_) => logging::info!("reloaded config on SIGHUP"),
+                    Err(e) => log::warn!("config reload failed: {}", e),
+                }
+            }
+        });
+    }
+    let make_svc = make_service_fn(move |conn: &listener::Conn| {
+        let mut rook = rook.clone();
+        let conn = Connection {
+            remote: conn.remote_addr(),
+            client_cn: conn.client_cn().map(str::to_string),
+        };
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(conn.clone());
+                rook.call(req)
+            }))
+        }
+    });
+    let socket = tcp.local_addr().map_err(|e| e.to_string())?;
+    let listener_fd = tcp.as_raw_fd();
+    // slow clients (or slowloris) can hold a connection open indefinitely without these:
+    // the header timeout covers the PROXY preamble and request headers, and the router bounds
+    // the body read with cfg.body_timeout
+    let tls = listener::tls_acceptor(&cfg)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    // once a new rook has the listener this one stops accepting, then finishes the connections
+    // it has
+    let (handed_over, stopped) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+    let incoming = listener::incoming(
+        tcp,
+        cfg.proxy_protocol,
+        tls,
+        cfg.header_timeout,
+        cfg.max_connections,
+        handed_over.clone(),
+        stopped.clone(),
+    );
+    let server = Server::builder(accept::from_stream(incoming))
+        .http1_header_read_timeout(cfg.header_timeout)
+        .serve(make_svc)
+        .with_graceful_shutdown(async move { stopped.notified().await });
+    logging::info!(
+        "listening on {}://{}:{}{}",
+        scheme,
+        socket.ip(),
+        socket.port(),
+        if cfg.proxy_protocol {
+            " (proxy protocol)"
+        } else {
+            ""
+        }
+    );
+    if cfg.dry_run {
+        logging::info!("dry run: hooks are logged, not run");
+    }
+    let mut admin_fd = None;
+    if let Some(admin_socket) = cfg.admin {
+        feed::start()?;
+        let make_admin = make_service_fn(move |_: &AddrStream| {
+            let rook = admin_rook.clone();
+            let service = service_fn(move |req| admin::handle(req, rook.clone()));
+            async move { Ok::<_, Infallible>(service) }
+        });
+        let listener = match inherited.admin.take() {
+            Some(l) => match l.local_addr() {
+                Ok(addr) if addr == admin_socket => l,
+                _ => {
+                    return Err(format!(
+                        "the upgraded admin listener is not on {}; restart rook to move it",
+                        admin_socket
+                    ))
+                }
+            },
+            None => std::net::TcpListener::bind(admin_socket)
+                .and_then(|l| l.set_nonblocking(true).map(|()| l))
+                .map_err(|e| format!("failed to bind admin {}: {}", admin_socket, e))?,
+        };
+        admin_fd = Some(listener.as_raw_fd());
+        let admin = match Server::from_tcp(listener) {
+            Ok(b) => b.serve(make_admin),
+            Err(e) => return Err(format!("failed to bind admin {}: {}", admin_socket, e)),
+        };
+        logging::info!("admin listening on http://{}", admin_socket);
+        tokio::spawn(async move {
+            if let Err(e) = admin.await {
+                eprintln!("admin server error: {}", e);
+            }
+        });
+    }
+    #[cfg(feature = "mqtt")]
+    if cfg.mqtt.is_some() && !cfg.mqtt_hooks.is_empty() {
+        tokio::spawn(crate::mqtt::subscribe(cfg.clone()));
+    }
+    #[cfg(feature = "watch")]
+    if !cfg.watch_hooks.is_empty() {
+        tokio::spawn(crate::watch::watch(cfg.clone()));
+    }
+    if cfg.config_path.is_some() {
+        let mut upgrade = signal(SignalKind::user_defined2()).map_err(|e| e.to_string())?;
+        tokio::spawn(async move {
+            while upgrade.recv().await.is_some() {
+                logging::info!("upgrade: starting a new rook");
+                let started = match upgrade::start(listener_fd, admin_fd) {
+                    Ok(started) => tokio::task::spawn_blocking(|| started.wait()).await,
+                    Err(e) => Ok(Err(e)),
+                };
+                match started {
+                    Ok(Ok(pid)) => {
+                        logging::info!("upgrade: pid {} is serving; finishing requests", pid);
+                        handed_over.notify_one();
+                        return;
+                    }
+                    Ok(Err(e)) => log::warn!("upgrade failed: {}", e),
+                    Err(e) => log::warn!("upgrade failed: {}", e),
+                }
+            }
+        });
+    }
+    inherited.ready();
+    server.await.map_err(|e| format!("server error: {}", e))
+}
@@ -0,0 +1,367 @@
+//! rook's request handling as a [`tower_service::Service`], for composing into existing
+//! tower, hyper, or axum stacks.
+//!
+//! [`RookService`] answers every request itself, exactly as the rook server does: request ids,
+//! access logs, metrics, tracing, and history all apply.  [`RookLayer`] wraps another service
+//! and only takes the requests whose path is one of rook's routes, passing the rest through.
+//!
+//! Whatever accepts the connection should insert a [`Connection`] extension so rook knows who
+//! the client is; without one the client address is `0.0.0.0`, which never matches an
+//! `allow_cidrs` list.
+use crate::{
+    config::{self, RouteConfig},
+    exec, feed,
+    fixture::{Fixture, Recorder},
+    forwarded, history, logging, maintenance,
+    metrics::METRICS,
+    middleware::{self, Exchange, Middleware},
+    remote, router, trace,
+};
+use hyper::{Body, Request, Response};
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::sync::Semaphore;
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[cfg(feature = "postgres")]
+use crate::postgres::Postgres;
+
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+/// placeholder so callers don't need their own cfg gates; never constructed
+#[cfg(not(feature = "postgres"))]
+type Postgres = Infallible;
+
+/// Request extension describing the connection a request arrived on.
+#[derive(Clone)]
+pub struct Connection {
+    /// the socket's peer address, before PROXY protocol or trusted forwarding headers
+    pub remote: SocketAddr,
+    /// set when the client presented a verified certificate; empty if it had no CN
+    pub client_cn: Option<String>,
+}
+
+/// Routes each request to its hooks and answers it.  Clones share the config, `max_requests`
+/// limit, delivery history, and postgres connection.
+#[derive(Clone)]
+pub struct RookService {
+    /// swapped by [`RookService::reload`]; requests keep the config they started with
+    cfg: Arc<RwLock<Arc<RouteConfig>>>,
+    in_flight: Arc<Semaphore>,
+    history: Option<Arc<history::History>>,
+    postgres: Option<Arc<Postgres>>,
+    /// `--record`'s directory
+    recorder: Option<Arc<Recorder>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl RookService {
+    /// Serve `cfg`'s hooks.  Fails if `[history]`, `[postgres]`, or a `record` directory is
+    /// configured and can't be opened.
+    pub fn new(cfg: Arc<RouteConfig>) -> Result<Self, String> {
+        maintenance::apply(&cfg);
+        let history = cfg
+            .history
+            .as_ref()
+            .map(history::History::open)
+            .transpose()?
+            .map(Arc::new);
+        let postgres = cfg
+            .postgres
+            .as_ref()
+            .map(open_postgres)
+            .transpose()?
+            .map(Arc::new);
+        let recorder = cfg
+            .record
+            .as_deref()
+            .map(Recorder::open)
+            .transpose()?
+            .map(Arc::new);
+        Ok(RookService {
+            in_flight: Arc::new(Semaphore::new(cfg.max_requests)),
+            cfg: Arc::new(RwLock::new(cfg)),
+            history,
+            postgres,
+            recorder,
+            middleware: vec![
+                Arc::new(middleware::Metrics),
+                Arc::new(middleware::RequestIdHeader),
+            ],
+        })
+    }
+
+    /// Run `middleware` around every request; see [`crate::middleware`] for the order.
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// The config requests are routed with now.
+    pub fn config(&self) -> Arc<RouteConfig> {
+        self.cfg.read().expect("config lock poisoned").clone()
+    }
+
+    /// Read the config file again, or fetch a [remote](crate::remote) one again, and route new
+    /// requests with it.  If it doesn't load, the error is returned and the current config
+    /// stays.
+    ///
+    /// Hooks and their options, access lists, `trusted_proxies`, `path_prefix`,
+    /// `body_timeout`, and `[fallback]` change.  The listeners, `max_requests`, logging,
+    /// `[history]`, `[postgres]`, and mqtt and file watching hooks keep their startup settings.
+    /// A server in dry run stays in dry run.
+    pub async fn reload(&self) -> Result<Arc<RouteConfig>, String> {
+        let current = self.config();
+        if let Some(source) = &current.remote {
+            let text = source.fetch().await?;
+            return self.install_remote(source, &text);
+        }
+        let Some(path) = current.config_path.clone() else {
+            return Err("reload needs a config loaded from a file".to_string());
+        };
+        let loaded = tokio::task::spawn_blocking(move || config::from_file(&path))
+            .await
+            .map_err(|e| format!("reload failed: {}", e))?;
+        Ok(self.install(loaded.map_err(|e| e.to_string())?))
+    }
+
+    /// Load `text`, fetched and verified from `source`, and route new requests with it.
+    pub(crate) fn install_remote(
+        &self,
+        source: &Arc<remote::Source>,
+        text: &str,
+    ) -> Result<Arc<RouteConfig>, String> {
+        let cfg = config::from_remote(source.clone(), text).map_err(|e| e.to_string())?;
+        Ok(self.install(cfg))
+    }
+
+    fn install(&self, mut cfg: RouteConfig) -> Arc<RouteConfig> {
+        if self.config().dry_run {
+            cfg.set_dry_run();
+        }
+        config::debug_routes(&cfg);
+        config::warn_commands(&cfg);
+        maintenance::apply(&cfg);
+        let cfg = Arc::new(cfg);
+        *self.cfg.write().expect("config lock poisoned") = cfg.clone();
+        cfg
+    }
+
+    pub(crate) fn history(&self) -> Option<Arc<history::History>> {
+        self.history.clone()
+    }
+}
+
+impl Service<Request<Body>> for RookService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Response<Body>, Infallible>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        // requests over max_requests are answered with a 503 rather than held back
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        Box::pin(handle(self.clone(), req))
+    }
+}
+
+async fn handle(svc: RookService, mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let conn = req
+        .extensions()
+        .get::<Connection>()
+        .cloned()
+        .unwrap_or(Connection {
+            remote: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            client_cn: None,
+        });
+    let mut log = logging::log_context(&conn.remote);
+    let request_id = logging::request_id(req.headers());
+    req.extensions_mut()
+        .insert(router::RequestId(request_id.clone()));
+    let log_id = request_id.clone();
+    let traceparent = req.headers().get(trace::TRACEPARENT_HEADER).cloned();
+    let span_name = format!("{} {}", req.method(), req.uri().path());
+    let cfg = svc.config();
+    let handled = trace::with_trace(traceparent, span_name, async move {
+        let RookService {
+            cfg: _,
+            in_flight,
+            history,
+            postgres,
+            recorder,
+            middleware,
+        } = svc;
+        let client = forwarded::client_ip(conn.remote.ip(), req.headers(), &cfg.trusted_proxies);
+        log.start().req(&req).client(client).request_id(&request_id);
+        let (method, path) = (req.method().clone(), req.uri().path().to_string());
+        let started = Instant::now();
+        let recorded = history.is_some() || postgres.is_some();
+        // the query is kept so a replay matches the same hooks
+        let recorded =
+            recorded.then(|| (history::headers_json(req.headers()), req.uri().to_string()));
+        let fixture = recorder
+            .is_some()
+            .then(|| Fixture::start(&req, conn.remote.ip(), &request_id));
+        let peer = router::Peer {
+            ip: client,
+            client_cn: conn.client_cn,
+        };
+        let answered = middleware.iter().find_map(|m| m.before(&mut req, client));
+        let jobs_full = cfg.max_jobs.is_some_and(|max| exec::running_count() >= max);
+        let mut res = match answered {
+            Some(answered) => Ok(answered),
+            None if jobs_full => {
+                METRICS.request_shed();
+                Ok(router::overloaded(cfg.shed_retry_after))
+            }
+            None => match in_flight.try_acquire_owned() {
+                Ok(_permit) => {
+                    let _in_flight = METRICS.request_started();
+                    router::handle(req, &cfg, &peer).await
+                }
+                Err(_) => {
+                    METRICS.request_shed();
+                    Ok(router::overloaded(cfg.shed_retry_after))
+                }
+            },
+        };
+        if let Ok(r) = &mut res {
+            for (name, value) in &cfg.response_headers {
+                if !r.headers().contains_key(name) {
+                    r.headers_mut().insert(name, value.clone());
+                }
+            }
+            let exchange = Exchange {
+                method: &method,
+                path: &path,
+                client,
+                request_id: &request_id,
+                started,
+            };
+            for m in middleware.iter().rev() {
+                m.after(&exchange, r);
+            }
+        }
+        log.res(&res).end();
+        logging::access(&log);
+        if let Ok(r) = &res {
+            feed::delivery(&request_id, client, &method, &path, r);
+        }
+        // before the history takes the body
+        if let (Some(recorder), Some(mut fixture), Ok(r)) = (recorder, fixture, &res) {
+            fixture.status = Some(r.status().as_u16());
+            if let Some(dispatch) = r.extensions().get::<router::Dispatch>() {
+                fixture.body = dispatch.body.clone().unwrap_or_default();
+            }
+            recorder.record(fixture);
+        }
+        if let (Some((headers, target)), Ok(r)) = (recorded, &mut res) {
+            let record = history::Record::new(&request_id, client, &method, &target, headers, r);
+            if let (Some(postgres), true) = (postgres, record.verified > 0) {
+                let record = record.clone();
+                tokio::spawn(async move { record_postgres(&postgres, record).await });
+            }
+            if let Some(history) = history {
+                tokio::spawn(async move { history.record(record).await });
+            }
+        }
+        if let Ok(r) = &res {
+            trace::root_attr("http.response.status_code", r.status().as_u16().into());
+        }
+        trace::root_attr("http.request.method", method.as_str().into());
+        trace::root_attr("url.path", path.into());
+        trace::root_attr("client.address", client.to_string().into());
+        trace::root_attr("rook.request_id", request_id.into());
+        res
+    });
+    logging::with_request_id(log_id, handled).await
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres(cfg: &crate::config::PostgresConfig) -> Result<Postgres, String> {
+    Postgres::open(cfg)
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres(_: &crate::config::PostgresConfig) -> Result<Postgres, String> {
+    // config::build rejects [postgres] sections when the feature is disabled
+    Err("[postgres] requires rook built with the `postgres` feature".to_string())
+}
+
+#[cfg(feature = "postgres")]
+async fn record_postgres(postgres: &Postgres, record: history::Record) {
+    postgres.record(record).await
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn record_postgres(postgres: &Postgres, _: history::Record) {
+    match *postgres {}
+}
+
+/// Sends requests for rook's routes to a [`RookService`] and everything else to the wrapped
+/// service.
+#[derive(Clone)]
+pub struct RookLayer {
+    rook: RookService,
+}
+
+impl RookLayer {
+    pub fn new(rook: RookService) -> Self {
+        RookLayer { rook }
+    }
+}
+
+impl<S> Layer<S> for RookLayer {
+    type Service = Rooked<S>;
+
+    fn layer(&self, inner: S) -> Rooked<S> {
+        Rooked {
+            rook: self.rook.clone(),
+            inner,
+        }
+    }
+}
+
+/// A service wrapped by [`RookLayer`].
+#[derive(Clone)]
+pub struct Rooked<S> {
+    rook: RookService,
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for Rooked<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Response<Body>, S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if router::is_route(&self.rook.config(), req.uri().path()) {
+            let handled = self.rook.call(req);
+            Box::pin(async move {
+                match handled.await {
+                    Ok(res) => Ok(res),
+                    Err(e) => match e {},
+                }
+            })
+        } else {
+            Box::pin(self.inner.call(req))
+        }
+    }
+}
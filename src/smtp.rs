@@ -0,0 +1,220 @@
+//! Email a hook's failures through an SMTP server, for teams that want to hear about a broken
+//! deploy without a chat service.
+//!
+//! A failure is a command that exits nonzero, is killed by a signal, or can't be started.  The
+//! email names the hook, says how the command ended, and carries the end of its output.  It's
+//! sent from the forked process that waited on the command, once, with no retries.
+use crate::{
+    config::SmtpConfig,
+    encoding,
+    exec::{Ended, Notifier},
+    id,
+};
+use hyper::Uri;
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+
+const DEFAULT_PORT: u16 = 25;
+/// for connecting, and each command and its reply
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Email `to` when a hook's command fails.
+pub struct Email {
+    url: String,
+    addr: String,
+    from: String,
+    to: Vec<String>,
+    /// the `AUTH PLAIN` response
+    auth: Option<String>,
+    /// who rook says it is in `EHLO` and message ids
+    hostname: String,
+}
+
+impl Email {
+    pub fn new(cfg: &SmtpConfig, to: &[String]) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .url
+            .parse()
+            .map_err(|e| format!("bad smtp url '{}': {}", cfg.url, e))?;
+        let host = match (uri.scheme_str(), uri.host(), uri.path()) {
+            (Some("smtp"), Some(host), "" | "/") => host,
+            _ => return Err(format!("smtp url '{}' must be smtp://host:port", cfg.url)),
+        };
+        if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+            return Err(format!(
+                "smtp url '{}' must not carry credentials; use user and password_file",
+                cfg.url
+            ));
+        }
+        for address in to.iter().chain([&cfg.from]) {
+            if !is_address(address) {
+                return Err(format!("bad email address '{}'", address));
+            }
+        }
+        let auth = match (&cfg.user, &cfg.password_file) {
+            (Some(user), Some(path)) => {
+                let password = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read smtp password_file '{}': {}", path, e))?;
+                let plain = format!("\0{}\0{}", user, password.trim());
+                Some(encoding::base64(plain.as_bytes()))
+            }
+            (None, None) => None,
+            _ => return Err("[smtp] user and password_file go together".to_string()),
+        };
+        Ok(Email {
+            url: cfg.url.clone(),
+            addr: format!("{}:{}", host, uri.port_u16().unwrap_or(DEFAULT_PORT)),
+            from: cfg.from.clone(),
+            to: to.to_vec(),
+            auth,
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|h| h.trim().to_string())
+                .unwrap_or_else(|_| "localhost".to_string()),
+        })
+    }
+
+    /// The message, headers and all, with lines ending in CRLF and dot-stuffed for `DATA`.
+    fn message(&self, ended: &Ended) -> String {
+        let mut body = format!(
+            "route: {}\ncommand: {}\nrequest id: {}\nresult: {}\nduration: {:.1}s\n",
+            ended.route,
+            ended.command,
+            ended.request_id.unwrap_or("-"),
+            ended.describe(),
+            ended.duration.as_secs_f64(),
+        );
+        if !ended.output.is_empty() {
+            body.push_str("\nlast output:\n");
+            body.push_str(&ended.output_text());
+        }
+        let headers = [
+            ("From", self.from.clone()),
+            ("To", self.to.join(", ")),
+            (
+                "Subject",
+                format!("rook: {} {}", ended.route, ended.describe()),
+            ),
+            (
+                "Date",
+                OffsetDateTime::now_utc()
+                    .format(&Rfc2822)
+                    .unwrap_or_default(),
+            ),
+            (
+                "Message-ID",
+                format!("<{:016x}@{}>", id::random_u64(), self.hostname),
+            ),
+            ("MIME-Version", "1.0".to_string()),
+            ("Content-Type", "text/plain; charset=utf-8".to_string()),
+            ("Content-Transfer-Encoding", "8bit".to_string()),
+        ];
+        let mut message = String::new();
+        for (name, value) in headers {
+            // header values can't break the message
+            let value: String = value.chars().filter(|c| !c.is_control()).collect();
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str("\r\n");
+        for line in body.lines() {
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+
+    fn send(&self, ended: &Ended) -> Result<(), String> {
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("no address")?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+        let mut conn = BufReader::new(stream);
+        expect(&mut conn, None, 220)?;
+        expect(&mut conn, Some(&format!("EHLO {}", self.hostname)), 250)?;
+        if let Some(auth) = &self.auth {
+            expect(&mut conn, Some(&format!("AUTH PLAIN {}", auth)), 235)?;
+        }
+        expect(&mut conn, Some(&format!("MAIL FROM:<{}>", self.from)), 250)?;
+        for to in &self.to {
+            expect(&mut conn, Some(&format!("RCPT TO:<{}>", to)), 250)?;
+        }
+        expect(&mut conn, Some("DATA"), 354)?;
+        let message = self.message(ended);
+        expect(&mut conn, Some(&format!("{}.", message)), 250)?;
+        let _ = expect(&mut conn, Some("QUIT"), 221);
+        Ok(())
+    }
+}
+
+impl Notifier for Email {
+    fn notify(&self, ended: &Ended) -> Result<(), String> {
+        if !ended.failed() {
+            return Ok(());
+        }
+        self.send(ended)
+            .map_err(|e| format!("email via {}: {}", self.url, e))
+    }
+}
+
+/// Send `line`, if any, then read the reply and check its code is `code`.  A `250` also
+/// accepts `251`, which some servers send for forwarded recipients.
+fn expect(conn: &mut BufReader<TcpStream>, line: Option<&str>, code: u16) -> Result<(), String> {
+    if let Some(line) = line {
+        let sent = conn.get_mut().write_all(format!("{}\r\n", line).as_bytes());
+        sent.map_err(|e| e.to_string())?;
+    }
+    let reply = read_reply(conn).map_err(|e| e.to_string())?;
+    match reply.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+        Some(c) if c == code || (code == 250 && c == 251) => Ok(()),
+        _ => Err(reply),
+    }
+}
+
+/// A whole reply, joining the lines of a multiline one.
+fn read_reply(conn: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+        let line = line.trim_end();
+        reply.push_str(line);
+        // `250-` continues, `250 ` ends
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(reply);
+        }
+        reply.push(' ');
+    }
+}
+
+/// Enough of an address to send to, and nothing that could break an SMTP command.
+fn is_address(address: &str) -> bool {
+    match address.split_once('@') {
+        Some((user, domain)) => {
+            !user.is_empty()
+                && !domain.is_empty()
+                && !address
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || "<>,;".contains(c))
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,105 @@
+//! Request bodies too large to hold in memory, written to a temporary file as they're read.
+//!
+//! A rook hook with `spill` has any body longer than `spill.above` streamed to a new file in
+//! `spill.dir`, with its signature checked a chunk at a time along the way, instead of being
+//! collected in memory.  Each command it starts gets its own link to the file, named in
+//! `ROOK_INPUT_FILE` and removed once the command exits; the file itself is removed once the
+//! request is answered, so nothing is left behind.  A `format = "binary"` hook's bodies are
+//! handed to commands the same way, written to the system's temp directory if they weren't
+//! spilled already.
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+/// Where and when a hook's bodies are written to disk.
+pub struct Spill {
+    /// bodies longer than this many bytes are spilled; shorter ones are held in memory
+    pub above: u64,
+    /// longest body accepted, in bytes
+    pub max_size: u64,
+    dir: PathBuf,
+}
+
+impl Spill {
+    /// Spill into `dir`, or the system's temp directory.  `above` can't be more than the
+    /// largest body rook holds in memory.
+    pub fn new(
+        above: u64,
+        max_size: u64,
+        dir: Option<&str>,
+        in_memory: u64,
+    ) -> Result<Self, String> {
+        if above == 0 || above > in_memory {
+            return Err(format!("spill.above must be between 1 and {}", in_memory));
+        }
+        if max_size <= above {
+            return Err("spill.max_size must be more than spill.above".to_string());
+        }
+        let dir = dir.map_or_else(env::temp_dir, PathBuf::from);
+        if !dir.is_dir() {
+            return Err(format!("spill.dir '{}' is not a directory", dir.display()));
+        }
+        Ok(Spill {
+            above,
+            max_size,
+            dir,
+        })
+    }
+
+    /// A new, empty file in the spill directory that only rook's user can read.
+    pub async fn create(&self) -> io::Result<SpillFile> {
+        create_in(&self.dir).await
+    }
+}
+
+/// A body held in memory, written to a new file in the system's temp directory for the
+/// commands of a `format = "binary"` hook.
+pub async fn to_file(body: &[u8]) -> io::Result<SpillFile> {
+    let mut file = create_in(&env::temp_dir()).await?;
+    file.write(body).await?;
+    file.flush().await?;
+    Ok(file)
+}
+
+async fn create_in(dir: &Path) -> io::Result<SpillFile> {
+    let path = dir.join(format!("rook-{:016x}.body", crate::id::random_u64()));
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .await?;
+    Ok(SpillFile { path, file })
+}
+
+/// A body being written to disk, removed when dropped.
+pub struct SpillFile {
+    path: PathBuf,
+    file: tokio::fs::File,
+}
+
+impl SpillFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk).await
+    }
+
+    /// Wait for everything written to reach the file, before commands read it.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        // commands that started hold their own links to it
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::config::StatsdConfig;
+use std::{io, net::UdpSocket, sync::OnceLock, time::Duration};
+
+/// Unset unless `[statsd]` is configured, which makes every emit a no-op.
+static STATSD: OnceLock<Statsd> = OnceLock::new();
+
+/// Fire-and-forget [statsd](https://github.com/statsd/statsd/blob/master/docs/metric_types.md)
+/// over udp.  Tags use the dogstatsd `|#key:value` extension and are left off for plain statsd.
+struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    /// `key:value,...` from the config, sent with every metric
+    tags: String,
+    dogstatsd: bool,
+}
+
+pub fn init(cfg: &StatsdConfig) -> io::Result<()> {
+    let socket = UdpSocket::bind(if cfg.address.starts_with('[') {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    })?;
+    socket.connect(&cfg.address)?;
+    // a slow or missing agent must never hold up a request
+    socket.set_nonblocking(true)?;
+    let tags = cfg
+        .tags
+        .iter()
+        .map(|(k, v)| format!("{}:{}", k, v))
+        .collect::<Vec<_>>()
+        .join(",");
+    let _ = STATSD.set(Statsd {
+        socket,
+        prefix: cfg.prefix.trim_end_matches('.').to_string(),
+        tags,
+        dogstatsd: cfg.dogstatsd,
+    });
+    Ok(())
+}
+
+pub fn count(name: &str, tags: &[(&str, &str)]) {
+    send(name, "1", "c", tags);
+}
+
+pub fn timing(name: &str, elapsed: Duration, tags: &[(&str, &str)]) {
+    send(
+        name,
+        &format!("{:.3}", elapsed.as_secs_f64() * 1000.0),
+        "ms",
+        tags,
+    );
+}
+
+fn send(name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+    let Some(statsd) = STATSD.get() else {
+        return;
+    };
+    let mut msg = format!("{}.{}:{}|{}", statsd.prefix, name, value, kind);
+    if statsd.dogstatsd && !(statsd.tags.is_empty() && tags.is_empty()) {
+        msg.push_str("|#");
+        msg.push_str(&statsd.tags);
+        for (i, (k, v)) in tags.iter().enumerate() {
+            if i > 0 || !statsd.tags.is_empty() {
+                msg.push(',');
+            }
+            // dogstatsd splits tags on spaces and commas
+            msg.push_str(&format!("{}:{}", k, v.replace([' ', ','], "_")));
+        }
+    }
+    if let Err(e) = statsd.socket.send(msg.as_bytes()) {
+        debug!("statsd send failed: {}", e);
+    }
+}
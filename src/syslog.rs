@@ -0,0 +1,152 @@
+use crate::{
+    config::{Facility, SyslogConfig},
+    logging::Sink,
+};
+use log::Level;
+use std::{
+    fs,
+    io::{self, Write},
+    net::{TcpStream, UdpSocket},
+    os::unix::net::UnixDatagram,
+    process,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// [RFC 5424](https://www.rfc-editor.org/rfc/rfc5424) messages to the local syslog socket, or to
+/// a remote collector over udp or tcp (octet-counted framing, RFC 6587).
+pub struct Syslog {
+    address: String,
+    transport: Transport,
+    facility: Facility,
+    hostname: String,
+    app_name: String,
+}
+
+enum Transport {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(Option<TcpStream>),
+}
+
+impl Syslog {
+    pub fn connect(cfg: &SyslogConfig) -> io::Result<Syslog> {
+        let transport = if let Some(remote) = cfg.address.strip_prefix("udp://") {
+            let socket = UdpSocket::bind(if remote.starts_with('[') {
+                "[::]:0"
+            } else {
+                "0.0.0.0:0"
+            })?;
+            socket.connect(remote)?;
+            Transport::Udp(socket)
+        } else if let Some(remote) = cfg.address.strip_prefix("tcp://") {
+            Transport::Tcp(Some(TcpStream::connect(remote)?))
+        } else {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(&cfg.address)?;
+            Transport::Unix(socket)
+        };
+        let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|h| h.trim().to_string())
+            .ok()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "-".to_string());
+        Ok(Syslog {
+            address: cfg.address.clone(),
+            transport,
+            facility: cfg.facility,
+            hostname,
+            app_name: cfg.app_name.clone(),
+        })
+    }
+
+    fn format(&self, level: Level, line: &str) -> String {
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            self.facility as u8 * 8 + severity(level),
+            OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_else(|_| "-".to_string()),
+            self.hostname,
+            self.app_name,
+            process::id(),
+            line
+        )
+    }
+}
+
+impl Sink for Syslog {
+    fn write(&mut self, level: Level, line: &str) -> io::Result<()> {
+        let msg = self.format(level, line);
+        match &mut self.transport {
+            Transport::Unix(socket) => socket.send(msg.as_bytes()).map(|_| ()),
+            Transport::Udp(socket) => socket.send(msg.as_bytes()).map(|_| ()),
+            Transport::Tcp(stream) => {
+                let framed = format!("{} {}", msg.len(), msg);
+                if let Some(s) = stream {
+                    if s.write_all(framed.as_bytes()).is_ok() {
+                        return Ok(());
+                    }
+                }
+                // the collector restarted or dropped us; reconnect once per message
+                let remote = self.address.trim_start_matches("tcp://");
+                *stream = None;
+                let mut s = TcpStream::connect(remote)?;
+                s.write_all(framed.as_bytes())?;
+                *stream = Some(s);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// systemd-journald's [native protocol](https://systemd.io/JOURNAL_NATIVE_PROTOCOL/), which
+/// keeps the level as `PRIORITY` instead of folding it into the message.
+pub struct Journald {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl Journald {
+    pub fn connect(identifier: &str) -> io::Result<Journald> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(Journald {
+            socket,
+            identifier: identifier.to_string(),
+        })
+    }
+}
+
+impl Sink for Journald {
+    fn write(&mut self, level: Level, line: &str) -> io::Result<()> {
+        let mut msg = Vec::with_capacity(line.len() + 64);
+        push_field(&mut msg, "PRIORITY", &severity(level).to_string());
+        push_field(&mut msg, "SYSLOG_IDENTIFIER", &self.identifier);
+        push_field(&mut msg, "MESSAGE", line);
+        self.socket.send(&msg).map(|_| ())
+    }
+}
+
+/// `KEY=value\n`, or `KEY\n<u64 le length><value>\n` when the value spans lines
+fn push_field(msg: &mut Vec<u8>, key: &str, value: &str) {
+    msg.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        msg.push(b'\n');
+        msg.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        msg.push(b'=');
+    }
+    msg.extend_from_slice(value.as_bytes());
+    msg.push(b'\n');
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
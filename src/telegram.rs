@@ -0,0 +1,245 @@
+//! Report hooks to a Telegram chat through a bot: how their commands ended, and deliveries
+//! turned away for their signatures.
+//!
+//! Results are sent from the forked process that waited on the command.  Alerts are sent off
+//! the request's thread, at most one a minute for each url, noting how many were held back in
+//! between, so someone hammering a url with bad signatures can't flood the chat.  Neither is
+//! retried.
+use crate::{
+    blocking::Client,
+    config::TelegramConfig,
+    encoding::escape_html,
+    exec::{Ended, Notifier},
+    logging::Alert,
+};
+use hyper::Uri;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// the most of a failed command's output a message carries; Telegram caps messages at 4096
+const MAX_OUTPUT: usize = 3000;
+const ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A bot posting to one chat, shared by every hook that reports to it.
+#[derive(Clone)]
+pub struct Telegram(Arc<Bot>);
+
+struct Bot {
+    chat_id: String,
+    /// the bot's `sendMessage` url, which holds its token
+    send_url: String,
+    client: Client,
+    /// for each url: when its last alert was sent, and how many have been held back since
+    alerted: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl Telegram {
+    pub fn new(cfg: &TelegramConfig) -> Result<Self, String> {
+        let uri: Uri = cfg
+            .api_url
+            .parse()
+            .map_err(|e| format!("bad telegram api_url '{}': {}", cfg.api_url, e))?;
+        match (uri.scheme_str(), uri.host(), uri.path()) {
+            (Some("http" | "https"), Some(_), "" | "/") => {}
+            _ => {
+                return Err(format!(
+                    "telegram api_url '{}' must be https://host[:port]",
+                    cfg.api_url
+                ))
+            }
+        }
+        let token = fs::read_to_string(&cfg.bot_token_file).map_err(|e| {
+            format!(
+                "failed to read telegram bot_token_file '{}': {}",
+                cfg.bot_token_file, e
+            )
+        })?;
+        // `123456:ABC-DEF...`
+        let token = token.trim();
+        let well_formed = token.split_once(':').is_some_and(|(id, key)| {
+            id.parse::<u64>().is_ok()
+                && !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        if !well_formed {
+            return Err(format!(
+                "bad telegram bot token in '{}'",
+                cfg.bot_token_file
+            ));
+        }
+        let chat_id = &cfg.chat_id;
+        let named = chat_id.len() > 1
+            && chat_id.starts_with('@')
+            && chat_id[1..]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if chat_id.parse::<i64>().is_err() && !named {
+            return Err(format!(
+                "telegram chat_id '{}' must be a number or @channelname",
+                chat_id
+            ));
+        }
+        Ok(Telegram(Arc::new(Bot {
+            chat_id: chat_id.clone(),
+            send_url: format!(
+                "{}/bot{}/sendMessage",
+                cfg.api_url.trim_end_matches('/'),
+                token
+            ),
+            client: Client::new(&uri, &cfg.ca_file)?,
+            alerted: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    /// A notifier that reports a hook's successes, failures, or both.
+    pub fn results(&self, success: bool, failure: bool) -> Results {
+        Results {
+            bot: self.0.clone(),
+            success,
+            failure,
+        }
+    }
+
+    /// The alert for rejected signatures; the same one every time it's asked for.
+    pub fn alert(&self) -> Arc<dyn Alert> {
+        self.0.clone()
+    }
+}
+
+/// Reports how a hook's command ended.
+pub struct Results {
+    bot: Arc<Bot>,
+    success: bool,
+    failure: bool,
+}
+
+impl Notifier for Results {
+    fn notify(&self, ended: &Ended) -> Result<(), String> {
+        let wanted = match ended.failed() {
+            true => self.failure,
+            false => self.success,
+        };
+        if !wanted {
+            return Ok(());
+        }
+        let mut text = format!(
+            "<b>rook</b>: <code>{}</code> {} after {:.1}s",
+            escape_html(ended.route),
+            escape_html(&ended.describe()),
+            ended.duration.as_secs_f64()
+        );
+        if let Some(id) = ended.request_id {
+            text.push_str(&format!(" (request <code>{}</code>)", escape_html(id)));
+        }
+        let output = ended.output_text();
+        if ended.failed() && !output.is_empty() {
+            text.push_str(&format!("\n<pre>{}</pre>", escape_html(last(&output))));
+        }
+        send(
+            &self.bot.client,
+            &self.bot.send_url,
+            &self.bot.chat_id,
+            &text,
+        )
+        .map_err(|e| format!("telegram chat {}: {}", self.bot.chat_id, e))
+    }
+}
+
+impl Alert for Bot {
+    fn signature_failure(&self, client: IpAddr, path: &str, reason: &str) {
+        let held = {
+            let Ok(mut alerted) = self.alerted.lock() else {
+                return;
+            };
+            match alerted.get_mut(path) {
+                Some((last, held)) if last.elapsed() < ALERT_INTERVAL => {
+                    *held += 1;
+                    return;
+                }
+                _ => {
+                    let held = alerted.remove(path).map_or(0, |(_, held)| held);
+                    // mqtt topics under a wildcard are the publisher's to choose
+                    alerted.retain(|_, (last, held)| *held > 0 || last.elapsed() < ALERT_INTERVAL);
+                    alerted.insert(path.to_string(), (Instant::now(), 0));
+                    held
+                }
+            }
+        };
+        let mut text = format!(
+            "<b>rook</b>: signature failure on <code>{}</code> from <code>{}</code>: {}",
+            escape_html(path),
+            client,
+            escape_html(reason)
+        );
+        if held > 0 {
+            text.push_str(&format!(" ({} more since the last alert)", held));
+        }
+        let (client, url, chat_id) = (
+            self.client.clone(),
+            self.send_url.clone(),
+            self.chat_id.clone(),
+        );
+        let path = path.to_string();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = send(&client, &url, &chat_id, &text) {
+                log::warn!(
+                    "telegram alert failed: chat={} path={} error={}",
+                    chat_id,
+                    path,
+                    e
+                );
+            }
+        });
+    }
+}
+
+/// Post `text`, formatted as html, to the chat.
+fn send(client: &Client, url: &str, chat_id: &str, text: &str) -> Result<(), String> {
+    let body = json!({
+        "chat_id": chat_id,
+        "text": text,
+        "parse_mode": "HTML",
+        "link_preview_options": { "is_disabled": true },
+    });
+    let res = client.send(
+        "POST",
+        url,
+        &[("content-type", "application/json")],
+        body.to_string().as_bytes(),
+    )?;
+    if (200..300).contains(&res.status) {
+        return Ok(());
+    }
+    // errors are json like {"ok": false, "description": "Bad Request: chat not found"}
+    let description = serde_json::from_slice::<Value>(&res.body)
+        .ok()
+        .and_then(|v| v["description"].as_str().map(str::to_string));
+    Err(match description {
+        Some(description) => format!("{} {}", res.status, description),
+        None => res.status.to_string(),
+    })
+}
+
+/// The end of `output`, from the first whole line within the last [`MAX_OUTPUT`] bytes.
+fn last(output: &str) -> &str {
+    if output.len() <= MAX_OUTPUT {
+        return output;
+    }
+    let mut start = output.len() - MAX_OUTPUT;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    let tail = &output[start..];
+    match tail.split_once('\n') {
+        Some((_, rest)) if !rest.is_empty() => rest,
+        _ => tail,
+    }
+}
@@ -0,0 +1,208 @@
+//! Integration tests for hook setups, without a real server or shell scripts.
+//!
+//! With the `test-util` feature, a config built in code (or loaded from toml) can be served
+//! in process by a [`TestService`], or on an ephemeral port by a [`TestServer`], and sent
+//! requests signed the way GitHub or another service would sign them.  Both capture each
+//! verified delivery instead of running its command, unless started with `running`:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use rook::{config::RouteConfig, testing::{self, TestService}};
+//!
+//! let cfg = RouteConfig::builder()
+//!     .rook_hook("/deploy", "hunter2", "/usr/local/bin/deploy.sh")
+//!     .build()
+//!     .map_err(|e| e.to_string())?;
+//! let mut rook = TestService::new(cfg)?;
+//! let res = rook.call(testing::rook_request("/deploy", "hunter2", "v1.2")).await;
+//! assert_eq!(res.status(), 200);
+//! let deliveries = rook.deliveries();
+//! assert_eq!(deliveries[0].body, b"v1.2");
+//!
+//! let res = rook.call(testing::rook_request("/deploy", "wrong", "v1.2")).await;
+//! assert_eq!(res.status(), 403);
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    channel::{self, Delivery},
+    client::{self, Style},
+    config::RouteConfig,
+    serve_on,
+    service::{Connection, RookService},
+};
+use hyper::{body, Body, Request, Response};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+use tower_service::Service;
+
+/// most deliveries captured between reads; past that, hooks fail to start
+const CAPTURED: usize = 1024;
+
+/// A config's hooks, answering requests in process.
+pub struct TestService {
+    rook: RookService,
+    client: IpAddr,
+    deliveries: Option<mpsc::Receiver<Delivery>>,
+}
+
+impl TestService {
+    /// Serve `cfg`, capturing deliveries instead of running commands.
+    pub fn new(mut cfg: RouteConfig) -> Result<Self, String> {
+        let (executor, deliveries) = channel::channel(CAPTURED);
+        cfg.set_executors(executor);
+        Ok(TestService {
+            deliveries: Some(deliveries),
+            ..TestService::running(cfg)?
+        })
+    }
+
+    /// Serve `cfg` with its executors as configured, so commands run.
+    pub fn running(cfg: RouteConfig) -> Result<Self, String> {
+        Ok(TestService {
+            rook: RookService::new(Arc::new(cfg))?,
+            client: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            deliveries: None,
+        })
+    }
+
+    /// Send requests from `client` rather than `127.0.0.1`, for testing access lists.
+    pub fn client(mut self, client: IpAddr) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Route `req` as the server would, and read the whole response.
+    pub async fn call(&mut self, mut req: Request<Body>) -> Response<Vec<u8>> {
+        req.extensions_mut().insert(Connection {
+            remote: SocketAddr::new(self.client, 0),
+            client_cn: None,
+        });
+        let res = self.rook.call(req).await.unwrap_or_else(|e| match e {});
+        let (parts, body) = res.into_parts();
+        let body = body::to_bytes(body).await.unwrap_or_default();
+        Response::from_parts(parts, body.to_vec())
+    }
+
+    /// The deliveries captured since the last call, oldest first.
+    pub fn deliveries(&mut self) -> Vec<Delivery> {
+        drain(&mut self.deliveries)
+    }
+
+    /// The service requests are routed with, for wrapping in other tower layers.
+    pub fn service(&self) -> RookService {
+        self.rook.clone()
+    }
+}
+
+/// The full server on `127.0.0.1` and an ephemeral port, stopped when dropped.  Start it from
+/// inside a tokio runtime, such as a `#[tokio::test]`.
+pub struct TestServer {
+    addr: SocketAddr,
+    task: JoinHandle<Result<(), String>>,
+    deliveries: Option<mpsc::Receiver<Delivery>>,
+}
+
+impl TestServer {
+    /// Serve `cfg`, capturing deliveries instead of running commands.  Its `addr` and `port`
+    /// are ignored.
+    pub async fn start(mut cfg: RouteConfig) -> Result<Self, String> {
+        let (executor, deliveries) = channel::channel(CAPTURED);
+        cfg.set_executors(executor);
+        TestServer::bind(cfg, Some(deliveries)).await
+    }
+
+    /// Serve `cfg` with its executors as configured, so commands run.
+    pub async fn running(cfg: RouteConfig) -> Result<Self, String> {
+        TestServer::bind(cfg, None).await
+    }
+
+    async fn bind(
+        cfg: RouteConfig,
+        deliveries: Option<mpsc::Receiver<Delivery>>,
+    ) -> Result<Self, String> {
+        let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|e| format!("failed to bind a test port: {}", e))?;
+        let addr = tcp.local_addr().map_err(|e| e.to_string())?;
+        Ok(TestServer {
+            addr,
+            task: tokio::spawn(serve_on(Arc::new(cfg), tcp)),
+            deliveries,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The server's url for `path`, such as `http://127.0.0.1:41234/deploy`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// The deliveries captured since the last call, oldest first.
+    pub fn deliveries(&mut self) -> Vec<Delivery> {
+        drain(&mut self.deliveries)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn drain(deliveries: &mut Option<mpsc::Receiver<Delivery>>) -> Vec<Delivery> {
+    let mut drained = vec![];
+    if let Some(deliveries) = deliveries {
+        while let Ok(delivery) = deliveries.try_recv() {
+            drained.push(delivery);
+        }
+    }
+    drained
+}
+
+/// A POST of `body` to `url`, signed with `secret` for a `"rook"` hook.  `url` can be a path
+/// for a [`TestService`], or a [`TestServer::url`].
+///
+/// Panics if `url` isn't a valid url or path.
+pub fn rook_request(
+    url: &str,
+    secret: impl AsRef<[u8]>,
+    body: impl Into<Vec<u8>>,
+) -> Request<Body> {
+    signed(url, secret.as_ref(), body.into(), &Style::Rook)
+}
+
+/// [`rook_request`] with a fresh timestamp and nonce, for hooks with a `replay_window`.
+pub fn stamped_request(
+    url: &str,
+    secret: impl AsRef<[u8]>,
+    body: impl Into<Vec<u8>>,
+) -> Request<Body> {
+    signed(url, secret.as_ref(), body.into(), &Style::RookStamped)
+}
+
+/// A POST of `body` to `url`, signed and labelled as GitHub would send `event`, for a
+/// `"github"` hook.
+///
+/// Panics if `url` isn't a valid url or path.
+pub fn github_request(
+    url: &str,
+    secret: impl AsRef<[u8]>,
+    event: &str,
+    body: impl Into<Vec<u8>>,
+) -> Request<Body> {
+    let style = Style::Github {
+        event: event.to_string(),
+    };
+    signed(url, secret.as_ref(), body.into(), &style)
+}
+
+fn signed(url: &str, secret: &[u8], body: Vec<u8>, style: &Style) -> Request<Body> {
+    client::request(url, secret, body, style).unwrap_or_else(|e| panic!("{}", e))
+}
@@ -0,0 +1,263 @@
+use crate::config::TlsConfig;
+use hyper::{
+    client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    },
+    service::Service,
+    Uri,
+};
+use std::{
+    error::Error,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03]; // 2.5.4.3
+
+/// Build the acceptor for the listener.  With `client_ca_file` set, client certificates are
+/// requested and verified during the handshake but not required; hooks decide whether a request
+/// without one is acceptable (see `require_client_cert`).
+pub fn acceptor(cfg: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let certs = read_certs(&cfg.cert_file)?;
+    let key = PrivateKeyDer::from_pem_file(&cfg.key_file)
+        .map_err(|e| format!("failed to read tls key '{}': {}", cfg.key_file, e))?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match &cfg.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("bad client ca '{}': {}", ca_file, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .map_err(|e| format!("bad client ca '{}': {}", ca_file, e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("bad tls certificate '{}': {}", cfg.cert_file, e))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// The subject CN of a verified client certificate.  `Some("")` when the client presented a
+/// valid certificate without a CN, None when it didn't present one.
+pub fn client_cn(conn: &ServerConnection) -> Option<String> {
+    let leaf = conn.peer_certificates()?.first()?;
+    Some(subject_cn(leaf).unwrap_or_default())
+}
+
+/// Client connector for `http://` and `https://` uris, trusting the CAs in `ca_file` (usually
+/// the system bundle) for https.
+#[derive(Clone)]
+pub struct HttpsConnector {
+    http: HttpConnector,
+    tls: TlsConnector,
+}
+
+pub fn https_connector(ca_file: &str) -> Result<HttpsConnector, String> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    Ok(HttpsConnector {
+        http,
+        tls: TlsConnector::from(client_config(ca_file)?),
+    })
+}
+
+/// Client settings that trust the CAs in `ca_file`.
+pub fn client_config(ca_file: &str) -> Result<Arc<ClientConfig>, String> {
+    let mut roots = RootCertStore::empty();
+    // system bundles can hold a few certificates webpki won't parse; skip those
+    let (_, skipped) = roots.add_parsable_certificates(read_certs(ca_file)?);
+    if roots.is_empty() {
+        return Err(format!(
+            "no usable certificates in '{}' ({} skipped)",
+            ca_file, skipped
+        ));
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Start TLS to `host` over a blocking `tcp` stream.  The handshake runs on the first read or
+/// write.
+#[cfg_attr(
+    not(any(feature = "matrix", feature = "postgres", feature = "telegram")),
+    allow(dead_code)
+)]
+pub fn connect_blocking(
+    config: &Arc<ClientConfig>,
+    host: &str,
+    tcp: std::net::TcpStream,
+) -> Result<StreamOwned<ClientConnection, std::net::TcpStream>, String> {
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let conn = ClientConnection::new(config.clone(), name).map_err(|e| e.to_string())?;
+    Ok(StreamOwned::new(conn, tcp))
+}
+
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Service<Uri> for HttpsConnector {
+    type Response = ClientStream;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<ClientStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let https = uri.scheme_str() == Some("https");
+        let host = uri.host().unwrap_or_default().to_string();
+        let connecting = self.http.call(uri);
+        let tls = self.tls.clone();
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            if !https {
+                return Ok(ClientStream::Plain(tcp));
+            }
+            let name = ServerName::try_from(host)?;
+            Ok(ClientStream::Tls(Box::new(tls.connect(name, tcp).await?)))
+        })
+    }
+}
+
+impl Connection for ClientStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            ClientStream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            ClientStream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(s) => Pin::new(s).poll_flush(cx),
+            ClientStream::Tls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            ClientStream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificates '{}': {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in '{}'", path));
+    }
+    Ok(certs)
+}
+
+/// Walk just enough DER to pull the CN out of an already-verified certificate:
+///   Certificate ::= SEQUENCE { tbsCertificate, .. }
+///   TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serial, signature, issuer, validity, subject, .. }
+///   Name ::= SEQUENCE OF SET OF SEQUENCE { type OID, value ANY }
+fn subject_cn(cert: &[u8]) -> Option<String> {
+    let (_, cert, _) = der_next(cert)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let (tag, _, after_version) = der_next(tbs)?;
+    let mut fields = if tag == 0xa0 { after_version } else { tbs };
+    for _ in 0..4 {
+        fields = der_next(fields)?.2;
+    }
+    let (_, mut rdns, _) = der_next(fields)?;
+    while !rdns.is_empty() {
+        let (_, mut attrs, rest) = der_next(rdns)?;
+        rdns = rest;
+        while !attrs.is_empty() {
+            let (_, attr, rest) = der_next(attrs)?;
+            attrs = rest;
+            let (_, oid, value) = der_next(attr)?;
+            if oid == OID_COMMON_NAME {
+                let (_, cn, _) = der_next(value)?;
+                return String::from_utf8(cn.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// split one DER element off the front of `input`: (tag, contents, rest)
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, b| acc << 8 | *b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
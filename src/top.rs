@@ -0,0 +1,154 @@
+//! `rook top`: a dashboard of a running server, drawn from its admin endpoints and redrawn in
+//! place until interrupted.
+use crate::cli::{self, Args, USAGE};
+use hyper::{body, client::HttpConnector, Client, StatusCode};
+use serde_json::Value;
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    mem,
+    time::Duration,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+/// move to the top left and clear the screen
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+pub async fn top(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[], &["--interval"])?;
+    let [admin] = args.positional.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    let base = if admin.contains("://") {
+        admin.trim_end_matches('/').to_string()
+    } else {
+        format!("http://{}", admin)
+    };
+    let interval = match args.value("--interval") {
+        Some(secs) => secs
+            .parse::<f64>()
+            .ok()
+            .filter(|s| *s >= 0.1)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("bad --interval '{}': expected seconds, at least 0.1", secs))?,
+        None => DEFAULT_INTERVAL,
+    };
+    let client = Client::new();
+    // a wrong address should fail fast, not draw an error screen forever
+    fetch(&client, &base).await?;
+    loop {
+        let screen = match fetch(&client, &base).await {
+            Ok((status, deliveries)) => render(&base, &status, &deliveries),
+            Err(e) => format!("rook top  {}\n\n{}\n", base, e),
+        };
+        let mut out = io::stdout().lock();
+        let _ = write!(out, "{}{}", CLEAR, screen);
+        let _ = out.flush();
+        drop(out);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// `/admin/status` and `/admin/deliveries`
+async fn fetch(client: &Client<HttpConnector>, base: &str) -> Result<(Value, Value), String> {
+    let status = get(client, &format!("{}/admin/status", base)).await?;
+    let deliveries = get(client, &format!("{}/admin/deliveries", base)).await?;
+    Ok((status, deliveries))
+}
+
+async fn get(client: &Client<HttpConnector>, url: &str) -> Result<Value, String> {
+    let uri = url
+        .parse()
+        .map_err(|e| format!("bad admin address '{}': {}", url, e))?;
+    let res = client
+        .get(uri)
+        .await
+        .map_err(|e| format!("failed to reach '{}': {}", url, e))?;
+    let status = res.status();
+    let body = body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| format!("failed to read '{}': {}", url, e))?;
+    if status != StatusCode::OK {
+        let body = String::from_utf8_lossy(&body);
+        return Err(format!("{} from '{}': {}", status, url, body.trim()));
+    }
+    serde_json::from_slice(&body).map_err(|e| format!("bad json from '{}': {}", url, e))
+}
+
+/// One screenful: the gauges, running jobs, and as many recent deliveries as fit.
+fn render(base: &str, status: &Value, deliveries: &Value) -> String {
+    let (width, height) = terminal_size();
+    let jobs = status["jobs"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!("rook top  {}", base),
+        format!(
+            "connections {}/{}   requests in flight {}/{}   jobs running {}{}",
+            status["connections"],
+            status["max_connections"],
+            status["requests_in_flight"],
+            status["max_requests"],
+            jobs.len(),
+            match status["max_jobs"].as_u64() {
+                Some(max) => format!("/{}", max),
+                None => String::new(),
+            }
+        ),
+        String::new(),
+    ];
+    let mut rows = vec![["PID", "RUNTIME", "ROUTE", "COMMAND", "REQUEST ID"].map(String::from)];
+    for job in jobs {
+        rows.push([
+            cli::text(&job["pid"]),
+            runtime(job["runtime_ms"].as_u64().unwrap_or_default()),
+            cli::text(&job["route"]),
+            cli::text(&job["command"]),
+            cli::text(&job["request_id"]),
+        ]);
+    }
+    lines.extend(cli::table(&rows));
+    lines.push(String::new());
+    // the header and a line to spare for the cursor
+    let fit = height.saturating_sub(lines.len() + 2);
+    let mut rows =
+        vec![["TIME", "STATUS", "METHOD", "PATH", "CLIENT", "REQUEST ID"].map(String::from)];
+    for delivery in deliveries.as_array().into_iter().flatten().take(fit) {
+        rows.push([
+            cli::text(&delivery["time"]),
+            cli::text(&delivery["status"]),
+            cli::text(&delivery["method"]),
+            cli::text(&delivery["path"]),
+            cli::text(&delivery["client"]),
+            cli::text(&delivery["request_id"]),
+        ]);
+    }
+    lines.extend(cli::table(&rows));
+    let mut screen = String::new();
+    for line in lines {
+        let _ = writeln!(screen, "{}", line.chars().take(width).collect::<String>());
+    }
+    screen
+}
+
+/// `1h02m`, `3m05s`, `12.4s`
+fn runtime(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..=59 => format!("{:.1}s", ms as f64 / 1000.0),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Columns and rows of the terminal on stdout, or 80x24 when it isn't one.
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 && size.ws_row > 0 {
+        (size.ws_col as usize, size.ws_row as usize)
+    } else {
+        (80, 24)
+    }
+}
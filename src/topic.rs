@@ -0,0 +1,44 @@
+//! MQTT topic filters: `+` matches any one level and a final `#` matches the rest, including
+//! none.  `home/+/door` matches `home/garage/door`; `home/#` matches `home` and everything
+//! under it.  Topics starting with `$` only match filters that start with the same level.
+
+/// Check `filter` is one an MQTT broker would accept: wildcards fill whole levels, and `#` only
+/// ends it.
+pub fn validate(filter: &str) -> Result<(), String> {
+    if filter.is_empty() || filter.contains('\0') {
+        return Err(format!("bad mqtt topic '{}'", filter));
+    }
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        let wildcard = level.contains(['+', '#']);
+        if wildcard && level.len() > 1 {
+            return Err(format!(
+                "mqtt topic '{}' wildcards must fill a whole level",
+                filter
+            ));
+        }
+        if *level == "#" && i + 1 < levels.len() {
+            return Err(format!("mqtt topic '{}' may only end with '#'", filter));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a message published to `topic` is one `filter` subscribes to.
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && !filter.starts_with('$') {
+        return false;
+    }
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            (_, None) => return false,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part != level => return false,
+            _ => {}
+        }
+    }
+    levels.next().is_none()
+}
@@ -0,0 +1,289 @@
+use crate::{
+    config::OtlpConfig,
+    encoding::{decode_hex, hex},
+    id,
+};
+use hyper::{
+    client::HttpConnector,
+    header::{self, HeaderValue},
+    Body, Client, Method, Request, Uri,
+};
+use serde_json::{json, Value};
+use std::{cell::RefCell, future::Future, sync::OnceLock, time::Duration};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// spans waiting to be exported; new traces are dropped while the exporter is this far behind
+const EXPORT_QUEUE: usize = 256;
+const EXPORT_BATCH: usize = 64;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_ERROR: u8 = 2;
+
+static EXPORTER: OnceLock<mpsc::Sender<Vec<Span>>> = OnceLock::new();
+
+tokio::task_local! {
+    /// spans finished so far by the request being handled
+    static TRACE: RefCell<Trace>;
+}
+
+struct Trace {
+    trace_id: [u8; 16],
+    root: Span,
+    finished: Vec<Span>,
+}
+
+struct Span {
+    name: String,
+    kind: u8,
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    start: OffsetDateTime,
+    end: Option<OffsetDateTime>,
+    attributes: Vec<(&'static str, Value)>,
+    error: bool,
+}
+
+/// A child of the request's root span, finished when dropped.  A no-op outside a traced
+/// request or when no exporter is configured.
+pub struct SpanGuard(Option<Span>);
+
+/// Start exporting spans to `cfg.endpoint` with OTLP/HTTP json.
+pub fn init(cfg: &OtlpConfig) -> Result<(), String> {
+    let uri: Uri = format!("{}/v1/traces", cfg.endpoint.trim_end_matches('/'))
+        .parse()
+        .map_err(|e| format!("bad otlp endpoint '{}': {}", cfg.endpoint, e))?;
+    if uri.scheme_str() != Some("http") {
+        return Err("otlp endpoint must be http://; send through a local collector".to_string());
+    }
+    let (tx, rx) = mpsc::channel(EXPORT_QUEUE);
+    let _ = EXPORTER.set(tx);
+    tokio::spawn(export(uri, cfg.service_name.clone(), rx));
+    Ok(())
+}
+
+/// Run `f` inside a server span named `name`.  The span continues the trace from an incoming
+/// `traceparent` header, and isn't recorded when that header says the caller isn't sampling.
+pub async fn with_trace<F: Future>(
+    traceparent: Option<HeaderValue>,
+    name: String,
+    f: F,
+) -> F::Output {
+    if EXPORTER.get().is_none() {
+        return f.await;
+    }
+    let parent = traceparent.and_then(|v| parse_traceparent(v.to_str().ok()?));
+    if let Some((_, _, false)) = parent {
+        return f.await;
+    }
+    let trace_id = parent.map_or_else(new_trace_id, |(trace_id, _, _)| trace_id);
+    let mut root = Span::new(name, SPAN_KIND_SERVER, trace_id, parent.map(|p| p.1));
+    root.attributes
+        .push(("service.instance.id", json!(std::process::id().to_string())));
+    let trace = RefCell::new(Trace {
+        trace_id,
+        root,
+        finished: Vec::new(),
+    });
+    TRACE
+        .scope(trace, async {
+            let out = f.await;
+            let spans = TRACE.with(|t| {
+                let mut t = t.borrow_mut();
+                let mut root = Span::new(String::new(), 0, t.trace_id, None);
+                std::mem::swap(&mut root, &mut t.root);
+                root.end = Some(OffsetDateTime::now_utc());
+                let mut spans = std::mem::take(&mut t.finished);
+                spans.push(root);
+                spans
+            });
+            if let Some(tx) = EXPORTER.get() {
+                let _ = tx.try_send(spans);
+            }
+            out
+        })
+        .await
+}
+
+/// Add an attribute to the request's root span.
+pub fn root_attr(key: &'static str, value: Value) {
+    let _ = TRACE.try_with(|t| t.borrow_mut().root.attributes.push((key, value)));
+}
+
+/// Start a child span of the request's root span.
+pub fn span(name: &str) -> SpanGuard {
+    SpanGuard(
+        TRACE
+            .try_with(|t| {
+                let t = t.borrow();
+                Span::new(
+                    name.to_string(),
+                    SPAN_KIND_INTERNAL,
+                    t.trace_id,
+                    Some(t.root.span_id),
+                )
+            })
+            .ok(),
+    )
+}
+
+impl SpanGuard {
+    pub fn attr(&mut self, key: &'static str, value: Value) -> &mut Self {
+        if let Some(span) = &mut self.0 {
+            span.attributes.push((key, value));
+        }
+        self
+    }
+
+    pub fn error(&mut self) -> &mut Self {
+        if let Some(span) = &mut self.0 {
+            span.error = true;
+        }
+        self
+    }
+
+    /// W3C `traceparent` naming this span as the parent, for processes it starts.
+    pub fn traceparent(&self) -> Option<String> {
+        let span = self.0.as_ref()?;
+        Some(format!(
+            "00-{}-{}-01",
+            hex(&span.trace_id),
+            hex(&span.span_id)
+        ))
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some(mut span) = self.0.take() {
+            span.end = Some(OffsetDateTime::now_utc());
+            let _ = TRACE.try_with(|t| t.borrow_mut().finished.push(span));
+        }
+    }
+}
+
+impl Span {
+    fn new(name: String, kind: u8, trace_id: [u8; 16], parent_id: Option<[u8; 8]>) -> Span {
+        Span {
+            name,
+            kind,
+            trace_id,
+            span_id: id::random_u64().to_be_bytes(),
+            parent_id,
+            start: OffsetDateTime::now_utc(),
+            end: None,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let nanos = |t: OffsetDateTime| t.unix_timestamp_nanos().to_string();
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&self.span_id),
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end.unwrap_or(self.start)),
+            "attributes": self.attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+        });
+        if let Some(parent) = &self.parent_id {
+            span["parentSpanId"] = json!(hex(parent));
+        }
+        if self.error {
+            span["status"] = json!({ "code": STATUS_ERROR });
+        }
+        span
+    }
+}
+
+/// OTLP's tagged attribute values
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+async fn export(uri: Uri, service_name: String, mut rx: mpsc::Receiver<Vec<Span>>) {
+    let client: Client<HttpConnector> = Client::new();
+    let mut batch: Vec<Span> = Vec::new();
+    let mut flush = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        let closed = tokio::select! {
+            spans = rx.recv() => match spans {
+                Some(spans) => {
+                    batch.extend(spans);
+                    if batch.len() < EXPORT_BATCH {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = flush.tick() => false,
+        };
+        if !batch.is_empty() {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [
+                            attribute("service.name", &json!(service_name)),
+                            attribute("process.pid", &json!(std::process::id())),
+                        ],
+                    },
+                    "scopeSpans": [{
+                        "scope": { "name": "rook", "version": env!("CARGO_PKG_VERSION") },
+                        "spans": batch.drain(..).map(|s| s.to_json()).collect::<Vec<_>>(),
+                    }],
+                }],
+            });
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(uri.clone())
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("error building body");
+            match client.request(req).await {
+                Ok(res) if res.status().is_success() => {}
+                Ok(res) => debug!("otlp export rejected: {}", res.status()),
+                Err(e) => debug!("otlp export failed: {}", e),
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+/// `00-<trace id>-<parent id>-<flags>` into (trace id, parent id, sampled)
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], bool)> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if version == "ff" || version.len() != 2 || flags.len() != 2 {
+        return None;
+    }
+    let trace_id: [u8; 16] = decode_hex(trace_id)?.try_into().ok()?;
+    let parent_id: [u8; 8] = decode_hex(parent_id)?.try_into().ok()?;
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+    let sampled = decode_hex(flags)?[0] & 1 == 1;
+    Some((trace_id, parent_id, sampled))
+}
+
+fn new_trace_id() -> [u8; 16] {
+    let mut id = [0u8; 16];
+    id[..8].copy_from_slice(&id::random_u64().to_be_bytes());
+    id[8..].copy_from_slice(&id::random_u64().to_be_bytes());
+    id
+}
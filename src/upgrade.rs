@@ -0,0 +1,160 @@
+//! Hot upgrades: replace a running rook with a new binary without closing its listening
+//! sockets.
+//!
+//! On `SIGUSR2` the server starts the executable it was started as (`argv[0]`) again, with the
+//! same arguments, and hands it the webhook and admin listeners as open descriptors.  Both
+//! processes accept from the same sockets, so no connection waiting to be accepted is refused.
+//! Once the new process is listening it says so over a pipe; the old one then stops accepting,
+//! finishes the requests it has, and exits.  A new process that fails to start (a bad config,
+//! a missing binary) exits without saying so, and the old one keeps serving.
+use crate::exec;
+use std::{
+    env,
+    fs::File,
+    io::{self, Read, Write},
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::{self, Command},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+const LISTEN_FD: &str = "ROOK_LISTEN_FD";
+const ADMIN_FD: &str = "ROOK_ADMIN_FD";
+const READY_FD: &str = "ROOK_READY_FD";
+const PREVIOUS_PID: &str = "ROOK_UPGRADE_FROM";
+
+/// Only the first server in a process takes the listeners; later ones bind their own.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The pid of the rook this process is taking over from, when it was started by an upgrade.
+pub fn previous_pid() -> Option<i32> {
+    env::var(PREVIOUS_PID).ok()?.parse().ok()
+}
+
+/// What the previous rook handed over, closed on exec again.
+#[derive(Default)]
+pub(crate) struct Inherited {
+    pub listener: Option<TcpListener>,
+    pub admin: Option<TcpListener>,
+    ready: Option<File>,
+}
+
+impl Inherited {
+    /// Tell the previous rook this one is serving, so it can finish up and exit.
+    pub fn ready(&mut self) {
+        if let Some(mut ready) = self.ready.take() {
+            let _ = ready.write_all(process::id().to_string().as_bytes());
+        }
+    }
+}
+
+/// The listeners this process was started with, the first time it's called.
+pub(crate) fn take() -> Result<Inherited, String> {
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(Inherited::default());
+    }
+    Ok(Inherited {
+        listener: inherited_listener(LISTEN_FD)?,
+        admin: inherited_listener(ADMIN_FD)?,
+        // SAFETY: the previous rook left the pipe's write end open at this descriptor
+        ready: inherited_fd(READY_FD)?.map(|fd| unsafe { File::from_raw_fd(fd) }),
+    })
+}
+
+fn inherited_fd(var: &str) -> Result<Option<RawFd>, String> {
+    match env::var(var) {
+        Ok(fd) => match fd.parse::<RawFd>() {
+            // SAFETY: fcntl only fails on a descriptor that isn't open
+            Ok(fd)
+                if fd > 2 && unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == 0 =>
+            {
+                Ok(Some(fd))
+            }
+            _ => Err(format!(
+                "{} must be an open file descriptor, not '{}'",
+                var, fd
+            )),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn inherited_listener(var: &str) -> Result<Option<TcpListener>, String> {
+    let Some(fd) = inherited_fd(var)? else {
+        return Ok(None);
+    };
+    // SAFETY: the previous rook left the listener open at this descriptor
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    listener
+        .local_addr()
+        .and_then(|_| listener.set_nonblocking(true))
+        .map_err(|e| format!("{}={} is not a listening socket: {}", var, fd, e))?;
+    Ok(Some(listener))
+}
+
+/// A new rook started with this one's listeners.
+pub(crate) struct Upgrade {
+    child: process::Child,
+    ready: File,
+}
+
+/// Start the rook executable again with this process's arguments, handing it `listener` and
+/// `admin`.
+pub(crate) fn start(listener: RawFd, admin: Option<RawFd>) -> Result<Upgrade, String> {
+    let mut args = env::args_os();
+    let program = args.next().ok_or("no argv[0] to start again")?;
+    let (ready, ready_write) = exec::pipe().map_err(|e| format!("failed to open pipe: {}", e))?;
+    let write_fd = ready_write.as_raw_fd();
+    let mut command = Command::new(&program);
+    command
+        .args(args)
+        .env(LISTEN_FD, listener.to_string())
+        .env(READY_FD, write_fd.to_string())
+        .env(PREVIOUS_PID, process::id().to_string())
+        .env_remove(ADMIN_FD);
+    if let Some(admin) = admin {
+        command.env(ADMIN_FD, admin.to_string());
+    }
+    let inherit = [Some(listener), admin, Some(write_fd)];
+    // SAFETY: fcntl is async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            for fd in inherit.into_iter().flatten() {
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = command
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", program.to_string_lossy(), e))?;
+    // dropping our write end leaves the child's as the only one, so a child that exits early
+    // closes the pipe
+    drop(ready_write);
+    Ok(Upgrade {
+        child,
+        ready: ready.into(),
+    })
+}
+
+impl Upgrade {
+    /// Block until the new rook is serving, returning its pid, or fail with how it exited.
+    pub fn wait(mut self) -> Result<String, String> {
+        let mut pid = String::new();
+        if let Ok(1..) = self.ready.read_to_string(&mut pid) {
+            // reap it once it exits, as it does straight away with --daemon
+            thread::spawn(move || self.child.wait());
+            return Ok(pid);
+        }
+        match self.child.wait() {
+            Ok(status) => Err(format!("new rook exited before serving ({})", status)),
+            Err(e) => Err(format!("new rook exited before serving: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Hook secrets kept in HashiCorp Vault: `secret_vault = "kv/data/rook/deploy#value"` reads
+//! field `value` of the secret at `kv/data/rook/deploy`.
+//!
+//! Secrets are read while the config loads, each path once, and kept in memory with the rest
+//! of the config; loading the config again reads them again.  Both KV v2 (`<mount>/data/...`)
+//! and KV v1 paths work.
+use crate::config::VaultConfig;
+use hyper::{body, header, Body, Client, Method, Request, StatusCode, Uri};
+use serde_json::{json, Value};
+use std::{collections::HashMap, env, fs, time::Duration};
+
+#[cfg(feature = "tls")]
+type Connector = crate::tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = hyper::client::HttpConnector;
+
+const TOKEN_HEADER: &str = "x-vault-token";
+const NAMESPACE_HEADER: &str = "x-vault-namespace";
+/// per request, login included
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to reach and log in to Vault, from `[vault]` with the usual `VAULT_*` environment
+/// variables filling in what it leaves out.
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+struct Settings {
+    address: String,
+    namespace: Option<String>,
+    auth: Auth,
+    ca_file: String,
+}
+
+enum Auth {
+    Token(String),
+    AppRole {
+        mount: String,
+        role_id: String,
+        secret_id: String,
+    },
+}
+
+/// Read every `path#field` in `references`, keyed by reference.
+pub async fn load(
+    cfg: Option<VaultConfig>,
+    references: Vec<String>,
+) -> Result<HashMap<String, Vec<u8>>, String> {
+    let settings = settings(cfg.as_ref())?;
+    read_all(settings, references).await
+}
+
+fn settings(cfg: Option<&VaultConfig>) -> Result<Settings, String> {
+    let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+    let address = cfg
+        .and_then(|c| c.address.clone())
+        .or_else(|| var("VAULT_ADDR"))
+        .ok_or("secret_vault needs vault.address or VAULT_ADDR")?;
+    let auth = match cfg {
+        Some(VaultConfig {
+            role_id: Some(role_id),
+            secret_id_file,
+            approle_mount,
+            ..
+        }) => {
+            let path = secret_id_file
+                .as_deref()
+                .ok_or("vault.role_id needs vault.secret_id_file")?;
+            Auth::AppRole {
+                mount: approle_mount.clone(),
+                role_id: role_id.clone(),
+                secret_id: read_file(path)?,
+            }
+        }
+        Some(VaultConfig {
+            token_file: Some(path),
+            ..
+        }) => Auth::Token(read_file(path)?),
+        _ => Auth::Token(
+            var("VAULT_TOKEN")
+                .ok_or("secret_vault needs vault.token_file, vault.role_id, or VAULT_TOKEN")?,
+        ),
+    };
+    Ok(Settings {
+        address: address.trim_end_matches('/').to_string(),
+        namespace: cfg
+            .and_then(|c| c.namespace.clone())
+            .or_else(|| var("VAULT_NAMESPACE")),
+        auth,
+        ca_file: cfg.map_or_else(crate::config::default_ca_file, |c| c.ca_file.clone()),
+    })
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .map_err(|e| format!("failed to read '{}': {}", path, e))
+}
+
+async fn read_all(
+    settings: Settings,
+    references: Vec<String>,
+) -> Result<HashMap<String, Vec<u8>>, String> {
+    let uri: Uri = settings
+        .address
+        .parse()
+        .map_err(|e| format!("bad vault address '{}': {}", settings.address, e))?;
+    let client = Client::builder().build(connector(&settings, &uri)?);
+    let token = match &settings.auth {
+        Auth::Token(token) => token.clone(),
+        Auth::AppRole {
+            mount,
+            role_id,
+            secret_id,
+        } => {
+            let login = json!({ "role_id": role_id, "secret_id": secret_id });
+            let path = format!("auth/{}/login", mount);
+            let res = call(&client, &settings, Method::POST, &path, None, login).await?;
+            res["auth"]["client_token"]
+                .as_str()
+                .ok_or("vault approle login returned no client_token")?
+                .to_string()
+        }
+    };
+    let mut paths: HashMap<String, Value> = HashMap::new();
+    let mut secrets = HashMap::new();
+    for reference in references {
+        let (path, field) = reference
+            .rsplit_once('#')
+            .filter(|(p, f)| !p.is_empty() && !f.is_empty())
+            .ok_or_else(|| format!("secret_vault '{}' must be PATH#FIELD", reference))?;
+        if !paths.contains_key(path) {
+            let res = call(
+                &client,
+                &settings,
+                Method::GET,
+                path,
+                Some(&token),
+                Value::Null,
+            )
+            .await?;
+            paths.insert(path.to_string(), res);
+        }
+        let data = &paths[path]["data"];
+        // KV v2 nests the secret under data.data, next to its metadata
+        let data = match (&data["data"], &data["metadata"]) {
+            (Value::Object(_), Value::Object(_)) => &data["data"],
+            _ => data,
+        };
+        let value = data[field]
+            .as_str()
+            .ok_or_else(|| format!("vault secret '{}' has no string field '{}'", path, field))?;
+        secrets.insert(reference.clone(), value.as_bytes().to_vec());
+    }
+    Ok(secrets)
+}
+
+/// One Vault API call under `/v1/`, returning the json body of a `200`.
+async fn call(
+    client: &Client<Connector>,
+    settings: &Settings,
+    method: Method,
+    path: &str,
+    token: Option<&str>,
+    body: Value,
+) -> Result<Value, String> {
+    let url = format!("{}/v1/{}", settings.address, path.trim_start_matches('/'));
+    let mut req = Request::builder().method(method).uri(&url);
+    if let Some(token) = token {
+        req = req.header(TOKEN_HEADER, token);
+    }
+    if let Some(namespace) = &settings.namespace {
+        req = req.header(NAMESPACE_HEADER, namespace.as_str());
+    }
+    let req = match body {
+        Value::Null => req.body(Body::empty()),
+        body => req
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+    }
+    .map_err(|e| format!("bad vault request '{}': {}", url, e))?;
+    let exchange = async {
+        let res = client.request(req).await?;
+        let status = res.status();
+        body::to_bytes(res.into_body()).await.map(|b| (status, b))
+    };
+    let (status, bytes) = tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| format!("vault request '{}' timed out", url))?
+        .map_err(|e| format!("vault request '{}' failed: {}", url, e))?;
+    let res: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    if status != StatusCode::OK {
+        // vault explains itself in {"errors": [...]}; never echo anything else back
+        let errors: Vec<&str> = res["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        return Err(format!(
+            "vault answered {} for '{}': {}",
+            status,
+            path,
+            errors.join("; ")
+        ));
+    }
+    Ok(res)
+}
+
+#[cfg(feature = "tls")]
+fn connector(settings: &Settings, _: &Uri) -> Result<Connector, String> {
+    crate::tls::https_connector(&settings.ca_file)
+}
+
+#[cfg(not(feature = "tls"))]
+fn connector(_: &Settings, uri: &Uri) -> Result<Connector, String> {
+    if uri.scheme_str() != Some("http") {
+        return Err("https vault addresses need rook built with the `tls` feature".to_string());
+    }
+    Ok(Connector::new())
+}
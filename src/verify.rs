@@ -0,0 +1,658 @@
+//! Signature checks for webhook deliveries.
+//!
+//! Every hook has a [`Verifier`] that decides whether a delivery really came from its sender.
+//! Both GitHub and rook payloads carry an HMAC-SHA256 of the body in a header, formatted as
+//! `sha256=<hex digest>`, and are checked by [`HmacSha256`]; other schemes implement the trait.
+//! [`signature`] pulls the claimed digest out of the headers and [`check_hmac`] compares it
+//! against a hook's secret; [`sign`] computes the header value a sender should use.
+//!
+//! rook senders may also stamp a delivery with [`ROOK_TIMESTAMP_HEADER`] and
+//! [`ROOK_NONCE_HEADER`].  The signature then covers `<timestamp>.<nonce>.<body>` (see
+//! [`sign_stamped`]), and a hook with a replay window rejects stale timestamps and nonces it
+//! has already seen, so a captured delivery can't be sent again later.
+//!
+//! rook senders can sign with another [`Algorithm`] the hook accepts by naming it in
+//! [`ROOK_ALG_HEADER`] and putting `<algorithm>=<hex digest>` in [`ROOK_ALG_SIGNATURE_HEADER`].
+//!
+//! Senders that can only set a static header use [`Bearer`] instead.
+//!
+//! Bodies too large to hold in memory are checked as they're read, through
+//! [`Verifier::verify_chunked`].
+use crate::encoding;
+use hmac::{digest::KeyInit, Hmac, Mac};
+use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use sha2::{Sha256, Sha512};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Header GitHub puts the body's signature in.
+pub const GITHUB_SIGNATURE_HEADER: &str = "x-hub-signature-256";
+/// Header rook payloads put the body's signature in.
+pub const ROOK_SIGNATURE_HEADER: &str = "x-rook-signature-256";
+/// Every signature header value starts with this.
+pub const DIGEST_PREFIX: &str = "sha256=";
+/// Header rook deliveries signed with an [`Algorithm`] other than the default name it in.
+pub const ROOK_ALG_HEADER: &str = "x-rook-signature-alg";
+/// Header rook deliveries that name their [`Algorithm`] put the signature in.
+pub const ROOK_ALG_SIGNATURE_HEADER: &str = "x-rook-signature";
+/// Header stamped rook deliveries put the unix time they were signed at in.
+pub const ROOK_TIMESTAMP_HEADER: &str = "x-rook-timestamp";
+/// Header stamped rook deliveries put a value unique to the delivery in: letters, digits, `-`,
+/// and `_`.
+pub const ROOK_NONCE_HEADER: &str = "x-rook-nonce";
+/// Longest nonce a stamped delivery may carry.
+const MAX_NONCE_LEN: usize = 128;
+/// What [`Bearer`] tokens are digested with, so they can be compared in constant time.
+const BEARER_CONTEXT: &[u8] = b"rook bearer token";
+/// [`Algorithm::Blake3`] keys are derived from the hook's secret in this context.
+pub const BLAKE3_CONTEXT: &str = "rook webhook signature v1";
+
+/// Why a delivery's signature was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// the signature header wasn't sent
+    Missing,
+    /// the header isn't `sha256=` followed by a hex or base64 digest
+    Malformed,
+    /// the digest doesn't match the body under the hook's secret
+    Mismatch,
+    /// the hook has a replay window but the delivery has no timestamp or nonce
+    Unstamped,
+    /// the timestamp is outside the hook's replay window
+    Stale,
+    /// the nonce was already used within the replay window
+    Replayed,
+    /// the delivery was signed with an algorithm the hook doesn't accept
+    Unsupported,
+}
+
+impl VerifyError {
+    /// Short reason, as written to the audit log and metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            VerifyError::Missing => "missing signature",
+            VerifyError::Malformed => "malformed signature",
+            VerifyError::Mismatch => "signature mismatch",
+            VerifyError::Unstamped => "missing timestamp",
+            VerifyError::Stale => "stale timestamp",
+            VerifyError::Replayed => "replayed nonce",
+            VerifyError::Unsupported => "unsupported algorithm",
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason())
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Decides whether a delivery was signed by the hook's sender.
+pub trait Verifier: Send + Sync {
+    /// Check the signature `headers` claim for `body`.
+    fn verify(&self, headers: &HeaderMap<HeaderValue>, body: &[u8]) -> Result<(), VerifyError>;
+
+    /// The headers and values that would pass [`Verifier::verify`] for `body`, so rook can
+    /// send its own deliveries (`rook selftest`).  None when the sender's signature can't be
+    /// forged from the hook's config.
+    fn sign(&self, _body: &[u8]) -> Option<Vec<(&'static str, String)>> {
+        None
+    }
+
+    /// Start checking the signature `headers` claim over a body read a chunk at a time, so a
+    /// large body needn't be held in memory.  The default collects the chunks and calls
+    /// [`Verifier::verify`] with them.
+    fn verify_chunked<'a>(
+        &'a self,
+        headers: &'a HeaderMap<HeaderValue>,
+    ) -> Box<dyn ChunkedVerify + 'a> {
+        Box::new(Collected {
+            verifier: self,
+            headers,
+            body: vec![],
+        })
+    }
+
+    /// Identifies the check this verifier makes: verifiers with the same key accept exactly the
+    /// same deliveries, so rook checks each delivery once for all of them, such as for hooks
+    /// sharing a secret.  None, the default, when the check can't be shared.
+    fn check_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A signature check fed the body a chunk at a time, from [`Verifier::verify_chunked`].
+pub trait ChunkedVerify: Send {
+    /// Add the next chunk of the body.
+    fn update(&mut self, chunk: &[u8]);
+
+    /// Check the signature over every chunk added.
+    fn finish(self: Box<Self>) -> Result<(), VerifyError>;
+}
+
+/// The default [`Verifier::verify_chunked`]: the whole body, verified once it's all read.
+struct Collected<'a, V: ?Sized> {
+    verifier: &'a V,
+    headers: &'a HeaderMap<HeaderValue>,
+    body: Vec<u8>,
+}
+
+impl<V: Verifier + ?Sized> ChunkedVerify for Collected<'_, V> {
+    fn update(&mut self, chunk: &[u8]) {
+        self.body.extend_from_slice(chunk);
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), VerifyError> {
+        self.verifier.verify(self.headers, &self.body)
+    }
+}
+
+/// A check that doesn't depend on the body, already made.
+struct Checked(Result<(), VerifyError>);
+
+impl ChunkedVerify for Checked {
+    fn update(&mut self, _chunk: &[u8]) {}
+
+    fn finish(self: Box<Self>) -> Result<(), VerifyError> {
+        self.0
+    }
+}
+
+/// A keyed hash a rook sender can sign with.  GitHub always uses [`Algorithm::Sha256`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// HMAC-SHA256, the default
+    Sha256,
+    /// HMAC-SHA512
+    Sha512,
+    /// BLAKE3 in keyed mode, with the key derived from the secret in [`BLAKE3_CONTEXT`]
+    Blake3,
+}
+
+impl Algorithm {
+    /// `sha256`, `sha512`, or `blake3`, as sent in [`ROOK_ALG_HEADER`] and before the digest
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Length of the hash, in bytes.
+    fn len(&self) -> usize {
+        match self {
+            Algorithm::Sha256 | Algorithm::Blake3 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    /// The signature header value for `body` under `secret`: `<name>=<hex digest>`.
+    pub fn sign(&self, secret: &[u8], body: &[u8]) -> String {
+        self.header_value(secret, &[body])
+    }
+
+    /// The signature header value for a delivery stamped with `timestamp` (unix seconds) and
+    /// `nonce`: the digest of `<timestamp>.<nonce>.<body>`.
+    pub fn sign_stamped(&self, secret: &[u8], timestamp: u64, nonce: &str, body: &[u8]) -> String {
+        let prefix = format!("{}.{}.", timestamp, nonce);
+        self.header_value(secret, &[prefix.as_bytes(), body])
+    }
+
+    fn header_value(&self, secret: &[u8], parts: &[&[u8]]) -> String {
+        format!(
+            "{}={}",
+            self.name(),
+            encoding::hex(&self.digest(secret, parts))
+        )
+    }
+
+    /// The keyed hash of the concatenation of `parts`.
+    fn digest(&self, secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        let mut keyed = self.keyed(secret);
+        for part in parts {
+            keyed.update(part);
+        }
+        keyed.finalize()
+    }
+
+    /// Check `signature` is the keyed hash of the concatenation of `parts`, in constant time.
+    fn check(&self, secret: &[u8], parts: &[&[u8]], signature: &[u8]) -> Result<(), VerifyError> {
+        let mut keyed = self.keyed(secret);
+        for part in parts {
+            keyed.update(part);
+        }
+        keyed.check(signature)
+    }
+
+    /// The keyed hash under `secret`, before any input.
+    fn keyed(&self, secret: &[u8]) -> Keyed {
+        match self {
+            Algorithm::Sha256 => Keyed::Sha256(hmac(secret)),
+            Algorithm::Sha512 => Keyed::Sha512(hmac(secret)),
+            Algorithm::Blake3 => Keyed::Blake3(Box::new(blake3::Hasher::new_keyed(
+                &blake3::derive_key(BLAKE3_CONTEXT, secret),
+            ))),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => Err(format!(
+                "unknown signature algorithm '{}', expected sha256, sha512, or blake3",
+                s
+            )),
+        }
+    }
+}
+
+fn hmac<M: Mac + KeyInit>(secret: &[u8]) -> M {
+    <M as Mac>::new_from_slice(secret).expect("error initializing hmac")
+}
+
+/// An [`Algorithm`]'s keyed hash part way through its input.
+enum Keyed {
+    Sha256(Hmac<Sha256>),
+    Sha512(Hmac<Sha512>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Keyed {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Keyed::Sha256(mac) => mac.update(data),
+            Keyed::Sha512(mac) => mac.update(data),
+            Keyed::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Keyed::Sha256(mac) => mac.finalize().into_bytes().to_vec(),
+            Keyed::Sha512(mac) => mac.finalize().into_bytes().to_vec(),
+            Keyed::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+
+    /// Check `signature` is the hash of everything added, in constant time.
+    fn check(self, signature: &[u8]) -> Result<(), VerifyError> {
+        let matched = match self {
+            Keyed::Sha256(mac) => mac.verify_slice(signature).is_ok(),
+            Keyed::Sha512(mac) => mac.verify_slice(signature).is_ok(),
+            // blake3::Hash compares in constant time
+            Keyed::Blake3(hasher) => <[u8; blake3::OUT_LEN]>::try_from(signature)
+                .is_ok_and(|s| hasher.finalize() == blake3::Hash::from(s)),
+        };
+        if matched {
+            debug!("hmac check success");
+            Ok(())
+        } else {
+            debug!("hmac check failed");
+            Err(VerifyError::Mismatch)
+        }
+    }
+}
+
+/// `sha256=<hex digest>` HMAC of the body in a header, as GitHub and rook send.  rook's scheme
+/// can also accept other [`Algorithm`]s.
+pub struct HmacSha256 {
+    header: &'static str,
+    secret: Vec<u8>,
+    /// whether rook's extensions apply: [`ROOK_ALG_HEADER`], and [`ROOK_TIMESTAMP_HEADER`] and
+    /// [`ROOK_NONCE_HEADER`] covered by the signature when they're sent
+    extensions: bool,
+    /// accepted, the first one preferred
+    algorithms: Vec<Algorithm>,
+    replay: Option<ReplayGuard>,
+}
+
+impl HmacSha256 {
+    /// GitHub's scheme, in [`GITHUB_SIGNATURE_HEADER`]
+    pub fn github(secret: Vec<u8>) -> Self {
+        HmacSha256 {
+            header: GITHUB_SIGNATURE_HEADER,
+            secret,
+            extensions: false,
+            algorithms: vec![Algorithm::Sha256],
+            replay: None,
+        }
+    }
+
+    /// rook's scheme, in [`ROOK_SIGNATURE_HEADER`]
+    pub fn rook(secret: Vec<u8>) -> Self {
+        HmacSha256 {
+            header: ROOK_SIGNATURE_HEADER,
+            secret,
+            extensions: true,
+            algorithms: vec![Algorithm::Sha256],
+            replay: None,
+        }
+    }
+
+    /// Accept deliveries signed with any of `algorithms` instead of only SHA-256; the first is
+    /// what rook signs its own deliveries with.  Only rook's scheme can change algorithms.
+    pub fn algorithms(mut self, algorithms: Vec<Algorithm>) -> Self {
+        if self.extensions && !algorithms.is_empty() {
+            self.algorithms = algorithms;
+        }
+        self
+    }
+
+    /// Require stamped deliveries, reject timestamps more than `window` away from now, and
+    /// reject nonces already seen within it.  Only rook's scheme can be stamped.
+    pub fn replay_window(mut self, window: Duration) -> Self {
+        if self.extensions {
+            self.replay = Some(ReplayGuard {
+                window: window.as_secs(),
+                seen: Mutex::new(HashMap::new()),
+            });
+        }
+        self
+    }
+}
+
+impl HmacSha256 {
+    /// The algorithm a delivery names, and its signature decoded.
+    fn signature(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<(Algorithm, Vec<u8>), VerifyError> {
+        let named = if self.extensions {
+            headers.get(ROOK_ALG_HEADER)
+        } else {
+            None
+        };
+        let (algorithm, header) = match named {
+            Some(name) => {
+                let algorithm = name.to_str().ok().and_then(|n| n.parse().ok());
+                (
+                    algorithm.ok_or(VerifyError::Unsupported)?,
+                    ROOK_ALG_SIGNATURE_HEADER,
+                )
+            }
+            None => (Algorithm::Sha256, self.header),
+        };
+        if !self.algorithms.contains(&algorithm) {
+            return Err(VerifyError::Unsupported);
+        }
+        Ok((algorithm, decode(headers, header, algorithm)?))
+    }
+}
+
+impl Verifier for HmacSha256 {
+    fn verify(&self, headers: &HeaderMap<HeaderValue>, body: &[u8]) -> Result<(), VerifyError> {
+        let mut chunked = self.verify_chunked(headers);
+        chunked.update(body);
+        chunked.finish()
+    }
+
+    fn verify_chunked<'a>(
+        &'a self,
+        headers: &'a HeaderMap<HeaderValue>,
+    ) -> Box<dyn ChunkedVerify + 'a> {
+        let started = self.signature(headers).and_then(|(algorithm, signature)| {
+            let stamp = if self.extensions {
+                stamp(headers)?
+            } else {
+                None
+            };
+            let mut keyed = algorithm.keyed(&self.secret);
+            match (stamp, &self.replay) {
+                (None, Some(_)) => return Err(VerifyError::Unstamped),
+                (Some((timestamp, nonce)), _) => {
+                    keyed.update(format!("{}.{}.", timestamp, nonce).as_bytes())
+                }
+                (None, None) => {}
+            }
+            Ok(HmacChunks {
+                keyed,
+                signature,
+                stamp,
+                replay: self.replay.as_ref(),
+            })
+        });
+        match started {
+            Ok(chunks) => Box::new(chunks),
+            Err(e) => Box::new(Checked(Err(e))),
+        }
+    }
+
+    fn check_key(&self) -> Option<Vec<u8>> {
+        // every hook records nonces in its own replay guard
+        if self.replay.is_some() {
+            return None;
+        }
+        let mut key = vec![self.extensions as u8];
+        key.extend(self.header.as_bytes());
+        for algorithm in &self.algorithms {
+            key.push(b' ');
+            key.extend(algorithm.name().as_bytes());
+        }
+        key.push(0);
+        key.extend(&self.secret);
+        Some(key)
+    }
+
+    fn sign(&self, body: &[u8]) -> Option<Vec<(&'static str, String)>> {
+        let algorithm = self.algorithms[0];
+        let header = match algorithm {
+            Algorithm::Sha256 => self.header,
+            _ => ROOK_ALG_SIGNATURE_HEADER,
+        };
+        let mut headers = match self.replay {
+            None => vec![(header, algorithm.sign(&self.secret, body))],
+            Some(_) => {
+                let (timestamp, nonce) = (unix_now(), format!("{:016x}", crate::id::random_u64()));
+                vec![
+                    (
+                        header,
+                        algorithm.sign_stamped(&self.secret, timestamp, &nonce, body),
+                    ),
+                    (ROOK_TIMESTAMP_HEADER, timestamp.to_string()),
+                    (ROOK_NONCE_HEADER, nonce),
+                ]
+            }
+        };
+        if algorithm != Algorithm::Sha256 {
+            headers.push((ROOK_ALG_HEADER, algorithm.name().to_string()));
+        }
+        Some(headers)
+    }
+}
+
+/// [`HmacSha256`]'s check part way through the body.
+struct HmacChunks<'a> {
+    keyed: Keyed,
+    signature: Vec<u8>,
+    stamp: Option<(u64, &'a str)>,
+    replay: Option<&'a ReplayGuard>,
+}
+
+impl ChunkedVerify for HmacChunks<'_> {
+    fn update(&mut self, chunk: &[u8]) {
+        self.keyed.update(chunk);
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), VerifyError> {
+        self.keyed.check(&self.signature)?;
+        match (self.stamp, self.replay) {
+            (Some((timestamp, nonce)), Some(replay)) => replay.check(timestamp, nonce),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A static `Authorization: Bearer <token>`, for senders that can't sign deliveries.  The body
+/// isn't covered, so only use it over TLS.
+pub struct Bearer {
+    token: String,
+    /// HMAC of [`BEARER_CONTEXT`] keyed by the token
+    digest: Vec<u8>,
+}
+
+impl Bearer {
+    pub fn new(token: String) -> Self {
+        let digest = Algorithm::Sha256.digest(token.as_bytes(), &[BEARER_CONTEXT]);
+        Bearer { token, digest }
+    }
+}
+
+impl Verifier for Bearer {
+    fn verify(&self, headers: &HeaderMap<HeaderValue>, _body: &[u8]) -> Result<(), VerifyError> {
+        let header = headers
+            .get(AUTHORIZATION)
+            .ok_or(VerifyError::Missing)?
+            .to_str()
+            .map_err(|_| VerifyError::Malformed)?;
+        let token = header
+            .split_once(' ')
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or(VerifyError::Malformed)?;
+        // compare digests rather than tokens; verify_slice does it in constant time
+        check_hmac(token.as_bytes(), BEARER_CONTEXT, &self.digest)
+    }
+
+    fn sign(&self, _body: &[u8]) -> Option<Vec<(&'static str, String)>> {
+        Some(vec![(
+            AUTHORIZATION.as_str(),
+            format!("Bearer {}", self.token),
+        )])
+    }
+
+    fn verify_chunked<'a>(
+        &'a self,
+        headers: &'a HeaderMap<HeaderValue>,
+    ) -> Box<dyn ChunkedVerify + 'a> {
+        Box::new(Checked(self.verify(headers, &[])))
+    }
+}
+
+/// Nonces seen within a hook's replay window.
+struct ReplayGuard {
+    /// seconds
+    window: u64,
+    /// nonce -> unix time it falls out of the window
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ReplayGuard {
+    /// Check a delivery whose signature already verified, and remember its nonce.
+    fn check(&self, timestamp: u64, nonce: &str) -> Result<(), VerifyError> {
+        let now = unix_now();
+        if now.abs_diff(timestamp) > self.window {
+            return Err(VerifyError::Stale);
+        }
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|_, expires| *expires >= now);
+        if seen.contains_key(nonce) {
+            return Err(VerifyError::Replayed);
+        }
+        seen.insert(nonce.to_string(), timestamp + self.window);
+        Ok(())
+    }
+}
+
+/// The timestamp and nonce a delivery was stamped with, if any.  Sending only one of them, a
+/// timestamp that isn't unix seconds, or a nonce with anything but letters, digits, `-`, and
+/// `_`, is malformed.
+fn stamp(headers: &HeaderMap<HeaderValue>) -> Result<Option<(u64, &str)>, VerifyError> {
+    let value = |name| {
+        headers
+            .get(name)
+            .map(|v| v.to_str().map_err(|_| VerifyError::Malformed))
+            .transpose()
+    };
+    match (value(ROOK_TIMESTAMP_HEADER)?, value(ROOK_NONCE_HEADER)?) {
+        (None, None) => Ok(None),
+        (Some(timestamp), Some(nonce)) => {
+            // digits only; parse alone would take a leading `+`
+            if !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(VerifyError::Malformed);
+            }
+            let timestamp = timestamp.parse().map_err(|_| VerifyError::Malformed)?;
+            // no `.`, so a nonce can't take the start of the body and still sign the same bytes
+            let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_';
+            if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN || !nonce.bytes().all(valid) {
+                return Err(VerifyError::Malformed);
+            }
+            Ok(Some((timestamp, nonce)))
+        }
+        _ => Err(VerifyError::Malformed),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Decode the SHA-256 digest claimed in header `name`.
+pub fn signature(headers: &HeaderMap<HeaderValue>, name: &str) -> Result<Vec<u8>, VerifyError> {
+    decode(headers, name, Algorithm::Sha256)
+}
+
+/// Decode the `<algorithm>=<digest>` in header `name`, with the digest in hex or base64.
+fn decode(
+    headers: &HeaderMap<HeaderValue>,
+    name: &str,
+    algorithm: Algorithm,
+) -> Result<Vec<u8>, VerifyError> {
+    let header = headers
+        .get(name)
+        .ok_or(VerifyError::Missing)?
+        .to_str()
+        .map_err(|_| VerifyError::Malformed)?;
+    let digest = header
+        .strip_prefix(algorithm.name())
+        .and_then(|h| h.strip_prefix('='))
+        .ok_or(VerifyError::Malformed)?;
+    // a hex digest is exactly twice as long as the hash; anything else must be base64
+    let decoded = if digest.len() == algorithm.len() * 2 {
+        encoding::decode_hex(digest)
+    } else {
+        encoding::decode_base64(digest)
+    };
+    decoded.ok_or(VerifyError::Malformed)
+}
+
+/// The signature header value for `body` under `secret`: `sha256=` and the hex digest.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    Algorithm::Sha256.sign(secret, body)
+}
+
+/// The signature header value for a delivery stamped with `timestamp` (unix seconds) and
+/// `nonce`: the SHA-256 digest of `<timestamp>.<nonce>.<body>`.
+pub fn sign_stamped(secret: &[u8], timestamp: u64, nonce: &str, body: &[u8]) -> String {
+    Algorithm::Sha256.sign_stamped(secret, timestamp, nonce, body)
+}
+
+/// Check `value`, written the way [`Algorithm::sign`] writes it (`<name>=<hex digest>`), is the
+/// keyed hash of `body` under `secret`, in constant time.
+pub fn check_signed(secret: &[u8], body: &[u8], value: &str) -> Result<(), VerifyError> {
+    let (name, digest) = value.trim().split_once('=').ok_or(VerifyError::Malformed)?;
+    let algorithm: Algorithm = name.parse().map_err(|_| VerifyError::Unsupported)?;
+    let digest = encoding::decode_hex(digest).ok_or(VerifyError::Malformed)?;
+    algorithm.check(secret, &[body], &digest)
+}
+
+/// Check `signature` is the HMAC-SHA256 of `body` under `secret`, in constant time.
+pub fn check_hmac(secret: &[u8], body: &[u8], signature: &[u8]) -> Result<(), VerifyError> {
+    Algorithm::Sha256.check(secret, &[body], signature)
+}
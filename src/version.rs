@@ -0,0 +1,52 @@
+//! What this build of rook is: its version, commit, build time, and cargo features.
+use serde_json::{json, Value};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// short git commit, or `unknown` when built outside a checkout
+pub const COMMIT: &str = env!("ROOK_COMMIT");
+/// RFC 3339, in UTC
+pub const BUILD_TIME: &str = env!("ROOK_BUILD_TIME");
+
+/// The cargo features this build was compiled with.
+pub fn features() -> Vec<&'static str> {
+    let features = [
+        ("age", cfg!(feature = "age")),
+        ("aws", cfg!(feature = "aws")),
+        ("axum", cfg!(feature = "axum")),
+        ("client", cfg!(feature = "client")),
+        ("exec", cfg!(feature = "exec")),
+        ("github", cfg!(feature = "github")),
+        ("kafka", cfg!(feature = "kafka")),
+        ("kv", cfg!(feature = "kv")),
+        ("matrix", cfg!(feature = "matrix")),
+        ("metrics", cfg!(feature = "metrics")),
+        ("mqtt", cfg!(feature = "mqtt")),
+        ("nats", cfg!(feature = "nats")),
+        ("postgres", cfg!(feature = "postgres")),
+        ("redis", cfg!(feature = "redis")),
+        ("rhai", cfg!(feature = "rhai")),
+        ("server", cfg!(feature = "server")),
+        ("smtp", cfg!(feature = "smtp")),
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("telegram", cfg!(feature = "telegram")),
+        ("test-util", cfg!(feature = "test-util")),
+        ("tls", cfg!(feature = "tls")),
+        ("vault", cfg!(feature = "vault")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("watch", cfg!(feature = "watch")),
+    ];
+    features
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+}
+
+/// Everything above, as `/admin/version` serves it.
+pub fn json() -> Value {
+    json!({
+        "version": VERSION,
+        "commit": COMMIT,
+        "build_time": BUILD_TIME,
+        "features": features(),
+    })
+}
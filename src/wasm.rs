@@ -0,0 +1,133 @@
+//! [`Filter`]s compiled to WebAssembly, loaded from a hook's `wasm_filter`.
+//!
+//! A module exports its `memory` and two functions:
+//!
+//! * `alloc(len: i32) -> i32` returns a pointer to `len` free bytes, where rook writes the input
+//! * `filter(ptr: i32, len: i32) -> i64` reads the input and returns the output's pointer in the
+//!   high 32 bits and its length in the low 32 bits
+//!
+//! The input is json: `{"provider": "github", "route": "/hooks/gh", "event": "push",
+//! "payload": {..}, "env": {..}}`, where `payload` is the GitHub json or the rook payload as
+//! a string.  The output is json too: `{"allow": true, "env": {"NAME": "value"}, "args": []}`.
+//!
+//! Modules import nothing, get a fresh instance for every delivery, and are cut off after
+//! [`FUEL`] instructions or [`MAX_MEMORY`] bytes of memory.
+use crate::{
+    exec::{Invocation, Payload},
+    filter::{Filter, Verdict},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, sync::OnceLock};
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// instructions a module may run per delivery
+pub const FUEL: u64 = 50_000_000;
+/// bytes of linear memory a module may grow to
+pub const MAX_MEMORY: usize = 64 * 1024 * 1024;
+/// largest output rook will read back
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+pub struct WasmFilter {
+    path: String,
+    module: Module,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    allow: bool,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+fn engine() -> Result<&'static Engine, String> {
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine);
+    }
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(|e| format!("failed to start wasm engine: {}", e))?;
+    Ok(ENGINE.get_or_init(|| engine))
+}
+
+impl WasmFilter {
+    /// Compile the module at `path` and check it has the exports rook calls.
+    pub fn load(path: &str) -> Result<WasmFilter, String> {
+        let module = Module::from_file(engine()?, path)
+            .map_err(|e| format!("failed to load wasm_filter '{}': {}", path, e))?;
+        for export in ["memory", "alloc", "filter"] {
+            if module.get_export(export).is_none() {
+                return Err(format!(
+                    "wasm_filter '{}' doesn't export `{}`",
+                    path, export
+                ));
+            }
+        }
+        Ok(WasmFilter {
+            path: path.to_string(),
+            module,
+        })
+    }
+
+    fn run(&self, input: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store: Store<StoreLimits> = Store::new(self.module.engine(), limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL)?;
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("missing memory export"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let filter = instance.get_typed_func::<(i32, i32), i64>(&mut store, "filter")?;
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input)?;
+        let packed = filter.call(&mut store, (ptr, len))? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        if out_len > MAX_OUTPUT {
+            return Err(wasmtime::Error::msg("output too large"));
+        }
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output)?;
+        Ok(output)
+    }
+}
+
+impl Filter for WasmFilter {
+    fn filter(&self, invocation: &Invocation) -> Result<Verdict, String> {
+        let (event, payload) = match invocation.payload {
+            Payload::Github(event) => (
+                Some(event.name()),
+                serde_json::from_slice(invocation.body).unwrap_or(Value::Null),
+            ),
+            Payload::Rook(text) | Payload::Mqtt(text) | Payload::Watch(text) => (None, json!(text)),
+        };
+        let env: BTreeMap<&str, &str> = invocation
+            .env
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let input = json!({
+            "provider": invocation.payload.provider(),
+            "route": invocation.route,
+            "event": event,
+            "payload": payload,
+            "env": env,
+        });
+        let output = self
+            .run(input.to_string().as_bytes())
+            .map_err(|e| format!("wasm_filter '{}' failed: {}", self.path, e))?;
+        let output: Output = serde_json::from_slice(&output)
+            .map_err(|e| format!("wasm_filter '{}' returned bad output: {}", self.path, e))?;
+        Ok(Verdict {
+            allow: output.allow,
+            env: output.env.into_iter().collect(),
+            args: output.args,
+        })
+    }
+}
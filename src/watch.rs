@@ -0,0 +1,121 @@
+//! Run `"watch"` hooks when matching files appear, with inotify.
+//!
+//! A file appears when it's closed after being written, or when it's moved into a watched
+//! directory.  Writers that write in one go, or that write under another name and rename into
+//! place, never hand a command a half-written file.  Files already there when rook starts
+//! don't run anything, and subdirectories aren't watched.
+use crate::{config::RouteConfig, glob, logging, router};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    str,
+    sync::Arc,
+};
+use tokio::io::unix::AsyncFd;
+
+const EVENTS: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_ONLYDIR;
+/// `struct inotify_event` without its name
+const HEADER: usize = 16;
+
+/// Watch the directory of every `"watch"` hook's pattern for as long as `cfg` is served.
+pub async fn watch(cfg: Arc<RouteConfig>) {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+        log::error!("watch: {}", io::Error::last_os_error());
+        return;
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut dirs: HashMap<i32, String> = HashMap::new();
+    for hook in &cfg.watch_hooks {
+        let dir = glob::dir(&hook.watch);
+        if dirs.values().any(|d| d == dir) {
+            continue;
+        }
+        // the pattern was checked for nul bytes
+        let Ok(path) = CString::new(dir) else {
+            continue;
+        };
+        let wd = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), EVENTS) };
+        if wd < 0 {
+            log::error!(
+                "watch failed: dir={} error={}",
+                dir,
+                io::Error::last_os_error()
+            );
+            continue;
+        }
+        dirs.insert(wd, dir.to_string());
+    }
+    if dirs.is_empty() {
+        return;
+    }
+    let fd = match AsyncFd::new(fd) {
+        Ok(fd) => fd,
+        Err(e) => {
+            log::error!("watch: {}", e);
+            return;
+        }
+    };
+    log::info!("watching {} directories", dirs.len());
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let mut ready = match fd.readable().await {
+            Ok(ready) => ready,
+            Err(e) => {
+                log::error!("watch: {}", e);
+                return;
+            }
+        };
+        let read = ready.try_io(|fd| {
+            let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            match n {
+                n if n < 0 => Err(io::Error::last_os_error()),
+                n => Ok(n as usize),
+            }
+        });
+        let n = match read {
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => {
+                log::error!("watch: {}", e);
+                return;
+            }
+            // woken without anything to read
+            Err(_) => continue,
+        };
+        let mut events = &buf[..n];
+        while events.len() >= HEADER {
+            let field = |i: usize| {
+                u32::from_ne_bytes([events[i], events[i + 1], events[i + 2], events[i + 3]])
+            };
+            let (wd, mask, len) = (field(0) as i32, field(4), field(12) as usize);
+            let Some(name) = events.get(HEADER..HEADER + len) else {
+                break;
+            };
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(len)];
+            events = &events[HEADER + len..];
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                log::warn!("watch queue overflowed; files that appeared may have been missed");
+                continue;
+            }
+            if mask & libc::IN_IGNORED != 0 {
+                if let Some(dir) = dirs.remove(&wd) {
+                    log::warn!("watch stopped: dir={} error=directory removed", dir);
+                }
+                continue;
+            }
+            let (Some(dir), Ok(name)) = (dirs.get(&wd), str::from_utf8(name)) else {
+                continue;
+            };
+            if mask & libc::IN_ISDIR != 0 {
+                continue;
+            }
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            logging::with_request_id(logging::new_request_id(), async {
+                router::watched_file(&cfg, &path)
+            })
+            .await;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use hyper::{body, Body, Client, Request};
+use rook::{config, testing::TestServer};
+use std::{env, fs, net::TcpListener, process, time::Duration};
+
+const SECRET: &str = "hunter2";
+
+#[tokio::test]
+async fn replay_keeps_the_query() {
+    let dir = env::temp_dir().join(format!("rook-replay-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("secret"), SECRET).unwrap();
+    let admin = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let cfg = config::from_toml(&format!(
+        r#"
+        addr = "127.0.0.1"
+        port = 0
+
+        [admin]
+        addr = "127.0.0.1"
+        port = {port}
+
+        [history]
+        path = "{dir}/history.db"
+        store_body = true
+
+        [[hooks]]
+        type = "rook"
+        url = "/deploy"
+        secret_file = "{dir}/secret"
+        command_path = "/opt/deploy.sh"
+
+        [[hooks]]
+        type = "rook"
+        url = "/deploy"
+        query = {{ env = "staging" }}
+        secret_file = "{dir}/secret"
+        command_path = "/opt/staging.sh"
+        "#,
+        port = admin.port(),
+        dir = dir.display(),
+    ))
+    .unwrap();
+    let mut server = TestServer::start(cfg).await.unwrap();
+    let client = Client::new();
+
+    let mut req = rook::testing::rook_request(&server.url("/deploy?env=staging"), SECRET, "v1.2");
+    req.headers_mut()
+        .insert("x-request-id", "staged".parse().unwrap());
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), 200);
+
+    // the history is written after the response
+    let replay = format!("http://{}/admin/deliveries/staged/replay", admin);
+    let mut status = 0;
+    for _ in 0..50 {
+        let req = Request::post(&replay).body(Body::empty()).unwrap();
+        let res = client.request(req).await.unwrap();
+        status = res.status().as_u16();
+        if status != 404 {
+            let text = body::to_bytes(res.into_body()).await.unwrap();
+            assert!(String::from_utf8_lossy(&text).contains(r#""status":200"#));
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, 200);
+
+    let commands: Vec<String> = server.deliveries().into_iter().map(|d| d.command).collect();
+    assert_eq!(commands, ["/opt/staging.sh", "/opt/staging.sh"]);
+    let _ = fs::remove_dir_all(dir);
+}
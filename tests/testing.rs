@@ -0,0 +1,91 @@
+use hyper::{body, Client};
+use rook::{
+    config::RouteConfig,
+    testing::{self, TestServer, TestService},
+};
+use serde_json::json;
+
+const SECRET: &str = "hunter2";
+
+fn config() -> RouteConfig {
+    RouteConfig::builder()
+        .github_hook("/deploy", "me/site", SECRET, "/opt/deploy.sh")
+        .rook_hook("/build", SECRET, "/opt/build.sh")
+        .build()
+        .unwrap()
+}
+
+fn push() -> Vec<u8> {
+    json!({
+        "ref": "refs/heads/main",
+        "before": "0".repeat(40),
+        "after": "0".repeat(40),
+        "commits": [],
+        "head_commit": null,
+        "repository": {"id": 0, "full_name": "me/site", "name": "site"},
+        "sender": {"id": 0, "login": "octocat"},
+    })
+    .to_string()
+    .into_bytes()
+}
+
+#[tokio::test]
+async fn server_accepts_signed_requests() {
+    let mut server = TestServer::start(config()).await.unwrap();
+    let client = Client::new();
+
+    let req = testing::github_request(&server.url("/deploy"), SECRET, "push", push());
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), 200);
+
+    let req = testing::rook_request(&server.url("/build"), SECRET, "v1.2");
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), 200);
+
+    let deliveries = server.deliveries();
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(deliveries[0].route, "/deploy");
+    assert_eq!(deliveries[0].command, "/opt/deploy.sh");
+    assert_eq!(deliveries[0].body, push());
+    assert_eq!(deliveries[1].route, "/build");
+    assert_eq!(deliveries[1].body, b"v1.2");
+}
+
+#[tokio::test]
+async fn server_rejects_bad_signatures() {
+    let mut server = TestServer::start(config()).await.unwrap();
+    let client = Client::new();
+
+    let req = testing::github_request(&server.url("/deploy"), "wrong", "push", push());
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), 403);
+
+    let req = testing::rook_request(&server.url("/build"), "wrong", "v1.2");
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), 403);
+    let text = body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(text, "signature mismatch");
+
+    assert!(server.deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn service_routes_in_process() {
+    let mut rook = TestService::new(config()).unwrap();
+
+    let res = rook
+        .call(testing::github_request("/deploy", SECRET, "push", push()))
+        .await;
+    assert_eq!(res.status(), 200);
+    let res = rook
+        .call(testing::rook_request("/build", SECRET, "v1.2"))
+        .await;
+    assert_eq!(res.status(), 200);
+    let res = rook
+        .call(testing::rook_request("/missing", SECRET, "v1.2"))
+        .await;
+    assert_eq!(res.status(), 404);
+
+    let routes: Vec<String> = rook.deliveries().into_iter().map(|d| d.route).collect();
+    assert_eq!(routes, ["/deploy", "/build"]);
+}
@@ -0,0 +1,72 @@
+use hyper::header::{HeaderMap, HeaderValue};
+use rook::verify::{
+    self, HmacSha256, Verifier, VerifyError, ROOK_NONCE_HEADER, ROOK_SIGNATURE_HEADER,
+    ROOK_TIMESTAMP_HEADER,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECRET: &[u8] = b"hunter2";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn stamped(timestamp: u64, nonce: &str, signature: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = |v: &str| HeaderValue::from_str(v).unwrap();
+    headers.insert(ROOK_TIMESTAMP_HEADER, value(&timestamp.to_string()));
+    headers.insert(ROOK_NONCE_HEADER, value(nonce));
+    headers.insert(ROOK_SIGNATURE_HEADER, value(signature));
+    headers
+}
+
+fn guarded() -> HmacSha256 {
+    HmacSha256::rook(SECRET.to_vec()).replay_window(Duration::from_secs(300))
+}
+
+#[test]
+fn stamped_delivery_verifies_once() {
+    let hook = guarded();
+    let (timestamp, body) = (now(), b"deploy v1.2");
+    let signature = verify::sign_stamped(SECRET, timestamp, "n0nce", body);
+    let headers = stamped(timestamp, "n0nce", &signature);
+    assert_eq!(hook.verify(&headers, body), Ok(()));
+    assert_eq!(hook.verify(&headers, body), Err(VerifyError::Replayed));
+}
+
+#[test]
+fn nonce_cant_take_the_start_of_the_body() {
+    let hook = guarded();
+    let (timestamp, body) = (now(), b"deploy v1.2");
+    let signature = verify::sign_stamped(SECRET, timestamp, "n0nce", body);
+    assert_eq!(
+        hook.verify(&stamped(timestamp, "n0nce", &signature), body),
+        Ok(())
+    );
+    // the same signed bytes, `<timestamp>.n0nce.deploy v1.2`, split at the body's `.`
+    let shifted = stamped(timestamp, "n0nce.deploy v1", &signature);
+    assert_eq!(hook.verify(&shifted, b"2"), Err(VerifyError::Malformed));
+}
+
+#[test]
+fn nonce_charset() {
+    let hook = guarded();
+    let timestamp = now();
+    for nonce in ["a.b", "a b", "a/b", "ü"] {
+        let signature = verify::sign_stamped(SECRET, timestamp, nonce, b"");
+        let Ok(value) = HeaderValue::from_str(nonce) else {
+            continue;
+        };
+        let mut headers = stamped(timestamp, "placeholder", &signature);
+        headers.insert(ROOK_NONCE_HEADER, value);
+        assert_eq!(hook.verify(&headers, b""), Err(VerifyError::Malformed));
+    }
+    let signature = verify::sign_stamped(SECRET, timestamp, "Ab9-_", b"");
+    assert_eq!(
+        hook.verify(&stamped(timestamp, "Ab9-_", &signature), b""),
+        Ok(())
+    );
+}